@@ -0,0 +1,71 @@
+//! Generates the gRPC service from `proto/agent.proto` without needing
+//! protoc: the methods are declared here and the messages are written out
+//! in `src/grpc.rs`, so all three must be kept in step.
+
+use tonic_build::manual::{Builder, Method, Service};
+
+fn method(name: &str, route: &str, input: &str, output: &str, streaming: bool) -> Method {
+    let builder = Method::builder()
+        .name(name)
+        .route_name(route)
+        .input_type(format!("crate::grpc::{}", input))
+        .output_type(format!("crate::grpc::{}", output))
+        .codec_path("tonic_prost::ProstCodec");
+    if streaming {
+        builder.server_streaming().build()
+    } else {
+        builder.build()
+    }
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=proto/agent.proto");
+    let service = Service::builder()
+        .name("Agent")
+        .package("mcagent.v1")
+        .method(method(
+            "list_servers",
+            "ListServers",
+            "ListServersRequest",
+            "ListServersResponse",
+            false,
+        ))
+        .method(method(
+            "start_server",
+            "StartServer",
+            "ServerRequest",
+            "ActionResponse",
+            false,
+        ))
+        .method(method(
+            "stop_server",
+            "StopServer",
+            "ServerRequest",
+            "ActionResponse",
+            false,
+        ))
+        .method(method(
+            "send_command",
+            "SendCommand",
+            "CommandRequest",
+            "ActionResponse",
+            false,
+        ))
+        .method(method(
+            "stream_console",
+            "StreamConsole",
+            "ServerRequest",
+            "ConsoleLine",
+            true,
+        ))
+        .method(method(
+            "stream_metrics",
+            "StreamMetrics",
+            "ServerRequest",
+            "MetricsSample",
+            true,
+        ))
+        .build();
+    Builder::new().build_client(false).compile(&[service]);
+}
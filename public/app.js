@@ -5,6 +5,10 @@ let metricsWs = null;
 let cpuChart = null;
 let memChart = null;
 let isEditing = false;
+let editingServer = null;
+// {token, username, expires_in_secs} from /api/login, kept across reloads
+let session = JSON.parse(localStorage.getItem('session') || 'null');
+let refreshTimer = null;
 
 // DOM Elements
 const serverListSection = document.getElementById('server-list-section');
@@ -14,14 +18,110 @@ const consoleOutput = document.getElementById('console-output');
 const consoleInput = document.getElementById('console-input');
 const modal = document.getElementById('server-modal');
 const serverForm = document.getElementById('server-form');
+const loginSection = document.getElementById('login-section');
+const loginForm = document.getElementById('login-form');
+const logoutButton = document.getElementById('btn-logout');
 
 // Initialize
 document.addEventListener('DOMContentLoaded', () => {
-    loadServers();
     setupEventListeners();
     initCharts();
+    if (session) {
+        logoutButton.style.display = 'inline-block';
+        refreshSession();
+    }
+    loadServers();
 });
 
+// Raised when the agent wants a login; the login form is already showing
+class LoginRequired extends Error {}
+
+// fetch with the session token attached
+async function api(url, options = {}) {
+    const headers = { ...(options.headers || {}) };
+    if (session) headers['Authorization'] = `Bearer ${session.token}`;
+    const res = await fetch(url, { ...options, headers });
+    if (res.status === 401) {
+        showLogin();
+        throw new LoginRequired();
+    }
+    return res;
+}
+
+// Browsers can't set headers on a WebSocket, so the token rides along as a subprotocol
+function openWebSocket(url) {
+    return session ? new WebSocket(url, ['bearer', `bearer.${session.token}`]) : new WebSocket(url);
+}
+
+function setSession(newSession) {
+    session = newSession;
+    clearTimeout(refreshTimer);
+    if (session) {
+        localStorage.setItem('session', JSON.stringify(session));
+        // Swap the token for a fresh one halfway through its life
+        refreshTimer = setTimeout(refreshSession, session.expires_in_secs * 500);
+    } else {
+        localStorage.removeItem('session');
+    }
+    logoutButton.style.display = session ? 'inline-block' : 'none';
+}
+
+async function refreshSession() {
+    try {
+        const res = await api('/api/session/refresh', { method: 'POST' });
+        if (res.ok) setSession(await res.json());
+    } catch (err) {
+        if (!(err instanceof LoginRequired)) console.error('Failed to refresh session:', err);
+    }
+}
+
+function showLogin() {
+    setSession(null);
+    closeWebSockets();
+    closeModal();
+    currentServerId = null;
+    serverListSection.style.display = 'none';
+    serverDetailsSection.style.display = 'none';
+    loginSection.style.display = 'block';
+}
+
+async function handleLogin(e) {
+    e.preventDefault();
+    const errorText = document.getElementById('login-error');
+    errorText.textContent = '';
+    try {
+        const res = await fetch('/api/login', {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify({
+                username: document.getElementById('login-username').value,
+                password: document.getElementById('login-password').value
+            })
+        });
+        const body = await res.json();
+        if (!res.ok) {
+            errorText.textContent = body.error;
+            return;
+        }
+        setSession(body);
+        loginForm.reset();
+        loginSection.style.display = 'none';
+        showServerList();
+    } catch (err) {
+        console.error('Failed to log in:', err);
+        errorText.textContent = 'Failed to log in';
+    }
+}
+
+async function logout() {
+    try {
+        await api('/api/logout', { method: 'POST' });
+    } catch (err) {
+        if (!(err instanceof LoginRequired)) console.error('Failed to log out:', err);
+    }
+    showLogin();
+}
+
 function setupEventListeners() {
     document.getElementById('btn-refresh').addEventListener('click', loadServers);
     document.getElementById('btn-back').addEventListener('click', showServerList);
@@ -45,14 +145,18 @@ function setupEventListeners() {
         if (e.target === modal) closeModal();
     });
     serverForm.addEventListener('submit', handleServerSubmit);
+
+    loginForm.addEventListener('submit', handleLogin);
+    logoutButton.addEventListener('click', logout);
 }
 
 async function loadServers() {
     try {
-        const res = await fetch(API_BASE);
+        const res = await api(API_BASE);
         const servers = await res.json();
         renderServerList(servers);
     } catch (err) {
+        if (err instanceof LoginRequired) return;
         console.error('Failed to load servers:', err);
         alert('Failed to load servers');
     }
@@ -99,7 +203,7 @@ function showServerDetails(server) {
 async function serverAction(action) {
     if (!currentServerId) return;
     try {
-        const res = await fetch(`${API_BASE}/${currentServerId}/${action}`, { method: 'POST' });
+        const res = await api(`${API_BASE}/${currentServerId}/${action}`, { method: 'POST' });
         if (!res.ok) {
             const err = await res.json();
             alert(`Failed to ${action}: ${err.error}`);
@@ -112,7 +216,7 @@ async function serverAction(action) {
 async function deleteServer() {
     if (!currentServerId || !confirm('Are you sure you want to delete this server?')) return;
     try {
-        const res = await fetch(`${API_BASE}/${currentServerId}`, { method: 'DELETE' });
+        const res = await api(`${API_BASE}/${currentServerId}`, { method: 'DELETE' });
         if (res.ok) {
             showServerList();
         } else {
@@ -131,22 +235,66 @@ function connectWebSockets(id) {
     const host = window.location.host;
     
     // Console WS
-    consoleWs = new WebSocket(`${protocol}//${host}/api/servers/${id}/console/ws`);
+    consoleWs = openWebSocket(
+        `${protocol}//${host}/api/servers/${id}/console/ws?format=batch&ansi=spans`);
     consoleWs.onmessage = (event) => {
-        const line = document.createElement('div');
-        line.textContent = event.data;
-        consoleOutput.appendChild(line);
+        const frame = JSON.parse(event.data);
+        // A batch is added in one go, so the page lays out once per frame
+        const lines = document.createDocumentFragment();
+        if (frame.type === 'lines') {
+            frame.lines.forEach(entry => lines.appendChild(renderLine(entry)));
+        } else {
+            const line = document.createElement('div');
+            if (frame.type === 'error') {
+                line.textContent = `[${frame.code}] ${frame.message}`;
+            } else if (frame.type === 'skipped') {
+                line.textContent = `[${frame.lines} lines skipped]`;
+            }
+            lines.appendChild(line);
+        }
+        consoleOutput.appendChild(lines);
         consoleOutput.scrollTop = consoleOutput.scrollHeight;
     };
     
     // Metrics WS
-    metricsWs = new WebSocket(`${protocol}//${host}/api/servers/${id}/metrics/ws`);
+    metricsWs = openWebSocket(`${protocol}//${host}/api/servers/${id}/metrics/ws`);
     metricsWs.onmessage = (event) => {
         const metrics = JSON.parse(event.data);
+        if (metrics.type === 'error') return;
         updateCharts(metrics);
     };
 }
 
+function renderLine(entry) {
+    const line = document.createElement('div');
+    line.title = new Date(entry.timestamp_ms).toLocaleTimeString();
+    if (entry.stream === 'stderr') line.classList.add('stderr');
+    entry.spans.forEach(span => line.appendChild(renderSpan(span)));
+    return line;
+}
+
+// Console colours arrive as spans of text with an optional style
+function renderSpan(span) {
+    const el = document.createElement('span');
+    el.textContent = span.text;
+    if (span.fg) el.style.color = span.fg;
+    if (span.bg) el.style.backgroundColor = span.bg;
+    if (span.bold) el.style.fontWeight = 'bold';
+    if (span.italic) el.style.fontStyle = 'italic';
+    if (span.underline) el.style.textDecoration = 'underline';
+    return el;
+}
+
+// Sockets report refusals and closures as {"type":"error","code":...,"message":...}
+function parseWsError(data) {
+    if (!data.startsWith('{"type":"error"')) return null;
+    try {
+        return JSON.parse(data);
+    } catch (err) {
+        return null;
+    }
+}
+
 function closeWebSockets() {
     if (consoleWs) {
         consoleWs.close();
@@ -237,19 +385,23 @@ async function openModal(id = null) {
     isEditing = !!id;
     document.getElementById('modal-title').textContent = isEditing ? 'Edit Server' : 'Add Server';
     document.getElementById('server-id').disabled = isEditing;
+    editingServer = null;
     
     if (isEditing) {
         try {
-            const res = await fetch(API_BASE);
+            const res = await api(API_BASE);
             const servers = await res.json();
             const server = servers.find(s => s.id === id);
             if (server) {
+                editingServer = server;
                 document.getElementById('server-id').value = server.id;
                 document.getElementById('server-name').value = server.name;
                 document.getElementById('server-dir').value = server.directory;
                 document.getElementById('server-jar').value = server.jar;
                 document.getElementById('server-mem').value = server.memory_mb;
                 document.getElementById('server-port').value = server.port;
+                document.getElementById('server-jvm-profile').value = server.jvm_profile || 'default';
+                document.getElementById('server-jvm-args').value = (server.jvm_args || []).join('\n');
                 document.getElementById('server-backup-dir').value = server.backup_directory || '';
                 document.getElementById('server-autostart').checked = server.autostart;
             }
@@ -271,13 +423,22 @@ async function handleServerSubmit(e) {
     e.preventDefault();
     
     const backupDir = document.getElementById('server-backup-dir').value.trim();
+    // Keep config fields the form doesn't expose so an edit doesn't reset them
+    const { status, pid, uptime_seconds, ...existing } = editingServer || {};
     const serverData = {
+        ...existing,
         id: document.getElementById('server-id').value,
         name: document.getElementById('server-name').value,
         directory: document.getElementById('server-dir').value,
         jar: document.getElementById('server-jar').value,
         memory_mb: parseInt(document.getElementById('server-mem').value),
-        port: parseInt(document.getElementById('server-port').value),
+        // 0 asks the agent to allocate a free port
+        port: parseInt(document.getElementById('server-port').value) || 0,
+        jvm_profile: document.getElementById('server-jvm-profile').value,
+        jvm_args: document.getElementById('server-jvm-args').value
+            .split('\n')
+            .map(a => a.trim())
+            .filter(a => a),
         backup_directory: backupDir ? backupDir : null,
         autostart: document.getElementById('server-autostart').checked
     };
@@ -286,7 +447,7 @@ async function handleServerSubmit(e) {
         const url = isEditing ? `${API_BASE}/${serverData.id}` : API_BASE;
         const method = isEditing ? 'PUT' : 'POST';
         
-        const res = await fetch(url, {
+        const res = await api(url, {
             method,
             headers: { 'Content-Type': 'application/json' },
             body: JSON.stringify(serverData)
@@ -305,6 +466,7 @@ async function handleServerSubmit(e) {
             alert(`Failed to save server: ${err.error}`);
         }
     } catch (err) {
+        if (err instanceof LoginRequired) return;
         console.error('Failed to save server:', err);
         alert('Failed to save server');
     }
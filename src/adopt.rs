@@ -0,0 +1,92 @@
+//! Servers a crashed agent left running as its own children. Their console
+//! pipes went with that agent, so commands can't reach them; the new agent
+//! follows their `logs/latest.log` and watches the PID instead, until the
+//! server exits or is stopped with a signal.
+
+use std::{path::Path, process::ExitStatus, time::Duration};
+
+use sysinfo::{Pid, System};
+use tokio::sync::watch;
+
+use crate::{
+    config::ServerConfig,
+    console::{tail_file, ReadOnly},
+    pidfile::ProcessIdentity,
+};
+
+/// How often the process is checked for life.
+const LIVENESS_INTERVAL: Duration = Duration::from_secs(1);
+/// Log output replayed into the console buffer when adopting.
+const BACKLOG_BYTES: u64 = 64 * 1024;
+
+/// A server process that is no one's child any more.
+pub struct AdoptedProcess {
+    pub pid: u32,
+    identity: ProcessIdentity,
+    /// Set once the process is gone. Its exit status can't be read, so it
+    /// counts as a clean exit.
+    exit: watch::Receiver<Option<ExitStatus>>,
+}
+
+impl AdoptedProcess {
+    pub async fn wait(&mut self) -> std::io::Result<ExitStatus> {
+        let status = self
+            .exit
+            .wait_for(Option::is_some)
+            .await
+            .map_err(std::io::Error::other)?;
+        Ok((*status).unwrap_or_default())
+    }
+
+    pub fn try_wait(&self) -> Option<ExitStatus> {
+        *self.exit.borrow()
+    }
+
+    /// Whether the PID still belongs to the server.
+    pub fn owns_pid(&self) -> bool {
+        self.try_wait().is_none() && self.identity.is_running()
+    }
+
+    pub fn kill(&self) -> std::io::Result<()> {
+        if !self.owns_pid() {
+            return Ok(());
+        }
+        let mut sys = System::new();
+        let pid = Pid::from_u32(self.pid);
+        sys.refresh_process(pid);
+        match sys.process(pid) {
+            Some(process) if process.kill() => Ok(()),
+            Some(_) => Err(std::io::Error::other(format!("Failed to kill PID {}", self.pid))),
+            // Already gone
+            None => Ok(()),
+        }
+    }
+}
+
+/// An adopted process with its read-only console.
+pub struct Adopted {
+    pub process: AdoptedProcess,
+    pub console: ReadOnly,
+}
+
+pub async fn adopt(server_cfg: &ServerConfig, identity: ProcessIdentity) -> Adopted {
+    let (exit_tx, exit) = watch::channel(None);
+    let watched = identity.clone();
+    tokio::spawn(async move {
+        while watched.is_running() {
+            tokio::time::sleep(LIVENESS_INTERVAL).await;
+        }
+        exit_tx.send_replace(Some(ExitStatus::default()));
+    });
+    let log_path = Path::new(&server_cfg.directory).join("logs").join("latest.log");
+    let size = tokio::fs::metadata(&log_path).await.map(|m| m.len()).unwrap_or(0);
+    let output = tail_file(log_path, size.saturating_sub(BACKLOG_BYTES), exit.clone(), None);
+    Adopted {
+        process: AdoptedProcess {
+            pid: identity.pid,
+            identity,
+            exit,
+        },
+        console: ReadOnly::new(output),
+    }
+}
@@ -0,0 +1,144 @@
+//! Announcements broadcast in game while a server runs, every so many
+//! minutes or on a cron schedule. The config is read on every tick, so
+//! announcements edited through the API apply without a restart. Intervals
+//! count from when the server finished starting.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+
+use crate::{
+    config::{Announcement, BroadcastCommand},
+    process::send_command,
+    state::{AppState, ServerPhase},
+};
+
+const TICK: Duration = Duration::from_secs(1);
+
+enum Timer {
+    Interval {
+        period: Duration,
+        next: Instant,
+    },
+    Cron {
+        expr: String,
+        schedule: Box<cron::Schedule>,
+        /// Up to when the schedule has been acted on.
+        checked: DateTime<Local>,
+    },
+}
+
+impl Timer {
+    fn new(announcement: &Announcement) -> Option<Self> {
+        if let Some(minutes) = announcement.interval_minutes {
+            let period = Duration::from_secs(u64::from(minutes) * 60);
+            return Some(Timer::Interval {
+                period,
+                next: Instant::now() + period,
+            });
+        }
+        let schedule = announcement.schedule()?.ok()?;
+        Some(Timer::Cron {
+            expr: announcement.cron.clone().unwrap_or_default(),
+            schedule: Box::new(schedule),
+            checked: Local::now(),
+        })
+    }
+
+    /// Whether the timer still fits the announcement after an edit.
+    fn matches(&self, announcement: &Announcement) -> bool {
+        match self {
+            Timer::Interval { period, .. } => announcement
+                .interval_minutes
+                .is_some_and(|m| Duration::from_secs(u64::from(m) * 60) == *period),
+            Timer::Cron { expr, .. } => announcement.cron.as_ref() == Some(expr),
+        }
+    }
+
+    fn is_due(&mut self) -> bool {
+        match self {
+            Timer::Interval { period, next } => {
+                let now = Instant::now();
+                if now < *next {
+                    return false;
+                }
+                *next = now + *period;
+                true
+            }
+            Timer::Cron {
+                schedule, checked, ..
+            } => {
+                let now = Local::now();
+                let due = schedule.after(checked).next().is_some_and(|at| at <= now);
+                *checked = now;
+                due
+            }
+        }
+    }
+}
+
+fn broadcast_command(command: BroadcastCommand, message: &str) -> String {
+    match command {
+        BroadcastCommand::Say => format!("say {}", message),
+        BroadcastCommand::Tellraw => {
+            let text = serde_json::json!({ "text": message, "color": "gold" });
+            format!("tellraw @a {}", text)
+        }
+    }
+}
+
+pub fn spawn_scheduler(state: AppState) {
+    tokio::spawn(async move {
+        // By server id and PID, and announcement id
+        let mut timers: HashMap<(String, u32, String), Timer> = HashMap::new();
+        let mut tick = tokio::time::interval(TICK);
+        loop {
+            tick.tick().await;
+            let servers: Vec<(String, Vec<Announcement>)> = {
+                let config = state.config.read().await;
+                config
+                    .servers
+                    .iter()
+                    .filter(|s| !s.announcements.is_empty())
+                    .map(|s| (s.id.clone(), s.announcements.clone()))
+                    .collect()
+            };
+            let mut active = HashSet::new();
+            for (server_id, announcements) in servers {
+                let Some(instance) = state.servers.get(&server_id).map(|i| i.value().clone())
+                else {
+                    continue;
+                };
+                if *instance.phase.lock().await != ServerPhase::Running {
+                    continue;
+                }
+                for announcement in announcements.iter().filter(|a| a.enabled) {
+                    let key = (server_id.clone(), instance.pid, announcement.id.clone());
+                    let timer = match timers.remove(&key) {
+                        Some(timer) if timer.matches(announcement) => Some(timer),
+                        _ => Timer::new(announcement),
+                    };
+                    let Some(timer) = timer else {
+                        continue;
+                    };
+                    let timer = timers.entry(key.clone()).or_insert(timer);
+                    active.insert(key);
+                    if !timer.is_due() {
+                        continue;
+                    }
+                    let command = broadcast_command(announcement.command, &announcement.message);
+                    if let Err(e) = send_command(&state, &server_id, &command).await {
+                        tracing::warn!(
+                            "Announcement '{}' on '{}' failed: {}",
+                            announcement.id,
+                            server_id,
+                            e
+                        );
+                    }
+                }
+            }
+            timers.retain(|key, _| active.contains(key));
+        }
+    });
+}
@@ -0,0 +1,240 @@
+//! ANSI escape codes in console output. Paper colours its console, which
+//! terminals render but simple clients show as garbage, so each console
+//! connection picks whether it gets the codes as they are, without them, or
+//! as JSON frames of styled spans.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AnsiMode {
+    /// Lines exactly as the server printed them.
+    #[default]
+    Raw,
+    /// Lines with every escape sequence removed.
+    Strip,
+    /// `{"type":"line","text":...,"spans":[...]}` frames, or `spans` next
+    /// to each line's text in batched frames.
+    Spans,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Style {
+    /// Foreground colour as "#rrggbb"; the client's default when absent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fg: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bg: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub bold: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub italic: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub underline: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Span {
+    pub text: String,
+    #[serde(flatten)]
+    pub style: Style,
+}
+
+#[derive(Serialize)]
+struct LineFrame {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    /// The line without escape codes.
+    text: String,
+    spans: Vec<Span>,
+}
+
+/// The 16 basic colours as xterm shows them.
+const BASIC: [&str; 16] = [
+    "#000000", "#cd0000", "#00cd00", "#cdcd00", "#0000ee", "#cd00cd", "#00cdcd", "#e5e5e5",
+    "#7f7f7f", "#ff0000", "#00ff00", "#ffff00", "#5c5cff", "#ff00ff", "#00ffff", "#ffffff",
+];
+
+fn rgb(r: u8, g: u8, b: u8) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// A colour of the 256-colour palette.
+fn indexed(n: u8) -> String {
+    match n {
+        0..=15 => BASIC[n as usize].to_string(),
+        16..=231 => {
+            let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+            let n = n - 16;
+            rgb(level(n / 36), level(n / 6 % 6), level(n % 6))
+        }
+        _ => {
+            let gray = 8 + (n - 232) * 10;
+            rgb(gray, gray, gray)
+        }
+    }
+}
+
+/// Reads the colour after a 38 or 48: `5;n` or `2;r;g;b`.
+fn extended(params: &mut impl Iterator<Item = u16>) -> Option<String> {
+    let mut next = || params.next().map(|p| p.min(255) as u8);
+    match next()? {
+        5 => next().map(indexed),
+        2 => Some(rgb(next()?, next()?, next()?)),
+        _ => None,
+    }
+}
+
+impl Style {
+    /// Applies an SGR ("m") sequence.
+    fn apply(&mut self, params: &str) {
+        let mut params = params.split([';', ':']).map(|p| p.parse::<u16>().unwrap_or(0));
+        while let Some(p) = params.next() {
+            match p {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                22 => self.bold = false,
+                23 => self.italic = false,
+                24 => self.underline = false,
+                30..=37 => self.fg = Some(BASIC[(p - 30) as usize].to_string()),
+                38 => self.fg = extended(&mut params),
+                39 => self.fg = None,
+                40..=47 => self.bg = Some(BASIC[(p - 40) as usize].to_string()),
+                48 => self.bg = extended(&mut params),
+                49 => self.bg = None,
+                90..=97 => self.fg = Some(BASIC[(p - 90 + 8) as usize].to_string()),
+                100..=107 => self.bg = Some(BASIC[(p - 100 + 8) as usize].to_string()),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Splits a line into styled spans, dropping every escape sequence.
+pub fn spans(line: &str) -> Vec<Span> {
+    let mut spans: Vec<Span> = Vec::new();
+    let mut style = Style::default();
+    let mut text = String::new();
+    let mut chars = line.chars().peekable();
+    let mut flush = |text: &mut String, style: &Style| {
+        if text.is_empty() {
+            return;
+        }
+        match spans.last_mut() {
+            Some(last) if last.style == *style => last.text.push_str(text),
+            _ => spans.push(Span {
+                text: text.clone(),
+                style: style.clone(),
+            }),
+        }
+        text.clear();
+    };
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI: parameters, then a final byte in '@'..='~'
+            Some('[') => {
+                let mut params = String::new();
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        if c == 'm' {
+                            flush(&mut text, &style);
+                            style.apply(&params);
+                        }
+                        break;
+                    }
+                    params.push(c);
+                }
+            }
+            // OSC: ends with BEL or ESC \
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    flush(&mut text, &style);
+    spans
+}
+
+pub fn strip(line: &str) -> String {
+    if !line.contains('\x1b') {
+        return line.to_string();
+    }
+    spans(line).into_iter().map(|span| span.text).collect()
+}
+
+impl AnsiMode {
+    /// The line's text as this mode sends it, with its spans in `Spans` mode.
+    pub fn apply(self, line: String) -> (String, Option<Vec<Span>>) {
+        match self {
+            AnsiMode::Raw => (line, None),
+            AnsiMode::Strip => (strip(&line), None),
+            AnsiMode::Spans => {
+                let spans = spans(&line);
+                let text = spans.iter().map(|span| span.text.as_str()).collect();
+                (text, Some(spans))
+            }
+        }
+    }
+
+    /// The WebSocket text for one console line.
+    pub fn frame(self, line: String) -> String {
+        match self.apply(line) {
+            (text, None) => text,
+            (text, Some(spans)) => {
+                let frame = LineFrame {
+                    kind: "line",
+                    text,
+                    spans,
+                };
+                serde_json::to_string(&frame).unwrap_or_default()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spans_follow_sgr_codes() {
+        let spans = spans("\x1b[1;31mError\x1b[0m: \x1b[38;5;46mok\x1b[m");
+        let texts: Vec<&str> = spans.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(texts, ["Error", ": ", "ok"]);
+        assert_eq!(spans[0].style.fg.as_deref(), Some("#cd0000"));
+        assert!(spans[0].style.bold);
+        assert_eq!(spans[1].style, Style::default());
+        assert_eq!(spans[2].style.fg.as_deref(), Some("#00ff00"));
+    }
+
+    #[test]
+    fn truecolour_and_bright_colours() {
+        let spans = spans("\x1b[38;2;18;52;86;103mx");
+        assert_eq!(spans[0].style.fg.as_deref(), Some("#123456"));
+        assert_eq!(spans[0].style.bg.as_deref(), Some("#ffff00"));
+    }
+
+    #[test]
+    fn runs_of_one_style_are_merged() {
+        let spans = spans("\x1b[32ma\x1b[32mb\x1b[Kc");
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].text, "abc");
+    }
+
+    #[test]
+    fn other_sequences_are_dropped() {
+        assert_eq!(strip("\x1b]0;title\x07\x1b[2Jdone\x1b]8;;x\x1b\\"), "done");
+        assert_eq!(strip("plain"), "plain");
+    }
+}
@@ -1,22 +1,45 @@
+use std::{cmp::Ordering, collections::BTreeMap, convert::Infallible};
+
 use axum::{
     extract::{
-        ws::{Message, WebSocket, WebSocketUpgrade},
-        Path, State,
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        Extension, Path, Query, RawQuery, State,
     },
     http::StatusCode,
-    response::IntoResponse,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
     Json,
 };
+use futures_util::{SinkExt, Stream, StreamExt};
 use serde::{Deserialize, Serialize};
-use tokio::io::AsyncWriteExt;
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    mpsc, watch,
+};
 
 use crate::{
-    config::{save_config, validate_server_config, ServerConfig},
-    process::{restart_server, start_server, stop_server, backup_server},
-    state::AppState,
+    ansi, auth,
+    console::{ConsoleLine, ConsoleStream, LogLevel},
+    config::{
+        save_config, validate_announcement, validate_node_config, validate_server_config,
+        Announcement, DesiredState, NodeConfig, ServerConfig, ServerFlavor,
+    },
+    bans, diagnostics, downloads, geyser, maintenance, motd, packs, playtime, pregen,
+    health, history, ipfilter::ClientIp, nodeauth::{self, ManagerToken}, nodes, preflight, proxy,
+    reconcile, reload,
+    ratelimit::{Class, ClientKey},
+    resources, rollout, runtimes, sessions::{self, Session}, sizing, whitelist, world,
+    properties::{PropertyChange, ServerProperties},
+    process::{
+        answer_prompt, backup_server, restart_server, run_macro, send_chat, send_command,
+        start_server, stop_server,
+    },
+    state::{AppState, ExitInfo},
 };
 
-#[derive(Serialize)]
+#[derive(Serialize, utoipa::ToSchema)]
 pub struct ServerStatus {
     #[serde(flatten)]
     pub config: ServerConfig,
@@ -25,10 +48,25 @@ pub struct ServerStatus {
     pub pid: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub uptime_seconds: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pending_prompt: Option<String>,
+    /// How the agent talks to the running server's console.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub console: Option<crate::console::ConsoleKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_exit: Option<ExitInfo>,
+    #[serde(flatten)]
+    pub disk: crate::disk::DiskUsage,
+    /// Latest Server List Ping answer while running.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ping: Option<crate::slp::SlpStatus>,
+    /// Router port forwarding outcome while running with `port_forward`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port_mapping: Option<crate::portmap::MappingStatus>,
 }
 
-#[derive(Serialize)]
-struct ApiError {
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct ApiError {
     error: String,
 }
 
@@ -36,56 +74,466 @@ fn err_response(status: StatusCode, msg: impl Into<String>) -> impl IntoResponse
     (status, Json(ApiError { error: msg.into() }))
 }
 
-pub async fn list_servers(State(state): State<AppState>) -> impl IntoResponse {
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "health",
+    responses(
+        (status = 200, description = "The agent is alive", body = serde_json::Value),
+    )
+)]
+pub async fn healthz() -> impl IntoResponse {
+    Json(serde_json::json!({ "status": "ok" }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "health",
+    responses(
+        (status = 200, description = "Ready", body = crate::health::ReadinessReport),
+        (status = 503, description = "Not ready", body = crate::health::ReadinessReport),
+    )
+)]
+pub async fn readyz(State(state): State<AppState>) -> impl IntoResponse {
+    let report = health::readiness(&state).await;
+    let status = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
+
+/// Statuses of the servers running on this agent.
+pub(crate) async fn local_server_statuses(state: &AppState) -> Vec<ServerStatus> {
     let config = state.config.read().await;
-    let result: Vec<ServerStatus> = config
-        .servers
-        .iter()
-        .map(|cfg| {
-            if let Some(inst) = state.servers.get(&cfg.id) {
-                ServerStatus {
-                    config: cfg.clone(),
-                    status: "running",
-                    pid: Some(inst.pid),
-                    uptime_seconds: Some(inst.started_at.elapsed().as_secs()),
+    let mut result: Vec<ServerStatus> = Vec::with_capacity(config.servers.len());
+    for cfg in &config.servers {
+        let instance = state.servers.get(&cfg.id).map(|r| r.value().clone());
+        let status = if let Some(inst) = instance {
+            ServerStatus {
+                config: cfg.clone(),
+                status: inst.phase.lock().await.as_str(),
+                pid: Some(inst.pid),
+                uptime_seconds: Some(inst.started_at.elapsed().as_secs()),
+                pending_prompt: inst.pending_prompt.lock().await.clone(),
+                console: Some(inst.console.lock().await.kind()),
+                last_exit: None,
+                disk: crate::disk::cached(state, &cfg.id),
+                ping: state.slp.get(&cfg.id).map(|s| s.value().clone()),
+                port_mapping: state.port_mappings.get(&cfg.id).map(|m| m.value().clone()),
+            }
+        } else {
+            let last_exit = state.last_exits.get(&cfg.id).map(|e| e.value().clone());
+            ServerStatus {
+                config: cfg.clone(),
+                status: if last_exit.as_ref().is_some_and(|e| e.crashed) {
+                    "crashed"
+                } else {
+                    "stopped"
+                },
+                pid: None,
+                uptime_seconds: None,
+                pending_prompt: None,
+                console: None,
+                last_exit,
+                disk: crate::disk::cached(state, &cfg.id),
+                ping: None,
+                port_mapping: None,
+            }
+        };
+        result.push(status);
+    }
+    result
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListServersQuery {
+    /// "local" lists only this agent's servers, not those of its nodes.
+    #[serde(default)]
+    pub scope: Option<String>,
+    /// Comma-separated statuses to keep, e.g. "running,starting".
+    #[serde(default)]
+    pub status: Option<String>,
+    /// Comma-separated tags a server must all carry.
+    #[serde(default)]
+    pub tag: Option<String>,
+    /// One of id, name, status, uptime, port or memory; a leading '-'
+    /// sorts descending. Config order when absent.
+    #[serde(default)]
+    pub sort: Option<String>,
+    /// 1-based page; everything is returned when neither page nor per_page is set.
+    #[serde(default)]
+    pub page: Option<usize>,
+    #[serde(default)]
+    pub per_page: Option<usize>,
+}
+
+const DEFAULT_PER_PAGE: usize = 50;
+const MAX_PER_PAGE: usize = 500;
+
+/// Where each sort key lives in a serialized `ServerStatus`.
+fn sort_pointer(key: &str) -> Option<&'static str> {
+    match key {
+        "id" => Some("/id"),
+        "name" => Some("/name"),
+        "status" => Some("/status"),
+        "uptime" => Some("/uptime_seconds"),
+        "port" => Some("/port"),
+        "memory" => Some("/memory_mb"),
+        _ => None,
+    }
+}
+
+/// Orders numbers and strings naturally; missing values sort last.
+fn compare_values(a: Option<&serde_json::Value>, b: Option<&serde_json::Value>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => match (a.as_f64(), b.as_f64()) {
+            (Some(x), Some(y)) => x.total_cmp(&y),
+            _ => a.as_str().unwrap_or_default().cmp(b.as_str().unwrap_or_default()),
+        },
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// Filters, sorts and pages serialized server statuses, returning the page
+/// and the number of matches before paging.
+fn apply_list_query(
+    mut servers: Vec<serde_json::Value>,
+    query: &ListServersQuery,
+) -> Result<(Vec<serde_json::Value>, usize), String> {
+    if let Some(ref status) = query.status {
+        let wanted: Vec<&str> = status.split(',').map(str::trim).collect();
+        servers.retain(|s| s["status"].as_str().is_some_and(|st| wanted.contains(&st)));
+    }
+    if let Some(ref tag) = query.tag {
+        let wanted: Vec<&str> = tag.split(',').map(str::trim).collect();
+        servers.retain(|s| wanted.iter().all(|t| has_tag(s, t)));
+    }
+    if let Some(ref sort) = query.sort {
+        let (key, descending) = match sort.strip_prefix('-') {
+            Some(key) => (key, true),
+            None => (sort.as_str(), false),
+        };
+        let pointer = sort_pointer(key).ok_or_else(|| format!("Unknown sort key '{}'", key))?;
+        servers.sort_by(|a, b| {
+            let (a, b) = (a.pointer(pointer), b.pointer(pointer));
+            if descending {
+                // Missing values stay last either way
+                match (a, b) {
+                    (Some(_), Some(_)) => compare_values(b, a),
+                    _ => compare_values(a, b),
                 }
             } else {
-                ServerStatus {
-                    config: cfg.clone(),
-                    status: "stopped",
-                    pid: None,
-                    uptime_seconds: None,
-                }
+                compare_values(a, b)
             }
-        })
+        });
+    }
+    let total = servers.len();
+    if query.page.is_none() && query.per_page.is_none() {
+        return Ok((servers, total));
+    }
+    let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE);
+    if per_page == 0 || per_page > MAX_PER_PAGE {
+        return Err(format!("per_page must be between 1 and {}", MAX_PER_PAGE));
+    }
+    let page = query.page.unwrap_or(1);
+    if page == 0 {
+        return Err("page starts at 1".to_string());
+    }
+    let page = servers
+        .into_iter()
+        .skip((page - 1).saturating_mul(per_page))
+        .take(per_page)
         .collect();
-    Json(result)
+    Ok((page, total))
 }
 
-pub async fn create_server(
+/// The total before paging is sent as `X-Total-Count`.
+#[utoipa::path(
+    get,
+    path = "/api/servers",
+    tag = "servers",
+    params(ListServersQuery),
+    responses(
+        (status = 200, description = "Servers here and on nodes", body = Vec<ServerStatus>),
+        (status = 400, description = "Invalid filter, sort or page", body = ApiError),
+    )
+)]
+pub async fn list_servers(
     State(state): State<AppState>,
-    Json(input): Json<ServerConfig>,
+    Query(query): Query<ListServersQuery>,
 ) -> impl IntoResponse {
-    {
-        let config = state.config.read().await;
-        if config.servers.iter().any(|s| s.id == input.id) {
-            return err_response(StatusCode::CONFLICT, format!("Server id '{}' already exists", input.id))
-                .into_response();
+    let aggregate = query.scope.as_deref() != Some("local");
+    let servers = server_values(&state, aggregate).await;
+    match apply_list_query(servers, &query) {
+        Ok((page, total)) => ([("x-total-count", total.to_string())], Json(page)).into_response(),
+        Err(e) => err_response(StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+/// Serialized statuses of this agent's servers and, when aggregating with
+/// nodes registered, of theirs, each tagged with its node.
+async fn server_values(state: &AppState, aggregate: bool) -> Vec<serde_json::Value> {
+    let local = local_server_statuses(state).await;
+    let nodes = state.config.read().await.nodes.clone();
+    let aggregate = aggregate && !nodes.is_empty();
+
+    let mut result: Vec<serde_json::Value> = Vec::with_capacity(local.len());
+    for status in local {
+        let mut value = serde_json::to_value(status).unwrap_or_default();
+        if let Some(obj) = value.as_object_mut().filter(|_| aggregate) {
+            obj.insert("node".to_string(), "local".into());
         }
+        result.push(value);
     }
-    if let Err(e) = validate_server_config(&input) {
+    if aggregate {
+        let remote = futures_util::future::join_all(
+            nodes.iter().map(|n| nodes::servers_or_last_known(state, n)),
+        )
+        .await;
+        result.extend(remote.into_iter().flatten());
+    }
+    result
+}
+
+fn has_tag(server: &serde_json::Value, tag: &str) -> bool {
+    server["tags"]
+        .as_array()
+        .is_some_and(|tags| tags.iter().any(|t| t.as_str() == Some(tag)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/nodes",
+    tag = "nodes",
+    responses(
+        (status = 200, description = "Nodes and their health", body = Vec<nodes::NodeInfo>),
+    )
+)]
+pub async fn list_nodes(State(state): State<AppState>) -> impl IntoResponse {
+    let nodes = state.config.read().await.nodes.clone();
+    Json(futures_util::future::join_all(nodes.iter().map(|n| nodes::info(&state, n))).await)
+}
+
+/// Registers an agent after checking that it answers.
+#[utoipa::path(
+    post,
+    path = "/api/nodes",
+    tag = "nodes",
+    request_body = NodeConfig,
+    responses(
+        (status = 201, description = "Registered", body = nodes::NodeInfo),
+        (status = 409, description = "Node id taken", body = ApiError),
+        (status = 502, description = "Node unreachable", body = ApiError),
+    )
+)]
+pub async fn register_node(
+    State(state): State<AppState>,
+    Json(input): Json<NodeConfig>,
+) -> impl IntoResponse {
+    if let Err(e) = validate_node_config(&input) {
         return err_response(StatusCode::BAD_REQUEST, e).into_response();
     }
+    let resources = match nodes::resources(&input).await {
+        Ok(r) => r,
+        Err(e) => return err_response(StatusCode::BAD_GATEWAY, e).into_response(),
+    };
+    let mut config = state.config.write().await;
+    if config.nodes.iter().any(|n| n.id == input.id) {
+        return err_response(StatusCode::CONFLICT, format!("Node id '{}' already exists", input.id))
+            .into_response();
+    }
+    let info = nodes::NodeInfo {
+        id: input.id.clone(),
+        url: input.url.clone(),
+        resources: Some(resources),
+        error: None,
+        health: None,
+    };
+    config.nodes.push(input);
+    if let Err(e) = save_config(&config).await {
+        return err_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+    (StatusCode::CREATED, Json(info)).into_response()
+}
+
+/// Gives a node a fresh token without a window in which requests fail.
+#[utoipa::path(
+    post,
+    path = "/api/nodes/{id}/rotate-token",
+    tag = "nodes",
+    params(("id" = String, Path, description = "Node id")),
+    responses(
+        (status = 204, description = "Token rotated"),
+        (status = 404, description = "Node not found", body = ApiError),
+        (status = 502, description = "Node unreachable", body = ApiError),
+    )
+)]
+pub async fn rotate_node_token(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let Some(node) = state.config.read().await.nodes.iter().find(|n| n.id == id).cloned() else {
+        return err_response(StatusCode::NOT_FOUND, format!("Node '{}' not found", id))
+            .into_response();
+    };
+    let rotated = match nodes::rotate_token(&node, &nodeauth::random_token(48)).await {
+        Ok(rotated) => rotated,
+        Err(e) => return err_response(StatusCode::BAD_GATEWAY, e).into_response(),
+    };
     {
         let mut config = state.config.write().await;
-        config.servers.push(input);
+        if let Some(n) = config.nodes.iter_mut().find(|n| n.id == id) {
+            *n = rotated.clone();
+        }
         if let Err(e) = save_config(&config).await {
             return err_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
         }
     }
-    StatusCode::CREATED.into_response()
+    // The agent still accepts the old token if this fails, so nothing breaks
+    if let Err(e) = nodes::retire_previous_token(&rotated).await {
+        return err_response(StatusCode::BAD_GATEWAY, e).into_response();
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/nodes/{id}",
+    tag = "nodes",
+    params(("id" = String, Path, description = "Node id")),
+    responses(
+        (status = 204, description = "Removed"),
+        (status = 404, description = "Node not found", body = ApiError),
+    )
+)]
+pub async fn remove_node(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let mut config = state.config.write().await;
+    let before = config.nodes.len();
+    config.nodes.retain(|n| n.id != id);
+    if config.nodes.len() == before {
+        return err_response(StatusCode::NOT_FOUND, format!("Node '{}' not found", id))
+            .into_response();
+    }
+    if let Err(e) = save_config(&config).await {
+        return err_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CreateServerQuery {
+    /// Property profile from the agent config written to server.properties.
+    pub properties_profile: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/servers",
+    tag = "servers",
+    params(CreateServerQuery),
+    request_body = ServerConfig,
+    responses(
+        (status = 201, description = "Created with its port", body = serde_json::Value),
+        (status = 400, description = "Invalid config", body = ApiError),
+        (status = 409, description = "Id, port or directory taken", body = ApiError),
+    )
+)]
+pub async fn create_server(
+    State(state): State<AppState>,
+    Query(query): Query<CreateServerQuery>,
+    Json(mut input): Json<ServerConfig>,
+) -> impl IntoResponse {
+    // Held throughout so concurrent creates can't be handed the same port
+    let mut config = state.config.write().await;
+    if config.servers.iter().any(|s| s.id == input.id) {
+        return err_response(StatusCode::CONFLICT, format!("Server id '{}' already exists", input.id))
+            .into_response();
+    }
+    let profile = match query.properties_profile {
+        Some(ref name) => match config.agent.property_profiles.get(name) {
+            Some(_) if !input.flavor().has_world() => {
+                return err_response(
+                    StatusCode::BAD_REQUEST,
+                    format!("{} servers have no server.properties", input.flavor().as_str()),
+                )
+                .into_response()
+            }
+            Some(profile) => Some(profile.clone()),
+            None => {
+                return err_response(
+                    StatusCode::BAD_REQUEST,
+                    format!("Property profile '{}' not found", name),
+                )
+                .into_response()
+            }
+        },
+        None => None,
+    };
+    let auto_port = input.port == 0;
+    if auto_port {
+        match crate::ports::allocate(&config).await {
+            Ok(port) => input.port = port,
+            Err(e) => return err_response(StatusCode::CONFLICT, e).into_response(),
+        }
+    }
+    if let Err(e) =
+        validate_server_config(&input).and_then(|_| proxy::check_backends(&config, &input))
+    {
+        return err_response(StatusCode::BAD_REQUEST, e).into_response();
+    }
+    let mut candidate = config.clone();
+    candidate.servers.push(input.clone());
+    if let Some(conflict) = crate::schema::conflicts(&candidate).into_iter().next() {
+        return err_response(StatusCode::CONFLICT, conflict).into_response();
+    }
+    if let Some(profile) = profile {
+        let applied = async {
+            let mut properties = ServerProperties::load(&input.directory).await?;
+            for (key, value) in &profile {
+                properties.set_text(key, value);
+            }
+            properties.save(&input.directory).await
+        };
+        if let Err(e) = applied.await {
+            return err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+        }
+    }
+    if auto_port {
+        if let Err(e) = crate::ports::write_server_port(&input).await {
+            return err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+        }
+    }
+    let port = input.port;
+    config.servers.push(input);
+    if let Err(e) = save_config(&config).await {
+        return err_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+    (StatusCode::CREATED, Json(serde_json::json!({ "port": port }))).into_response()
 }
 
+#[utoipa::path(
+    put,
+    path = "/api/servers/{id}",
+    tag = "servers",
+    params(("id" = String, Path, description = "Server id")),
+    request_body = ServerConfig,
+    responses(
+        (status = 200, description = "Updated"),
+        (status = 400, description = "Invalid config", body = ApiError),
+        (status = 404, description = "Server not found", body = ApiError),
+        (status = 409, description = "Blocked while running, or clashes", body = ApiError),
+    )
+)]
 pub async fn update_server(
     Path(id): Path<String>,
     State(state): State<AppState>,
@@ -118,9 +566,17 @@ pub async fn update_server(
     }
     {
         let mut config = state.config.write().await;
-        if let Some(s) = config.servers.iter_mut().find(|s| s.id == id) {
+        if let Err(e) = proxy::check_backends(&config, &input) {
+            return err_response(StatusCode::BAD_REQUEST, e).into_response();
+        }
+        let mut candidate = config.clone();
+        if let Some(s) = candidate.servers.iter_mut().find(|s| s.id == id) {
             *s = input;
         }
+        if let Some(conflict) = crate::schema::conflicts(&candidate).into_iter().next() {
+            return err_response(StatusCode::CONFLICT, conflict).into_response();
+        }
+        *config = candidate;
         if let Err(e) = save_config(&config).await {
             return err_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
         }
@@ -128,6 +584,17 @@ pub async fn update_server(
     StatusCode::OK.into_response()
 }
 
+#[utoipa::path(
+    delete,
+    path = "/api/servers/{id}",
+    tag = "servers",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 404, description = "Server not found", body = ApiError),
+        (status = 409, description = "Server is running", body = ApiError),
+    )
+)]
 pub async fn delete_server(
     Path(id): Path<String>,
     State(state): State<AppState>,
@@ -151,26 +618,183 @@ pub async fn delete_server(
     StatusCode::NO_CONTENT.into_response()
 }
 
+#[derive(Debug, Clone, Copy, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BulkAction {
+    Start,
+    Stop,
+    Restart,
+    Backup,
+}
+
+impl BulkAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BulkAction::Start => "start",
+            BulkAction::Stop => "stop",
+            BulkAction::Restart => "restart",
+            BulkAction::Backup => "backup",
+        }
+    }
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct BulkActionRequest {
+    pub action: BulkAction,
+    /// Servers on this agent or on any registered node.
+    #[serde(default)]
+    pub ids: Vec<String>,
+    /// Adds every server carrying this tag, here and on nodes.
+    #[serde(default)]
+    pub tag: Option<String>,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct BulkActionResult {
+    pub id: String,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Node the action was forwarded to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node: Option<String>,
+}
+
+async fn run_bulk_action(state: &AppState, id: String, action: BulkAction) -> BulkActionResult {
+    let (node, outcome) = if find_server_config(state, &id).await.is_some() {
+        let outcome = match action {
+            BulkAction::Start => {
+                state.restart_attempts.remove(&id);
+                reconcile::set_desired(state, &id, DesiredState::Running).await;
+                start_server(state.clone(), &id).await
+            }
+            BulkAction::Stop => {
+                reconcile::set_desired(state, &id, DesiredState::Stopped).await;
+                stop_server(state.clone(), &id).await
+            }
+            BulkAction::Restart => restart_server(state.clone(), &id).await,
+            BulkAction::Backup => backup_server(state.clone(), &id).await.map(|_| ()),
+        };
+        (None, outcome)
+    } else if let Some(node) = nodes::locate(state, &id).await {
+        let outcome = nodes::server_action(&node, &id, action.as_str()).await;
+        (Some(node.id), outcome)
+    } else {
+        (None, Err(format!("Server '{}' not found", id)))
+    };
+    BulkActionResult {
+        id,
+        ok: outcome.is_ok(),
+        error: outcome.err(),
+        node,
+    }
+}
+
+/// Runs one action on many servers at once and reports each outcome; the
+/// request succeeds even when some of the servers fail.
+#[utoipa::path(
+    post,
+    path = "/api/servers/actions",
+    tag = "servers",
+    request_body = BulkActionRequest,
+    responses(
+        (status = 200, description = "Outcome per server", body = Vec<BulkActionResult>),
+        (status = 400, description = "No servers selected", body = ApiError),
+    )
+)]
+pub async fn bulk_action(
+    State(state): State<AppState>,
+    Json(req): Json<BulkActionRequest>,
+) -> impl IntoResponse {
+    let mut ids = req.ids;
+    if let Some(ref tag) = req.tag {
+        let tagged = server_values(&state, true).await.into_iter().filter(|s| has_tag(s, tag));
+        ids.extend(tagged.filter_map(|s| s["id"].as_str().map(str::to_string)));
+    }
+    let mut seen = std::collections::HashSet::new();
+    ids.retain(|id| seen.insert(id.clone()));
+    if ids.is_empty() {
+        return err_response(StatusCode::BAD_REQUEST, "No servers selected").into_response();
+    }
+    let results = futures_util::future::join_all(
+        ids.into_iter().map(|id| run_bulk_action(&state, id, req.action)),
+    )
+    .await;
+    Json(results).into_response()
+}
+
+/// Checks a server config as create or update would, plus whether it could
+/// start here now, without saving anything.
+#[utoipa::path(
+    post,
+    path = "/api/servers/validate",
+    tag = "servers",
+    request_body = ServerConfig,
+    responses(
+        (status = 200, description = "Findings", body = preflight::ValidationReport),
+    )
+)]
+pub async fn validate_server(
+    State(state): State<AppState>,
+    Json(input): Json<ServerConfig>,
+) -> impl IntoResponse {
+    Json(preflight::validate(&state, &input).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/servers/{id}/start",
+    tag = "servers",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "Started"),
+        (status = 400, description = "Start failed", body = ApiError),
+    )
+)]
 pub async fn start_server_handler(
     Path(id): Path<String>,
     State(state): State<AppState>,
 ) -> impl IntoResponse {
+    // A manual start gives a server that exhausted its retries a fresh budget
+    state.restart_attempts.remove(&id);
+    reconcile::set_desired(&state, &id, DesiredState::Running).await;
     match start_server(state, &id).await {
         Ok(()) => StatusCode::OK.into_response(),
         Err(e) => err_response(StatusCode::BAD_REQUEST, e).into_response(),
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/servers/{id}/stop",
+    tag = "servers",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "Stopped"),
+        (status = 400, description = "Stop failed", body = ApiError),
+    )
+)]
 pub async fn stop_server_handler(
     Path(id): Path<String>,
     State(state): State<AppState>,
 ) -> impl IntoResponse {
+    reconcile::set_desired(&state, &id, DesiredState::Stopped).await;
     match stop_server(state, &id).await {
         Ok(()) => StatusCode::OK.into_response(),
         Err(e) => err_response(StatusCode::BAD_REQUEST, e).into_response(),
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/servers/{id}/restart",
+    tag = "servers",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "Restarted"),
+        (status = 400, description = "Restart failed", body = ApiError),
+    )
+)]
 pub async fn restart_server_handler(
     Path(id): Path<String>,
     State(state): State<AppState>,
@@ -180,6 +804,58 @@ pub async fn restart_server_handler(
         Err(e) => err_response(StatusCode::BAD_REQUEST, e).into_response(),
     }
 }
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct DesiredStateRequest {
+    /// null hands the server back to `autostart` and manual control.
+    pub desired_state: Option<DesiredState>,
+}
+
+/// Declares whether the server should run; the reconciler starts or stops
+/// it to match within a few seconds.
+#[utoipa::path(
+    put,
+    path = "/api/servers/{id}/desired-state",
+    tag = "servers",
+    params(("id" = String, Path, description = "Server id")),
+    request_body = DesiredStateRequest,
+    responses(
+        (status = 204, description = "Desired state saved"),
+        (status = 400, description = "Conflicts with other settings", body = ApiError),
+        (status = 404, description = "Server not found", body = ApiError),
+    )
+)]
+pub async fn set_desired_state(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Json(req): Json<DesiredStateRequest>,
+) -> impl IntoResponse {
+    let mut config = state.config.write().await;
+    let Some(server) = config.servers.iter_mut().find(|s| s.id == id) else {
+        return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response();
+    };
+    let mut updated = server.clone();
+    updated.desired_state = req.desired_state;
+    if let Err(e) = validate_server_config(&updated) {
+        return err_response(StatusCode::BAD_REQUEST, e).into_response();
+    }
+    *server = updated;
+    if let Err(e) = save_config(&config).await {
+        return err_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/servers/{id}/backup",
+    tag = "servers",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "Backup written"),
+        (status = 400, description = "Backup failed", body = ApiError),
+    )
+)]
 pub async fn backup_server_handler(
     Path(id): Path<String>,
     State(state): State<AppState>,
@@ -189,106 +865,3508 @@ pub async fn backup_server_handler(
         Err(e) => err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
     }
 }
-pub async fn console_ws(
-    ws: WebSocketUpgrade,
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct ChatRequest {
+    #[serde(default = "default_chat_sender")]
+    pub sender: String,
+    pub message: String,
+}
+
+fn default_chat_sender() -> String {
+    "Admin".to_string()
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/servers/{id}/chat",
+    tag = "console",
+    params(("id" = String, Path, description = "Server id")),
+    request_body = ChatRequest,
+    responses(
+        (status = 200, description = "Sent"),
+        (status = 400, description = "Not sent", body = ApiError),
+    )
+)]
+pub async fn chat_handler(
     Path(id): Path<String>,
     State(state): State<AppState>,
+    Json(input): Json<ChatRequest>,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_console_ws(socket, id, state))
+    if !state.servers.contains_key(&id) {
+        return err_response(StatusCode::CONFLICT, format!("Server '{}' is not running", id))
+            .into_response();
+    }
+    match send_chat(&state, &id, &input.sender, &input.message).await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(e) => err_response(StatusCode::BAD_REQUEST, e).into_response(),
+    }
 }
 
-async fn handle_console_ws(mut socket: WebSocket, id: String, state: AppState) {
-    let instance = match state.servers.get(&id).map(|r| r.value().clone()) {
-        Some(i) => i,
-        None => {
-            let _ = socket
-                .send(Message::Text("Server is not running".to_string().into()))
-                .await;
-            return;
-        }
+#[utoipa::path(
+    post,
+    path = "/api/servers/{id}/macros/{name}",
+    tag = "console",
+    params(
+        ("id" = String, Path, description = "Server id"),
+        ("name" = String, Path, description = "Macro name"),
+    ),
+    responses(
+        (status = 202, description = "Macro started"),
+        (status = 400, description = "Server not running", body = ApiError),
+        (status = 404, description = "Server or macro not found", body = ApiError),
+    )
+)]
+pub async fn macro_handler(
+    Path((id, name)): Path<(String, String)>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let Some(cfg) = find_server_config(&state, &id).await else {
+        return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response();
     };
-
-    // Send buffered lines
-    {
-        let buf = instance.console_buffer.lock().await;
-        let recent: Vec<String> = buf.iter().rev().take(100).cloned().collect();
-        for line in recent.into_iter().rev() {
-            if socket.send(Message::Text(line.into())).await.is_err() {
-                return;
-            }
-        }
+    if !cfg.macros.iter().any(|m| m.name == name) {
+        return err_response(
+            StatusCode::NOT_FOUND,
+            format!("Macro '{}' not found for server '{}'", name, id),
+        )
+        .into_response();
+    }
+    match run_macro(state, &id, &name).await {
+        Ok(()) => StatusCode::ACCEPTED.into_response(),
+        Err(e) => err_response(StatusCode::BAD_REQUEST, e).into_response(),
     }
+}
 
-    let mut console_rx = instance.console_tx.subscribe();
+#[utoipa::path(
+    get,
+    path = "/api/servers/{id}/announcements",
+    tag = "announcements",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "The server's announcements", body = Vec<Announcement>),
+        (status = 404, description = "Server not found", body = ApiError),
+    )
+)]
+pub async fn list_announcements(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match find_server_config(&state, &id).await {
+        Some(cfg) => Json(cfg.announcements).into_response(),
+        None => err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response(),
+    }
+}
 
-    loop {
-        tokio::select! {
-            msg = console_rx.recv() => {
-                match msg {
-                    Ok(line) => {
-                        if socket.send(Message::Text(line.into())).await.is_err() {
-                            break;
-                        }
-                    }
-                    Err(_) => break,
-                }
-            }
-            ws_msg = socket.recv() => {
-                match ws_msg {
-                    Some(Ok(Message::Text(text))) => {
-                        #[derive(Deserialize)]
-                        struct WsCommand {
-                            #[serde(rename = "type")]
-                            kind: String,
-                            data: String,
-                        }
-                        if let Ok(cmd) = serde_json::from_str::<WsCommand>(&text) {
-                            if cmd.kind == "command" {
-                                let mut stdin = instance.stdin.lock().await;
-                                let line = format!("{}\n", cmd.data);
-                                let _ = stdin.write_all(line.as_bytes()).await;
-                                let _ = stdin.flush().await;
-                            }
-                        }
-                    }
-                    Some(Ok(Message::Close(_))) | None => break,
-                    _ => {}
-                }
-            }
+/// Adds an announcement, or with `replace` changes the one with its id.
+/// Takes effect at once, also on a running server.
+async fn save_announcement(
+    state: &AppState,
+    id: &str,
+    announcement: Announcement,
+    replace: bool,
+) -> axum::response::Response {
+    if let Err(e) = validate_announcement(&announcement) {
+        return err_response(StatusCode::BAD_REQUEST, e).into_response();
+    }
+    let mut config = state.config.write().await;
+    let Some(cfg) = config.servers.iter_mut().find(|s| s.id == id) else {
+        return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response();
+    };
+    let existing = cfg.announcements.iter_mut().find(|a| a.id == announcement.id);
+    match (existing, replace) {
+        (Some(existing), true) => *existing = announcement.clone(),
+        (None, false) => cfg.announcements.push(announcement.clone()),
+        (Some(_), false) => {
+            let message = format!("Announcement '{}' already exists", announcement.id);
+            return err_response(StatusCode::CONFLICT, message).into_response();
+        }
+        (None, true) => {
+            let message = format!("Announcement '{}' not found", announcement.id);
+            return err_response(StatusCode::NOT_FOUND, message).into_response();
         }
     }
+    if let Err(e) = save_config(&config).await {
+        return err_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+    let status = if replace { StatusCode::OK } else { StatusCode::CREATED };
+    (status, Json(announcement)).into_response()
 }
 
-pub async fn metrics_ws(
-    ws: WebSocketUpgrade,
+#[utoipa::path(
+    post,
+    path = "/api/servers/{id}/announcements",
+    tag = "announcements",
+    params(("id" = String, Path, description = "Server id")),
+    request_body = Announcement,
+    responses(
+        (status = 201, description = "Added", body = Announcement),
+        (status = 400, description = "Invalid announcement", body = ApiError),
+        (status = 404, description = "Server not found", body = ApiError),
+        (status = 409, description = "Announcement id taken", body = ApiError),
+    )
+)]
+pub async fn create_announcement(
     Path(id): Path<String>,
     State(state): State<AppState>,
+    Json(input): Json<Announcement>,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_metrics_ws(socket, id, state))
+    save_announcement(&state, &id, input, false).await
 }
 
-async fn handle_metrics_ws(mut socket: WebSocket, id: String, state: AppState) {
-    let instance = match state.servers.get(&id).map(|r| r.value().clone()) {
-        Some(i) => i,
-        None => {
-            let _ = socket
-                .send(Message::Text("Server is not running".to_string().into()))
-                .await;
-            return;
-        }
-    };
+#[utoipa::path(
+    put,
+    path = "/api/servers/{id}/announcements/{announcement}",
+    tag = "announcements",
+    params(
+        ("id" = String, Path, description = "Server id"),
+        ("announcement" = String, Path, description = "Announcement id"),
+    ),
+    request_body = Announcement,
+    responses(
+        (status = 200, description = "Updated", body = Announcement),
+        (status = 400, description = "Invalid announcement", body = ApiError),
+        (status = 404, description = "Server or announcement not found", body = ApiError),
+    )
+)]
+pub async fn update_announcement(
+    Path((id, announcement)): Path<(String, String)>,
+    State(state): State<AppState>,
+    Json(mut input): Json<Announcement>,
+) -> impl IntoResponse {
+    // The path names the announcement; its id is not changed this way
+    input.id = announcement;
+    save_announcement(&state, &id, input, true).await
+}
 
-    let mut metrics_rx = instance.metrics_tx.subscribe();
-    loop {
-        match metrics_rx.recv().await {
-            Ok(metrics) => {
-                if let Ok(json) = serde_json::to_string(&metrics) {
-                    if socket.send(Message::Text(json.into())).await.is_err() {
-                        break;
-                    }
-                }
-            }
-            Err(_) => break,
-        }
+#[utoipa::path(
+    delete,
+    path = "/api/servers/{id}/announcements/{announcement}",
+    tag = "announcements",
+    params(
+        ("id" = String, Path, description = "Server id"),
+        ("announcement" = String, Path, description = "Announcement id"),
+    ),
+    responses(
+        (status = 204, description = "Removed"),
+        (status = 404, description = "Server or announcement not found", body = ApiError),
+    )
+)]
+pub async fn delete_announcement(
+    Path((id, announcement)): Path<(String, String)>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let mut config = state.config.write().await;
+    let Some(cfg) = config.servers.iter_mut().find(|s| s.id == id) else {
+        return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response();
+    };
+    let before = cfg.announcements.len();
+    cfg.announcements.retain(|a| a.id != announcement);
+    if cfg.announcements.len() == before {
+        let message = format!("Announcement '{}' not found", announcement);
+        return err_response(StatusCode::NOT_FOUND, message).into_response();
+    }
+    if let Err(e) = save_config(&config).await {
+        return err_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
     }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct DownloadRequest {
+    pub url: String,
+    pub sha256: String,
+    /// Destination relative to the server directory, e.g. "mods/foo.jar".
+    pub path: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/servers/{id}/downloads",
+    tag = "servers",
+    params(("id" = String, Path, description = "Server id")),
+    request_body = DownloadRequest,
+    responses(
+        (status = 200, description = "Installed"),
+        (status = 400, description = "Invalid path", body = ApiError),
+        (status = 404, description = "Server not found", body = ApiError),
+        (status = 502, description = "Download failed", body = ApiError),
+    )
+)]
+pub async fn download_handler(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Json(input): Json<DownloadRequest>,
+) -> impl IntoResponse {
+    if input.path.is_empty() || input.path.contains("..") || input.path.starts_with('/') {
+        return err_response(
+            StatusCode::BAD_REQUEST,
+            "path must be relative and must not contain '..'",
+        )
+        .into_response();
+    }
+    let (data_directory, server_dir, flavor) = {
+        let config = state.config.read().await;
+        match config.servers.iter().find(|s| s.id == id) {
+            Some(s) => (config.agent.data_directory.clone(), s.directory.clone(), s.flavor()),
+            None => {
+                return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+                    .into_response()
+            }
+        }
+    };
+    // Catch plugins sent to a modded server and the other way around
+    let content_dir = input.path.split('/').next().unwrap_or_default();
+    if flavor != ServerFlavor::Custom
+        && ["plugins", "mods"].contains(&content_dir)
+        && flavor.content_dir() != Some(content_dir)
+    {
+        return err_response(
+            StatusCode::BAD_REQUEST,
+            format!("{} servers do not load content from '{}/'", flavor.as_str(), content_dir),
+        )
+        .into_response();
+    }
+    let dest = std::path::Path::new(&server_dir).join(&input.path);
+    match downloads::install_cached(&data_directory, &input.url, &input.sha256, &dest).await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(e) => err_response(StatusCode::BAD_GATEWAY, e).into_response(),
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct ProxyStatus {
+    pub software: crate::config::ProxySoftware,
+    pub forwarding: crate::config::ForwardingMode,
+    pub backends: Vec<proxy::BackendStatus>,
+}
+
+/// Finds `id` and its proxy settings, or the response explaining why not.
+async fn find_proxy(
+    state: &AppState,
+    id: &str,
+) -> Result<(ServerConfig, crate::config::ProxyConfig), axum::response::Response> {
+    let config = state.config.read().await;
+    let cfg = config.servers.iter().find(|s| s.id == id).ok_or_else(|| {
+        err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id)).into_response()
+    })?;
+    let proxy_cfg = cfg.proxy.clone().ok_or_else(|| {
+        err_response(StatusCode::BAD_REQUEST, format!("Server '{}' is not a proxy", id))
+            .into_response()
+    })?;
+    Ok((cfg.clone(), proxy_cfg))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/servers/{id}/proxy",
+    tag = "proxy",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "Proxy backends", body = ProxyStatus),
+        (status = 400, description = "Not a proxy", body = ApiError),
+        (status = 404, description = "Server not found", body = ApiError),
+    )
+)]
+pub async fn proxy_status(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let (cfg, proxy_cfg) = match find_proxy(&state, &id).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    let config = state.config.read().await.clone();
+    Json(ProxyStatus {
+        software: proxy_cfg.software,
+        forwarding: proxy_cfg.forwarding_mode(),
+        backends: proxy::backend_statuses(&config, &cfg).await,
+    })
+    .into_response()
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/servers/{id}/proxy/provision",
+    tag = "proxy",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "Proxy jar installed", body = proxy::Provisioned),
+        (status = 400, description = "Not a proxy", body = ApiError),
+        (status = 404, description = "Server not found", body = ApiError),
+        (status = 502, description = "Download failed", body = ApiError),
+    )
+)]
+pub async fn provision_proxy(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let (cfg, proxy_cfg) = match find_proxy(&state, &id).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    let config = state.config.read().await.clone();
+    let provisioned =
+        match proxy::provision(&config.agent.data_directory, &cfg, &proxy_cfg).await {
+            Ok(p) => p,
+            Err(e) => return err_response(StatusCode::BAD_GATEWAY, e).into_response(),
+        };
+    if let Err(e) = proxy::write_config(&config, &cfg).await {
+        return err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+    }
+    Json(provisioned).into_response()
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct AddBackendRequest {
+    #[serde(flatten)]
+    pub backend: crate::config::ProxyBackend,
+    /// Also rewrite the backend's own config for the proxy's forwarding.
+    #[serde(default = "default_configure_backend")]
+    pub configure: bool,
+}
+
+fn default_configure_backend() -> bool {
+    true
+}
+
+/// Registers a backend with a proxy. Changes reach a running proxy at its
+/// next start.
+#[utoipa::path(
+    post,
+    path = "/api/servers/{id}/proxy/backends",
+    tag = "proxy",
+    params(("id" = String, Path, description = "Server id")),
+    request_body = AddBackendRequest,
+    responses(
+        (status = 200, description = "Backend registered"),
+        (status = 400, description = "Invalid backend", body = ApiError),
+        (status = 404, description = "Server not found", body = ApiError),
+    )
+)]
+pub async fn add_proxy_backend(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Json(input): Json<AddBackendRequest>,
+) -> impl IntoResponse {
+    let mut config = state.config.write().await;
+    let Some(index) = config.servers.iter().position(|s| s.id == id) else {
+        return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response();
+    };
+    let mut cfg = config.servers[index].clone();
+    let Some(ref mut proxy_cfg) = cfg.proxy else {
+        return err_response(StatusCode::BAD_REQUEST, format!("Server '{}' is not a proxy", id))
+            .into_response();
+    };
+    proxy_cfg.backends.retain(|b| b.name != input.backend.name);
+    proxy_cfg.backends.push(input.backend.clone());
+    if let Err(e) = validate_server_config(&cfg).and_then(|_| proxy::check_backends(&config, &cfg))
+    {
+        return err_response(StatusCode::BAD_REQUEST, e).into_response();
+    }
+    if input.configure {
+        let backend = config.servers.iter().find(|s| s.id == input.backend.server_id);
+        if let Some(backend) = backend {
+            if let Err(e) = proxy::configure_backend(&cfg, backend).await {
+                return err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+            }
+        }
+    }
+    config.servers[index] = cfg.clone();
+    if let Err(e) = save_config(&config).await {
+        return err_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+    if let Err(e) = proxy::write_config(&config, &cfg).await {
+        return err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+    }
+    StatusCode::OK.into_response()
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/servers/{id}/proxy/backends/{name}",
+    tag = "proxy",
+    params(
+        ("id" = String, Path, description = "Server id"),
+        ("name" = String, Path, description = "Backend name"),
+    ),
+    responses(
+        (status = 204, description = "Backend removed"),
+        (status = 400, description = "Not a proxy", body = ApiError),
+        (status = 404, description = "Server or backend not found", body = ApiError),
+    )
+)]
+pub async fn remove_proxy_backend(
+    Path((id, name)): Path<(String, String)>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let mut config = state.config.write().await;
+    let Some(index) = config.servers.iter().position(|s| s.id == id) else {
+        return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response();
+    };
+    let Some(ref mut proxy_cfg) = config.servers[index].proxy else {
+        return err_response(StatusCode::BAD_REQUEST, format!("Server '{}' is not a proxy", id))
+            .into_response();
+    };
+    let before = proxy_cfg.backends.len();
+    proxy_cfg.backends.retain(|b| b.name != name);
+    if proxy_cfg.backends.len() == before {
+        return err_response(StatusCode::NOT_FOUND, format!("Backend '{}' not found", name))
+            .into_response();
+    }
+    if let Err(e) = save_config(&config).await {
+        return err_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+    if let Err(e) = proxy::write_config(&config, &config.servers[index]).await {
+        return err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/servers/{id}/geyser",
+    tag = "geyser",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "Geyser builds", body = geyser::GeyserStatus),
+        (status = 404, description = "Server not found", body = ApiError),
+    )
+)]
+pub async fn geyser_status(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let Some(cfg) = find_server_config(&state, &id).await else {
+        return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response();
+    };
+    Json(geyser::status(&cfg).await).into_response()
+}
+
+/// Installs Geyser, or updates it to the latest build.
+#[utoipa::path(
+    post,
+    path = "/api/servers/{id}/geyser",
+    tag = "geyser",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "Installed", body = geyser::InstalledPlugins),
+        (status = 400, description = "No bedrock section", body = ApiError),
+        (status = 404, description = "Server not found", body = ApiError),
+        (status = 502, description = "Download failed", body = ApiError),
+    )
+)]
+pub async fn install_geyser(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let Some(cfg) = find_server_config(&state, &id).await else {
+        return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response();
+    };
+    if cfg.bedrock.is_none() {
+        return err_response(
+            StatusCode::BAD_REQUEST,
+            format!("Server '{}' has no bedrock section", id),
+        )
+        .into_response();
+    }
+    let data_directory = state.config.read().await.agent.data_directory.clone();
+    match geyser::install(&data_directory, &cfg).await {
+        Ok(installed) => Json(installed).into_response(),
+        Err(e) => err_response(StatusCode::BAD_GATEWAY, e).into_response(),
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct WsTicketResponse {
+    pub ticket: String,
+    pub expires_in_secs: u64,
+}
+
+/// Issues a single-use ticket for opening a WebSocket from a browser,
+/// passed as `?ticket=` on the socket URL.
+#[utoipa::path(
+    post,
+    path = "/api/ws-tickets",
+    tag = "node",
+    responses(
+        (status = 201, description = "Ticket issued", body = WsTicketResponse),
+    )
+)]
+pub async fn issue_ws_ticket(
+    State(state): State<AppState>,
+    caller: Option<Extension<ManagerToken>>,
+    session: Option<Extension<Session>>,
+) -> impl IntoResponse {
+    let token = caller
+        .map(|Extension(ManagerToken(t))| t)
+        .or(session.map(|Extension(s)| s.token));
+    let response = WsTicketResponse {
+        ticket: nodeauth::issue_ws_ticket(&state, token),
+        expires_in_secs: nodeauth::WS_TICKET_TTL.as_secs(),
+    };
+    (StatusCode::CREATED, Json(response))
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct SessionResponse {
+    /// Sent as a bearer token, or as a `bearer.<token>` WebSocket subprotocol.
+    pub token: String,
+    pub username: String,
+    pub expires_in_secs: i64,
+}
+
+fn session_response(session: Session) -> SessionResponse {
+    SessionResponse {
+        expires_in_secs: session.expires_at - chrono::Utc::now().timestamp(),
+        token: session.token,
+        username: session.username,
+    }
+}
+
+/// Logs in one of `agent.users`, starting a session.
+#[utoipa::path(
+    post,
+    path = "/api/login",
+    tag = "node",
+    request_body = LoginRequest,
+    responses(
+        (status = 200, description = "Logged in", body = SessionResponse),
+        (status = 401, description = "Wrong username or password", body = ApiError),
+    ),
+    security(())
+)]
+pub async fn login(
+    State(state): State<AppState>,
+    Json(input): Json<LoginRequest>,
+) -> impl IntoResponse {
+    let (users, hours) = {
+        let config = state.config.read().await;
+        (config.agent.users.clone(), config.agent.session_hours)
+    };
+    let checked = tokio::task::spawn_blocking(move || {
+        sessions::check_password(&users, &input.username, &input.password).cloned()
+    })
+    .await;
+    match checked {
+        Ok(Some(user)) => {
+            tracing::info!("User '{}' logged in", user.username);
+            Json(session_response(state.sessions.issue(&user, hours))).into_response()
+        }
+        Ok(None) => err_response(StatusCode::UNAUTHORIZED, "Wrong username or password")
+            .into_response(),
+        Err(e) => err_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Ends the session the request was made with.
+#[utoipa::path(
+    post,
+    path = "/api/logout",
+    tag = "node",
+    responses(
+        (status = 204, description = "Logged out"),
+        (status = 400, description = "Not made with a session", body = ApiError),
+    )
+)]
+pub async fn logout(
+    State(state): State<AppState>,
+    session: Option<Extension<Session>>,
+) -> impl IntoResponse {
+    let Some(Extension(session)) = session else {
+        return err_response(StatusCode::BAD_REQUEST, "Not logged in").into_response();
+    };
+    state.sessions.revoke(&session);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Swaps the session the request was made with for a fresh one, so an
+/// open UI can stay logged in past `agent.session_hours`.
+#[utoipa::path(
+    post,
+    path = "/api/session/refresh",
+    tag = "node",
+    responses(
+        (status = 200, description = "New session", body = SessionResponse),
+        (status = 400, description = "Not made with a session", body = ApiError),
+    )
+)]
+pub async fn refresh_session(
+    State(state): State<AppState>,
+    session: Option<Extension<Session>>,
+) -> impl IntoResponse {
+    let Some(Extension(session)) = session else {
+        return err_response(StatusCode::BAD_REQUEST, "Not logged in").into_response();
+    };
+    let config = state.config.read().await;
+    // The middleware only lets through sessions of users that still exist
+    let Some(user) = config.agent.users.iter().find(|u| u.username == session.username) else {
+        return err_response(StatusCode::UNAUTHORIZED, "Not logged in").into_response();
+    };
+    state.sessions.revoke(&session);
+    Json(session_response(state.sessions.issue(user, config.agent.session_hours)))
+        .into_response()
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct ManagerTokenRequest {
+    pub token: String,
+}
+
+/// Adds a manager token, keeping the caller's current one valid until it
+/// is retired. Only a manager token can rotate manager tokens; the first
+/// one is set in the config file.
+#[utoipa::path(
+    post,
+    path = "/api/node/token",
+    tag = "node",
+    request_body = ManagerTokenRequest,
+    responses(
+        (status = 204, description = "Token added"),
+        (status = 400, description = "Invalid token", body = ApiError),
+        (status = 403, description = "Not called with a manager token", body = ApiError),
+    )
+)]
+pub async fn add_manager_token(
+    State(state): State<AppState>,
+    caller: Option<Extension<ManagerToken>>,
+    Json(input): Json<ManagerTokenRequest>,
+) -> impl IntoResponse {
+    let Some(Extension(ManagerToken(current))) = caller else {
+        return err_response(
+            StatusCode::FORBIDDEN,
+            "manager tokens can only be changed with a manager token; set the first one in the \
+             config file",
+        )
+        .into_response();
+    };
+    if input.token.len() < 16 || input.token.chars().any(|c| c.is_whitespace()) {
+        return err_response(
+            StatusCode::BAD_REQUEST,
+            "token must be at least 16 characters without whitespace",
+        )
+        .into_response();
+    }
+    let mut config = state.config.write().await;
+    config.agent.manager_tokens = vec![input.token, current];
+    config.agent.manager_tokens.dedup();
+    if let Err(e) = save_config(&config).await {
+        return err_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Drops every manager token except the one this request used.
+#[utoipa::path(
+    delete,
+    path = "/api/node/token/previous",
+    tag = "node",
+    responses(
+        (status = 204, description = "Other tokens retired"),
+        (status = 400, description = "Manager authentication is not enabled", body = ApiError),
+    )
+)]
+pub async fn retire_manager_tokens(
+    State(state): State<AppState>,
+    caller: Option<Extension<ManagerToken>>,
+) -> impl IntoResponse {
+    let Some(Extension(ManagerToken(token))) = caller else {
+        return err_response(StatusCode::BAD_REQUEST, "Manager authentication is not enabled")
+            .into_response();
+    };
+    let mut config = state.config.write().await;
+    config.agent.manager_tokens = vec![token];
+    if let Err(e) = save_config(&config).await {
+        return err_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Proves this agent holds the manager's token before the manager sends it:
+/// the `x-agent-proof` header carries an HMAC of the `x-manager-nonce`
+/// header per configured token.
+#[utoipa::path(
+    get,
+    path = "/api/node/challenge",
+    tag = "node",
+    responses(
+        (status = 204, description = "Proofs in the x-agent-proof header"),
+        (status = 400, description = "Missing or malformed nonce", body = ApiError),
+    )
+)]
+pub async fn node_challenge(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+) -> impl IntoResponse {
+    let nonce = headers
+        .get(nodeauth::NONCE_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    match nodeauth::answer_challenge(&state, nonce).await {
+        Ok(proofs) => {
+            (StatusCode::NO_CONTENT, [(nodeauth::PROOF_HEADER, proofs)]).into_response()
+        }
+        Err(e) => err_response(StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct MaintenanceStatus {
+    pub enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since_ms: Option<u64>,
+}
+
+async fn current_maintenance(state: &AppState) -> MaintenanceStatus {
+    let maintenance = state.maintenance.read().await.clone();
+    MaintenanceStatus {
+        enabled: maintenance.is_some(),
+        reason: maintenance.as_ref().map(|m| m.reason.clone()),
+        since_ms: maintenance.map(|m| m.since_ms),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/maintenance",
+    tag = "node",
+    responses(
+        (status = 200, description = "Maintenance mode state", body = MaintenanceStatus),
+    )
+)]
+pub async fn maintenance_status(State(state): State<AppState>) -> impl IntoResponse {
+    Json(current_maintenance(&state).await)
+}
+
+#[derive(Deserialize, Default, utoipa::ToSchema)]
+pub struct MaintenanceRequest {
+    /// Shown to anyone whose request is refused.
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// Puts the agent in read-only maintenance mode: servers can be listed and
+/// their consoles watched, but every request that would change something
+/// gets 503, and nothing is started automatically.
+#[utoipa::path(
+    post,
+    path = "/api/maintenance",
+    tag = "node",
+    request_body = MaintenanceRequest,
+    responses(
+        (status = 200, description = "Maintenance mode is on", body = MaintenanceStatus),
+    )
+)]
+pub async fn enter_maintenance(
+    State(state): State<AppState>,
+    input: Option<Json<MaintenanceRequest>>,
+) -> impl IntoResponse {
+    let Json(input) = input.unwrap_or_default();
+    let maintenance = maintenance::Maintenance {
+        reason: input
+            .reason
+            .filter(|r| !r.trim().is_empty())
+            .unwrap_or_else(|| "host maintenance".to_string()),
+        since_ms: chrono::Utc::now().timestamp_millis() as u64,
+    };
+    if let Err(e) = maintenance::set(&state, Some(maintenance)).await {
+        return err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+    }
+    Json(current_maintenance(&state).await).into_response()
+}
+
+/// Leaves maintenance mode. Servers with `desired_state: running` are
+/// brought up on the reconciler's next pass.
+#[utoipa::path(
+    delete,
+    path = "/api/maintenance",
+    tag = "node",
+    responses(
+        (status = 200, description = "Maintenance mode is off", body = MaintenanceStatus),
+    )
+)]
+pub async fn leave_maintenance(State(state): State<AppState>) -> impl IntoResponse {
+    if let Err(e) = maintenance::set(&state, None).await {
+        return err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+    }
+    Json(current_maintenance(&state).await).into_response()
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+pub struct MemoryAdviceQuery {
+    /// Hours of metrics history to judge by; defaults to 24, at most
+    /// `metrics_retention_hours`.
+    #[serde(default)]
+    pub hours: Option<u64>,
+}
+
+/// Compares each server's `-Xmx` with the heap it keeps live and the memory
+/// its process holds, flagging servers to resize.
+#[utoipa::path(
+    get,
+    path = "/api/node/memory-advice",
+    tag = "node",
+    params(MemoryAdviceQuery),
+    responses(
+        (status = 200, description = "Sizing advice per server", body = sizing::MemoryAdvice),
+    )
+)]
+pub async fn memory_advice(
+    State(state): State<AppState>,
+    Query(query): Query<MemoryAdviceQuery>,
+) -> impl IntoResponse {
+    let retention_hours = state.config.read().await.agent.metrics_retention_hours;
+    let hours = query.hours.unwrap_or(24).clamp(1, retention_hours.max(1));
+    Json(sizing::advise_all(&state, hours).await)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/node/resources",
+    tag = "node",
+    responses(
+        (status = 200, description = "Capacity of this agent", body = resources::NodeResources),
+    )
+)]
+pub async fn node_resources(State(state): State<AppState>) -> impl IntoResponse {
+    Json(resources::node_resources(&state).await)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/servers/{id}/java",
+    tag = "servers",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "Selected Java", body = runtimes::JavaSelection),
+        (status = 404, description = "Server not found", body = ApiError),
+    )
+)]
+pub async fn java_selection(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let (server_cfg, data_directory) = {
+        let config = state.config.read().await;
+        match config.servers.iter().find(|s| s.id == id) {
+            Some(s) => (s.clone(), config.agent.data_directory.clone()),
+            None => {
+                return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+                    .into_response()
+            }
+        }
+    };
+    match runtimes::select_java(&server_cfg, &data_directory).await {
+        Ok(selection) => Json(selection).into_response(),
+        Err(e) => err_response(StatusCode::UNPROCESSABLE_ENTITY, e).into_response(),
+    }
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PlayerStatsQuery {
+    /// Only this player, by name or UUID.
+    pub player: Option<String>,
+    /// Sessions listed per player, most recent first.
+    #[serde(default = "default_session_history")]
+    pub sessions: usize,
+}
+
+fn default_session_history() -> usize {
+    10
+}
+
+/// Playtime and session history of everyone who has joined the server.
+#[utoipa::path(
+    get,
+    path = "/api/servers/{id}/players/stats",
+    tag = "players",
+    params(("id" = String, Path, description = "Server id"), PlayerStatsQuery),
+    responses(
+        (status = 200, description = "Players, most recently seen first",
+            body = Vec<playtime::PlayerStats>),
+        (status = 404, description = "Server not found", body = ApiError),
+    )
+)]
+pub async fn player_stats(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Query(query): Query<PlayerStatsQuery>,
+) -> impl IntoResponse {
+    if find_server_config(&state, &id).await.is_none() {
+        return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response();
+    }
+    let data_directory = state.config.read().await.agent.data_directory.clone();
+    let open = match state.servers.get(&id).map(|r| r.value().clone()) {
+        Some(instance) => instance.players.lock().await.values().cloned().collect(),
+        None => Vec::new(),
+    };
+    let mut stats = match playtime::stats(&data_directory, &id, open, query.sessions).await {
+        Ok(stats) => stats,
+        Err(e) => return err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    };
+    if let Some(player) = query.player {
+        stats.retain(|p| {
+            p.name.eq_ignore_ascii_case(&player) || p.uuid.as_deref() == Some(player.as_str())
+        });
+    }
+    Json(stats).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/servers/{id}/last-exit",
+    tag = "servers",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "How the server last exited", body = ExitInfo),
+        (status = 404, description = "Server not found or never exited", body = ApiError),
+    )
+)]
+pub async fn last_exit(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    if find_server_config(&state, &id).await.is_none() {
+        return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response();
+    }
+    match state.last_exits.get(&id) {
+        Some(exit) => Json(exit.value().clone()).into_response(),
+        None => err_response(
+            StatusCode::NOT_FOUND,
+            format!("Server '{}' has not exited since it was last started", id),
+        )
+        .into_response(),
+    }
+}
+
+async fn find_server_config(state: &AppState, id: &str) -> Option<ServerConfig> {
+    let config = state.config.read().await;
+    config.servers.iter().find(|s| s.id == id).cloned()
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/servers/{id}/world/seed",
+    tag = "world",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "The world seed", body = serde_json::Value),
+        (status = 404, description = "Server not found", body = ApiError),
+    )
+)]
+pub async fn world_seed(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let Some(server_cfg) = find_server_config(&state, &id).await else {
+        return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response();
+    };
+    match world::world_seed(&server_cfg).await {
+        Ok(seed) => Json(serde_json::json!({ "seed": seed })).into_response(),
+        Err(e) => err_response(StatusCode::UNPROCESSABLE_ENTITY, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/servers/{id}/worlds",
+    tag = "world",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "World folders", body = [world::WorldInfo]),
+        (status = 404, description = "Server not found", body = ApiError),
+    )
+)]
+pub async fn list_worlds(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let Some(server_cfg) = find_server_config(&state, &id).await else {
+        return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response();
+    };
+    match world::list_worlds(&server_cfg).await {
+        Ok(worlds) => Json(worlds).into_response(),
+        Err(e) => err_response(StatusCode::UNPROCESSABLE_ENTITY, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/servers/{id}/worlds/{name}/info",
+    tag = "world",
+    params(
+        ("id" = String, Path, description = "Server id"),
+        ("name" = String, Path, description = "World folder"),
+    ),
+    responses(
+        (status = 200, description = "What level.dat records", body = world::LevelInfo),
+        (status = 404, description = "Server or world not found", body = ApiError),
+    )
+)]
+pub async fn world_info(
+    Path((id, name)): Path<(String, String)>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let Some(server_cfg) = find_server_config(&state, &id).await else {
+        return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response();
+    };
+    if let Err(response) = find_world(&server_cfg, &name).await {
+        return response;
+    }
+    match world::level_info(&server_cfg, &name).await {
+        Ok(info) => Json(info).into_response(),
+        Err(e) => err_response(StatusCode::UNPROCESSABLE_ENTITY, e).into_response(),
+    }
+}
+
+/// Checks `name` is one of the server's world folders.
+async fn find_world(
+    server_cfg: &ServerConfig,
+    name: &str,
+) -> Result<(), axum::response::Response> {
+    let found = world::is_world(server_cfg, name)
+        .await
+        .map_err(|e| err_response(StatusCode::UNPROCESSABLE_ENTITY, e).into_response())?;
+    if !found {
+        return Err(err_response(StatusCode::NOT_FOUND, format!("World '{}' not found", name))
+            .into_response());
+    }
+    Ok(())
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct WorldActivation {
+    pub level_name: String,
+    /// Whether the server was running and got restarted onto the world.
+    pub restarted: bool,
+}
+
+/// Sets `level-name` to another world folder, restarting the server if it
+/// is running.
+#[utoipa::path(
+    post,
+    path = "/api/servers/{id}/worlds/{name}/activate",
+    tag = "world",
+    params(
+        ("id" = String, Path, description = "Server id"),
+        ("name" = String, Path, description = "World folder"),
+    ),
+    responses(
+        (status = 200, description = "World activated", body = WorldActivation),
+        (status = 404, description = "Server or world not found", body = ApiError),
+        (status = 400, description = "Switching failed", body = ApiError),
+    )
+)]
+pub async fn activate_world(
+    Path((id, name)): Path<(String, String)>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let Some(server_cfg) = find_server_config(&state, &id).await else {
+        return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response();
+    };
+    if let Err(response) = find_world(&server_cfg, &name).await {
+        return response;
+    }
+    match world::activate_world(&state, &server_cfg, &name).await {
+        Ok(restarted) => Json(WorldActivation {
+            level_name: name,
+            restarted,
+        })
+        .into_response(),
+        Err(e) => err_response(StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+#[derive(Deserialize, Default, utoipa::ToSchema)]
+pub struct WorldResetRequest {
+    /// New `level-seed`; empty for a random one. Kept when absent, so the
+    /// same world is generated again.
+    #[serde(default)]
+    pub seed: Option<String>,
+}
+
+/// Archives and deletes the active world, optionally setting a new seed,
+/// and restarts the server if it is running so it generates a fresh one.
+#[utoipa::path(
+    post,
+    path = "/api/servers/{id}/worlds/reset",
+    tag = "world",
+    params(("id" = String, Path, description = "Server id")),
+    request_body = WorldResetRequest,
+    responses(
+        (status = 200, description = "World reset", body = world::WorldReset),
+        (status = 404, description = "Server not found", body = ApiError),
+        (status = 400, description = "Reset failed", body = ApiError),
+    )
+)]
+pub async fn reset_world(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    body: Option<Json<WorldResetRequest>>,
+) -> impl IntoResponse {
+    let Some(server_cfg) = find_server_config(&state, &id).await else {
+        return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response();
+    };
+    let Json(request) = body.unwrap_or_default();
+    match world::reset_world(&state, &server_cfg, request.seed.as_deref()).await {
+        Ok(reset) => Json(reset).into_response(),
+        Err(e) => err_response(StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+/// Archives and deletes the Nether or the End of the active world, so the
+/// server generates it anew on its next start.
+#[utoipa::path(
+    post,
+    path = "/api/servers/{id}/worlds/reset/{dimension}",
+    tag = "world",
+    params(
+        ("id" = String, Path, description = "Server id"),
+        ("dimension" = world::Dimension, Path, description = "nether or end"),
+    ),
+    responses(
+        (status = 200, description = "Dimension reset", body = world::DimensionReset),
+        (status = 404, description = "Server not found", body = ApiError),
+        (status = 409, description = "Server is running", body = ApiError),
+        (status = 400, description = "Reset failed", body = ApiError),
+    )
+)]
+pub async fn reset_dimension(
+    Path((id, dimension)): Path<(String, world::Dimension)>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let Some(server_cfg) = find_server_config(&state, &id).await else {
+        return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response();
+    };
+    if state.servers.contains_key(&id) {
+        return err_response(StatusCode::CONFLICT, format!("Server '{}' must be stopped first", id))
+            .into_response();
+    }
+    match world::reset_dimension(&state, &server_cfg, dimension).await {
+        Ok(reset) => Json(reset).into_response(),
+        Err(e) => err_response(StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+/// Deletes region files outside a radius or unused for a number of days,
+/// archiving them first. Needs the server stopped unless `dry_run` is set.
+#[utoipa::path(
+    post,
+    path = "/api/servers/{id}/worlds/{name}/trim",
+    tag = "world",
+    params(
+        ("id" = String, Path, description = "Server id"),
+        ("name" = String, Path, description = "World folder"),
+    ),
+    request_body = world::TrimRequest,
+    responses(
+        (status = 200, description = "Trimmed, or what would be", body = world::TrimReport),
+        (status = 404, description = "Server or world not found", body = ApiError),
+        (status = 409, description = "Server is running", body = ApiError),
+        (status = 400, description = "Trimming failed", body = ApiError),
+    )
+)]
+pub async fn trim_world(
+    Path((id, name)): Path<(String, String)>,
+    State(state): State<AppState>,
+    Json(request): Json<world::TrimRequest>,
+) -> impl IntoResponse {
+    let Some(server_cfg) = find_server_config(&state, &id).await else {
+        return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response();
+    };
+    if let Err(response) = find_world(&server_cfg, &name).await {
+        return response;
+    }
+    if !request.dry_run && state.servers.contains_key(&id) {
+        return err_response(StatusCode::CONFLICT, format!("Server '{}' must be stopped first", id))
+            .into_response();
+    }
+    match world::trim_world(&state, &server_cfg, &name, &request).await {
+        Ok(report) => Json(report).into_response(),
+        Err(e) => err_response(StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct PregenStatusResponse {
+    /// Whether a Chunky jar is in the server's plugins or mods.
+    pub chunky_installed: bool,
+    /// The running job, or the last one since the agent started.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job: Option<pregen::PregenJob>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/servers/{id}/pregen",
+    tag = "world",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "Pregeneration progress", body = PregenStatusResponse),
+        (status = 404, description = "Server not found", body = ApiError),
+    )
+)]
+pub async fn pregen_status(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let Some(server_cfg) = find_server_config(&state, &id).await else {
+        return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response();
+    };
+    Json(PregenStatusResponse {
+        chunky_installed: pregen::installed(&server_cfg).await,
+        job: state.pregen_jobs.get(&id).map(|j| j.value().clone()),
+    })
+    .into_response()
+}
+
+/// Starts pregenerating a world with Chunky. Chunky is installed first
+/// when missing, which then needs a restart before a job can run.
+#[utoipa::path(
+    post,
+    path = "/api/servers/{id}/pregen",
+    tag = "world",
+    params(("id" = String, Path, description = "Server id")),
+    request_body = pregen::PregenRequest,
+    responses(
+        (status = 200, description = "Job started", body = pregen::PregenJob),
+        (status = 404, description = "Server not found", body = ApiError),
+        (status = 409, description = "Not running, or Chunky was just installed", body = ApiError),
+        (status = 400, description = "Chunky refused the job", body = ApiError),
+        (status = 502, description = "Installing Chunky failed", body = ApiError),
+    )
+)]
+pub async fn start_pregen(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Json(request): Json<pregen::PregenRequest>,
+) -> impl IntoResponse {
+    let Some(server_cfg) = find_server_config(&state, &id).await else {
+        return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response();
+    };
+    if !server_cfg.flavor().has_world() {
+        return err_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("'{}' is a proxy and has no world", id),
+        )
+        .into_response();
+    }
+    if !pregen::installed(&server_cfg).await {
+        let data_directory = state.config.read().await.agent.data_directory.clone();
+        return match pregen::install(&data_directory, &server_cfg).await {
+            Ok(version) => err_response(
+                StatusCode::CONFLICT,
+                format!("Installed Chunky {}; it loads when '{}' next starts", version, id),
+            )
+            .into_response(),
+            Err(e) => err_response(StatusCode::BAD_GATEWAY, e).into_response(),
+        };
+    }
+    if !state.servers.contains_key(&id) {
+        return err_response(StatusCode::CONFLICT, format!("Server '{}' is not running", id))
+            .into_response();
+    }
+    match pregen::start(&state, &server_cfg, request).await {
+        Ok(job) => Json(job).into_response(),
+        Err(e) => err_response(StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/servers/{id}/pregen",
+    tag = "world",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "Job cancelled", body = pregen::PregenJob),
+        (status = 400, description = "Nothing to cancel", body = ApiError),
+    )
+)]
+pub async fn cancel_pregen(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match pregen::cancel(&state, &id).await {
+        Ok(job) => Json(job).into_response(),
+        Err(e) => err_response(StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/servers/{id}/diagnostics",
+    tag = "diagnostics",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "Saved dumps, newest first",
+            body = [diagnostics::DiagnosticFile]),
+        (status = 404, description = "Server not found", body = ApiError),
+    )
+)]
+pub async fn list_diagnostics(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let Some(server_cfg) = find_server_config(&state, &id).await else {
+        return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response();
+    };
+    let data_directory = state.config.read().await.agent.data_directory.clone();
+    Json(diagnostics::list(&data_directory, &server_cfg).await).into_response()
+}
+
+/// Saves a thread dump of the running server, through jcmd or, without a
+/// JDK, SIGQUIT and the console.
+#[utoipa::path(
+    post,
+    path = "/api/servers/{id}/diagnostics/thread-dump",
+    tag = "diagnostics",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "Thread dump saved", body = diagnostics::DiagnosticFile),
+        (status = 404, description = "Server not found", body = ApiError),
+        (status = 409, description = "Server is not running", body = ApiError),
+        (status = 500, description = "Dump failed", body = ApiError),
+    )
+)]
+pub async fn thread_dump(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let server_cfg = match dump_target(&state, &id).await {
+        Ok(cfg) => cfg,
+        Err(response) => return response,
+    };
+    match diagnostics::thread_dump(&state, &server_cfg).await {
+        Ok(file) => Json(file).into_response(),
+        Err(e) => err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+/// Saves a heap dump of the running server through jcmd, which needs a JDK.
+/// The server pauses while its heap is written.
+#[utoipa::path(
+    post,
+    path = "/api/servers/{id}/diagnostics/heap-dump",
+    tag = "diagnostics",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "Heap dump saved", body = diagnostics::DiagnosticFile),
+        (status = 404, description = "Server not found", body = ApiError),
+        (status = 409, description = "Server is not running", body = ApiError),
+        (status = 500, description = "Dump failed", body = ApiError),
+    )
+)]
+pub async fn heap_dump(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let server_cfg = match dump_target(&state, &id).await {
+        Ok(cfg) => cfg,
+        Err(response) => return response,
+    };
+    match diagnostics::heap_dump(&state, &server_cfg).await {
+        Ok(file) => Json(file).into_response(),
+        Err(e) => err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+/// The config of a running server to inspect.
+async fn dump_target(
+    state: &AppState,
+    id: &str,
+) -> Result<ServerConfig, axum::response::Response> {
+    let Some(server_cfg) = find_server_config(state, id).await else {
+        return Err(err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response());
+    };
+    if !state.servers.contains_key(id) {
+        return Err(err_response(StatusCode::CONFLICT, format!("Server '{}' is not running", id))
+            .into_response());
+    }
+    Ok(server_cfg)
+}
+
+/// Starts a Java Flight Recorder recording, for JDK Mission Control. Once
+/// it stops, by itself or through DELETE, its .jfr is among the dumps.
+#[utoipa::path(
+    post,
+    path = "/api/servers/{id}/diagnostics/jfr",
+    tag = "diagnostics",
+    params(("id" = String, Path, description = "Server id")),
+    request_body = diagnostics::JfrRequest,
+    responses(
+        (status = 200, description = "Recording started", body = diagnostics::JfrRecording),
+        (status = 400, description = "Already recording or jcmd failed", body = ApiError),
+        (status = 404, description = "Server not found", body = ApiError),
+        (status = 409, description = "Server is not running", body = ApiError),
+    )
+)]
+pub async fn start_recording(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Json(request): Json<diagnostics::JfrRequest>,
+) -> impl IntoResponse {
+    let server_cfg = match dump_target(&state, &id).await {
+        Ok(cfg) => cfg,
+        Err(response) => return response,
+    };
+    match diagnostics::start_recording(&state, &server_cfg, request).await {
+        Ok(recording) => Json(recording).into_response(),
+        Err(e) => err_response(StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/servers/{id}/diagnostics/jfr",
+    tag = "diagnostics",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "Recording saved", body = diagnostics::DiagnosticFile),
+        (status = 400, description = "Not recording or jcmd failed", body = ApiError),
+        (status = 404, description = "Server not found", body = ApiError),
+        (status = 409, description = "Server is not running", body = ApiError),
+    )
+)]
+pub async fn stop_recording(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let server_cfg = match dump_target(&state, &id).await {
+        Ok(cfg) => cfg,
+        Err(response) => return response,
+    };
+    match diagnostics::stop_recording(&state, &server_cfg).await {
+        Ok(file) => Json(file).into_response(),
+        Err(e) => err_response(StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/servers/{id}/diagnostics/{file}",
+    tag = "diagnostics",
+    params(
+        ("id" = String, Path, description = "Server id"),
+        ("file" = String, Path, description = "Dump file name"),
+    ),
+    responses(
+        (status = 200, description = "The dump", content_type = "application/octet-stream"),
+        (status = 404, description = "Server or dump not found", body = ApiError),
+    )
+)]
+pub async fn download_diagnostic(
+    Path((id, name)): Path<(String, String)>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    use tokio::io::AsyncReadExt;
+
+    let Some(server_cfg) = find_server_config(&state, &id).await else {
+        return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response();
+    };
+    let data_directory = state.config.read().await.agent.data_directory.clone();
+    let Some(path) = diagnostics::file_path(&data_directory, &server_cfg, &name) else {
+        return err_response(StatusCode::NOT_FOUND, format!("Dump '{}' not found", name))
+            .into_response();
+    };
+    let file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(e) => {
+            return err_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    };
+    // Streamed, since heap dumps run to gigabytes
+    let chunks = futures_util::stream::try_unfold(file, |mut file| async move {
+        let mut buf = vec![0; 64 * 1024];
+        let n = file.read(&mut buf).await?;
+        buf.truncate(n);
+        Ok::<_, std::io::Error>((n > 0).then(|| (axum::body::Bytes::from(buf), file)))
+    });
+    let content_type = if name.ends_with(".txt") {
+        "text/plain; charset=utf-8"
+    } else {
+        "application/octet-stream"
+    };
+    (
+        [
+            (axum::http::header::CONTENT_TYPE, content_type.to_string()),
+            (
+                axum::http::header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", name),
+            ),
+        ],
+        axum::body::Body::from_stream(chunks),
+    )
+        .into_response()
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/servers/{id}/diagnostics/{file}",
+    tag = "diagnostics",
+    params(
+        ("id" = String, Path, description = "Server id"),
+        ("file" = String, Path, description = "Dump file name"),
+    ),
+    responses(
+        (status = 204, description = "Dump deleted"),
+        (status = 404, description = "Server or dump not found", body = ApiError),
+    )
+)]
+pub async fn delete_diagnostic(
+    Path((id, name)): Path<(String, String)>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let Some(server_cfg) = find_server_config(&state, &id).await else {
+        return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response();
+    };
+    let data_directory = state.config.read().await.agent.data_directory.clone();
+    let Some(path) = diagnostics::file_path(&data_directory, &server_cfg, &name) else {
+        return err_response(StatusCode::NOT_FOUND, format!("Dump '{}' not found", name))
+            .into_response();
+    };
+    match tokio::fs::remove_file(&path).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => err_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct MotdResponse {
+    /// With `§` format codes, lines separated by `\n`.
+    pub motd: String,
+    /// As players read it, without format codes.
+    pub plain: String,
+    /// Whether the server is running and shows the change after a restart.
+    pub restart_required: bool,
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct MotdUpdate {
+    /// Up to two lines; `&` codes such as `&6` become `§` codes.
+    pub motd: String,
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct IconCropQuery {
+    /// Left edge of the square to use, in image pixels.
+    pub x: Option<u32>,
+    /// Top edge of the square to use, in image pixels.
+    pub y: Option<u32>,
+    /// Side of the square; the centered largest square when absent.
+    pub size: Option<u32>,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct IconResponse {
+    /// Whether the server is running and shows the icon after a restart.
+    pub restart_required: bool,
+}
+
+/// The server's config, when its list appearance is set in server.properties.
+async fn listed_server(
+    state: &AppState,
+    id: &str,
+) -> Result<ServerConfig, axum::response::Response> {
+    let Some(server_cfg) = find_server_config(state, id).await else {
+        return Err(
+            err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+                .into_response(),
+        );
+    };
+    if server_cfg.flavor() == ServerFlavor::Proxy {
+        return Err(err_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Proxies set their MOTD and icon in their own config",
+        )
+        .into_response());
+    }
+    Ok(server_cfg)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/servers/{id}/motd",
+    tag = "servers",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "The MOTD in server.properties", body = MotdResponse),
+        (status = 404, description = "Server not found", body = ApiError),
+        (status = 422, description = "The server is a proxy", body = ApiError),
+    )
+)]
+pub async fn get_motd(Path(id): Path<String>, State(state): State<AppState>) -> impl IntoResponse {
+    let server_cfg = match listed_server(&state, &id).await {
+        Ok(server_cfg) => server_cfg,
+        Err(response) => return response,
+    };
+    match ServerProperties::load(&server_cfg.directory).await {
+        Ok(properties) => {
+            let motd = properties.get_text("motd").unwrap_or_default();
+            Json(MotdResponse {
+                plain: motd::plain(&motd),
+                motd,
+                restart_required: false,
+            })
+            .into_response()
+        }
+        Err(e) => err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/servers/{id}/motd",
+    tag = "servers",
+    params(("id" = String, Path, description = "Server id")),
+    request_body = MotdUpdate,
+    responses(
+        (status = 200, description = "MOTD saved", body = MotdResponse),
+        (status = 400, description = "Invalid MOTD", body = ApiError),
+        (status = 404, description = "Server not found", body = ApiError),
+        (status = 422, description = "The server is a proxy", body = ApiError),
+    )
+)]
+pub async fn set_motd(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Json(update): Json<MotdUpdate>,
+) -> impl IntoResponse {
+    let server_cfg = match listed_server(&state, &id).await {
+        Ok(server_cfg) => server_cfg,
+        Err(response) => return response,
+    };
+    let motd = match motd::format(&update.motd) {
+        Ok(motd) => motd,
+        Err(e) => return err_response(StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let saved = async {
+        let mut properties = ServerProperties::load(&server_cfg.directory).await?;
+        properties.set_text("motd", &motd);
+        properties.save(&server_cfg.directory).await
+    };
+    if let Err(e) = saved.await {
+        return err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+    }
+    Json(MotdResponse {
+        plain: motd::plain(&motd),
+        motd,
+        restart_required: state.servers.contains_key(&id),
+    })
+    .into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/servers/{id}/icon",
+    tag = "servers",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "The server icon", content_type = "image/png"),
+        (status = 404, description = "Server or icon not found", body = ApiError),
+    )
+)]
+pub async fn get_icon(Path(id): Path<String>, State(state): State<AppState>) -> impl IntoResponse {
+    let server_cfg = match listed_server(&state, &id).await {
+        Ok(server_cfg) => server_cfg,
+        Err(response) => return response,
+    };
+    let path = std::path::Path::new(&server_cfg.directory).join(motd::ICON_FILE);
+    match tokio::fs::read(&path).await {
+        Ok(png) => ([(axum::http::header::CONTENT_TYPE, "image/png")], png).into_response(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            err_response(StatusCode::NOT_FOUND, format!("Server '{}' has no icon", id))
+                .into_response()
+        }
+        Err(e) => err_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Sets the icon from an uploaded PNG of any size, cropped to a square and
+/// scaled to 64x64.
+#[utoipa::path(
+    put,
+    path = "/api/servers/{id}/icon",
+    tag = "servers",
+    params(("id" = String, Path, description = "Server id"), IconCropQuery),
+    request_body(content = Vec<u8>, content_type = "image/png"),
+    responses(
+        (status = 200, description = "Icon saved", body = IconResponse),
+        (status = 400, description = "Invalid image or crop", body = ApiError),
+        (status = 404, description = "Server not found", body = ApiError),
+        (status = 422, description = "The server is a proxy", body = ApiError),
+    )
+)]
+pub async fn set_icon(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Query(query): Query<IconCropQuery>,
+    body: axum::body::Bytes,
+) -> impl IntoResponse {
+    let server_cfg = match listed_server(&state, &id).await {
+        Ok(server_cfg) => server_cfg,
+        Err(response) => return response,
+    };
+    let crop = match (query.x, query.y, query.size) {
+        (None, None, None) => None,
+        (x, y, Some(size)) => Some(motd::Crop {
+            x: x.unwrap_or(0),
+            y: y.unwrap_or(0),
+            size,
+        }),
+        _ => {
+            return err_response(StatusCode::BAD_REQUEST, "x and y need a crop size")
+                .into_response()
+        }
+    };
+    let icon = match tokio::task::spawn_blocking(move || motd::make_icon(&body, crop)).await {
+        Ok(Ok(icon)) => icon,
+        Ok(Err(e)) => return err_response(StatusCode::BAD_REQUEST, e).into_response(),
+        Err(e) => {
+            return err_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    };
+    let path = std::path::Path::new(&server_cfg.directory).join(motd::ICON_FILE);
+    if let Err(e) = tokio::fs::write(&path, icon).await {
+        return err_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to write {}: {}", motd::ICON_FILE, e),
+        )
+        .into_response();
+    }
+    Json(IconResponse {
+        restart_required: state.servers.contains_key(&id),
+    })
+    .into_response()
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/servers/{id}/icon",
+    tag = "servers",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 204, description = "Icon removed"),
+        (status = 404, description = "Server or icon not found", body = ApiError),
+    )
+)]
+pub async fn delete_icon(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let server_cfg = match listed_server(&state, &id).await {
+        Ok(server_cfg) => server_cfg,
+        Err(response) => return response,
+    };
+    let path = std::path::Path::new(&server_cfg.directory).join(motd::ICON_FILE);
+    match tokio::fs::remove_file(&path).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            err_response(StatusCode::NOT_FOUND, format!("Server '{}' has no icon", id))
+                .into_response()
+        }
+        Err(e) => err_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/servers/{id}/resource-pack",
+    tag = "servers",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "The hosted pack", body = packs::ResourcePack),
+        (status = 404, description = "Server or pack not found", body = ApiError),
+        (status = 422, description = "The server is a proxy", body = ApiError),
+    )
+)]
+pub async fn get_resource_pack(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let server_cfg = match listed_server(&state, &id).await {
+        Ok(server_cfg) => server_cfg,
+        Err(response) => return response,
+    };
+    match packs::get(&state, &server_cfg).await {
+        Ok(Some(pack)) => Json(pack).into_response(),
+        Ok(None) => err_response(
+            StatusCode::NOT_FOUND,
+            format!("Server '{}' has no hosted resource pack", id),
+        )
+        .into_response(),
+        Err(e) => err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ResourcePackQuery {
+    /// Name the pack is served under, ending in `.zip`.
+    pub file: String,
+}
+
+/// Hosts an uploaded zip as the server's resource pack, replacing the
+/// previous one, and writes its URL and SHA-1 to server.properties.
+#[utoipa::path(
+    put,
+    path = "/api/servers/{id}/resource-pack",
+    tag = "servers",
+    params(("id" = String, Path, description = "Server id"), ResourcePackQuery),
+    request_body(content = Vec<u8>, content_type = "application/zip"),
+    responses(
+        (status = 200, description = "Pack hosted", body = packs::ResourcePack),
+        (status = 400, description = "Invalid pack or agent.public_url unset", body = ApiError),
+        (status = 404, description = "Server not found", body = ApiError),
+        (status = 422, description = "The server is a proxy", body = ApiError),
+    )
+)]
+pub async fn upload_resource_pack(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Query(query): Query<ResourcePackQuery>,
+    body: axum::body::Bytes,
+) -> impl IntoResponse {
+    let server_cfg = match listed_server(&state, &id).await {
+        Ok(server_cfg) => server_cfg,
+        Err(response) => return response,
+    };
+    match packs::upload(&state, &server_cfg, &query.file, body).await {
+        Ok(pack) => Json(pack).into_response(),
+        Err(e) => err_response(StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/servers/{id}/resource-pack",
+    tag = "servers",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 204, description = "Pack removed"),
+        (status = 404, description = "Server or pack not found", body = ApiError),
+    )
+)]
+pub async fn delete_resource_pack(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let server_cfg = match listed_server(&state, &id).await {
+        Ok(server_cfg) => server_cfg,
+        Err(response) => return response,
+    };
+    match packs::remove(&state, &server_cfg).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => err_response(
+            StatusCode::NOT_FOUND,
+            format!("Server '{}' has no hosted resource pack", id),
+        )
+        .into_response(),
+        Err(e) => err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct PropertyProfile {
+    pub name: String,
+    pub properties: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct PropertyProfileChanges {
+    pub profile: String,
+    /// Settings that differ from the profile, with their current values.
+    pub changes: Vec<PropertyChange>,
+    pub applied: bool,
+    /// Whether the server is running and takes the changes on its next start.
+    pub restart_required: bool,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/property-profiles",
+    tag = "servers",
+    responses(
+        (status = 200, description = "Profiles in the agent config", body = Vec<PropertyProfile>),
+    )
+)]
+pub async fn list_property_profiles(State(state): State<AppState>) -> impl IntoResponse {
+    let config = state.config.read().await;
+    let profiles: Vec<PropertyProfile> = config
+        .agent
+        .property_profiles
+        .iter()
+        .map(|(name, properties)| PropertyProfile {
+            name: name.clone(),
+            properties: properties.clone(),
+        })
+        .collect();
+    Json(profiles)
+}
+
+/// Compares a server's server.properties with a profile, writing the
+/// profile's values when `apply` is set.
+async fn property_profile_changes(
+    state: &AppState,
+    id: &str,
+    profile: String,
+    apply: bool,
+) -> axum::response::Response {
+    let (server_cfg, values) = {
+        let config = state.config.read().await;
+        let Some(server_cfg) = config.servers.iter().find(|s| s.id == id).cloned() else {
+            return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+                .into_response();
+        };
+        let Some(values) = config.agent.property_profiles.get(&profile).cloned() else {
+            return err_response(
+                StatusCode::NOT_FOUND,
+                format!("Property profile '{}' not found", profile),
+            )
+            .into_response();
+        };
+        (server_cfg, values)
+    };
+    if !server_cfg.flavor().has_world() {
+        return err_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("{} servers have no server.properties", server_cfg.flavor().as_str()),
+        )
+        .into_response();
+    }
+    let mut properties = match ServerProperties::load(&server_cfg.directory).await {
+        Ok(properties) => properties,
+        Err(e) => return err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    };
+    let changes = properties.diff(&values);
+    let applied = apply && !changes.is_empty();
+    if applied {
+        for change in &changes {
+            properties.set_text(&change.key, &change.to);
+        }
+        if let Err(e) = properties.save(&server_cfg.directory).await {
+            return err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+        }
+    }
+    let restart_required = !changes.is_empty() && state.servers.contains_key(id);
+    Json(PropertyProfileChanges {
+        profile,
+        changes,
+        applied,
+        restart_required,
+    })
+    .into_response()
+}
+
+/// Previews what applying a profile would change, without writing anything.
+#[utoipa::path(
+    get,
+    path = "/api/servers/{id}/property-profiles/{profile}",
+    tag = "servers",
+    params(
+        ("id" = String, Path, description = "Server id"),
+        ("profile" = String, Path, description = "Property profile"),
+    ),
+    responses(
+        (status = 200, description = "Settings the profile would change",
+            body = PropertyProfileChanges),
+        (status = 404, description = "Server or profile not found", body = ApiError),
+        (status = 422, description = "The server has no server.properties", body = ApiError),
+    )
+)]
+pub async fn preview_property_profile(
+    Path((id, profile)): Path<(String, String)>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    property_profile_changes(&state, &id, profile, false).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/servers/{id}/property-profiles/{profile}",
+    tag = "servers",
+    params(
+        ("id" = String, Path, description = "Server id"),
+        ("profile" = String, Path, description = "Property profile"),
+    ),
+    responses(
+        (status = 200, description = "Settings the profile changed", body = PropertyProfileChanges),
+        (status = 404, description = "Server or profile not found", body = ApiError),
+        (status = 422, description = "The server has no server.properties", body = ApiError),
+    )
+)]
+pub async fn apply_property_profile(
+    Path((id, profile)): Path<(String, String)>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    property_profile_changes(&state, &id, profile, true).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/servers/{id}/query",
+    tag = "servers",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "GameSpy4 query answer", body = crate::query::QueryStatus),
+        (status = 404, description = "Server not found", body = ApiError),
+    )
+)]
+pub async fn server_query(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let Some(server_cfg) = find_server_config(&state, &id).await else {
+        return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response();
+    };
+    if !state.servers.contains_key(&id) {
+        return err_response(StatusCode::CONFLICT, format!("Server '{}' is not running", id))
+            .into_response();
+    }
+    match crate::query::query(&server_cfg).await {
+        Ok(status) => Json(status).into_response(),
+        Err(e) => err_response(StatusCode::BAD_GATEWAY, e).into_response(),
+    }
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LocateQuery {
+    #[serde(default = "default_locate_kind")]
+    pub kind: String,
+    pub target: String,
+}
+
+fn default_locate_kind() -> String {
+    "structure".to_string()
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/servers/{id}/world/locate",
+    tag = "world",
+    params(
+        ("id" = String, Path, description = "Server id"),
+        LocateQuery,
+    ),
+    responses(
+        (status = 200, description = "Nearest match", body = world::LocateResult),
+        (status = 404, description = "Server not found", body = ApiError),
+    )
+)]
+pub async fn world_locate(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Query(query): Query<LocateQuery>,
+) -> impl IntoResponse {
+    let Some(server_cfg) = find_server_config(&state, &id).await else {
+        return err_response(StatusCode::NOT_FOUND, format!("Server '{}' not found", id))
+            .into_response();
+    };
+    if !state.servers.contains_key(&id) {
+        return err_response(StatusCode::CONFLICT, format!("Server '{}' is not running", id))
+            .into_response();
+    }
+    match world::locate(&state, &server_cfg, &query.kind, &query.target).await {
+        Ok(result) => Json(result).into_response(),
+        Err(e) => err_response(StatusCode::UNPROCESSABLE_ENTITY, e).into_response(),
+    }
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CanQuery {
+    pub action: String,
+    #[serde(default)]
+    pub server: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/auth/can",
+    tag = "auth",
+    params(CanQuery),
+    responses(
+        (status = 200, description = "Authorization decision", body = auth::Decision),
+        (status = 400, description = "Unknown action or server", body = ApiError),
+    )
+)]
+pub async fn auth_can(
+    State(state): State<AppState>,
+    caller: Option<Extension<ManagerToken>>,
+    session: Option<Extension<Session>>,
+    client_ip: Option<Extension<ClientIp>>,
+    Query(query): Query<CanQuery>,
+) -> impl IntoResponse {
+    let caller = match (caller, session) {
+        (Some(_), _) => auth::Caller::Manager,
+        (None, Some(Extension(s))) => auth::Caller::Session(s.username),
+        (None, None) => auth::Caller::Anonymous,
+    };
+    let client_ip = client_ip.map(|Extension(ClientIp(ip))| ip);
+    match auth::evaluate(&state, caller, client_ip, &query.action, query.server.as_deref()).await {
+        Ok(decision) => Json(decision).into_response(),
+        Err(e) => err_response(StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+/// Applies hand edits to the config file now instead of waiting for the watcher.
+#[utoipa::path(
+    post,
+    path = "/api/config/reload",
+    tag = "config",
+    responses(
+        (status = 200, description = "What was applied", body = reload::ReloadReport),
+        (status = 400, description = "Unreadable config file", body = ApiError),
+    )
+)]
+pub async fn reload_config(State(state): State<AppState>) -> impl IntoResponse {
+    match reload::reload(&state).await {
+        Ok(report) => Json(report).into_response(),
+        Err(e) => err_response(StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/runtimes",
+    tag = "runtimes",
+    responses(
+        (status = 200, description = "Managed Java runtimes", body = Vec<runtimes::RuntimeInfo>),
+    )
+)]
+pub async fn list_runtimes(State(state): State<AppState>) -> impl IntoResponse {
+    let data_directory = state.config.read().await.agent.data_directory.clone();
+    Json(runtimes::list_runtimes(&data_directory))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/runtimes/{version}",
+    tag = "runtimes",
+    params(("version" = u32, Path, description = "Java major version")),
+    responses(
+        (status = 200, description = "Installed", body = runtimes::RuntimeInfo),
+        (status = 502, description = "Download failed", body = ApiError),
+    )
+)]
+pub async fn install_runtime(
+    Path(version): Path<u32>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let data_directory = state.config.read().await.agent.data_directory.clone();
+    match runtimes::install_runtime(&data_directory, version).await {
+        Ok(info) => Json(info).into_response(),
+        Err(e) => err_response(StatusCode::BAD_GATEWAY, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/runtimes/{version}",
+    tag = "runtimes",
+    params(("version" = u32, Path, description = "Java major version")),
+    responses(
+        (status = 204, description = "Removed"),
+        (status = 409, description = "Runtime in use", body = ApiError),
+    )
+)]
+pub async fn remove_runtime(
+    Path(version): Path<u32>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let data_directory = state.config.read().await.agent.data_directory.clone();
+    {
+        let config = state.config.read().await;
+        let in_use = config.servers.iter().any(|s| {
+            s.java_version == Some(version)
+                && s.java_path.is_none()
+                && state.servers.contains_key(&s.id)
+        });
+        if in_use {
+            return err_response(StatusCode::CONFLICT, "Runtime is in use by a running server")
+                .into_response();
+        }
+    }
+    match runtimes::remove_runtime(&data_directory, version).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => err_response(StatusCode::NOT_FOUND, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/updates",
+    tag = "updates",
+    responses(
+        (status = 200, description = "Paper builds by rollout stage", body = rollout::RolloutState),
+    )
+)]
+pub async fn list_updates(State(state): State<AppState>) -> impl IntoResponse {
+    let rollout = state.rollout.lock().await;
+    Json(rollout.clone())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/updates/check",
+    tag = "updates",
+    responses(
+        (status = 202, description = "Update check started"),
+    )
+)]
+pub async fn check_updates(State(state): State<AppState>) -> impl IntoResponse {
+    tokio::spawn(async move { rollout::run_rollout_cycle(&state).await });
+    StatusCode::ACCEPTED
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/updates/{version}/{build}/promote",
+    tag = "updates",
+    params(
+        ("version" = String, Path, description = "Minecraft version"),
+        ("build" = u32, Path, description = "Paper build"),
+    ),
+    responses(
+        (status = 200, description = "Promoted", body = rollout::BuildStatus),
+        (status = 404, description = "Unknown build", body = ApiError),
+    )
+)]
+pub async fn promote_update(
+    Path((version, build)): Path<(String, u32)>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match rollout::promote_build(&state, &version, build).await {
+        Ok(status) => Json(status).into_response(),
+        Err(e) => err_response(StatusCode::NOT_FOUND, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/cache",
+    tag = "cache",
+    responses(
+        (status = 200, description = "Cached downloads", body = Vec<downloads::CacheEntry>),
+    )
+)]
+pub async fn list_cache(State(state): State<AppState>) -> impl IntoResponse {
+    let data_directory = state.config.read().await.agent.data_directory.clone();
+    match downloads::list_cache(&data_directory).await {
+        Ok(entries) => Json(entries).into_response(),
+        Err(e) => err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/cache",
+    tag = "cache",
+    responses(
+        (status = 204, description = "Cache cleared"),
+    )
+)]
+pub async fn clear_cache(State(state): State<AppState>) -> impl IntoResponse {
+    let data_directory = state.config.read().await.agent.data_directory.clone();
+    match downloads::clear_cache(&data_directory).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct WhitelistGroup {
+    pub group: String,
+    /// Ids of the servers sharing the whitelist.
+    pub members: Vec<String>,
+    pub players: Vec<whitelist::WhitelistEntry>,
+}
+
+fn validate_player(name: &str, uuid: &str) -> Result<(), &'static str> {
+    let valid_name = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    if name.is_empty() || name.len() > 16 || !name.chars().all(valid_name) {
+        return Err("name must be a Minecraft username");
+    }
+    let hex = uuid.chars().filter(|c| *c != '-').collect::<String>();
+    if hex.len() != 32 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("uuid must be a player UUID");
+    }
+    Ok(())
+}
+
+/// The group's members, or a 404 when no server is in it.
+async fn whitelist_members(
+    state: &AppState,
+    group: &str,
+) -> Result<Vec<String>, axum::response::Response> {
+    let members = whitelist::members(state, group).await;
+    if members.is_empty() {
+        return Err(err_response(
+            StatusCode::NOT_FOUND,
+            format!("No server is in whitelist group '{}'", group),
+        )
+        .into_response());
+    }
+    Ok(members)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/whitelist-groups/{group}",
+    tag = "whitelist",
+    params(("group" = String, Path, description = "Whitelist group")),
+    responses(
+        (status = 200, description = "Group members and whitelist", body = WhitelistGroup),
+        (status = 404, description = "No server is in the group", body = ApiError),
+    )
+)]
+pub async fn get_whitelist_group(
+    Path(group): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let members = match whitelist_members(&state, &group).await {
+        Ok(members) => members,
+        Err(response) => return response,
+    };
+    match whitelist::list(&state, &group).await {
+        Ok(players) => Json(WhitelistGroup {
+            group,
+            members,
+            players,
+        })
+        .into_response(),
+        Err(e) => err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+/// Whitelists a player on every server in the group.
+#[utoipa::path(
+    post,
+    path = "/api/whitelist-groups/{group}/players",
+    tag = "whitelist",
+    params(("group" = String, Path, description = "Whitelist group")),
+    request_body = whitelist::WhitelistEntry,
+    responses(
+        (status = 200, description = "The group's whitelist",
+            body = Vec<whitelist::WhitelistEntry>),
+        (status = 400, description = "Invalid player", body = ApiError),
+        (status = 404, description = "No server is in the group", body = ApiError),
+    )
+)]
+pub async fn add_whitelist_player(
+    Path(group): Path<String>,
+    State(state): State<AppState>,
+    Json(entry): Json<whitelist::WhitelistEntry>,
+) -> impl IntoResponse {
+    if let Err(e) = validate_player(&entry.name, &entry.uuid) {
+        return err_response(StatusCode::BAD_REQUEST, e).into_response();
+    }
+    if let Err(response) = whitelist_members(&state, &group).await {
+        return response;
+    }
+    match whitelist::apply(&state, &group, whitelist::Change::Add(entry)).await {
+        Ok(players) => Json(players).into_response(),
+        Err(e) => err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+/// Removes a player from the whitelist of every server in the group.
+#[utoipa::path(
+    delete,
+    path = "/api/whitelist-groups/{group}/players/{player}",
+    tag = "whitelist",
+    params(
+        ("group" = String, Path, description = "Whitelist group"),
+        ("player" = String, Path, description = "Player name or UUID"),
+    ),
+    responses(
+        (status = 204, description = "Player removed"),
+        (status = 404, description = "No such group or player", body = ApiError),
+    )
+)]
+pub async fn remove_whitelist_player(
+    Path((group, player)): Path<(String, String)>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    if let Err(response) = whitelist_members(&state, &group).await {
+        return response;
+    }
+    let listed = match whitelist::list(&state, &group).await {
+        Ok(players) => players.iter().any(|e| e.is(&player)),
+        Err(e) => return err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    };
+    if !listed {
+        return err_response(
+            StatusCode::NOT_FOUND,
+            format!("'{}' is not on the '{}' whitelist", player, group),
+        )
+        .into_response();
+    }
+    match whitelist::apply(&state, &group, whitelist::Change::Remove(player)).await {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct BanGroup {
+    pub group: String,
+    /// Ids of the servers sharing the ban list.
+    pub members: Vec<String>,
+    pub bans: Vec<bans::BanEntry>,
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct BanRequest {
+    pub name: String,
+    pub uuid: String,
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Who issued the ban, taken only from a manager forwarding it; anyone
+    /// else is recorded as the logged-in user.
+    #[serde(default)]
+    pub source: Option<String>,
+    /// When the ban was issued, kept as given only when a manager forwards it.
+    #[serde(default)]
+    pub created: Option<String>,
+    /// Defaults to "forever".
+    #[serde(default)]
+    pub expires: Option<String>,
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PardonQuery {
+    /// Who issued the pardon, taken only from a manager forwarding it;
+    /// anyone else is recorded as the logged-in user.
+    pub source: Option<String>,
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BanAuditQuery {
+    /// Most records returned, most recent first.
+    #[serde(default = "default_ban_audit_limit")]
+    pub limit: usize,
+}
+
+fn default_ban_audit_limit() -> usize {
+    100
+}
+
+/// Who made a ban or pardon through the API, and the origin recorded for it.
+fn ban_issuer(
+    caller: Option<Extension<ManagerToken>>,
+    session: Option<Extension<Session>>,
+) -> (String, &'static str) {
+    match (caller, session) {
+        (Some(_), _) => ("manager".to_string(), "manager"),
+        (None, Some(Extension(session))) => (session.username, "api"),
+        (None, None) => ("API".to_string(), "api"),
+    }
+}
+
+/// The group's members, or a 404 when no server is in it.
+async fn ban_members(
+    state: &AppState,
+    group: &str,
+) -> Result<Vec<String>, axum::response::Response> {
+    let members = bans::members(state, group).await;
+    if members.is_empty() {
+        return Err(err_response(
+            StatusCode::NOT_FOUND,
+            format!("No server is in ban group '{}'", group),
+        )
+        .into_response());
+    }
+    Ok(members)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/ban-groups/{group}",
+    tag = "bans",
+    params(("group" = String, Path, description = "Ban group")),
+    responses(
+        (status = 200, description = "Group members and bans", body = BanGroup),
+        (status = 404, description = "No server is in the group", body = ApiError),
+    )
+)]
+pub async fn get_ban_group(
+    Path(group): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let members = match ban_members(&state, &group).await {
+        Ok(members) => members,
+        Err(response) => return response,
+    };
+    match bans::list(&state, &group).await {
+        Ok(bans) => Json(BanGroup {
+            group,
+            members,
+            bans,
+        })
+        .into_response(),
+        Err(e) => err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+/// Bans a player on every server in the group, replacing an earlier ban.
+#[utoipa::path(
+    post,
+    path = "/api/ban-groups/{group}/bans",
+    tag = "bans",
+    params(("group" = String, Path, description = "Ban group")),
+    request_body = BanRequest,
+    responses(
+        (status = 200, description = "The group's bans", body = Vec<bans::BanEntry>),
+        (status = 400, description = "Invalid player", body = ApiError),
+        (status = 404, description = "No server is in the group", body = ApiError),
+    )
+)]
+pub async fn ban_player(
+    Path(group): Path<String>,
+    State(state): State<AppState>,
+    caller: Option<Extension<ManagerToken>>,
+    session: Option<Extension<Session>>,
+    Json(request): Json<BanRequest>,
+) -> impl IntoResponse {
+    if let Err(e) = validate_player(&request.name, &request.uuid) {
+        return err_response(StatusCode::BAD_REQUEST, e).into_response();
+    }
+    let single_line = |s: &Option<String>| s.as_ref().is_none_or(|s| !s.contains(['\n', '\r']));
+    if ![&request.reason, &request.source, &request.created, &request.expires]
+        .into_iter()
+        .all(single_line)
+    {
+        return err_response(StatusCode::BAD_REQUEST, "ban fields must be single lines")
+            .into_response();
+    }
+    if let Err(response) = ban_members(&state, &group).await {
+        return response;
+    }
+    // Only a manager forwarding a ban may say who issued it and when
+    let forwarded = caller.is_some();
+    let (issuer, origin) = ban_issuer(caller, session);
+    let mut entry = bans::BanEntry::new(
+        request.name,
+        request.uuid,
+        request.source.filter(|_| forwarded).unwrap_or(issuer),
+        request.reason,
+    );
+    if let Some(created) = request.created.filter(|_| forwarded) {
+        entry.created = created;
+    }
+    if let Some(expires) = request.expires {
+        entry.expires = expires;
+    }
+    match bans::apply(&state, &group, bans::Change::Ban(entry), origin).await {
+        Ok(bans) => Json(bans).into_response(),
+        Err(e) => err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+/// Pardons a player on every server in the group.
+#[utoipa::path(
+    delete,
+    path = "/api/ban-groups/{group}/bans/{player}",
+    tag = "bans",
+    params(
+        ("group" = String, Path, description = "Ban group"),
+        ("player" = String, Path, description = "Player name or UUID"),
+        PardonQuery,
+    ),
+    responses(
+        (status = 204, description = "Player pardoned"),
+        (status = 404, description = "No such group or ban", body = ApiError),
+    )
+)]
+pub async fn pardon_player(
+    Path((group, player)): Path<(String, String)>,
+    State(state): State<AppState>,
+    caller: Option<Extension<ManagerToken>>,
+    session: Option<Extension<Session>>,
+    Query(query): Query<PardonQuery>,
+) -> impl IntoResponse {
+    if let Err(response) = ban_members(&state, &group).await {
+        return response;
+    }
+    let banned = match bans::list(&state, &group).await {
+        Ok(bans) => bans.iter().any(|e| e.is(&player)),
+        Err(e) => return err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    };
+    if !banned {
+        return err_response(
+            StatusCode::NOT_FOUND,
+            format!("'{}' is not banned in '{}'", player, group),
+        )
+        .into_response();
+    }
+    let forwarded = caller.is_some();
+    let (issuer, origin) = ban_issuer(caller, session);
+    let change = bans::Change::Pardon {
+        player,
+        source: query.source.filter(|_| forwarded).unwrap_or(issuer),
+    };
+    match bans::apply(&state, &group, change, origin).await {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+/// Bans and pardons in the group, with who issued each and where.
+#[utoipa::path(
+    get,
+    path = "/api/ban-groups/{group}/audit",
+    tag = "bans",
+    params(("group" = String, Path, description = "Ban group"), BanAuditQuery),
+    responses(
+        (status = 200, description = "Audit records, most recent first",
+            body = Vec<bans::BanAudit>),
+        (status = 404, description = "No server is in the group", body = ApiError),
+    )
+)]
+pub async fn ban_audit(
+    Path(group): Path<String>,
+    State(state): State<AppState>,
+    Query(query): Query<BanAuditQuery>,
+) -> impl IntoResponse {
+    if let Err(response) = ban_members(&state, &group).await {
+        return response;
+    }
+    let data_directory = state.config.read().await.agent.data_directory.clone();
+    match bans::audit(&data_directory, &group, query.limit).await {
+        Ok(records) => Json(records).into_response(),
+        Err(e) => err_response(StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct PromptAnswer {
+    pub answer: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/servers/{id}/prompt",
+    tag = "console",
+    params(("id" = String, Path, description = "Server id")),
+    request_body = PromptAnswer,
+    responses(
+        (status = 200, description = "Answer sent"),
+        (status = 400, description = "No prompt pending", body = ApiError),
+    )
+)]
+pub async fn prompt_handler(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Json(input): Json<PromptAnswer>,
+) -> impl IntoResponse {
+    match answer_prompt(&state, &id, &input.answer).await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(e) => err_response(StatusCode::CONFLICT, e).into_response(),
+    }
+}
+
+/// Application-level reasons a console or metrics socket is refused or closed.
+/// Each one is sent as a JSON error frame followed by a close frame carrying
+/// the matching code, so clients can decide whether reconnecting makes sense.
+#[derive(Debug, Clone, Copy)]
+pub enum WsError {
+    /// Retry later: the server is not running yet.
+    NotRunning,
+    /// Give up: the caller is not allowed to open this socket.
+    #[allow(dead_code)]
+    Unauthorized,
+    /// Retry later: the server exited while the socket was open.
+    ServerStoppedMidStream,
+    /// Reconnect now: the client fell too far behind and missed messages.
+    Lagged,
+}
+
+impl WsError {
+    pub fn code(self) -> &'static str {
+        match self {
+            WsError::NotRunning => "not_running",
+            WsError::Unauthorized => "unauthorized",
+            WsError::ServerStoppedMidStream => "server_stopped_mid_stream",
+            WsError::Lagged => "lagged",
+        }
+    }
+
+    pub fn close_code(self) -> u16 {
+        match self {
+            WsError::NotRunning => 4004,
+            WsError::Unauthorized => 4001,
+            WsError::ServerStoppedMidStream => 4010,
+            WsError::Lagged => 4008,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct WsErrorFrame {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    code: &'static str,
+    message: String,
+}
+
+/// The error frame and the close frame that follows it.
+fn error_messages(err: WsError, message: impl Into<String>) -> [Message; 2] {
+    let frame = WsErrorFrame {
+        kind: "error",
+        code: err.code(),
+        message: message.into(),
+    };
+    let json = serde_json::to_string(&frame).unwrap_or_default();
+    [
+        Message::Text(json.into()),
+        Message::Close(Some(CloseFrame {
+            code: err.close_code(),
+            reason: err.code().into(),
+        })),
+    ]
+}
+
+async fn close_with_error(socket: &mut WebSocket, err: WsError, message: impl Into<String>) {
+    for message in error_messages(err, message) {
+        let _ = socket.send(message).await;
+    }
+}
+
+pub(crate) async fn wait_for_exit(exited_rx: &mut watch::Receiver<bool>) {
+    let _ = exited_rx.wait_for(|exited| *exited).await;
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MetricsQuery {
+    /// Range start in epoch milliseconds; defaults to one hour ago.
+    #[serde(default)]
+    pub from: Option<u64>,
+    /// Range end in epoch milliseconds; defaults to now.
+    #[serde(default)]
+    pub to: Option<u64>,
+    /// Bucket size in seconds.
+    #[serde(default)]
+    pub step: Option<u64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/servers/{id}/metrics",
+    tag = "metrics",
+    params(
+        ("id" = String, Path, description = "Server id"),
+        MetricsQuery,
+    ),
+    responses(
+        (status = 200, description = "Stored samples", body = Vec<history::Sample>),
+        (status = 404, description = "Server not found", body = ApiError),
+    )
+)]
+pub async fn metrics_history(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Query(query): Query<MetricsQuery>,
+) -> impl IntoResponse {
+    let (data_directory, from, to, step_ms) = match metrics_range(&state, &id, &query).await {
+        Ok(range) => range,
+        Err(response) => return response,
+    };
+    Json(history::query(&data_directory, &id, from, to, step_ms).await).into_response()
+}
+
+/// Online player counts over time, from the stored metrics samples. The
+/// range reaches back at most `metrics_retention_hours`.
+#[utoipa::path(
+    get,
+    path = "/api/servers/{id}/players/history",
+    tag = "players",
+    params(
+        ("id" = String, Path, description = "Server id"),
+        MetricsQuery,
+    ),
+    responses(
+        (status = 200, description = "Average and peak players per bucket",
+            body = Vec<history::PlayerCountPoint>),
+        (status = 404, description = "Server not found", body = ApiError),
+    )
+)]
+pub async fn player_history(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Query(query): Query<MetricsQuery>,
+) -> impl IntoResponse {
+    let (data_directory, from, to, step_ms) = match metrics_range(&state, &id, &query).await {
+        Ok(range) => range,
+        Err(response) => return response,
+    };
+    Json(history::player_counts(&data_directory, &id, from, to, step_ms).await).into_response()
+}
+
+/// The data directory and the range and step in milliseconds of a history
+/// query against server `id`.
+async fn metrics_range(
+    state: &AppState,
+    id: &str,
+    query: &MetricsQuery,
+) -> Result<(String, u64, u64, Option<u64>), axum::response::Response> {
+    let data_directory = {
+        let config = state.config.read().await;
+        if !config.servers.iter().any(|s| s.id == id) {
+            return Err(err_response(
+                StatusCode::NOT_FOUND,
+                format!("Server '{}' not found", id),
+            )
+            .into_response());
+        }
+        config.agent.data_directory.clone()
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let to = query.to.unwrap_or(now);
+    let from = query.from.unwrap_or(to.saturating_sub(60 * 60 * 1000));
+    if from > to {
+        return Err(
+            err_response(StatusCode::BAD_REQUEST, "from must not be after to").into_response(),
+        );
+    }
+    Ok((data_directory, from, to, query.step.map(|s| s.saturating_mul(1000))))
+}
+
+/// The node running `id` when it is not one of this agent's servers.
+async fn remote_node(state: &AppState, id: &str) -> Option<NodeConfig> {
+    if find_server_config(state, id).await.is_some() {
+        return None;
+    }
+    nodes::locate(state, id).await
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ConsoleWsQuery {
+    /// What happens to ANSI escape codes in console lines.
+    #[serde(default)]
+    pub ansi: ansi::AnsiMode,
+    #[serde(default)]
+    pub format: ConsoleFormat,
+    /// Only lines at this level or above. Lines without a level of their
+    /// own, like stack traces, go with the line before them.
+    pub level: Option<LogLevel>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ConsoleFormat {
+    /// One frame per line, as plain text unless `ansi=spans`.
+    #[default]
+    Lines,
+    /// `{"type":"lines","lines":[...]}` frames holding every line printed
+    /// within a short window, each with its timestamp and stream.
+    Batch,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/servers/{id}/console/ws",
+    tag = "console",
+    params(
+        ("id" = String, Path, description = "Server id"),
+        ConsoleWsQuery,
+    ),
+    responses(
+        (status = 101, description = "WebSocket of console lines that accepts commands"),
+    )
+)]
+pub async fn console_ws(
+    ws: WebSocketUpgrade,
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Query(query): Query<ConsoleWsQuery>,
+    RawQuery(raw_query): RawQuery,
+    ClientKey(client): ClientKey,
+) -> impl IntoResponse {
+    let ws = ws.protocols([nodeauth::WS_PROTOCOL]);
+    if let Some(node) = remote_node(&state, &id).await {
+        let mut path = format!("/api/servers/{}/console/ws", id);
+        if let Some(raw_query) = raw_query {
+            path = format!("{}?{}", path, raw_query);
+        }
+        return ws.on_upgrade(move |socket| nodes::proxy_ws(socket, node, path));
+    }
+    ws.on_upgrade(move |socket| handle_console_ws(socket, id, state, client, query))
+}
+
+/// Frames queued for one console viewer. A viewer whose connection can't
+/// keep up misses lines instead of holding up the stream for the others.
+const CONSOLE_SEND_BUFFER: usize = 1024;
+/// How long queued frames may take to go out once the stream ends.
+const CONSOLE_DRAIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+#[derive(Serialize)]
+struct WsSkippedFrame {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    lines: u64,
+}
+
+/// How long a batch stays open for more lines after its first one.
+const CONSOLE_BATCH_WINDOW: std::time::Duration = std::time::Duration::from_millis(50);
+/// Lines after which a batch goes out without waiting for the window.
+const CONSOLE_BATCH_MAX_LINES: usize = 500;
+
+#[derive(Serialize)]
+struct WsConsoleLine {
+    timestamp_ms: u64,
+    stream: ConsoleStream,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    level: Option<LogLevel>,
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spans: Option<Vec<ansi::Span>>,
+}
+
+#[derive(Serialize)]
+struct WsLinesFrame {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    lines: Vec<WsConsoleLine>,
+}
+
+/// Turns console lines into frames for one viewer, in the format it asked for.
+struct ConsoleFramer {
+    format: ConsoleFormat,
+    ansi: ansi::AnsiMode,
+    min_level: Option<LogLevel>,
+    /// The level of the last line that had one.
+    last_level: LogLevel,
+    pending: Vec<WsConsoleLine>,
+    /// When the open batch is due to go out.
+    deadline: Option<tokio::time::Instant>,
+}
+
+impl ConsoleFramer {
+    fn new(query: &ConsoleWsQuery) -> Self {
+        Self {
+            format: query.format,
+            ansi: query.ansi,
+            min_level: query.level,
+            last_level: LogLevel::Info,
+            pending: Vec::new(),
+            deadline: None,
+        }
+    }
+
+    /// The frame that is ready once `line` is added, with how many lines
+    /// it holds.
+    fn add(&mut self, line: ConsoleLine) -> Option<(String, u64)> {
+        if let Some(level) = line.level {
+            self.last_level = level;
+        }
+        if self.min_level.is_some_and(|min| self.last_level < min) {
+            return None;
+        }
+        if self.format == ConsoleFormat::Lines {
+            return Some((self.ansi.frame(line.text), 1));
+        }
+        let (text, spans) = self.ansi.apply(line.text);
+        self.pending.push(WsConsoleLine {
+            timestamp_ms: line.timestamp_ms,
+            stream: line.stream,
+            level: line.level,
+            text,
+            spans,
+        });
+        self.deadline
+            .get_or_insert_with(|| tokio::time::Instant::now() + CONSOLE_BATCH_WINDOW);
+        if self.pending.len() >= CONSOLE_BATCH_MAX_LINES {
+            return self.take();
+        }
+        None
+    }
+
+    /// The open batch as a frame.
+    fn take(&mut self) -> Option<(String, u64)> {
+        self.deadline = None;
+        if self.pending.is_empty() {
+            return None;
+        }
+        let frame = WsLinesFrame {
+            kind: "lines",
+            lines: std::mem::take(&mut self.pending),
+        };
+        let lines = frame.lines.len() as u64;
+        Some((serde_json::to_string(&frame).unwrap_or_default(), lines))
+    }
+}
+
+/// Queues console frames for the socket writer, counting the lines that
+/// didn't fit and reporting them before the next frame that does.
+struct ConsoleOutbox {
+    tx: mpsc::Sender<Message>,
+    skipped: u64,
+}
+
+impl ConsoleOutbox {
+    /// False once the socket is gone.
+    fn push(&mut self, frame: String, lines: u64) -> bool {
+        if self.skipped > 0 {
+            let marker = WsSkippedFrame {
+                kind: "skipped",
+                lines: self.skipped,
+            };
+            let marker = serde_json::to_string(&marker).unwrap_or_default();
+            match self.tx.try_send(Message::Text(marker.into())) {
+                Ok(()) => self.skipped = 0,
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    self.skipped += lines;
+                    return true;
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => return false,
+            }
+        }
+        match self.tx.try_send(Message::Text(frame.into())) {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.skipped += lines;
+                true
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+        }
+    }
+
+    /// A client too far behind to take these is dropped without them.
+    fn close_with_error(&self, err: WsError, message: impl Into<String>) {
+        for message in error_messages(err, message) {
+            if self.tx.try_send(message).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+async fn handle_console_ws(
+    mut socket: WebSocket,
+    id: String,
+    state: AppState,
+    client: String,
+    query: ConsoleWsQuery,
+) {
+    let instance = match state.servers.get(&id).map(|r| r.value().clone()) {
+        Some(i) => i,
+        None => {
+            close_with_error(&mut socket, WsError::NotRunning, "Server is not running").await;
+            return;
+        }
+    };
+
+    let (mut sink, mut stream) = socket.split();
+    let (tx, mut rx) = mpsc::channel::<Message>(CONSOLE_SEND_BUFFER);
+    let mut writer = tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            if sink.send(message).await.is_err() {
+                break;
+            }
+        }
+    });
+    let mut outbox = ConsoleOutbox { tx, skipped: 0 };
+    let mut framer = ConsoleFramer::new(&query);
+
+    // Send buffered lines
+    {
+        let buf = instance.console_buffer.lock().await;
+        let recent: Vec<ConsoleLine> = buf
+            .iter()
+            .rev()
+            .take(instance.console_replay_lines)
+            .cloned()
+            .collect();
+        let mut frames: Vec<(String, u64)> =
+            recent.into_iter().rev().filter_map(|line| framer.add(line)).collect();
+        frames.extend(framer.take());
+        for (frame, _) in frames {
+            if outbox.tx.send(Message::Text(frame.into())).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    let mut console_rx = instance.console_tx.subscribe();
+    let mut exited_rx = instance.exited.subscribe();
+
+    loop {
+        tokio::select! {
+            msg = console_rx.recv() => {
+                match msg {
+                    Ok(line) => {
+                        if let Some((frame, lines)) = framer.add(line) {
+                            if !outbox.push(frame, lines) {
+                                break;
+                            }
+                        }
+                    }
+                    // Picks up at the newest line, skipping what was left
+                    Err(RecvError::Lagged(n)) => {
+                        outbox.skipped += n + console_rx.len() as u64;
+                        console_rx = console_rx.resubscribe();
+                    }
+                    Err(RecvError::Closed) => {
+                        if let Some((frame, lines)) = framer.take() {
+                            outbox.push(frame, lines);
+                        }
+                        outbox.close_with_error(WsError::ServerStoppedMidStream, "Server stopped");
+                        break;
+                    }
+                }
+            }
+            _ = tokio::time::sleep_until(framer.deadline.unwrap_or_else(tokio::time::Instant::now)),
+                if framer.deadline.is_some() =>
+            {
+                if let Some((frame, lines)) = framer.take() {
+                    if !outbox.push(frame, lines) {
+                        break;
+                    }
+                }
+            }
+            _ = wait_for_exit(&mut exited_rx) => {
+                if let Some((frame, lines)) = framer.take() {
+                    outbox.push(frame, lines);
+                }
+                outbox.close_with_error(WsError::ServerStoppedMidStream, "Server stopped");
+                break;
+            }
+            ws_msg = stream.next() => {
+                match ws_msg {
+                    Some(Ok(Message::Text(text))) => {
+                        #[derive(Deserialize)]
+                        struct WsCommand {
+                            #[serde(rename = "type")]
+                            kind: String,
+                            data: String,
+                        }
+                        if let Ok(cmd) = serde_json::from_str::<WsCommand>(&text) {
+                            if cmd.kind == "command" {
+                                let mut refused = match maintenance::check(&state).await {
+                                    Err(message) => Some(("maintenance", message)),
+                                    Ok(()) => state
+                                        .rate_limiter
+                                        .check_configured(&state, &client, Class::Commands)
+                                        .await
+                                        .err()
+                                        .map(|message| ("rate_limited", message)),
+                                };
+                                // One frame is one command: line breaks are refused
+                                if refused.is_none() {
+                                    refused = send_command(&state, &id, &cmd.data)
+                                        .await
+                                        .err()
+                                        .map(|message| ("command_failed", message));
+                                }
+                                // Dropped, but the socket stays open
+                                if let Some((code, message)) = refused {
+                                    let frame = WsErrorFrame {
+                                        kind: "error",
+                                        code,
+                                        message,
+                                    };
+                                    let text = serde_json::to_string(&frame).unwrap_or_default();
+                                    let _ = outbox.tx.try_send(Message::Text(text.into()));
+                                }
+                            }
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | None => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    // Lets the writer get out what is queued, unless the client has stalled
+    drop(outbox);
+    if tokio::time::timeout(CONSOLE_DRAIN_TIMEOUT, &mut writer).await.is_err() {
+        writer.abort();
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/events/ws",
+    tag = "events",
+    responses(
+        (status = 101, description = "WebSocket of agent events"),
+    )
+)]
+pub async fn events_ws(ws: WebSocketUpgrade, State(state): State<AppState>) -> impl IntoResponse {
+    let ws = ws.protocols([nodeauth::WS_PROTOCOL]);
+    ws.on_upgrade(move |socket| handle_events_ws(socket, state))
+}
+
+async fn handle_events_ws(mut socket: WebSocket, state: AppState) {
+    let mut events_rx = state.events_tx.subscribe();
+    loop {
+        tokio::select! {
+            event = events_rx.recv() => {
+                match event {
+                    Ok(event) => {
+                        if let Ok(json) = serde_json::to_string(&event) {
+                            if socket.send(Message::Text(json.into())).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    Err(RecvError::Lagged(n)) => {
+                        close_with_error(
+                            &mut socket,
+                            WsError::Lagged,
+                            format!("Events consumer fell behind by {} events", n),
+                        )
+                        .await;
+                        break;
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+            ws_msg = socket.recv() => {
+                match ws_msg {
+                    Some(Ok(Message::Close(_))) | None => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/servers/{id}/metrics/ws",
+    tag = "metrics",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 101, description = "WebSocket of live metrics samples"),
+    )
+)]
+pub async fn metrics_ws(
+    ws: WebSocketUpgrade,
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let ws = ws.protocols([nodeauth::WS_PROTOCOL]);
+    if let Some(node) = remote_node(&state, &id).await {
+        let path = format!("/api/servers/{}/metrics/ws", id);
+        return ws.on_upgrade(move |socket| nodes::proxy_ws(socket, node, path));
+    }
+    ws.on_upgrade(move |socket| handle_metrics_ws(socket, id, state))
+}
+
+async fn handle_metrics_ws(mut socket: WebSocket, id: String, state: AppState) {
+    let instance = match state.servers.get(&id).map(|r| r.value().clone()) {
+        Some(i) => i,
+        None => {
+            close_with_error(&mut socket, WsError::NotRunning, "Server is not running").await;
+            return;
+        }
+    };
+
+    let mut metrics_rx = instance.metrics_tx.subscribe();
+    let mut exited_rx = instance.exited.subscribe();
+    loop {
+        tokio::select! {
+            msg = metrics_rx.recv() => {
+                match msg {
+                    Ok(metrics) => {
+                        if let Ok(json) = serde_json::to_string(&metrics) {
+                            if socket.send(Message::Text(json.into())).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    Err(RecvError::Lagged(n)) => {
+                        close_with_error(
+                            &mut socket,
+                            WsError::Lagged,
+                            format!("Metrics consumer fell behind by {} samples", n),
+                        )
+                        .await;
+                        break;
+                    }
+                    Err(RecvError::Closed) => {
+                        close_with_error(&mut socket, WsError::ServerStoppedMidStream, "Server stopped").await;
+                        break;
+                    }
+                }
+            }
+            _ = wait_for_exit(&mut exited_rx) => {
+                close_with_error(&mut socket, WsError::ServerStoppedMidStream, "Server stopped").await;
+                break;
+            }
+            ws_msg = socket.recv() => {
+                match ws_msg {
+                    Some(Ok(Message::Close(_))) | None => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+fn sse_error(err: WsError, message: impl Into<String>) -> Event {
+    let frame = WsErrorFrame {
+        kind: "error",
+        code: err.code(),
+        message: message.into(),
+    };
+    Event::default()
+        .event("error")
+        .data(serde_json::to_string(&frame).unwrap_or_default())
+}
+
+/// Relays a broadcast channel as events until the server exits or the
+/// client falls behind, ending with the error the websocket would close with.
+fn sse_events<T, F>(
+    rx: broadcast::Receiver<T>,
+    exited_rx: watch::Receiver<bool>,
+    consumer: &'static str,
+    to_event: F,
+) -> impl Stream<Item = Result<Event, Infallible>> + Send
+where
+    T: Clone + Send + 'static,
+    F: Fn(T) -> Event + Send + 'static,
+{
+    futures_util::stream::unfold(Some((rx, exited_rx, to_event)), move |open| async move {
+        let (mut rx, mut exited_rx, to_event) = open?;
+        let last = tokio::select! {
+            msg = rx.recv() => match msg {
+                Ok(item) => {
+                    let event = to_event(item);
+                    return Some((Ok(event), Some((rx, exited_rx, to_event))));
+                }
+                Err(RecvError::Lagged(n)) => sse_error(
+                    WsError::Lagged,
+                    format!("{} consumer fell behind by {} messages", consumer, n),
+                ),
+                Err(RecvError::Closed) => {
+                    sse_error(WsError::ServerStoppedMidStream, "Server stopped")
+                }
+            },
+            _ = wait_for_exit(&mut exited_rx) => {
+                sse_error(WsError::ServerStoppedMidStream, "Server stopped")
+            }
+        };
+        Some((Ok(last), None))
+    })
+}
+
+fn sse_response<S>(events: S) -> axum::response::Response
+where
+    S: Stream<Item = Result<Event, Infallible>> + Send + 'static,
+{
+    // Keeps nginx and similar proxies from buffering the stream
+    (
+        [("x-accel-buffering", "no")],
+        Sse::new(events).keep_alive(KeepAlive::default()),
+    )
+        .into_response()
+}
+
+/// Console lines as Server-Sent Events, for clients whose proxies break
+/// websockets. Lines are `message` events; the stream ends with an `error`
+/// event carrying the same code as the websocket close.
+#[utoipa::path(
+    get,
+    path = "/api/servers/{id}/console/sse",
+    tag = "console",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "Console lines", content_type = "text/event-stream"),
+    )
+)]
+pub async fn console_sse(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let Some(instance) = state.servers.get(&id).map(|r| r.value().clone()) else {
+        let not_running = sse_error(WsError::NotRunning, "Server is not running");
+        return sse_response(futures_util::stream::iter([Ok(not_running)]));
+    };
+    let recent: Vec<ConsoleLine> = {
+        let buf = instance.console_buffer.lock().await;
+        let mut recent: Vec<ConsoleLine> =
+            buf.iter().rev().take(instance.console_replay_lines).cloned().collect();
+        recent.reverse();
+        recent
+    };
+    let replay =
+        futures_util::stream::iter(recent).map(|line| Ok(Event::default().data(line.text)));
+    let live = sse_events(
+        instance.console_tx.subscribe(),
+        instance.exited.subscribe(),
+        "Console",
+        |line: ConsoleLine| Event::default().data(line.text),
+    );
+    sse_response(replay.chain(live))
+}
+
+/// Live metrics samples as Server-Sent Events with JSON `message` payloads.
+#[utoipa::path(
+    get,
+    path = "/api/servers/{id}/metrics/sse",
+    tag = "metrics",
+    params(("id" = String, Path, description = "Server id")),
+    responses(
+        (status = 200, description = "Metrics samples", content_type = "text/event-stream"),
+    )
+)]
+pub async fn metrics_sse(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let Some(instance) = state.servers.get(&id).map(|r| r.value().clone()) else {
+        let not_running = sse_error(WsError::NotRunning, "Server is not running");
+        return sse_response(futures_util::stream::iter([Ok(not_running)]));
+    };
+    sse_response(sse_events(
+        instance.metrics_tx.subscribe(),
+        instance.exited.subscribe(),
+        "Metrics",
+        |metrics: crate::state::Metrics| {
+            Event::default().data(serde_json::to_string(&metrics).unwrap_or_default())
+        },
+    ))
 }
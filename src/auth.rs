@@ -0,0 +1,207 @@
+//! Permission evaluation for `/api/auth/can`, replaying the rules the
+//! request layers enforce.
+
+use std::net::IpAddr;
+
+use serde::Serialize;
+
+use crate::{config::IpFilterConfig, ipfilter, state::AppState};
+
+/// Every action the API can authorize, named `<resource>.<verb>`.
+pub const ACTIONS: &[&str] = &[
+    "servers.list",
+    "servers.create",
+    "servers.update",
+    "servers.delete",
+    "servers.start",
+    "servers.stop",
+    "servers.restart",
+    "servers.backup",
+    "servers.console",
+    "servers.metrics",
+    "servers.chat",
+    "servers.macros",
+    "servers.files",
+    "node.read",
+    "node.manage",
+];
+
+/// Actions served by GET requests, which maintenance mode leaves open.
+const READ_ACTIONS: &[&str] = &["servers.list", "servers.metrics", "node.read"];
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct Decision {
+    pub allowed: bool,
+    pub action: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    /// The rule that decided the outcome.
+    pub rule: &'static str,
+    pub reason: String,
+}
+
+/// How `nodeauth::require_manager` let the request in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Caller {
+    Manager,
+    Session(String),
+    Anonymous,
+}
+
+/// What the request layers decide on.
+pub struct Context<'a> {
+    pub caller: Caller,
+    /// Whether manager tokens or users are configured.
+    pub auth_required: bool,
+    /// The reason, while maintenance mode is on.
+    pub maintenance: Option<&'a str>,
+    pub client_ip: Option<IpAddr>,
+    pub ip_filter: &'a IpFilterConfig,
+}
+
+/// Checks the rules in the order the layers run them: the address filter,
+/// authentication, then the maintenance guard. The first refusal decides;
+/// when nothing refuses, the rule that authenticated the caller does.
+pub fn decide(ctx: &Context, action: &str) -> (bool, &'static str, String) {
+    if let Some(ip) = ctx.client_ip {
+        if !ipfilter::permitted(ctx.ip_filter, ip) {
+            return (false, "ip_filter", format!("address {} is not allowed", ip));
+        }
+    }
+    let (rule, reason) = match &ctx.caller {
+        Caller::Manager => ("manager_token", "the caller holds a manager token".to_string()),
+        Caller::Session(user) => ("session", format!("the caller is logged in as '{}'", user)),
+        Caller::Anonymous if ctx.auth_required => {
+            return (
+                false,
+                "authentication",
+                "a manager token or login session is required".to_string(),
+            );
+        }
+        Caller::Anonymous => (
+            "auth_disabled",
+            "the agent has no authentication configured".to_string(),
+        ),
+    };
+    if let Some(why) = ctx.maintenance {
+        if !READ_ACTIONS.contains(&action) {
+            return (false, "maintenance", format!("the agent is in maintenance mode: {}", why));
+        }
+    }
+    (true, rule, reason)
+}
+
+/// Decides whether `caller`, connecting from `client_ip`, may perform
+/// `action`, optionally on `server`.
+pub async fn evaluate(
+    state: &AppState,
+    caller: Caller,
+    client_ip: Option<IpAddr>,
+    action: &str,
+    server: Option<&str>,
+) -> Result<Decision, String> {
+    if !ACTIONS.contains(&action) {
+        return Err(format!("unknown action '{}'", action));
+    }
+    let maintenance = state.maintenance.read().await.as_ref().map(|m| m.reason.clone());
+    let config = state.config.read().await;
+    if let Some(id) = server {
+        if !config.servers.iter().any(|s| s.id == id) {
+            return Err(format!("Server '{}' not found", id));
+        }
+    }
+    let ctx = Context {
+        caller,
+        auth_required: !config.agent.manager_tokens.is_empty() || !config.agent.users.is_empty(),
+        maintenance: maintenance.as_deref(),
+        client_ip,
+        ip_filter: &config.agent.ip_filter,
+    };
+    let (allowed, rule, reason) = decide(&ctx, action);
+    Ok(Decision {
+        allowed,
+        action: action.to_string(),
+        server: server.map(str::to_string),
+        rule,
+        reason,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(caller: Caller, ip_filter: &IpFilterConfig) -> Context<'_> {
+        Context {
+            caller,
+            auth_required: true,
+            maintenance: None,
+            client_ip: Some("10.0.0.5".parse().unwrap()),
+            ip_filter,
+        }
+    }
+
+    #[test]
+    fn manager_token_allows() {
+        let filter = IpFilterConfig::default();
+        let (allowed, rule, _) = decide(&context(Caller::Manager, &filter), "servers.start");
+        assert!(allowed);
+        assert_eq!(rule, "manager_token");
+    }
+
+    #[test]
+    fn session_allows() {
+        let filter = IpFilterConfig::default();
+        let ctx = context(Caller::Session("alice".to_string()), &filter);
+        let (allowed, rule, reason) = decide(&ctx, "servers.start");
+        assert!(allowed);
+        assert_eq!(rule, "session");
+        assert!(reason.contains("alice"));
+    }
+
+    #[test]
+    fn anonymous_needs_credentials_once_auth_is_configured() {
+        let filter = IpFilterConfig::default();
+        let mut ctx = context(Caller::Anonymous, &filter);
+        assert_eq!(decide(&ctx, "servers.list").1, "authentication");
+        assert!(!decide(&ctx, "servers.list").0);
+
+        ctx.auth_required = false;
+        let (allowed, rule, _) = decide(&ctx, "servers.list");
+        assert!(allowed);
+        assert_eq!(rule, "auth_disabled");
+    }
+
+    #[test]
+    fn maintenance_refuses_changes_but_not_reads() {
+        let filter = IpFilterConfig::default();
+        let mut ctx = context(Caller::Manager, &filter);
+        ctx.maintenance = Some("disk swap");
+        let (allowed, rule, reason) = decide(&ctx, "servers.stop");
+        assert!(!allowed);
+        assert_eq!(rule, "maintenance");
+        assert!(reason.contains("disk swap"));
+        assert_eq!(decide(&ctx, "servers.list").1, "manager_token");
+    }
+
+    #[test]
+    fn ip_filter_refuses_denied_addresses() {
+        let filter = IpFilterConfig {
+            deny: vec!["10.0.0.0/8".to_string()],
+            ..Default::default()
+        };
+        let (allowed, rule, _) = decide(&context(Caller::Manager, &filter), "servers.list");
+        assert!(!allowed);
+        assert_eq!(rule, "ip_filter");
+    }
+
+    #[test]
+    fn the_ip_filter_is_checked_before_authentication() {
+        let filter = IpFilterConfig {
+            deny: vec!["10.0.0.0/8".to_string()],
+            ..Default::default()
+        };
+        let (_, rule, _) = decide(&context(Caller::Anonymous, &filter), "servers.list");
+        assert_eq!(rule, "ip_filter");
+    }
+}
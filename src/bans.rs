@@ -0,0 +1,559 @@
+//! Ban groups: servers sharing a `ban_group` share one ban list, kept in
+//! `data_directory/bans/<group>.json`. Bans and pardons made on any member,
+//! in game or through the API, reach the others, and a manager carries them
+//! to the same group on its nodes. Each one is recorded in
+//! `<group>.audit.jsonl` with who issued it and where.
+//!
+//! A player banned on one member and pardoned on another between two syncs
+//! stays banned, and a player banned twice keeps the first ban. Stopped
+//! members get a new `banned-players.json`; running ones keep theirs in
+//! memory, so they are sent `ban` and `pardon` commands instead.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    config::NodeConfig,
+    nodes,
+    process::send_command,
+    state::AppState,
+    whitelist::{read_list, write_list},
+};
+
+const SYNC_INTERVAL: Duration = Duration::from_secs(5);
+const BANS_FILE: &str = "banned-players.json";
+
+/// One `banned-players.json` entry, in the server's own format.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct BanEntry {
+    #[serde(default)]
+    pub uuid: String,
+    pub name: String,
+    #[serde(default = "now_created")]
+    pub created: String,
+    /// Who issued the ban: a player, `Server` for the console, or an API user.
+    #[serde(default = "default_source")]
+    pub source: String,
+    #[serde(default = "default_expires")]
+    pub expires: String,
+    #[serde(default = "default_reason")]
+    pub reason: String,
+}
+
+fn now_created() -> String {
+    chrono::Local::now().format("%Y-%m-%d %H:%M:%S %z").to_string()
+}
+
+fn default_source() -> String {
+    "Server".to_string()
+}
+
+fn default_expires() -> String {
+    "forever".to_string()
+}
+
+fn default_reason() -> String {
+    "Banned by an operator.".to_string()
+}
+
+impl BanEntry {
+    pub fn new(name: String, uuid: String, source: String, reason: Option<String>) -> Self {
+        Self {
+            uuid,
+            name,
+            created: now_created(),
+            source,
+            expires: default_expires(),
+            reason: reason.unwrap_or_else(default_reason),
+        }
+    }
+
+    fn key(&self) -> String {
+        if self.uuid.is_empty() {
+            self.name.to_lowercase()
+        } else {
+            self.uuid.to_lowercase()
+        }
+    }
+
+    /// Whether `player` is this entry's name or UUID.
+    pub fn is(&self, player: &str) -> bool {
+        self.name.eq_ignore_ascii_case(player) || self.uuid.eq_ignore_ascii_case(player)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BanAction {
+    Ban,
+    Pardon,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct BanAudit {
+    pub timestamp_ms: u64,
+    pub action: BanAction,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub uuid: String,
+    /// Who issued it; unknown for pardons made in game.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Where it was made: a server id, `api`, `manager` or `node:<id>`.
+    pub origin: String,
+}
+
+impl BanAudit {
+    fn ban(entry: &BanEntry, origin: &str) -> Self {
+        Self {
+            timestamp_ms: chrono::Utc::now().timestamp_millis() as u64,
+            action: BanAction::Ban,
+            name: entry.name.clone(),
+            uuid: entry.uuid.clone(),
+            source: Some(entry.source.clone()),
+            reason: Some(entry.reason.clone()),
+            origin: origin.to_string(),
+        }
+    }
+
+    fn pardon(entry: &BanEntry, source: Option<String>, origin: &str) -> Self {
+        Self {
+            timestamp_ms: chrono::Utc::now().timestamp_millis() as u64,
+            action: BanAction::Pardon,
+            name: entry.name.clone(),
+            uuid: entry.uuid.clone(),
+            source,
+            reason: None,
+            origin: origin.to_string(),
+        }
+    }
+}
+
+/// A ban or pardon requested through the API, applied after the members'
+/// own edits.
+pub enum Change {
+    Ban(BanEntry),
+    Pardon { player: String, source: String },
+}
+
+/// Per group member, by server id or `node:<id>/<group>`: the list it had
+/// after its last sync, and for running servers the list last sent as
+/// commands.
+#[derive(Default)]
+pub struct SyncState {
+    synced: HashMap<String, Vec<BanEntry>>,
+    pushed: HashMap<String, Vec<BanEntry>>,
+    /// Counts finished pushes to nodes, which run without the lock, so a
+    /// sync can tell that the node lists it fetched may be out of date.
+    node_round: u64,
+    node_pushes: usize,
+}
+
+/// Bans and pardons made on members since their last sync.
+#[derive(Default)]
+struct Edits {
+    bans: Vec<(BanEntry, String)>,
+    pardons: Vec<(BanEntry, String)>,
+}
+
+impl Edits {
+    fn collect(&mut self, before: &[BanEntry], now: &[BanEntry], origin: &str) {
+        let before_keys: HashSet<String> = before.iter().map(BanEntry::key).collect();
+        let now_keys: HashSet<String> = now.iter().map(BanEntry::key).collect();
+        for entry in now.iter().filter(|e| !before_keys.contains(&e.key())) {
+            self.bans.push((entry.clone(), origin.to_string()));
+        }
+        for entry in before.iter().filter(|e| !now_keys.contains(&e.key())) {
+            self.pardons.push((entry.clone(), origin.to_string()));
+        }
+    }
+
+    fn apply(self, list: &mut Vec<BanEntry>, audit: &mut Vec<BanAudit>) {
+        let banned: HashSet<String> = self.bans.iter().map(|(e, _)| e.key()).collect();
+        for (entry, origin) in self.pardons {
+            if banned.contains(&entry.key()) {
+                tracing::warn!(
+                    "Kept the ban of '{}': it was pardoned on '{}' but banned elsewhere",
+                    entry.name,
+                    origin
+                );
+                continue;
+            }
+            let before = list.len();
+            list.retain(|e| e.key() != entry.key());
+            if list.len() != before {
+                audit.push(BanAudit::pardon(&entry, None, &origin));
+            }
+        }
+        for (entry, origin) in self.bans {
+            if !list.iter().any(|e| e.key() == entry.key()) {
+                audit.push(BanAudit::ban(&entry, &origin));
+                list.push(entry);
+            }
+        }
+    }
+}
+
+fn group_path(data_directory: &str, group: &str) -> PathBuf {
+    Path::new(data_directory).join("bans").join(format!("{}.json", group))
+}
+
+fn audit_path(data_directory: &str, group: &str) -> PathBuf {
+    Path::new(data_directory).join("bans").join(format!("{}.audit.jsonl", group))
+}
+
+async fn append_audit(path: &Path, records: &[BanAudit]) -> Result<(), String> {
+    let mut lines = String::new();
+    for record in records {
+        lines.push_str(&serde_json::to_string(record).map_err(|e| e.to_string())?);
+        lines.push('\n');
+    }
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .map_err(|e| format!("Failed to open '{}': {}", path.display(), e))?;
+    file.write_all(lines.as_bytes())
+        .await
+        .map_err(|e| format!("Failed to write '{}': {}", path.display(), e))
+}
+
+/// The latest `limit` audit records of a group, most recent first.
+pub async fn audit(
+    data_directory: &str,
+    group: &str,
+    limit: usize,
+) -> Result<Vec<BanAudit>, String> {
+    let path = audit_path(data_directory, group);
+    let contents = match tokio::fs::read_to_string(&path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to read '{}': {}", path.display(), e)),
+    };
+    Ok(contents
+        .lines()
+        .rev()
+        .filter_map(|l| serde_json::from_str(l).ok())
+        .take(limit)
+        .collect())
+}
+
+fn same_players(a: &[BanEntry], b: &[BanEntry]) -> bool {
+    let keys = |list: &[BanEntry]| list.iter().map(BanEntry::key).collect::<HashSet<_>>();
+    a.len() == b.len() && keys(a) == keys(b)
+}
+
+/// Server ids and directories of each group's members.
+async fn groups(state: &AppState) -> HashMap<String, Vec<(String, String)>> {
+    let config = state.config.read().await;
+    let mut groups: HashMap<String, Vec<(String, String)>> = HashMap::new();
+    for server in &config.servers {
+        if let Some(ref group) = server.ban_group {
+            let member = (server.id.clone(), server.directory.clone());
+            groups.entry(group.clone()).or_default().push(member);
+        }
+    }
+    groups
+}
+
+/// Ids of the servers in `group`.
+pub async fn members(state: &AppState, group: &str) -> Vec<String> {
+    groups(state)
+        .await
+        .remove(group)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect()
+}
+
+/// The group's bans, merged with its members' lists.
+pub async fn list(state: &AppState, group: &str) -> Result<Vec<BanEntry>, String> {
+    sync_group(state, group, None).await
+}
+
+/// Applies `change` to the group and pushes the result to every member.
+/// `origin` is recorded in the audit log.
+pub async fn apply(
+    state: &AppState,
+    group: &str,
+    change: Change,
+    origin: &str,
+) -> Result<Vec<BanEntry>, String> {
+    sync_group(state, group, Some((change, origin))).await
+}
+
+/// Whether `name` is a Minecraft username, and so safe in a command.
+fn valid_name(name: &str) -> bool {
+    (1..=16).contains(&name.len()) && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Brings a running member in line through the console.
+async fn push_commands(state: &AppState, id: &str, have: &[BanEntry], want: &[BanEntry]) {
+    let have_keys: HashSet<String> = have.iter().map(BanEntry::key).collect();
+    let want_keys: HashSet<String> = want.iter().map(BanEntry::key).collect();
+    // Lists come from member files and nodes, so names are checked here
+    let named = |e: &&BanEntry| {
+        let valid = valid_name(&e.name);
+        if !valid {
+            tracing::warn!("Not syncing the ban of '{}' to '{}': not a username", e.name, id);
+        }
+        valid
+    };
+    let bans = want.iter().filter(|e| !have_keys.contains(&e.key())).filter(named).map(|e| {
+        let reason = e.reason.replace(['\n', '\r'], " ");
+        format!("ban {} {}", e.name, reason)
+    });
+    let pardons = have.iter().filter(|e| !want_keys.contains(&e.key())).filter(named);
+    let commands: Vec<String> = bans.chain(pardons.map(|e| format!("pardon {}", e.name))).collect();
+    for command in commands {
+        if let Err(e) = send_command(state, id, &command).await {
+            tracing::warn!("Failed to sync bans to '{}': {}", id, e);
+            return;
+        }
+    }
+}
+
+/// Brings a node's group in line through its API.
+async fn push_node(
+    node: &NodeConfig,
+    group: &str,
+    have: &[BanEntry],
+    want: &[BanEntry],
+) -> Result<(), String> {
+    let have_keys: HashSet<String> = have.iter().map(BanEntry::key).collect();
+    let want_keys: HashSet<String> = want.iter().map(BanEntry::key).collect();
+    let path = format!("/api/ban-groups/{}/bans", group);
+    for entry in want.iter().filter(|e| !have_keys.contains(&e.key())) {
+        let body = serde_json::to_value(entry).map_err(|e| e.to_string())?;
+        nodes::send(node, reqwest::Method::POST, &path, Some(&body)).await?;
+    }
+    for entry in have.iter().filter(|e| !want_keys.contains(&e.key())) {
+        let player = if entry.uuid.is_empty() { &entry.name } else { &entry.uuid };
+        let path = format!("{}/{}", path, player);
+        nodes::send(node, reqwest::Method::DELETE, &path, None).await?;
+    }
+    Ok(())
+}
+
+async fn sync_group(
+    state: &AppState,
+    group: &str,
+    change: Option<(Change, &str)>,
+) -> Result<Vec<BanEntry>, String> {
+    let (data_directory, nodes) = {
+        let config = state.config.read().await;
+        (config.agent.data_directory.clone(), config.nodes.clone())
+    };
+    let members = groups(state).await.remove(group).unwrap_or_default();
+
+    // Fetched before taking the lock, which the syncs of every group share
+    let round = state.bans.lock().await.node_round;
+    let fetched = futures_util::future::join_all(
+        nodes.iter().filter(|n| !nodes::is_unreachable(state, &n.id)).map(|node| async move {
+            match nodes::get_optional::<BanGroupBans>(node, &group_api_path(group)).await {
+                Ok(Some(answer)) => Some((node, answer.bans)),
+                // The node has no servers in the group
+                Ok(None) => None,
+                Err(e) => {
+                    tracing::warn!("Skipping the '{}' bans of node '{}': {}", group, node.id, e);
+                    None
+                }
+            }
+        }),
+    )
+    .await;
+    let mut sync = state.bans.lock().await;
+    // Lists fetched around another sync's push may not show it yet, and
+    // would read as pardons; the next sync fetches them again
+    let fetched: Vec<(&NodeConfig, Vec<BanEntry>)> =
+        if sync.node_round == round && sync.node_pushes == 0 {
+            fetched.into_iter().flatten().collect()
+        } else {
+            Vec::new()
+        };
+
+    let path = group_path(&data_directory, group);
+    let stored = read_list::<BanEntry>(&path).await?;
+    let mut list = stored.clone().unwrap_or_default();
+    let mut edits = Edits::default();
+    let mut current: HashMap<String, Vec<BanEntry>> = HashMap::new();
+    for (id, directory) in &members {
+        let file = Path::new(directory).join(BANS_FILE);
+        let entries = match read_list(&file).await {
+            Ok(Some(entries)) => entries,
+            Ok(None) => continue,
+            // Possibly caught mid-write; tried again next time
+            Err(e) => {
+                tracing::warn!("Skipping the bans of '{}': {}", id, e);
+                continue;
+            }
+        };
+        // Not synced yet, so nothing it lacks counts as pardoned
+        edits.collect(sync.synced.get(id).map(Vec::as_slice).unwrap_or(&[]), &entries, id);
+        current.insert(id.clone(), entries);
+    }
+    let mut reachable = Vec::new();
+    for (node, bans) in fetched {
+        let key = node_key(&node.id, group);
+        let before = sync.synced.get(&key).map(Vec::as_slice).unwrap_or(&[]);
+        edits.collect(before, &bans, &format!("node:{}", node.id));
+        current.insert(key.clone(), bans);
+        reachable.push((key, node));
+    }
+
+    let mut audit = Vec::new();
+    edits.apply(&mut list, &mut audit);
+    match change {
+        Some((Change::Ban(entry), origin)) => {
+            list.retain(|e| e.key() != entry.key() && !e.is(&entry.name));
+            audit.push(BanAudit::ban(&entry, origin));
+            list.push(entry);
+        }
+        Some((Change::Pardon { player, source }, origin)) => {
+            for entry in list.iter().filter(|e| e.is(&player)) {
+                audit.push(BanAudit::pardon(entry, Some(source.clone()), origin));
+            }
+            list.retain(|e| !e.is(&player));
+        }
+        None => {}
+    }
+
+    if stored.as_deref() != Some(list.as_slice()) {
+        write_list(&path, &list).await?;
+    }
+    if !audit.is_empty() {
+        append_audit(&audit_path(&data_directory, group), &audit).await?;
+    }
+
+    for (id, directory) in &members {
+        let have = current.get(id).cloned().unwrap_or_default();
+        let in_sync = current.contains_key(id) && same_players(&have, &list);
+        if state.servers.contains_key(id) {
+            // Seen in the file once the server has applied the commands
+            if !in_sync && sync.pushed.get(id) != Some(&list) {
+                push_commands(state, id, &have, &list).await;
+                sync.pushed.insert(id.clone(), list.clone());
+            }
+            if current.contains_key(id) {
+                sync.synced.insert(id.clone(), have);
+            }
+            continue;
+        }
+        sync.pushed.remove(id);
+        if !in_sync {
+            let file = Path::new(directory).join(BANS_FILE);
+            if let Err(e) = write_list(&file, &list).await {
+                tracing::warn!("Failed to update the bans of '{}': {}", id, e);
+                continue;
+            }
+            tracing::info!("Synced the '{}' bans to '{}'", group, id);
+        }
+        sync.synced.insert(id.clone(), list.clone());
+    }
+    let mut outdated = Vec::new();
+    for (key, node) in reachable {
+        let have = current.remove(&key).unwrap_or_default();
+        if same_players(&have, &list) {
+            sync.synced.insert(key, list.clone());
+        } else {
+            outdated.push((key, node.clone(), have));
+        }
+    }
+    if outdated.is_empty() {
+        return Ok(list);
+    }
+    sync.node_pushes += 1;
+    drop(sync);
+
+    // Spawned, so a dropped request can't leave the push count raised
+    let (state, group, synced) = (state.clone(), group.to_string(), list.clone());
+    let _ = tokio::spawn(async move {
+        let mut pushed = Vec::with_capacity(outdated.len());
+        for (key, node, have) in outdated {
+            let result = push_node(&node, &group, &have, &synced).await;
+            pushed.push((key, node, have, result));
+        }
+        let mut sync = state.bans.lock().await;
+        sync.node_pushes -= 1;
+        sync.node_round += 1;
+        for (key, node, have, result) in pushed {
+            match result {
+                Ok(()) => sync.synced.insert(key, synced.clone()),
+                Err(e) => {
+                    let id = &node.id;
+                    tracing::warn!("Failed to sync the '{}' bans to node '{}': {}", group, id, e);
+                    sync.synced.insert(key, have)
+                }
+            };
+        }
+    })
+    .await;
+    Ok(list)
+}
+
+fn node_key(node_id: &str, group: &str) -> String {
+    format!("node:{}/{}", node_id, group)
+}
+
+fn group_api_path(group: &str) -> String {
+    format!("/api/ban-groups/{}", group)
+}
+
+/// The part of a node's ban group answer the manager merges.
+#[derive(Deserialize)]
+struct BanGroupBans {
+    bans: Vec<BanEntry>,
+}
+
+/// Periodically merges and pushes every group's bans, picking up `ban` and
+/// `pardon` run on any member.
+pub fn spawn_sync(state: AppState) {
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(SYNC_INTERVAL);
+        loop {
+            tick.tick().await;
+            let groups = groups(&state).await;
+            let node_keys: Vec<String> = {
+                let config = state.config.read().await;
+                let nodes = config.nodes.iter();
+                nodes.flat_map(|n| groups.keys().map(|g| node_key(&n.id, g))).collect()
+            };
+            let members: HashSet<&String> =
+                groups.values().flatten().map(|(id, _)| id).chain(&node_keys).collect();
+            {
+                let mut sync = state.bans.lock().await;
+                sync.synced.retain(|id, _| members.contains(id));
+                sync.pushed.retain(|id, _| members.contains(id));
+            }
+            for group in groups.keys() {
+                if let Err(e) = sync_group(&state, group, None).await {
+                    tracing::warn!("Ban group '{}' sync failed: {}", group, e);
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_usernames_reach_commands() {
+        assert!(valid_name("Steve"));
+        assert!(valid_name("a_b_1234567890ab"));
+        for name in ["", "a_b_1234567890abc", "Steve\nop Steve", "x; stop", "Stéve"] {
+            assert!(!valid_name(name), "{:?} was accepted", name);
+        }
+    }
+}
@@ -0,0 +1,284 @@
+//! `mcm`, a command-line client for the agent's HTTP API.
+//!
+//! The agent is found through `--url` or `MCM_URL` (default
+//! http://127.0.0.1:8080) and a manager token, when the agent requires one,
+//! through `--token` or `MCM_TOKEN`.
+
+use std::io::{IsTerminal, Write};
+use std::process::ExitCode;
+
+use anyhow::{anyhow, bail, Context};
+use futures_util::{SinkExt, StreamExt};
+use reqwest::{Method, StatusCode};
+use serde::Deserialize;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Message};
+
+const USAGE: &str = "\
+Usage: mcm [--url URL] [--token TOKEN] <command>
+
+Commands:
+  list              List servers and their status
+  start <id>        Start a server
+  stop <id>         Stop a server
+  restart <id>      Restart a server
+  backup <id>       Back up a server's world
+  console <id> [level]
+                    Attach to a server console; lines typed are run as commands.
+                    With a level (trace, debug, info, warn, error, fatal), only
+                    lines at that level or above are shown";
+
+struct Client {
+    http: reqwest::Client,
+    url: String,
+    token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    error: String,
+}
+
+/// Sent by the agent just before it closes a console socket, or without
+/// closing it when a command was dropped for exceeding the rate limit or
+/// during maintenance.
+#[derive(Deserialize)]
+struct WsErrorFrame {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    code: String,
+    message: String,
+}
+
+/// Sent in place of console lines this client was too slow to receive.
+#[derive(Deserialize)]
+struct WsSkippedFrame {
+    #[serde(rename = "type")]
+    kind: String,
+    lines: u64,
+}
+
+/// Console lines printed within a short window.
+#[derive(Deserialize)]
+struct WsLinesFrame {
+    #[serde(rename = "type")]
+    kind: String,
+    lines: Vec<WsConsoleLine>,
+}
+
+#[derive(Deserialize)]
+struct WsConsoleLine {
+    stream: String,
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct ServerEntry {
+    id: String,
+    name: String,
+    port: u16,
+    status: String,
+    #[serde(default)]
+    node: Option<String>,
+}
+
+impl Client {
+    async fn request(&self, method: Method, path: &str) -> anyhow::Result<reqwest::Response> {
+        let mut req = self.http.request(method, format!("{}{}", self.url, path));
+        if let Some(token) = &self.token {
+            req = req.bearer_auth(token);
+        }
+        let resp = req.send().await.with_context(|| format!("Failed to reach {}", self.url))?;
+        if resp.status().is_success() {
+            return Ok(resp);
+        }
+        let status = resp.status();
+        match resp.json::<ApiError>().await {
+            Ok(e) => bail!("{}", e.error),
+            Err(_) if status == StatusCode::UNAUTHORIZED => {
+                bail!("The agent requires a manager token (--token or MCM_TOKEN)")
+            }
+            Err(_) => bail!("Request failed with {}", status),
+        }
+    }
+
+    async fn list(&self) -> anyhow::Result<()> {
+        let servers: Vec<ServerEntry> = self
+            .request(Method::GET, "/api/servers")
+            .await?
+            .json()
+            .await
+            .context("Unexpected server list")?;
+        let with_nodes = servers.iter().any(|s| s.node.is_some());
+        let id_width = servers.iter().map(|s| s.id.len()).max().unwrap_or(0).max(2);
+        let name_width = servers.iter().map(|s| s.name.len()).max().unwrap_or(0).max(4);
+        let mut header = format!(
+            "{:id_width$}  {:name_width$}  {:8}  {:5}",
+            "ID", "NAME", "STATUS", "PORT"
+        );
+        if with_nodes {
+            header.push_str("  NODE");
+        }
+        println!("{}", header.trim_end());
+        for s in servers {
+            let mut row = format!(
+                "{:id_width$}  {:name_width$}  {:8}  {:5}",
+                s.id, s.name, s.status, s.port
+            );
+            if let Some(node) = s.node {
+                row.push_str("  ");
+                row.push_str(&node);
+            }
+            println!("{}", row);
+        }
+        Ok(())
+    }
+
+    async fn action(&self, id: &str, action: &str) -> anyhow::Result<()> {
+        self.request(Method::POST, &format!("/api/servers/{}/{}", id, action)).await?;
+        Ok(())
+    }
+
+    /// Streams console output to stdout, and the server's stderr to stderr,
+    /// and sends each stdin line as a command until either side closes.
+    /// Colours are kept for a terminal.
+    async fn console(&self, id: &str, level: Option<&str>) -> anyhow::Result<()> {
+        let ansi = if std::io::stdout().is_terminal() { "raw" } else { "strip" };
+        let mut ws_url = format!(
+            "{}/api/servers/{}/console/ws?format=batch&ansi={}",
+            self.url.replacen("http", "ws", 1),
+            id,
+            ansi
+        );
+        if let Some(level) = level {
+            ws_url.push_str("&level=");
+            ws_url.push_str(&level.to_lowercase());
+        }
+        let mut req = ws_url.as_str().into_client_request()?;
+        if let Some(token) = &self.token {
+            req.headers_mut().insert(
+                "authorization",
+                format!("Bearer {}", token).parse().context("Invalid token")?,
+            );
+        }
+        let (socket, _) = tokio_tungstenite::connect_async(req)
+            .await
+            .with_context(|| format!("Failed to open the console of '{}'", id))?;
+        let (mut sink, mut stream) = socket.split();
+        let mut stdin = BufReader::new(tokio::io::stdin()).lines();
+        loop {
+            tokio::select! {
+                msg = stream.next() => match msg {
+                    Some(Ok(Message::Text(line))) => {
+                        if let Ok(frame) = serde_json::from_str::<WsLinesFrame>(&line) {
+                            if frame.kind == "lines" {
+                                let mut stdout = std::io::stdout().lock();
+                                for line in frame.lines {
+                                    if line.stream == "stderr" {
+                                        eprintln!("{}", line.text);
+                                    } else {
+                                        writeln!(stdout, "{}", line.text)?;
+                                    }
+                                }
+                                continue;
+                            }
+                        }
+                        if let Ok(frame) = serde_json::from_str::<WsSkippedFrame>(&line) {
+                            if frame.kind == "skipped" {
+                                eprintln!("mcm: {} console lines skipped", frame.lines);
+                                continue;
+                            }
+                        }
+                        match serde_json::from_str::<WsErrorFrame>(&line) {
+                            // Only that command was dropped
+                            Ok(frame)
+                                if frame.kind == "error"
+                                    && (frame.code == "rate_limited"
+                                        || frame.code == "maintenance") =>
+                            {
+                                eprintln!("mcm: {}", frame.message);
+                            }
+                            Ok(frame) if frame.kind == "error" => bail!("{}", frame.message),
+                            _ => println!("{}", line),
+                        }
+                    }
+                    Some(Ok(Message::Close(frame))) => {
+                        if let Some(frame) = frame.filter(|f| !f.reason.is_empty()) {
+                            bail!("{}", frame.reason);
+                        }
+                        return Ok(());
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => bail!("Console connection lost: {}", e),
+                    None => return Ok(()),
+                },
+                line = stdin.next_line() => match line? {
+                    Some(line) if line.trim().is_empty() => {}
+                    Some(line) => {
+                        let cmd = serde_json::json!({ "type": "command", "data": line });
+                        sink.send(Message::Text(cmd.to_string().into())).await?;
+                    }
+                    None => {
+                        let _ = sink.send(Message::Close(None)).await;
+                        return Ok(());
+                    }
+                },
+            }
+        }
+    }
+}
+
+async fn run(mut args: Vec<String>) -> anyhow::Result<()> {
+    let mut url = std::env::var("MCM_URL").unwrap_or_else(|_| "http://127.0.0.1:8080".to_string());
+    let mut token = std::env::var("MCM_TOKEN").ok().filter(|t| !t.is_empty());
+    while let Some(flag) = args.first().filter(|a| a.starts_with("--")).cloned() {
+        args.remove(0);
+        if flag == "--help" {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+        if args.is_empty() {
+            bail!("{} needs a value", flag);
+        }
+        let value = args.remove(0);
+        match flag.as_str() {
+            "--url" => url = value,
+            "--token" => token = Some(value),
+            _ => bail!("Unknown option {}\n\n{}", flag, USAGE),
+        }
+    }
+    let client = Client {
+        http: reqwest::Client::new(),
+        url: url.trim_end_matches('/').to_string(),
+        token,
+    };
+
+    let mut args = args.into_iter();
+    let command = args.next().ok_or_else(|| anyhow!("{}", USAGE))?;
+    let mut id = || args.next().ok_or_else(|| anyhow!("mcm {} needs a server id", command));
+    match command.as_str() {
+        "list" => client.list().await,
+        "start" | "stop" | "restart" | "backup" => client.action(&id()?, &command).await,
+        "console" => {
+            let id = id()?;
+            client.console(&id, args.next().as_deref()).await
+        }
+        "help" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => bail!("Unknown command '{}'\n\n{}", command, USAGE),
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run(std::env::args().skip(1).collect()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("mcm: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
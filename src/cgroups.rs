@@ -0,0 +1,138 @@
+//! Per-server cgroup v2 limits. Each server gets `<cgroup_root>/<id>` with
+//! `memory.max` derived from `memory_mb` and `cpu.max` from `cpu_limit`, so
+//! a runaway server is throttled or OOM-killed without touching the node.
+//! The agent must be allowed to manage `cgroup_root`, e.g. via systemd's
+//! `Delegate=yes`, and must not itself live in that group.
+
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::config::ServerConfig;
+
+const CPU_PERIOD_USEC: u64 = 100_000;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CgroupStats {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cgroup_memory_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cgroup_memory_limit_bytes: Option<u64>,
+    /// CPU time used by the whole group, as a percentage of one core.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cgroup_cpu_percent: Option<f32>,
+}
+
+fn server_cgroup(root: &str, server_id: &str) -> PathBuf {
+    Path::new(root).join(server_id)
+}
+
+/// The `memory.max` for a server: its heap plus the configured overhead
+/// for metaspace, thread stacks and direct buffers.
+pub fn memory_limit_bytes(cfg: &ServerConfig, overhead_percent: u32) -> u64 {
+    cfg.memory_mb as u64 * (100 + overhead_percent as u64) / 100 * 1024 * 1024
+}
+
+async fn write(path: &Path, value: &str) -> Result<(), String> {
+    tokio::fs::write(path, value)
+        .await
+        .map_err(|e| format!("Failed to write '{}' to {}: {}", value, path.display(), e))
+}
+
+async fn place(
+    root: &str,
+    cfg: &ServerConfig,
+    overhead_percent: u32,
+    pid: u32,
+) -> Result<(), String> {
+    let group = server_cgroup(root, &cfg.id);
+    let root = Path::new(root);
+    tokio::fs::create_dir_all(root)
+        .await
+        .map_err(|e| format!("Failed to create {}: {}", root.display(), e))?;
+    write(&root.join("cgroup.subtree_control"), "+memory +cpu").await?;
+
+    tokio::fs::create_dir_all(&group)
+        .await
+        .map_err(|e| format!("Failed to create {}: {}", group.display(), e))?;
+    let memory_max = memory_limit_bytes(cfg, overhead_percent);
+    write(&group.join("memory.max"), &memory_max.to_string()).await?;
+    // Without swap the limit is hard: the kernel OOM-kills inside the group
+    let _ = write(&group.join("memory.swap.max"), "0").await;
+    let cpu_max = match cfg.cpu_limit {
+        Some(cores) => {
+            let quota = (cores as f64 * CPU_PERIOD_USEC as f64) as u64;
+            format!("{} {}", quota, CPU_PERIOD_USEC)
+        }
+        None => format!("max {}", CPU_PERIOD_USEC),
+    };
+    write(&group.join("cpu.max"), &cpu_max).await?;
+    write(&group.join("cgroup.procs"), &pid.to_string()).await?;
+    tracing::info!(
+        "Placed '{}' in {} (memory.max {} MB, cpu.max {})",
+        cfg.id,
+        group.display(),
+        memory_max / (1024 * 1024),
+        cpu_max
+    );
+    Ok(())
+}
+
+/// Moves a freshly spawned server into its own cgroup.
+pub async fn apply(root: Option<&str>, cfg: &ServerConfig, overhead_percent: u32, pid: u32) {
+    let Some(root) = root else {
+        if cfg.cpu_limit.is_some() {
+            tracing::warn!(
+                "Server '{}' has cpu_limit but agent.cgroup_root is not set",
+                cfg.id
+            );
+        }
+        return;
+    };
+    if let Err(e) = place(root, cfg, overhead_percent, pid).await {
+        tracing::warn!("Failed to apply cgroup limits for '{}': {}", cfg.id, e);
+    }
+}
+
+/// Removes a server's cgroup once its processes are gone.
+pub async fn clear(root: Option<&str>, server_id: &str) {
+    if let Some(root) = root {
+        let _ = tokio::fs::remove_dir(server_cgroup(root, server_id)).await;
+    }
+}
+
+async fn read_key(path: &Path, key: &str) -> Option<u64> {
+    let contents = tokio::fs::read_to_string(path).await.ok()?;
+    contents.lines().find_map(|line| {
+        let (k, v) = line.split_once(' ')?;
+        (k == key).then(|| v.trim().parse().ok()).flatten()
+    })
+}
+
+/// Whether the kernel OOM-killed anything in the server's group.
+pub async fn oom_killed(root: Option<&str>, server_id: &str) -> bool {
+    let Some(root) = root else {
+        return false;
+    };
+    let events = server_cgroup(root, server_id).join("memory.events");
+    read_key(&events, "oom_kill").await.is_some_and(|n| n > 0)
+}
+
+/// Cumulative CPU time of the group, for turning into a percentage.
+pub async fn cpu_usage_usec(root: &str, server_id: &str) -> Option<u64> {
+    read_key(&server_cgroup(root, server_id).join("cpu.stat"), "usage_usec").await
+}
+
+pub async fn memory_usage(root: &str, server_id: &str) -> (Option<u64>, Option<u64>) {
+    let group = server_cgroup(root, server_id);
+    let read = |file: &'static str| {
+        let path = group.join(file);
+        async move {
+            tokio::fs::read_to_string(path)
+                .await
+                .ok()
+                .and_then(|s| s.trim().parse().ok())
+        }
+    };
+    (read("memory.current").await, read("memory.max").await)
+}
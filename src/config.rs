@@ -1,14 +1,374 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
 use anyhow::Context;
 use serde::{Deserialize, Serialize};
 use tokio::io::AsyncWriteExt;
 
-pub const CONFIG_PATH: &str = "config.json";
-pub const CONFIG_TMP_PATH: &str = "config.json.tmp";
+use crate::{envsubst, schema};
+
+/// Looked for in this order; the first one present is used and saved back
+/// in its own format.
+const CONFIG_FILES: &[&str] = &["config.json", "config.toml", "config.yaml", "config.yml"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => ConfigFormat::Toml,
+            Some("yaml" | "yml") => ConfigFormat::Yaml,
+            _ => ConfigFormat::Json,
+        }
+    }
+}
+
+/// Settings given on the command line or through `MCM_*` variables. They
+/// win over the config file and are never written back to it.
+#[derive(Debug, Default)]
+pub struct Overrides {
+    pub config_path: Option<String>,
+    pub data_directory: Option<String>,
+    pub bind_address: Option<String>,
+}
+
+static OVERRIDES: OnceLock<Overrides> = OnceLock::new();
+
+/// Takes effect only when called before the config is first read.
+pub fn set_overrides(overrides: Overrides) {
+    let _ = OVERRIDES.set(overrides);
+}
+
+fn overrides() -> &'static Overrides {
+    OVERRIDES.get_or_init(Overrides::default)
+}
+
+/// The config file in use, picked once at startup.
+pub fn config_path() -> &'static Path {
+    static PATH: OnceLock<PathBuf> = OnceLock::new();
+    PATH.get_or_init(|| {
+        if let Some(path) = &overrides().config_path {
+            return PathBuf::from(path);
+        }
+        CONFIG_FILES
+            .iter()
+            .map(PathBuf::from)
+            .find(|p| p.exists())
+            .unwrap_or_else(|| PathBuf::from(CONFIG_FILES[0]))
+    })
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AgentConfig {
     pub bind_address: String,
     pub data_directory: String,
+    /// Host memory kept back for the OS and the agent itself.
+    #[serde(default = "default_reserved_memory_mb")]
+    pub reserved_memory_mb: u32,
+    /// CPU cores kept back for the OS and the agent itself.
+    #[serde(default = "default_reserved_cpu_cores")]
+    pub reserved_cpu_cores: f32,
+    /// Uplink interface that per-server egress limits are applied to.
+    #[serde(default)]
+    pub shaping_interface: Option<String>,
+    /// Lets shaping replace a root qdisc the agent didn't install on
+    /// `shaping_interface`, such as an admin's own cake or fq setup.
+    #[serde(default)]
+    pub shaping_replace_root: bool,
+    /// cgroup v2 directory under which each server gets its own group, e.g.
+    /// "/sys/fs/cgroup/mc-node-agent.service/servers"; disabled when absent.
+    #[serde(default)]
+    pub cgroup_root: Option<String>,
+    /// Headroom added to `memory_mb` for the cgroup memory limit, in percent.
+    #[serde(default = "default_cgroup_memory_overhead_percent")]
+    pub cgroup_memory_overhead_percent: u32,
+    /// Ports handed out to servers created with `port: 0`.
+    #[serde(default)]
+    pub port_range: PortRange,
+    /// /readyz fails when data_directory has less free space than this.
+    #[serde(default = "default_min_free_disk_mb")]
+    pub min_free_disk_mb: u64,
+    /// How often server and world directory sizes are re-measured.
+    #[serde(default = "default_disk_usage_interval_secs")]
+    pub disk_usage_interval_secs: u64,
+    /// How often running servers are queried with a Server List Ping; 0 disables it.
+    #[serde(default = "default_slp_interval_secs")]
+    pub slp_interval_secs: u64,
+    /// Hours of metrics history kept on disk; 0 disables persistence.
+    #[serde(default = "default_metrics_retention_hours")]
+    pub metrics_retention_hours: u64,
+    /// Size at which a server's console log under `<data_directory>/console`
+    /// is rotated; 0 disables it.
+    #[serde(default = "default_console_log_max_mb")]
+    pub console_log_max_mb: u64,
+    /// Rotated console logs kept per server besides the current one.
+    #[serde(default = "default_console_log_files")]
+    pub console_log_files: u32,
+    /// OTLP/HTTP telemetry export; disabled when absent.
+    #[serde(default)]
+    pub otlp: Option<OtlpConfig>,
+    /// How often the Paper API is polled for servers with an update_channel.
+    #[serde(default = "default_update_check_interval_secs")]
+    pub update_check_interval_secs: u64,
+    /// How long a build runs on beta servers before stable servers get it.
+    #[serde(default = "default_update_soak_secs")]
+    pub update_soak_secs: u64,
+    /// Beta crashes tolerated during the soak before a build is rejected.
+    #[serde(default)]
+    pub update_max_crashes: u32,
+    /// Bearer tokens a managing agent must present; the API is open when
+    /// this and `users` are empty. Holds two entries while a rotation is in
+    /// progress.
+    #[serde(default)]
+    pub manager_tokens: Vec<String>,
+    /// Accounts that can log in to the web UI.
+    #[serde(default)]
+    pub users: Vec<UserConfig>,
+    /// How long a login lasts before it has to be refreshed.
+    #[serde(default = "default_session_hours")]
+    pub session_hours: u64,
+    /// Runs servers detached from the agent so they keep going when it
+    /// restarts, and reattaches to them on startup. Console input goes
+    /// through a FIFO and output through a log file under
+    /// `<data_directory>/run`. Unix only; under systemd the agent's unit
+    /// needs `KillMode=process`.
+    #[serde(default)]
+    pub detach_servers: bool,
+    /// How often registered nodes are checked; 0 disables heartbeats.
+    #[serde(default = "default_node_heartbeat_secs")]
+    pub node_heartbeat_secs: u64,
+    /// Address of the gRPC control API, e.g. "0.0.0.0:8081"; disabled when absent.
+    #[serde(default)]
+    pub grpc_bind_address: Option<String>,
+    #[serde(default)]
+    pub rate_limits: RateLimits,
+    #[serde(default)]
+    pub cors: CorsConfig,
+    #[serde(default)]
+    pub ip_filter: IpFilterConfig,
+    /// Named sets of server.properties values, e.g. "hardcore-pvp", applied
+    /// when a server is created or later through the API.
+    #[serde(default)]
+    pub property_profiles: BTreeMap<String, BTreeMap<String, String>>,
+    /// Base URL players reach this agent's HTTP API at, e.g.
+    /// "http://mc.example.com:8080", used for hosted resource packs. The
+    /// ip_filter applies to pack downloads too.
+    #[serde(default)]
+    pub public_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserConfig {
+    /// Letters, digits, '-' and '_'.
+    pub username: String,
+    /// Argon2 hash in PHC form, as printed by `mc-node-agent --hash-password`.
+    pub password_hash: String,
+}
+
+/// Source-address filtering for the HTTP and gRPC APIs. Entries are single
+/// addresses or CIDR ranges such as "10.0.0.0/8".
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IpFilterConfig {
+    /// When non-empty, only these clients are let in.
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// Refused even when they are allowed.
+    #[serde(default)]
+    pub deny: Vec<String>,
+    /// Reverse proxies whose `X-Forwarded-For` is believed. The client is
+    /// the last address in it that isn't one of them.
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+}
+
+/// Cross-origin access for browser UIs hosted elsewhere. The bundled UI is
+/// served by the agent itself and needs none of this.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CorsConfig {
+    /// Exact origins such as "https://panel.example.com", or "*" for any.
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    /// Lets browsers send cookies along; cannot be combined with "*".
+    #[serde(default)]
+    pub allow_credentials: bool,
+}
+
+/// Requests each client may make, counted per manager token, or per
+/// address when the API is open.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimits {
+    /// Any API request not covered below.
+    #[serde(default = "default_api_rate_limit")]
+    pub api: RateLimit,
+    /// Start, stop, restart and backup, one server or in bulk.
+    #[serde(default = "default_action_rate_limit")]
+    pub actions: RateLimit,
+    /// Console commands over WebSocket or gRPC, chat, prompt answers and
+    /// macros.
+    #[serde(default = "default_command_rate_limit")]
+    pub commands: RateLimit,
+    /// Login attempts, counted per address.
+    #[serde(default = "default_login_rate_limit")]
+    pub login: RateLimit,
+    /// Requests refused for missing or wrong credentials, counted per
+    /// address ahead of authentication, so tokens can't be guessed at speed.
+    #[serde(default = "default_rejected_rate_limit")]
+    pub rejected: RateLimit,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            api: default_api_rate_limit(),
+            actions: default_action_rate_limit(),
+            commands: default_command_rate_limit(),
+            login: default_login_rate_limit(),
+            rejected: default_rejected_rate_limit(),
+        }
+    }
+}
+
+/// A token bucket refilled at `per_minute`, holding at most `burst`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RateLimit {
+    /// 0 turns the limit off.
+    pub per_minute: u32,
+    pub burst: u32,
+}
+
+fn default_api_rate_limit() -> RateLimit {
+    RateLimit {
+        per_minute: 600,
+        burst: 100,
+    }
+}
+
+fn default_action_rate_limit() -> RateLimit {
+    RateLimit {
+        per_minute: 12,
+        burst: 4,
+    }
+}
+
+fn default_command_rate_limit() -> RateLimit {
+    RateLimit {
+        per_minute: 120,
+        burst: 20,
+    }
+}
+
+fn default_login_rate_limit() -> RateLimit {
+    RateLimit {
+        per_minute: 10,
+        burst: 5,
+    }
+}
+
+fn default_rejected_rate_limit() -> RateLimit {
+    RateLimit {
+        per_minute: 10,
+        burst: 10,
+    }
+}
+
+/// Inclusive range of game ports available for automatic allocation.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl Default for PortRange {
+    fn default() -> Self {
+        Self {
+            start: 25565,
+            end: 25664,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OtlpConfig {
+    /// Collector base URL, e.g. "http://otel-collector:4318".
+    pub endpoint: String,
+    #[serde(default = "default_otlp_service_name")]
+    pub service_name: String,
+    #[serde(default = "default_true")]
+    pub traces: bool,
+    #[serde(default = "default_true")]
+    pub metrics: bool,
+    #[serde(default = "default_otlp_metrics_interval_secs")]
+    pub metrics_interval_secs: u64,
+}
+
+fn default_otlp_service_name() -> String {
+    "mc-node-agent".to_string()
+}
+
+fn default_otlp_metrics_interval_secs() -> u64 {
+    30
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_cgroup_memory_overhead_percent() -> u32 {
+    25
+}
+
+fn default_session_hours() -> u64 {
+    12
+}
+
+fn default_node_heartbeat_secs() -> u64 {
+    15
+}
+
+fn default_disk_usage_interval_secs() -> u64 {
+    300
+}
+
+fn default_slp_interval_secs() -> u64 {
+    15
+}
+
+fn default_console_log_max_mb() -> u64 {
+    10
+}
+
+fn default_console_log_files() -> u32 {
+    5
+}
+
+fn default_metrics_retention_hours() -> u64 {
+    72
+}
+
+fn default_min_free_disk_mb() -> u64 {
+    1024
+}
+
+fn default_update_check_interval_secs() -> u64 {
+    6 * 60 * 60
+}
+
+fn default_update_soak_secs() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_reserved_memory_mb() -> u32 {
+    1024
+}
+
+fn default_reserved_cpu_cores() -> f32 {
+    0.5
 }
 
 impl Default for AgentConfig {
@@ -16,21 +376,618 @@ impl Default for AgentConfig {
         Self {
             bind_address: "0.0.0.0:8080".to_string(),
             data_directory: "/servers".to_string(),
+            reserved_memory_mb: default_reserved_memory_mb(),
+            reserved_cpu_cores: default_reserved_cpu_cores(),
+            shaping_interface: None,
+            shaping_replace_root: false,
+            cgroup_root: None,
+            cgroup_memory_overhead_percent: default_cgroup_memory_overhead_percent(),
+            port_range: PortRange::default(),
+            min_free_disk_mb: default_min_free_disk_mb(),
+            disk_usage_interval_secs: default_disk_usage_interval_secs(),
+            slp_interval_secs: default_slp_interval_secs(),
+            metrics_retention_hours: default_metrics_retention_hours(),
+            console_log_max_mb: default_console_log_max_mb(),
+            console_log_files: default_console_log_files(),
+            otlp: None,
+            update_check_interval_secs: default_update_check_interval_secs(),
+            update_soak_secs: default_update_soak_secs(),
+            update_max_crashes: 0,
+            manager_tokens: Vec::new(),
+            users: Vec::new(),
+            session_hours: default_session_hours(),
+            detach_servers: false,
+            node_heartbeat_secs: default_node_heartbeat_secs(),
+            grpc_bind_address: None,
+            rate_limits: RateLimits::default(),
+            cors: CorsConfig::default(),
+            ip_filter: IpFilterConfig::default(),
+            property_profiles: BTreeMap::new(),
+            public_url: None,
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
 pub struct ServerConfig {
     pub id: String,
     pub name: String,
     pub directory: String,
     pub jar: String,
+    /// Server software; inferred by `flavor()` when absent.
+    #[serde(default)]
+    pub flavor: Option<ServerFlavor>,
     pub memory_mb: u32,
+    /// Initial heap (`-Xms`); `memory_mb` when absent, so the heap is
+    /// committed up front rather than grown through repeated collections.
+    #[serde(default)]
+    pub min_memory_mb: Option<u32>,
+    /// CPU cores the server may use, enforced through `agent.cgroup_root`.
+    #[serde(default)]
+    pub cpu_limit: Option<f32>,
+    /// 0 when creating a server picks the next free port in `agent.port_range`.
+    #[serde(default)]
     pub port: u16,
     pub autostart: bool,
+    /// Kept true by the reconciler, which starts or stops the server to
+    /// match; `autostart` alone only starts it at boot.
+    #[serde(default)]
+    pub desired_state: Option<DesiredState>,
     #[serde(default)]
     pub backup_directory: Option<String>,
+    /// Explicit java executable; takes precedence over `java_version`.
+    #[serde(default)]
+    pub java_path: Option<String>,
+    /// Managed runtime under `data_directory/runtimes` to launch with.
+    #[serde(default)]
+    pub java_version: Option<u32>,
+    #[serde(default)]
+    pub jvm_profile: JvmProfile,
+    /// Enables GC logging so heap and pause stats show up in metrics. Off
+    /// unless set, since it adds a JVM flag to the server's launch.
+    #[serde(default)]
+    pub gc_metrics: bool,
+    /// Seconds between tick performance samples; 0 disables sampling.
+    #[serde(default)]
+    pub tps_sample_secs: u64,
+    /// Commands whose output reports TPS/MSPT (Paper `tps`/`mspt`,
+    /// Forge `forge tps`, vanilla `tick query`).
+    #[serde(default = "default_tps_commands")]
+    pub tps_commands: Vec<String>,
+    /// Extra arguments passed to the JVM before `-jar`.
+    #[serde(default)]
+    pub jvm_args: Vec<String>,
+    /// Program and arguments launched instead of `java -jar`, e.g.
+    /// `["./run.sh", "nogui"]`. Java selection and JVM flags are skipped;
+    /// wrapper scripts should `exec` java so signals reach the JVM.
+    #[serde(default)]
+    pub command: Option<Vec<String>>,
+    /// Runs the server in a container instead of as a child process.
+    #[serde(default)]
+    pub docker: Option<DockerConfig>,
+    /// Makes this server a Velocity or BungeeCord proxy for other servers.
+    #[serde(default)]
+    pub proxy: Option<ProxyConfig>,
+    /// Bedrock crossplay through Geyser, installed by the API.
+    #[serde(default)]
+    pub bedrock: Option<BedrockConfig>,
+    #[serde(default = "default_console_buffer_lines")]
+    pub console_buffer_lines: usize,
+    #[serde(default = "default_console_replay_lines")]
+    pub console_replay_lines: usize,
+    #[serde(default)]
+    pub macros: Vec<CommandMacro>,
+    /// Egress cap for traffic leaving the server's ports, in kbit/s.
+    #[serde(default)]
+    pub egress_limit_kbit: Option<u32>,
+    /// Extra source ports sharing the egress cap, e.g. a dynmap web port.
+    #[serde(default)]
+    pub shaped_ports: Vec<u16>,
+    /// Paper update channel; servers without one are never auto-updated.
+    #[serde(default)]
+    pub update_channel: Option<UpdateChannel>,
+    /// Players allowed to run `!panel backup|restart` from in-game chat.
+    #[serde(default)]
+    pub panel_operators: Vec<String>,
+    /// Commands sent by `stop_server`; a plain `stop` is used when empty.
+    #[serde(default)]
+    pub stop_steps: Vec<StopStep>,
+    /// Stop the server once it has had no players for this long. Player
+    /// counts come from Server List Ping, so `slp_interval_secs` must be set.
+    #[serde(default)]
+    pub idle_shutdown_minutes: Option<u32>,
+    /// Forward the game and query ports on the router via UPnP or NAT-PMP.
+    #[serde(default)]
+    pub port_forward: bool,
+    /// While stopped, hold the port and start the server on a join attempt.
+    #[serde(default)]
+    pub wake_on_demand: bool,
+    /// What happens when the process exits without a stop request. Without
+    /// one, `autostart` servers are always restarted and others never are.
+    #[serde(default)]
+    pub restart_policy: Option<RestartPolicy>,
+    /// What a starting agent does with this server's process when an agent
+    /// that crashed left it running without `detach_servers`.
+    #[serde(default)]
+    pub orphan_policy: OrphanPolicy,
+    /// Detects a running server that has stopped responding on the console.
+    #[serde(default)]
+    pub watchdog: Option<WatchdogConfig>,
+    /// Actions run when a console line matches, e.g. a restart on a known
+    /// deadlock message.
+    #[serde(default)]
+    pub triggers: Vec<TriggerRule>,
+    /// Messages broadcast in game on a schedule while the server runs.
+    #[serde(default)]
+    pub announcements: Vec<Announcement>,
+    /// Relays chat between the server and a Discord channel while it runs.
+    #[serde(default)]
+    pub discord: Option<DiscordBridgeConfig>,
+    /// Servers naming the same group share one `whitelist.json`, kept in
+    /// sync by the agent.
+    #[serde(default)]
+    pub whitelist_group: Option<String>,
+    /// Servers naming the same group share one ban list, kept in sync by
+    /// the agent and carried to the same group on a manager's nodes.
+    #[serde(default)]
+    pub ban_group: Option<String>,
+    #[serde(default)]
+    pub hooks: LifecycleHooks,
+    /// Groups such as "survival" for selecting servers in lists and bulk actions.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// One line on what the server is for, shown next to its name.
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Free-form notes for whoever runs the server, such as who owns it.
+    #[serde(default)]
+    pub notes: Option<String>,
+    /// Icon the panel shows for the server: an icon name, an emoji or an
+    /// image URL.
+    #[serde(default)]
+    pub icon: Option<String>,
+}
+
+/// Shell commands run around lifecycle events; a failing `pre_start`
+/// aborts the start, other failures are only logged.
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct LifecycleHooks {
+    #[serde(default)]
+    pub pre_start: Option<String>,
+    /// Runs once the server has finished starting.
+    #[serde(default)]
+    pub post_start: Option<String>,
+    #[serde(default)]
+    pub pre_stop: Option<String>,
+    /// Runs after every exit, requested or not.
+    #[serde(default)]
+    pub post_stop: Option<String>,
+    #[serde(default)]
+    pub post_backup: Option<String>,
+    #[serde(default = "default_hook_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_hook_timeout_secs() -> u64 {
+    60
+}
+
+impl Default for LifecycleHooks {
+    fn default() -> Self {
+        Self {
+            pre_start: None,
+            post_start: None,
+            pre_stop: None,
+            post_stop: None,
+            post_backup: None,
+            timeout_secs: default_hook_timeout_secs(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// The configured flavor, or for older configs the one implied by the
+    /// proxy and update settings, falling back to `custom`.
+    pub fn flavor(&self) -> ServerFlavor {
+        self.flavor.unwrap_or(if self.proxy.is_some() {
+            ServerFlavor::Proxy
+        } else if self.update_channel.is_some() {
+            ServerFlavor::Paper
+        } else {
+            ServerFlavor::Custom
+        })
+    }
+
+    /// The initial heap in MB: `min_memory_mb`, or the whole `memory_mb`.
+    pub fn min_memory_mb(&self) -> u32 {
+        self.min_memory_mb.unwrap_or(self.memory_mb)
+    }
+
+    /// What the server should be doing at boot: the explicit desired state,
+    /// or running for `autostart` servers.
+    pub fn desired_state(&self) -> Option<DesiredState> {
+        self.desired_state
+            .or(self.autostart.then_some(DesiredState::Running))
+    }
+
+    pub fn effective_restart_policy(&self) -> RestartPolicy {
+        self.restart_policy.clone().unwrap_or(RestartPolicy {
+            mode: if self.autostart {
+                RestartMode::Always
+            } else {
+                RestartMode::Never
+            },
+            max_retries: None,
+            delay_secs: default_restart_delay_secs(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DesiredState {
+    Running,
+    Stopped,
+}
+
+/// Server software, deciding how it is launched, where its plugins or mods
+/// live and which agent features apply. `custom` makes no assumptions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerFlavor {
+    Vanilla,
+    Paper,
+    Fabric,
+    Forge,
+    Proxy,
+    Custom,
+}
+
+impl ServerFlavor {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ServerFlavor::Vanilla => "vanilla",
+            ServerFlavor::Paper => "paper",
+            ServerFlavor::Fabric => "fabric",
+            ServerFlavor::Forge => "forge",
+            ServerFlavor::Proxy => "proxy",
+            ServerFlavor::Custom => "custom",
+        }
+    }
+
+    /// Folder the server loads plugins or mods from, if it loads any.
+    pub fn content_dir(self) -> Option<&'static str> {
+        match self {
+            ServerFlavor::Paper | ServerFlavor::Proxy => Some("plugins"),
+            ServerFlavor::Fabric | ServerFlavor::Forge => Some("mods"),
+            ServerFlavor::Vanilla | ServerFlavor::Custom => None,
+        }
+    }
+
+    /// Proxies have no server.properties and no world.
+    pub fn has_world(self) -> bool {
+        self != ServerFlavor::Proxy
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct DockerConfig {
+    /// Image providing java, e.g. "eclipse-temurin:21-jre".
+    pub image: String,
+    /// Extra bind mounts in Docker's `host:container[:options]` form, with
+    /// `host` inside the server directory, relative to it or absolute.
+    #[serde(default)]
+    pub mounts: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct ProxyConfig {
+    pub software: ProxySoftware,
+    /// Velocity version provisioned by the API; the newest one when absent.
+    #[serde(default)]
+    pub version: Option<String>,
+    /// How player identity reaches backends. Defaults to `modern` on
+    /// Velocity and `legacy` on BungeeCord, which only supports that.
+    #[serde(default)]
+    pub forwarding: Option<ForwardingMode>,
+    /// Backends in the order players are sent to them on join.
+    #[serde(default)]
+    pub backends: Vec<ProxyBackend>,
+}
+
+impl ProxyConfig {
+    pub fn forwarding_mode(&self) -> ForwardingMode {
+        self.forwarding.unwrap_or(match self.software {
+            ProxySoftware::Velocity => ForwardingMode::Modern,
+            ProxySoftware::Bungeecord => ForwardingMode::Legacy,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxySoftware {
+    Velocity,
+    Bungeecord,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ForwardingMode {
+    /// Velocity's signed forwarding, checked against `forwarding.secret`.
+    Modern,
+    /// BungeeCord-style IP forwarding (`bungeecord: true` in spigot.yml).
+    Legacy,
+}
+
+impl ForwardingMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ForwardingMode::Modern => "modern",
+            ForwardingMode::Legacy => "legacy",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct ProxyBackend {
+    /// Name players see in `/server`.
+    pub name: String,
+    /// Managed server on this node that the name points at.
+    pub server_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct BedrockConfig {
+    /// UDP port Geyser listens on for Bedrock clients.
+    #[serde(default = "default_bedrock_port")]
+    pub port: u16,
+    /// Also install Floodgate so Bedrock players need no Java account.
+    #[serde(default = "default_true")]
+    pub floodgate: bool,
+}
+
+fn default_bedrock_port() -> u16 {
+    19132
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct WatchdogConfig {
+    /// Seconds without console output before the server counts as hung.
+    /// `probe_command` is sent halfway through so idle servers still answer.
+    pub timeout_secs: u64,
+    #[serde(default = "default_watchdog_probe_command")]
+    pub probe_command: String,
+    #[serde(default)]
+    pub action: WatchdogAction,
+}
+
+fn default_watchdog_probe_command() -> String {
+    "list".to_string()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default, utoipa::ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum WatchdogAction {
+    /// Log a thread dump to the console, then restart the server.
+    #[default]
+    Restart,
+    /// Only log a warning and a thread dump.
+    Notify,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct RestartPolicy {
+    pub mode: RestartMode,
+    /// Consecutive restarts allowed before giving up; unlimited when absent.
+    /// The count resets once a restarted server finishes starting.
+    #[serde(default)]
+    pub max_retries: Option<u32>,
+    #[serde(default = "default_restart_delay_secs")]
+    pub delay_secs: u64,
+}
+
+fn default_restart_delay_secs() -> u64 {
+    5
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartMode {
+    Never,
+    /// Restart only after a crash (non-zero exit code or a signal).
+    OnFailure,
+    /// Restart after any exit that was not requested through the agent.
+    Always,
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum OrphanPolicy {
+    /// Keep it running as this server, with a read-only console that
+    /// follows `logs/latest.log`. Stopping it sends SIGTERM.
+    Adopt,
+    /// Kill it, as agents always did before the policy existed.
+    #[default]
+    Kill,
+    /// Leave it running, unmanaged; starting the server will then fail on
+    /// its port.
+    Ignore,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct StopStep {
+    pub command: String,
+    /// Regex matched against console output after the command is sent.
+    #[serde(default)]
+    pub expect: Option<String>,
+    /// How long to wait for `expect` before moving on anyway.
+    #[serde(default = "default_expect_timeout_ms")]
+    pub expect_timeout_ms: u64,
+    /// Pause after this step before the next one is sent.
+    #[serde(default)]
+    pub wait_ms: u64,
+}
+
+fn default_expect_timeout_ms() -> u64 {
+    10_000
+}
+
+/// A message broadcast every `interval_minutes` or on a `cron` schedule.
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct Announcement {
+    pub id: String,
+    pub message: String,
+    #[serde(default)]
+    pub interval_minutes: Option<u32>,
+    /// Five cron fields, or six starting with seconds, in the agent's
+    /// local time, e.g. "0 */2 * * *" for every other hour.
+    #[serde(default)]
+    pub cron: Option<String>,
+    #[serde(default)]
+    pub command: BroadcastCommand,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+}
+
+impl Announcement {
+    /// The parsed `cron` schedule.
+    pub fn schedule(&self) -> Option<Result<cron::Schedule, String>> {
+        let expr = self.cron.as_deref()?;
+        // The cron crate wants a seconds field
+        let expr = if expr.split_whitespace().count() == 5 {
+            format!("0 {}", expr)
+        } else {
+            expr.to_string()
+        };
+        // Errors draw a caret under the bad field; the last line says what is wrong
+        Some(expr.parse::<cron::Schedule>().map_err(|e| {
+            let message = e.to_string();
+            message.lines().last().unwrap_or_default().trim().to_string()
+        }))
+    }
+}
+
+pub fn validate_announcement(announcement: &Announcement) -> Result<(), String> {
+    let id = &announcement.id;
+    let valid_char = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if id.is_empty() || id.len() > 64 || !id.chars().all(valid_char) {
+        return Err(format!("announcement id '{}' must be 1-64 letters, digits, '-' or '_'", id));
+    }
+    let message = &announcement.message;
+    if message.trim().is_empty() || message.contains('\n') || message.contains('\r') {
+        return Err(format!("announcement '{}' needs a single-line message", id));
+    }
+    match (announcement.interval_minutes, announcement.schedule()) {
+        (Some(_), Some(_)) | (None, None) => Err(format!(
+            "announcement '{}' needs exactly one of interval_minutes and cron",
+            id
+        )),
+        (Some(0), None) => Err(format!("announcement '{}' interval_minutes must be positive", id)),
+        (None, Some(Err(e))) => Err(format!("announcement '{}' has an invalid cron: {}", id, e)),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct DiscordBridgeConfig {
+    /// Bot token, best given as `${DISCORD_TOKEN}`. The bot needs the
+    /// Message Content intent to read what is written in the channel.
+    pub token: String,
+    pub channel_id: String,
+    /// How Discord messages are shown in game.
+    #[serde(default)]
+    pub relay_command: BroadcastCommand,
+    /// Also posts players joining and leaving.
+    #[serde(default = "default_true")]
+    pub announce_joins: bool,
+}
+
+/// The command the agent shows its own messages in game with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BroadcastCommand {
+    /// A coloured line, without the `[Server]` prefix of `say`.
+    #[default]
+    Tellraw,
+    /// `say`, for servers whose plugins filter `tellraw`.
+    Say,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct TriggerRule {
+    /// Regex matched against each console line, without colour codes.
+    pub pattern: String,
+    pub action: TriggerAction,
+    /// Matches within this long after the rule fired are ignored.
+    #[serde(default = "default_trigger_cooldown_secs")]
+    pub cooldown_secs: u64,
+}
+
+fn default_trigger_cooldown_secs() -> u64 {
+    60
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum TriggerAction {
+    /// Sends a console command; `$1` inserts the first capture group. Named
+    /// groups are written `$${name}`, as `${` starts an environment variable.
+    /// A line whose captures hold more than letters, digits and `_` sends
+    /// nothing, since they may be chat.
+    Command { command: String },
+    /// POSTs the server id, the rule's pattern and the line as JSON.
+    Webhook { url: String },
+    Restart,
+    Backup,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateChannel {
+    Stable,
+    Beta,
+}
+
+/// Tuned flag sets expanded from `memory_mb` and `min_memory_mb` at launch.
+/// `custom` adds no flags at all, leaving heap sizing entirely to `jvm_args`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum JvmProfile {
+    #[default]
+    Default,
+    Aikar,
+    Zgc,
+    Custom,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct CommandMacro {
+    pub name: String,
+    pub steps: Vec<MacroStep>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct MacroStep {
+    pub command: String,
+    /// Pause before this step is sent.
+    #[serde(default)]
+    pub delay_ms: u64,
+}
+
+fn default_tps_commands() -> Vec<String> {
+    vec!["tps".to_string(), "mspt".to_string()]
+}
+
+
+fn default_console_buffer_lines() -> usize {
+    500
+}
+
+fn default_console_replay_lines() -> usize {
+    100
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -39,33 +996,232 @@ pub struct Config {
     pub agent: AgentConfig,
     #[serde(default)]
     pub servers: Vec<ServerConfig>,
+    /// Other agents managed through this one.
+    #[serde(default)]
+    pub nodes: Vec<NodeConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct NodeConfig {
+    pub id: String,
+    /// Base URL of the agent, e.g. "https://10.0.0.12:8080".
+    pub url: String,
+    /// Sent as a bearer token on every request to the agent.
+    #[serde(default)]
+    pub token: Option<String>,
+    /// Allows an `http://` URL, which sends the token in the clear.
+    #[serde(default)]
+    pub allow_insecure: bool,
+}
+
+pub fn validate_agent_config(agent: &AgentConfig) -> Result<(), String> {
+    let filter = &agent.ip_filter;
+    for entry in filter.allow.iter().chain(&filter.deny).chain(&filter.trusted_proxies) {
+        if crate::ipfilter::parse_range(entry).is_none() {
+            return Err(format!("ip_filter entry '{}' is not an address or CIDR range", entry));
+        }
+    }
+    for (i, user) in agent.users.iter().enumerate() {
+        crate::sessions::validate_user(user)?;
+        if agent.users[..i].iter().any(|u| u.username == user.username) {
+            return Err(format!("user '{}' is listed twice", user.username));
+        }
+    }
+    if let Some(ref url) = agent.public_url {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(format!("public_url '{}' must be an http(s) URL", url));
+        }
+    }
+    for (name, properties) in &agent.property_profiles {
+        let valid_char = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if name.is_empty() || name.len() > 32 || !name.chars().all(valid_char) {
+            return Err(format!(
+                "property profile '{}' must be named with 1-32 letters, digits, '-' or '_'",
+                name
+            ));
+        }
+        for key in properties.keys() {
+            crate::properties::validate_setting(key)
+                .map_err(|e| format!("property profile '{}': {}", name, e))?;
+        }
+    }
+    Ok(())
+}
+
+pub fn validate_node_config(node: &NodeConfig) -> Result<(), String> {
+    if node.id.is_empty() || node.id == "local" || node.id.contains('/') {
+        return Err("node id must be non-empty, not 'local', and must not contain '/'".to_string());
+    }
+    if node.url.starts_with("http://") {
+        if !node.allow_insecure {
+            return Err("node url must use https:// unless allow_insecure is set".to_string());
+        }
+    } else if !node.url.starts_with("https://") {
+        return Err("node url must start with http:// or https://".to_string());
+    }
+    Ok(())
+}
+
+/// One problem per line, so every mistake in the file shows up at once.
+fn file_errors(action: &str, errors: Vec<String>) -> anyhow::Error {
+    anyhow::anyhow!("{} {}:\n  {}", action, config_path().display(), errors.join("\n  "))
+}
+
+fn apply_overrides(config: &mut Config) {
+    let overrides = overrides();
+    if let Some(dir) = &overrides.data_directory {
+        config.agent.data_directory = dir.clone();
+    }
+    if let Some(address) = &overrides.bind_address {
+        config.agent.bind_address = address.clone();
+    }
+}
+
+/// The config as it is written back: overridden settings keep the file's
+/// values, strings resolved from `${VAR}` placeholders get the placeholders
+/// back, and any other `${` is escaped.
+async fn for_file(config: &Config) -> anyhow::Result<Config> {
+    let overrides = overrides();
+    let mut config = config.clone();
+    let file = read_file().await.ok();
+    let file_agent = file.as_ref().map(|c| c.agent.clone()).unwrap_or_default();
+    if overrides.data_directory.is_some() {
+        config.agent.data_directory = file_agent.data_directory;
+    }
+    if overrides.bind_address.is_some() {
+        config.agent.bind_address = file_agent.bind_address;
+    }
+    let file = match file {
+        Some(file) => serde_json::to_value(&file)?,
+        None => serde_json::Value::Null,
+    };
+    let mut value = serde_json::to_value(&config).context("Failed to serialize config")?;
+    envsubst::restore(&mut value, &file);
+    serde_json::from_value(value).context("Failed to serialize config")
+}
+
+/// The file as written, placeholders and all.
+async fn read_file() -> anyhow::Result<Config> {
+    let path = config_path();
+    let contents = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    schema::parse(ConfigFormat::from_path(path), &contents)
+        .map_err(|errors| file_errors("Failed to parse", errors))
+}
+
+/// Reads and parses the config file, failing when it is missing.
+pub async fn read_config() -> anyhow::Result<Config> {
+    let file = read_file().await?;
+    let mut value = serde_json::to_value(&file).context("Failed to serialize config")?;
+    envsubst::substitute(&mut value)
+        .map_err(|e| anyhow::anyhow!("{}: {}", config_path().display(), e))?;
+    let mut config: Config = serde_json::from_value(value)
+        .with_context(|| format!("Failed to parse {}", config_path().display()))?;
+    let mut errors = schema::conflicts(&config);
+    if let Err(e) = validate_agent_config(&config.agent) {
+        errors.push(format!("agent: {}", e));
+    }
+    if !errors.is_empty() {
+        return Err(file_errors("Invalid settings in", errors));
+    }
+    apply_overrides(&mut config);
+    Ok(config)
 }
 
 pub async fn load_config() -> anyhow::Result<Config> {
-    match tokio::fs::read_to_string(CONFIG_PATH).await {
-        Ok(contents) => {
-            let config: Config = serde_json::from_str(&contents)
-                .context("Failed to parse config.json")?;
-            Ok(config)
+    if !config_path().exists() {
+        tracing::info!("{} not found, using defaults", config_path().display());
+        let mut config = Config::default();
+        apply_overrides(&mut config);
+        return Ok(config);
+    }
+    read_config().await
+}
+
+/// Carries the values of `new` into `old` while keeping the comments and
+/// layout of keys that are still present.
+fn merge_toml_table(old: &mut toml_edit::Table, new: toml_edit::Table) {
+    let removed: Vec<String> = old
+        .iter()
+        .map(|(k, _)| k.to_string())
+        .filter(|k| !new.contains_key(k))
+        .collect();
+    for key in removed {
+        old.remove(&key);
+    }
+    for (key, item) in new {
+        match old.get_mut(&key) {
+            Some(existing) => merge_toml_item(existing, item),
+            None => {
+                old.insert(&key, item);
+            }
         }
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-            tracing::info!("Config file not found, using defaults");
-            Ok(Config::default())
+    }
+}
+
+fn merge_toml_item(old: &mut toml_edit::Item, new: toml_edit::Item) {
+    use toml_edit::Item;
+    match (old, new) {
+        (Item::Table(old), Item::Table(new)) => merge_toml_table(old, new),
+        (Item::ArrayOfTables(old), Item::ArrayOfTables(new)) => {
+            let len = new.len();
+            for (i, table) in new.into_iter().enumerate() {
+                match old.get_mut(i) {
+                    Some(existing) => merge_toml_table(existing, table),
+                    None => old.push(table),
+                }
+            }
+            while old.len() > len {
+                old.remove(old.len() - 1);
+            }
+        }
+        (Item::Value(old), Item::Value(new)) => {
+            let decor = old.decor().clone();
+            *old = new;
+            *old.decor_mut() = decor;
+        }
+        (old, new) => *old = new,
+    }
+}
+
+async fn serialize_config(path: &Path, config: &Config) -> anyhow::Result<String> {
+    match ConfigFormat::from_path(path) {
+        ConfigFormat::Json => {
+            serde_json::to_string_pretty(config).context("Failed to serialize config")
+        }
+        ConfigFormat::Yaml => serde_yaml::to_string(config).context("Failed to serialize config"),
+        ConfigFormat::Toml => {
+            let fresh: toml_edit::DocumentMut = toml::to_string_pretty(config)
+                .context("Failed to serialize config")?
+                .parse()
+                .context("Failed to re-read serialized config")?;
+            // Hand-written comments survive as long as the file still parses
+            let existing = tokio::fs::read_to_string(path).await.ok();
+            let Some(mut doc) = existing.and_then(|c| c.parse::<toml_edit::DocumentMut>().ok())
+            else {
+                return Ok(fresh.to_string());
+            };
+            merge_toml_table(doc.as_table_mut(), fresh.as_table().clone());
+            Ok(doc.to_string())
         }
-        Err(e) => Err(e).context("Failed to read config.json"),
     }
 }
 
 pub async fn save_config(config: &Config) -> anyhow::Result<()> {
-    if let Some(parent) = std::path::Path::new(CONFIG_TMP_PATH).parent() {
+    let path = config_path();
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    if let Some(parent) = tmp_path.parent().filter(|p| !p.as_os_str().is_empty()) {
         tokio::fs::create_dir_all(parent).await.context("Failed to create config directory")?;
     }
 
-    let json = serde_json::to_string_pretty(config).context("Failed to serialize config")?;
-    let mut file = tokio::fs::File::create(CONFIG_TMP_PATH)
+    let contents = serialize_config(path, &for_file(config).await?).await?;
+    let mut file = tokio::fs::File::create(&tmp_path)
         .await
         .context("Failed to create temp config file")?;
-    file.write_all(json.as_bytes())
+    file.write_all(contents.as_bytes())
         .await
         .context("Failed to write temp config file")?;
     file.flush().await.context("Failed to flush temp config file")?;
@@ -73,11 +1229,11 @@ pub async fn save_config(config: &Config) -> anyhow::Result<()> {
 
     // fsync synchronously before rename
     {
-        let f = std::fs::File::open(CONFIG_TMP_PATH).context("Failed to open tmp for fsync")?;
+        let f = std::fs::File::open(&tmp_path).context("Failed to open tmp for fsync")?;
         f.sync_all().context("Failed to fsync tmp config file")?;
     }
 
-    tokio::fs::rename(CONFIG_TMP_PATH, CONFIG_PATH)
+    tokio::fs::rename(&tmp_path, path)
         .await
         .context("Failed to rename tmp config to final")?;
 
@@ -88,6 +1244,15 @@ pub fn validate_server_config(cfg: &ServerConfig) -> Result<(), String> {
     if cfg.memory_mb < 512 || cfg.memory_mb > 32768 {
         return Err("memory_mb must be between 512 and 32768".to_string());
     }
+    if cfg.min_memory_mb.is_some_and(|m| m < 128 || m > cfg.memory_mb) {
+        return Err("min_memory_mb must be between 128 and memory_mb".to_string());
+    }
+    if cfg.min_memory_mb.is_some() && cfg.jvm_profile == JvmProfile::Custom {
+        return Err("min_memory_mb has no effect with jvm_profile custom; use jvm_args".to_string());
+    }
+    if cfg.cpu_limit.is_some_and(|c| !(0.01..=1024.0).contains(&c)) {
+        return Err("cpu_limit must be between 0.01 and 1024".to_string());
+    }
     if cfg.port < 1024 {
         return Err("port must be between 1024 and 65535".to_string());
     }
@@ -100,6 +1265,45 @@ pub fn validate_server_config(cfg: &ServerConfig) -> Result<(), String> {
     if cfg.id.contains('/') || cfg.id.contains('\\') || cfg.id.contains("..") {
         return Err("id must not contain '/', '\\', or '..'".to_string());
     }
+    if cfg.desired_state == Some(DesiredState::Running)
+        && cfg.idle_shutdown_minutes.is_some()
+        && !cfg.wake_on_demand
+    {
+        return Err(
+            "desired_state 'running' needs wake_on_demand to use idle_shutdown_minutes".to_string(),
+        );
+    }
+    if cfg.desired_state == Some(DesiredState::Stopped) && cfg.wake_on_demand {
+        return Err("desired_state 'stopped' cannot be combined with wake_on_demand".to_string());
+    }
+    for (i, tag) in cfg.tags.iter().enumerate() {
+        let valid_char = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if tag.is_empty() || tag.len() > 32 || !tag.chars().all(valid_char) {
+            return Err(format!("tag '{}' must be 1-32 letters, digits, '-' or '_'", tag));
+        }
+        if cfg.tags[..i].contains(tag) {
+            return Err(format!("tag '{}' is listed twice", tag));
+        }
+    }
+    if let Some(ref description) = cfg.description {
+        if description.chars().count() > 200 || description.chars().any(char::is_control) {
+            return Err("description must be a single line of at most 200 characters".to_string());
+        }
+    }
+    if let Some(ref notes) = cfg.notes {
+        let control = |c: char| c.is_control() && !matches!(c, '\n' | '\r' | '\t');
+        if notes.chars().count() > 4000 || notes.chars().any(control) {
+            return Err("notes must be at most 4000 characters of text".to_string());
+        }
+    }
+    if let Some(ref icon) = cfg.icon {
+        if icon.is_empty()
+            || icon.chars().count() > 256
+            || icon.chars().any(|c| c.is_control() || c.is_whitespace())
+        {
+            return Err("icon must be 1-256 characters without spaces".to_string());
+        }
+    }
     if !std::path::Path::new(&cfg.directory).exists() {
         return Err(format!("directory '{}' does not exist", cfg.directory));
     }
@@ -108,5 +1312,191 @@ pub fn validate_server_config(cfg: &ServerConfig) -> Result<(), String> {
             return Err("backup_directory must not contain '..'".to_string());
         }
     }
+    if let Some(ref java_path) = cfg.java_path {
+        if !std::path::Path::new(java_path).is_file() {
+            return Err(format!("java_path '{}' does not exist", java_path));
+        }
+    }
+    if let Some(version) = cfg.java_version {
+        if !crate::runtimes::SUPPORTED_JAVA_VERSIONS.contains(&version) {
+            return Err(format!(
+                "java_version must be one of {:?}",
+                crate::runtimes::SUPPORTED_JAVA_VERSIONS
+            ));
+        }
+    }
+    if cfg.jvm_args.iter().any(|a| a.trim().is_empty()) {
+        return Err("jvm_args must not contain empty arguments".to_string());
+    }
+    if cfg.jvm_args.iter().any(|a| a == "-jar") {
+        return Err("jvm_args must not contain '-jar'".to_string());
+    }
+    if let Some(ref command) = cfg.command {
+        if command.first().is_none_or(|program| program.trim().is_empty()) {
+            return Err("command must start with a non-empty program".to_string());
+        }
+    }
+    if let Some(ref docker) = cfg.docker {
+        if docker.image.trim().is_empty() {
+            return Err("docker image must not be empty".to_string());
+        }
+        if cfg.java_path.is_some() {
+            return Err("java_path cannot be used with docker; the image provides java".to_string());
+        }
+        let inside = |host: &str| {
+            let host = Path::new(host);
+            (host.is_relative() || host.starts_with(&cfg.directory))
+                && host.components().all(|c| !matches!(c, std::path::Component::ParentDir))
+        };
+        let valid = |m: &String| m.split_once(':').is_some_and(|(host, _)| inside(host));
+        if !docker.mounts.iter().all(valid) {
+            return Err(
+                "docker mounts must be 'host:container' with host inside the server directory"
+                    .to_string(),
+            );
+        }
+    }
+    match (cfg.flavor, cfg.proxy.is_some()) {
+        (Some(ServerFlavor::Proxy), false) => {
+            return Err("proxy servers need a proxy section".to_string());
+        }
+        (Some(flavor), true) if flavor != ServerFlavor::Proxy => {
+            return Err(format!("{} servers cannot have a proxy section", flavor.as_str()));
+        }
+        _ => {}
+    }
+    if cfg.update_channel.is_some() && cfg.flavor() != ServerFlavor::Paper {
+        return Err("update_channel is only supported for paper servers".to_string());
+    }
+    if cfg.bedrock.is_some() && !matches!(cfg.flavor(), ServerFlavor::Paper | ServerFlavor::Proxy) {
+        return Err("bedrock needs a paper or proxy server".to_string());
+    }
+    if let Some(ref proxy) = cfg.proxy {
+        if proxy.software == ProxySoftware::Bungeecord
+            && proxy.forwarding_mode() == ForwardingMode::Modern
+        {
+            return Err("bungeecord proxies only support legacy forwarding".to_string());
+        }
+        for (i, backend) in proxy.backends.iter().enumerate() {
+            if backend.name.is_empty()
+                || !backend.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(
+                    "proxy backend names must be non-empty and use only letters, digits, '-' or '_'"
+                        .to_string(),
+                );
+            }
+            if proxy.backends[..i].iter().any(|other| other.name == backend.name) {
+                return Err(format!("duplicate proxy backend name '{}'", backend.name));
+            }
+            if backend.server_id == cfg.id {
+                return Err("a proxy cannot be its own backend".to_string());
+            }
+        }
+    }
+    if let Some(ref bedrock) = cfg.bedrock {
+        if bedrock.port < 1024 {
+            return Err("bedrock port must be between 1024 and 65535".to_string());
+        }
+    }
+    if cfg.idle_shutdown_minutes == Some(0) {
+        return Err("idle_shutdown_minutes must be greater than 0".to_string());
+    }
+    if let Some(ref policy) = cfg.restart_policy {
+        if policy.delay_secs > 3600 {
+            return Err("restart_policy delay_secs must be at most 3600".to_string());
+        }
+    }
+    if let Some(ref watchdog) = cfg.watchdog {
+        if watchdog.timeout_secs < 10 {
+            return Err("watchdog timeout_secs must be at least 10".to_string());
+        }
+        if watchdog.probe_command.trim().is_empty()
+            || watchdog.probe_command.contains('\n')
+            || watchdog.probe_command.contains('\r')
+        {
+            return Err("watchdog probe_command must be a non-empty single line".to_string());
+        }
+    }
+    if cfg.hooks.timeout_secs == 0 || cfg.hooks.timeout_secs > 3600 {
+        return Err("hooks timeout_secs must be between 1 and 3600".to_string());
+    }
+    if cfg.egress_limit_kbit == Some(0) {
+        return Err("egress_limit_kbit must be greater than 0".to_string());
+    }
+    if cfg.tps_commands.iter().any(|c| c.contains('\n') || c.contains('\r')) {
+        return Err("tps_commands must not contain line breaks".to_string());
+    }
+    for (i, announcement) in cfg.announcements.iter().enumerate() {
+        validate_announcement(announcement)?;
+        if cfg.announcements[..i].iter().any(|a| a.id == announcement.id) {
+            return Err(format!("announcement '{}' is listed twice", announcement.id));
+        }
+    }
+    let valid_group = |group: &str| {
+        let valid_char = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        !group.is_empty() && group.len() <= 32 && group.chars().all(valid_char)
+    };
+    if cfg.whitelist_group.as_deref().is_some_and(|g| !valid_group(g)) {
+        return Err("whitelist_group must be 1-32 letters, digits, '-' or '_'".to_string());
+    }
+    if cfg.ban_group.as_deref().is_some_and(|g| !valid_group(g)) {
+        return Err("ban_group must be 1-32 letters, digits, '-' or '_'".to_string());
+    }
+    if let Some(ref discord) = cfg.discord {
+        if discord.token.trim().is_empty() {
+            return Err("discord token must not be empty".to_string());
+        }
+        let numeric = discord.channel_id.chars().all(|c| c.is_ascii_digit());
+        if discord.channel_id.is_empty() || !numeric {
+            return Err("discord channel_id must be a numeric channel id".to_string());
+        }
+    }
+    for trigger in &cfg.triggers {
+        regex::Regex::new(&trigger.pattern)
+            .map_err(|e| format!("invalid trigger pattern '{}': {}", trigger.pattern, e))?;
+        match trigger.action {
+            TriggerAction::Command { ref command }
+                if command.trim().is_empty() || command.contains('\n') || command.contains('\r') =>
+            {
+                return Err("trigger commands must be non-empty single lines".to_string());
+            }
+            TriggerAction::Webhook { ref url }
+                if !url.starts_with("http://") && !url.starts_with("https://") =>
+            {
+                return Err(format!("trigger webhook '{}' must be an http(s) URL", url));
+            }
+            _ => {}
+        }
+    }
+    for step in &cfg.stop_steps {
+        if step.command.trim().is_empty() || step.command.contains('\n') || step.command.contains('\r') {
+            return Err("stop_steps commands must be non-empty single lines".to_string());
+        }
+        if let Some(ref pattern) = step.expect {
+            regex::Regex::new(pattern)
+                .map_err(|e| format!("invalid stop_steps expect pattern '{}': {}", pattern, e))?;
+        }
+    }
+    if cfg.console_buffer_lines == 0 || cfg.console_buffer_lines > 100_000 {
+        return Err("console_buffer_lines must be between 1 and 100000".to_string());
+    }
+    if cfg.console_replay_lines > cfg.console_buffer_lines {
+        return Err("console_replay_lines must not exceed console_buffer_lines".to_string());
+    }
+    for (i, m) in cfg.macros.iter().enumerate() {
+        if m.name.is_empty() || m.name.contains('/') {
+            return Err("macro name must be non-empty and must not contain '/'".to_string());
+        }
+        if cfg.macros[..i].iter().any(|other| other.name == m.name) {
+            return Err(format!("duplicate macro name '{}'", m.name));
+        }
+        if m.steps.is_empty() {
+            return Err(format!("macro '{}' must have at least one step", m.name));
+        }
+        if m.steps.iter().any(|step| step.command.contains('\n') || step.command.contains('\r')) {
+            return Err(format!("macro '{}' commands must not contain line breaks", m.name));
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,300 @@
+//! Where a server's console input goes and its output comes from. A process
+//! the agent holds on to directly is talked to over pipes, whether its own
+//! or a container attach; a detached one through the FIFO it reads and the
+//! log it writes. A server adopted after an agent crash can only be
+//! followed through its `logs/latest.log`. `ServerInstance` only sees the
+//! backend, so the console WebSocket, commands and stop steps work the same
+//! for all of them, and for a server an earlier agent spawned.
+
+use std::{path::PathBuf, pin::Pin, process::ExitStatus, sync::OnceLock, time::Duration};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt, DuplexStream},
+    sync::watch,
+};
+
+/// How often a tailed log is checked for new output.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The start of an INFO line the server wrote itself, vanilla
+/// `[12:00:00] [Server thread/INFO]: ` or Paper `[12:00:00 INFO]: `.
+/// Player chat only ever follows it as `<name> text`, so patterns anchored
+/// on it can't be matched by something a player typed.
+pub const INFO_PREFIX: &str = r"^\[\d{2}:\d{2}:\d{2}(?:\] \[[^\]]*/| )INFO\]: ";
+
+/// Where console commands are written.
+pub type ConsoleInput = Pin<Box<dyn AsyncWrite + Send>>;
+/// What the server prints, read line by line.
+pub type ConsoleOutput = Pin<Box<dyn AsyncRead + Send>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ConsoleStream {
+    Stdout,
+    Stderr,
+}
+
+/// Severity from a log line's prefix, least severe first.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, utoipa::ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
+}
+
+impl LogLevel {
+    /// The level in a vanilla `[12:00:00] [Server thread/WARN]: ` or a
+    /// Paper `[12:00:00 WARN]: ` prefix. Lines without one, such as stack
+    /// traces, have none.
+    pub fn parse(line: &str) -> Option<Self> {
+        static RE: OnceLock<Regex> = OnceLock::new();
+        let re = RE.get_or_init(|| {
+            Regex::new(r"^\[\d{2}:\d{2}:\d{2}(?:\] \[[^\]]*/| )([A-Z]+)\]").expect("valid regex")
+        });
+        let line = if line.contains('\x1b') {
+            std::borrow::Cow::Owned(crate::ansi::strip(line))
+        } else {
+            std::borrow::Cow::Borrowed(line)
+        };
+        let level = re.captures(&line)?;
+        match &level[1] {
+            "TRACE" => Some(LogLevel::Trace),
+            "DEBUG" => Some(LogLevel::Debug),
+            "INFO" => Some(LogLevel::Info),
+            "WARN" | "WARNING" => Some(LogLevel::Warn),
+            "ERROR" | "SEVERE" => Some(LogLevel::Error),
+            "FATAL" => Some(LogLevel::Fatal),
+            _ => None,
+        }
+    }
+}
+
+/// One line of output as it was captured.
+#[derive(Debug, Clone)]
+pub struct ConsoleLine {
+    pub text: String,
+    pub stream: ConsoleStream,
+    pub timestamp_ms: u64,
+    pub level: Option<LogLevel>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ConsoleKind {
+    /// The process's stdin and stdout, or a container attach.
+    Pipe,
+    /// A FIFO for input and a tailed log file for output.
+    FifoTail,
+    /// Output from the server's log file only; commands can't be sent.
+    ReadOnly,
+}
+
+pub trait ConsoleBackend: Send {
+    fn kind(&self) -> ConsoleKind;
+
+    /// Where command lines are written, unless the console is read-only.
+    fn input(&mut self) -> Option<&mut ConsoleInput>;
+
+    /// The output streams. Handed out once, to the console readers, when
+    /// the instance is attached; later calls return nothing. Output that
+    /// mixes stdout and stderr counts as stdout.
+    fn take_output(&mut self) -> Vec<(ConsoleStream, ConsoleOutput)>;
+}
+
+pub struct Pipe {
+    input: ConsoleInput,
+    output: Vec<(ConsoleStream, ConsoleOutput)>,
+}
+
+impl Pipe {
+    pub fn new(
+        stdin: impl AsyncWrite + Send + 'static,
+        stdout: impl AsyncRead + Send + 'static,
+        stderr: impl AsyncRead + Send + 'static,
+    ) -> Self {
+        Self {
+            input: Box::pin(stdin),
+            output: vec![
+                (ConsoleStream::Stdout, Box::pin(stdout)),
+                (ConsoleStream::Stderr, Box::pin(stderr)),
+            ],
+        }
+    }
+}
+
+impl ConsoleBackend for Pipe {
+    fn kind(&self) -> ConsoleKind {
+        ConsoleKind::Pipe
+    }
+
+    fn input(&mut self) -> Option<&mut ConsoleInput> {
+        Some(&mut self.input)
+    }
+
+    fn take_output(&mut self) -> Vec<(ConsoleStream, ConsoleOutput)> {
+        std::mem::take(&mut self.output)
+    }
+}
+
+/// A detached server's console. Stdout and stderr share the log, so there
+/// is a single output stream.
+#[cfg(unix)]
+pub struct FifoTail {
+    fifo: ConsoleInput,
+    tail: Option<ConsoleOutput>,
+}
+
+#[cfg(unix)]
+impl FifoTail {
+    pub fn new(
+        fifo: tokio::net::unix::pipe::Sender,
+        tail: impl AsyncRead + Send + 'static,
+    ) -> Self {
+        Self {
+            fifo: Box::pin(fifo),
+            tail: Some(Box::pin(tail)),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl ConsoleBackend for FifoTail {
+    fn kind(&self) -> ConsoleKind {
+        ConsoleKind::FifoTail
+    }
+
+    fn input(&mut self) -> Option<&mut ConsoleInput> {
+        Some(&mut self.fifo)
+    }
+
+    fn take_output(&mut self) -> Vec<(ConsoleStream, ConsoleOutput)> {
+        self.tail.take().map(|tail| (ConsoleStream::Stdout, tail)).into_iter().collect()
+    }
+}
+
+/// The console of a process the agent has no handle on.
+pub struct ReadOnly {
+    log: Option<ConsoleOutput>,
+}
+
+impl ReadOnly {
+    pub fn new(log: impl AsyncRead + Send + 'static) -> Self {
+        Self {
+            log: Some(Box::pin(log)),
+        }
+    }
+}
+
+impl ConsoleBackend for ReadOnly {
+    fn kind(&self) -> ConsoleKind {
+        ConsoleKind::ReadOnly
+    }
+
+    fn input(&mut self) -> Option<&mut ConsoleInput> {
+        None
+    }
+
+    fn take_output(&mut self) -> Vec<(ConsoleStream, ConsoleOutput)> {
+        self.log.take().map(|log| (ConsoleStream::Stdout, log)).into_iter().collect()
+    }
+}
+
+/// Streams a log file from `start` until `exit` is set and everything
+/// written before has been read. Starting mid-file skips to the next full
+/// line. A file that shrinks was truncated or rotated and is followed from
+/// its start again. With `truncate_after`, the file is emptied once that
+/// much has been read; the writer must have it open in append mode, and
+/// anything written between the last read and the truncation is lost.
+pub fn tail_file(
+    path: PathBuf,
+    start: u64,
+    mut exit: watch::Receiver<Option<ExitStatus>>,
+    truncate_after: Option<u64>,
+) -> DuplexStream {
+    let (reader, mut writer) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        let Ok(mut file) = tokio::fs::File::open(&path).await else {
+            return;
+        };
+        let mut offset = start;
+        if file.seek(std::io::SeekFrom::Start(offset)).await.is_err() {
+            return;
+        }
+        let mut partial = start > 0;
+        let mut buf = vec![0u8; 8192];
+        while let Ok(n) = file.read(&mut buf).await {
+            if n > 0 {
+                offset += n as u64;
+                let mut chunk = &buf[..n];
+                if partial {
+                    match chunk.iter().position(|b| *b == b'\n') {
+                        Some(i) => {
+                            chunk = &chunk[i + 1..];
+                            partial = false;
+                        }
+                        None => continue,
+                    }
+                }
+                if writer.write_all(chunk).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            if exit.borrow().is_some() {
+                break;
+            }
+            if truncate_after.is_some_and(|limit| offset >= limit) {
+                let truncated = std::fs::OpenOptions::new()
+                    .write(true)
+                    .open(&path)
+                    .and_then(|f| f.set_len(0));
+                if truncated.is_ok() && file.seek(std::io::SeekFrom::Start(0)).await.is_ok() {
+                    offset = 0;
+                }
+            } else if tokio::fs::metadata(&path).await.is_ok_and(|m| m.len() < offset) {
+                if let Ok(reopened) = tokio::fs::File::open(&path).await {
+                    file = reopened;
+                    offset = 0;
+                }
+            }
+            let _ = tokio::time::timeout(POLL_INTERVAL, exit.changed()).await;
+        }
+    });
+    reader
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_are_read_from_vanilla_and_paper_prefixes() {
+        let line = "[12:00:00] [Server thread/WARN]: Can't keep up!";
+        assert_eq!(LogLevel::parse(line), Some(LogLevel::Warn));
+        let line = "[12:00:00 ERROR]: Could not pass event";
+        assert_eq!(LogLevel::parse(line), Some(LogLevel::Error));
+        let line = "[12:00:00] [Server thread/SEVERE]: old Bukkit";
+        assert_eq!(LogLevel::parse(line), Some(LogLevel::Error));
+    }
+
+    #[test]
+    fn coloured_prefixes_are_read() {
+        let line = "\x1b[33m[12:00:00 WARN]: \x1b[0mslow tick";
+        assert_eq!(LogLevel::parse(line), Some(LogLevel::Warn));
+    }
+
+    #[test]
+    fn lines_without_a_prefix_have_no_level() {
+        assert_eq!(LogLevel::parse("\tat java.lang.Thread.run(Thread.java:833)"), None);
+        assert_eq!(LogLevel::parse("[12:00:00 NOTICE]: unknown level"), None);
+        assert_eq!(LogLevel::parse("player said [12:00:00 INFO]"), None);
+    }
+}
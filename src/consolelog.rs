@@ -0,0 +1,131 @@
+//! Console output kept on disk by the agent, apart from whatever the server
+//! writes to its own `logs/`: every line it captured, after redaction and
+//! with a timestamp, in `<data_directory>/console/<server id>/console.log`.
+//! The file is rotated to `console.log.1`, `.2` and so on once it reaches
+//! the size cap, so nothing printed while no client was watching is lost.
+//! Should the disk fall behind, lines are dropped rather than queued without
+//! bound, and the file notes how many.
+
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use tokio::{
+    fs::File,
+    io::{AsyncWriteExt, BufWriter},
+    sync::mpsc,
+};
+
+const CONSOLE_DIR: &str = "console";
+const LOG_FILE: &str = "console.log";
+/// Lines waiting for the writer before further ones are dropped.
+const QUEUE_LINES: usize = 4096;
+
+pub fn log_dir(data_directory: &str, server_id: &str) -> PathBuf {
+    Path::new(data_directory).join(CONSOLE_DIR).join(server_id)
+}
+
+/// Hands lines to a writer task, so a slow disk never holds up the console
+/// readers. The task finishes writing and exits once this is dropped.
+pub struct ConsoleLog {
+    tx: mpsc::Sender<String>,
+    /// Lines dropped since the writer last caught up.
+    dropped: Arc<AtomicU64>,
+}
+
+impl ConsoleLog {
+    /// `None` when `max_bytes` is 0, i.e. persistence is off.
+    pub fn open(data_directory: &str, server_id: &str, max_bytes: u64, files: u32) -> Option<Self> {
+        if max_bytes == 0 {
+            return None;
+        }
+        let (tx, rx) = mpsc::channel(QUEUE_LINES);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let dir = log_dir(data_directory, server_id);
+        tokio::spawn(write_lines(dir, max_bytes, files, rx, dropped.clone()));
+        Some(Self { tx, dropped })
+    }
+
+    pub fn append(&self, line: &str) {
+        let line = format!("{} {}\n", timestamp(), line);
+        if let Err(mpsc::error::TrySendError::Full(_)) = self.tx.try_send(line) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn timestamp() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+async fn open_append(path: &Path) -> std::io::Result<BufWriter<File>> {
+    let file = tokio::fs::OpenOptions::new().create(true).append(true).open(path).await?;
+    Ok(BufWriter::new(file))
+}
+
+/// Shifts `console.log` to `console.log.1`, and so on, dropping the oldest.
+async fn rotate(dir: &Path, files: u32) {
+    let path = dir.join(LOG_FILE);
+    if files == 0 {
+        let _ = tokio::fs::remove_file(&path).await;
+        return;
+    }
+    let numbered = |n: u32| dir.join(format!("{}.{}", LOG_FILE, n));
+    let _ = tokio::fs::remove_file(numbered(files)).await;
+    for n in (1..files).rev() {
+        let _ = tokio::fs::rename(numbered(n), numbered(n + 1)).await;
+    }
+    let _ = tokio::fs::rename(&path, numbered(1)).await;
+}
+
+async fn write_lines(
+    dir: PathBuf,
+    max_bytes: u64,
+    files: u32,
+    mut rx: mpsc::Receiver<String>,
+    dropped: Arc<AtomicU64>,
+) {
+    let path = dir.join(LOG_FILE);
+    let opened = match tokio::fs::create_dir_all(&dir).await {
+        Ok(()) => open_append(&path).await,
+        Err(e) => Err(e),
+    };
+    let mut file = match opened {
+        Ok(file) => file,
+        Err(e) => {
+            tracing::warn!("Console output is not kept: '{}': {}", path.display(), e);
+            return;
+        }
+    };
+    let mut size = tokio::fs::metadata(&path).await.map(|m| m.len()).unwrap_or(0);
+    while let Some(mut line) = rx.recv().await {
+        let missed = dropped.swap(0, Ordering::Relaxed);
+        if missed > 0 {
+            line = format!("{} [{} lines not kept: disk too slow]\n{}", timestamp(), missed, line);
+        }
+        if size > 0 && size + line.len() as u64 > max_bytes {
+            let _ = file.flush().await;
+            rotate(&dir, files).await;
+            file = match open_append(&path).await {
+                Ok(file) => file,
+                Err(e) => {
+                    tracing::warn!("Console output is no longer kept: '{}': {}", path.display(), e);
+                    return;
+                }
+            };
+            size = 0;
+        }
+        if let Err(e) = file.write_all(line.as_bytes()).await {
+            tracing::warn!("Console output is no longer kept: '{}': {}", path.display(), e);
+            return;
+        }
+        size += line.len() as u64;
+        // Written out whenever the backlog is drained
+        if rx.is_empty() {
+            let _ = file.flush().await;
+        }
+    }
+    let _ = file.flush().await;
+}
@@ -0,0 +1,47 @@
+//! CORS policy for the API, built from `agent.cors` at startup.
+
+use anyhow::{bail, Context};
+use axum::http::{
+    header::{HeaderName, RETRY_AFTER},
+    HeaderValue,
+};
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer};
+
+use crate::config::CorsConfig;
+
+/// Response headers browser code may read besides the safelisted ones.
+const EXPOSED_HEADERS: &[&str] = &["x-total-count", "x-agent-proof"];
+
+/// With no origins configured no cross-origin request is allowed.
+pub fn layer(cfg: &CorsConfig) -> anyhow::Result<CorsLayer> {
+    let any = cfg.allowed_origins.iter().any(|o| o == "*");
+    if any && cfg.allow_credentials {
+        bail!("agent.cors.allow_credentials cannot be used with the \"*\" origin");
+    }
+    let origins = if any {
+        AllowOrigin::any()
+    } else {
+        let origins = cfg
+            .allowed_origins
+            .iter()
+            .map(|o| {
+                let origin = o.trim_end_matches('/');
+                if !origin.starts_with("http://") && !origin.starts_with("https://") {
+                    bail!("CORS origin '{}' must start with http:// or https://", o);
+                }
+                HeaderValue::from_str(origin).with_context(|| format!("Invalid CORS origin '{}'", o))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        AllowOrigin::list(origins)
+    };
+    let exposed = EXPOSED_HEADERS
+        .iter()
+        .map(|h| HeaderName::from_static(h))
+        .chain([RETRY_AFTER]);
+    Ok(CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(AllowMethods::mirror_request())
+        .allow_headers(AllowHeaders::mirror_request())
+        .allow_credentials(cfg.allow_credentials)
+        .expose_headers(exposed.collect::<Vec<_>>()))
+}
@@ -0,0 +1,77 @@
+//! Finds the crash report a server left behind when its process exits:
+//! Minecraft's `crash-reports/crash-*.txt` or the JVM's `hs_err_pid<N>.log`.
+
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::Serialize;
+
+/// Lines of the report kept in `ExitInfo` so the panel can show the cause.
+const EXCERPT_LINES: usize = 40;
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct CrashReport {
+    /// Path relative to the server directory.
+    pub file: String,
+    /// The report's "Description:" line, or the JVM's problem summary.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub excerpt: String,
+}
+
+async fn newest_report_since(dir: &Path, since: SystemTime) -> Option<PathBuf> {
+    let mut entries = tokio::fs::read_dir(dir).await.ok()?;
+    let mut newest: Option<(SystemTime, PathBuf)> = None;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if !name.starts_with("crash-") || !name.ends_with(".txt") {
+            continue;
+        }
+        let Ok(modified) = entry.metadata().await.and_then(|m| m.modified()) else {
+            continue;
+        };
+        if modified >= since && newest.as_ref().is_none_or(|(t, _)| modified > *t) {
+            newest = Some((modified, entry.path()));
+        }
+    }
+    newest.map(|(_, path)| path)
+}
+
+fn describe(contents: &str) -> Option<String> {
+    contents.lines().find_map(|line| {
+        let line = line.trim();
+        line.strip_prefix("Description:")
+            // hs_err: "#  SIGSEGV (0xb) at pc=..." follows the "fatal error" header
+            .or_else(|| {
+                line.strip_prefix("#  ")
+                    .filter(|l| l.starts_with(|c: char| c.is_ascii_uppercase()))
+            })
+            .map(|d| d.trim().to_string())
+    })
+}
+
+/// Looks for a report written by the process `pid` started at `started`.
+pub async fn find_report(directory: &str, pid: u32, started: SystemTime) -> Option<CrashReport> {
+    let dir = Path::new(directory);
+    let hs_err = dir.join(format!("hs_err_pid{}.log", pid));
+    let path = if tokio::fs::try_exists(&hs_err).await.unwrap_or(false) {
+        hs_err
+    } else {
+        newest_report_since(&dir.join("crash-reports"), started).await?
+    };
+    let contents = tokio::fs::read_to_string(&path).await.ok()?;
+    Some(CrashReport {
+        file: path
+            .strip_prefix(dir)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .into_owned(),
+        description: describe(&contents),
+        excerpt: contents
+            .lines()
+            .take(EXCERPT_LINES)
+            .collect::<Vec<_>>()
+            .join("\n"),
+    })
+}
@@ -0,0 +1,207 @@
+//! Detached servers, which outlive the agent. Each one reads its console
+//! input from a FIFO and writes its output to a log file, both under
+//! `<data_directory>/run/<id>/`, next to its PID file. The
+//! agent writes commands into the FIFO and tails the log, so after an agent
+//! restart the next agent can pick the server up where it was instead of
+//! starting it over.
+
+use std::{
+    path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
+    time::Duration,
+};
+
+use nix::{
+    sys::{signal, stat::Mode},
+    unistd::{mkfifo, Pid},
+};
+use tokio::sync::watch;
+
+use crate::{
+    config::ServerConfig,
+    console::FifoTail,
+    pidfile::{self, run_dir, ProcessIdentity},
+};
+
+const FIFO_FILE: &str = "console.in";
+const LOG_FILE: &str = "console.log";
+
+/// How often a reattached process, which isn't the agent's child, is
+/// checked for life.
+const LIVENESS_INTERVAL: Duration = Duration::from_secs(1);
+/// Once this much has been read the log is emptied; the server writes in
+/// append mode, so it carries on at the start of the file.
+const MAX_LOG_BYTES: u64 = 16 * 1024 * 1024;
+/// Output replayed into the console buffer when reattaching.
+const REATTACH_BACKLOG_BYTES: u64 = 64 * 1024;
+
+/// A detached server process, whether this agent spawned it or reattached
+/// to it.
+pub struct DetachedProcess {
+    pub pid: u32,
+    identity: ProcessIdentity,
+    /// Set once the process is gone. A reattached process isn't the agent's
+    /// child, so its exit status can't be read and it counts as a clean exit.
+    exit: watch::Receiver<Option<ExitStatus>>,
+}
+
+impl DetachedProcess {
+    pub async fn wait(&mut self) -> std::io::Result<ExitStatus> {
+        let status = self
+            .exit
+            .wait_for(Option::is_some)
+            .await
+            .map_err(std::io::Error::other)?;
+        Ok((*status).unwrap_or_default())
+    }
+
+    pub fn try_wait(&self) -> Option<ExitStatus> {
+        *self.exit.borrow()
+    }
+
+    /// Whether the PID still belongs to the server.
+    pub fn owns_pid(&self) -> bool {
+        self.try_wait().is_none() && self.identity.is_running()
+    }
+
+    pub fn kill(&self) -> std::io::Result<()> {
+        if !self.owns_pid() {
+            return Ok(());
+        }
+        signal::kill(Pid::from_raw(self.pid as i32), signal::Signal::SIGKILL)
+            .map_err(std::io::Error::from)
+    }
+}
+
+/// A detached process with its console.
+pub struct Detached {
+    pub process: DetachedProcess,
+    pub console: FifoTail,
+    pub started_at_ms: u64,
+}
+
+fn now_ms() -> u64 {
+    chrono::Utc::now().timestamp_millis() as u64
+}
+
+/// Opens the FIFO for writing commands. This fails unless someone, i.e.
+/// the server, has it open for reading.
+fn tail(
+    path: PathBuf,
+    start: u64,
+    exit: watch::Receiver<Option<ExitStatus>>,
+) -> tokio::io::DuplexStream {
+    crate::console::tail_file(path, start, exit, Some(MAX_LOG_BYTES))
+}
+
+fn console_input(dir: &Path) -> Result<tokio::net::unix::pipe::Sender, String> {
+    tokio::net::unix::pipe::OpenOptions::new()
+        .open_sender(dir.join(FIFO_FILE))
+        .map_err(|e| format!("Failed to open the console FIFO: {}", e))
+}
+
+/// Starts the server in its own process group, so it neither gets the
+/// agent's Ctrl+C nor goes down with it.
+pub async fn spawn(
+    data_directory: &str,
+    server_cfg: &ServerConfig,
+    argv: Vec<String>,
+) -> Result<Detached, String> {
+    let dir = run_dir(data_directory, &server_cfg.id);
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .map_err(|e| format!("Failed to create '{}': {}", dir.display(), e))?;
+    let fifo = dir.join(FIFO_FILE);
+    if !fifo.exists() {
+        mkfifo(&fifo, Mode::S_IRUSR | Mode::S_IWUSR)
+            .map_err(|e| format!("Failed to create the console FIFO: {}", e))?;
+    }
+    // Opened for writing as well, so the server never reads end-of-file
+    // while no agent has the FIFO open
+    let stdin = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&fifo)
+        .map_err(|e| format!("Failed to open the console FIFO: {}", e))?;
+    let log_path = dir.join(LOG_FILE);
+    let log = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .and_then(|log| log.set_len(0).map(|_| log))
+        .map_err(|e| format!("Failed to open '{}': {}", log_path.display(), e))?;
+    let log_err = log
+        .try_clone()
+        .map_err(|e| format!("Failed to open '{}': {}", log_path.display(), e))?;
+
+    let mut cmd = tokio::process::Command::new(&argv[0]);
+    cmd.args(&argv[1..])
+        .current_dir(&server_cfg.directory)
+        .stdin(Stdio::from(stdin))
+        .stdout(Stdio::from(log))
+        .stderr(Stdio::from(log_err))
+        .process_group(0);
+    let mut child = cmd
+        .spawn()
+        .map_err(|e| format!("Failed to spawn server process: {}", e))?;
+    let pid = child.id().ok_or("Failed to get child PID")?;
+    let started_at_ms = now_ms();
+    // Without the PID file no agent could find it again
+    let pid_file = match pidfile::write(data_directory, &server_cfg.id, pid, started_at_ms).await {
+        Ok(pid_file) => pid_file,
+        Err(e) => {
+            let _ = child.start_kill();
+            return Err(e);
+        }
+    };
+
+    let (exit_tx, exit) = watch::channel(None);
+    tokio::spawn(async move {
+        let status = child.wait().await.unwrap_or_default();
+        exit_tx.send_replace(Some(status));
+    });
+    Ok(Detached {
+        console: FifoTail::new(console_input(&dir)?, tail(log_path, 0, exit.clone())),
+        process: DetachedProcess {
+            pid,
+            identity: pid_file.process,
+            exit,
+        },
+        started_at_ms,
+    })
+}
+
+/// Picks up a server a previous agent left running. Returns `None`, and
+/// forgets the PID file, when its process is gone.
+pub async fn reattach(data_directory: &str, server_id: &str) -> Option<Detached> {
+    let pid_file = pidfile::running(data_directory, server_id).await?;
+    let dir = run_dir(data_directory, server_id);
+    let pid = pid_file.process.pid;
+    let fifo = match console_input(&dir) {
+        Ok(fifo) => fifo,
+        Err(e) => {
+            tracing::warn!("Cannot reattach '{}' (PID {}): {}", server_id, pid, e);
+            return None;
+        }
+    };
+    let (exit_tx, exit) = watch::channel(None);
+    let identity = pid_file.process.clone();
+    tokio::spawn(async move {
+        while identity.is_running() {
+            tokio::time::sleep(LIVENESS_INTERVAL).await;
+        }
+        exit_tx.send_replace(Some(ExitStatus::default()));
+    });
+    let log_path = dir.join(LOG_FILE);
+    let size = tokio::fs::metadata(&log_path).await.map(|m| m.len()).unwrap_or(0);
+    let output = tail(log_path, size.saturating_sub(REATTACH_BACKLOG_BYTES), exit.clone());
+    Some(Detached {
+        console: FifoTail::new(fifo, output),
+        process: DetachedProcess {
+            pid,
+            identity: pid_file.process,
+            exit,
+        },
+        started_at_ms: pid_file.started_at_ms,
+    })
+}
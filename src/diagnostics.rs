@@ -0,0 +1,335 @@
+//! Thread dumps, heap dumps and Java Flight Recorder recordings of a
+//! running server's JVM, taken with the JDK's `jcmd` and saved under
+//! `<data directory>/diagnostics/<server id>` for download, out of the way
+//! of backups. Servers on a bare JRE have no `jcmd`; their thread dumps
+//! are requested with SIGQUIT instead and read back from the console, and
+//! the rest need a JDK.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{config::ServerConfig, state::AppState, state::ServerInstance};
+
+const DIAGNOSTICS_DIR: &str = "diagnostics";
+/// How long the JVM gets to start printing a console thread dump.
+const THREAD_DUMP_START: Duration = Duration::from_secs(5);
+/// A console thread dump is over once the console is quiet this long.
+const THREAD_DUMP_QUIET: Duration = Duration::from_secs(1);
+const THREAD_DUMP_MAX: Duration = Duration::from_secs(15);
+/// Name of the flight recording the agent starts and stops.
+const JFR_RECORDING: &str = "mc-node-agent";
+const MAX_JFR_DURATION_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct DiagnosticFile {
+    pub name: String,
+    pub size_bytes: u64,
+    pub created_ms: u64,
+}
+
+/// The JDK's bundled recording settings.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum JfrSettings {
+    /// Low overhead, suitable for leaving on.
+    #[default]
+    Default,
+    /// More detail, such as allocation and method sampling, at around 2%
+    /// overhead.
+    Profile,
+}
+
+impl JfrSettings {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Default => "default",
+            Self::Profile => "profile",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, utoipa::ToSchema)]
+pub struct JfrRequest {
+    #[serde(default)]
+    pub settings: JfrSettings,
+    /// Stops the recording by itself after this long; without it the
+    /// recording runs until stopped.
+    pub duration_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct JfrRecording {
+    /// File in the diagnostics folder the recording is written to once it
+    /// stops, or when the server does.
+    pub file: String,
+    pub settings: JfrSettings,
+    pub duration_secs: Option<u64>,
+}
+
+fn diagnostics_dir(data_directory: &str, server_id: &str) -> PathBuf {
+    Path::new(data_directory).join(DIAGNOSTICS_DIR).join(server_id)
+}
+
+fn running(state: &AppState, cfg: &ServerConfig) -> Result<Arc<ServerInstance>, String> {
+    state
+        .servers
+        .get(&cfg.id)
+        .map(|r| r.value().clone())
+        .ok_or_else(|| format!("Server '{}' is not running", cfg.id))
+}
+
+/// `jcmd` from the server's own Java installation when it has one, then
+/// the one on the PATH.
+async fn jcmd_candidates(state: &AppState, cfg: &ServerConfig) -> Vec<PathBuf> {
+    let data_directory = state.config.read().await.agent.data_directory.clone();
+    let mut candidates = Vec::new();
+    if let Ok(java) = crate::runtimes::java_binary(cfg, &data_directory) {
+        if let Some(bin) = java.parent().filter(|p| !p.as_os_str().is_empty()) {
+            let jcmd = bin.join(if cfg!(windows) { "jcmd.exe" } else { "jcmd" });
+            if jcmd.exists() {
+                candidates.push(jcmd);
+            }
+        }
+    }
+    candidates.push(PathBuf::from("jcmd"));
+    candidates
+}
+
+/// Runs a diagnostic command in the JVM through `jcmd`, returning its output.
+async fn jcmd(
+    state: &AppState,
+    cfg: &ServerConfig,
+    pid: u32,
+    args: &[&str],
+) -> Result<String, String> {
+    if cfg.docker.is_some() {
+        return Err("jcmd cannot reach a JVM inside a container".to_string());
+    }
+    let mut last_error = "jcmd was not found; it comes with a JDK".to_string();
+    for jcmd in jcmd_candidates(state, cfg).await {
+        let output = match tokio::process::Command::new(&jcmd)
+            .arg(pid.to_string())
+            .args(args)
+            .output()
+            .await
+        {
+            Ok(output) => output,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(format!("Failed to execute '{}': {}", jcmd.display(), e)),
+        };
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        if output.status.success() {
+            // jcmd heads its output with the target's pid
+            let header = format!("{}:\n", pid);
+            return Ok(stdout.strip_prefix(&header).unwrap_or(&stdout).to_string());
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        last_error = format!("'{}' failed: {}{}", jcmd.display(), stdout.trim(), stderr.trim());
+    }
+    Err(last_error)
+}
+
+/// Requests a thread dump with SIGQUIT and collects it from the console.
+async fn console_thread_dump(instance: &ServerInstance) -> Result<String, String> {
+    if !instance.child.lock().await.owns_pid().await {
+        return Err("The server's process can't be signalled".to_string());
+    }
+    let mut console_rx = instance.console_tx.subscribe();
+    crate::platform::request_thread_dump(instance.pid);
+    let started = tokio::time::Instant::now();
+    let mut dump = String::new();
+    loop {
+        let wait = if dump.is_empty() { THREAD_DUMP_START } else { THREAD_DUMP_QUIET };
+        if started.elapsed() > THREAD_DUMP_MAX {
+            break;
+        }
+        match tokio::time::timeout(wait, console_rx.recv()).await {
+            Ok(Ok(line)) => {
+                if dump.is_empty() && !line.text.contains("Full thread dump") {
+                    continue;
+                }
+                dump.push_str(&line.text);
+                dump.push('\n');
+            }
+            Ok(Err(RecvError::Lagged(_))) => {}
+            Ok(Err(RecvError::Closed)) | Err(_) => break,
+        }
+    }
+    if dump.is_empty() {
+        return Err("The JVM printed no thread dump".to_string());
+    }
+    Ok(dump)
+}
+
+async fn file_info(path: &Path) -> Option<DiagnosticFile> {
+    let metadata = tokio::fs::metadata(path).await.ok()?;
+    let created_ms = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default();
+    Some(DiagnosticFile {
+        name: path.file_name()?.to_string_lossy().into_owned(),
+        size_bytes: metadata.len(),
+        created_ms,
+    })
+}
+
+/// A new file in the diagnostics folder, named after `kind` and the time.
+async fn new_file(
+    state: &AppState,
+    cfg: &ServerConfig,
+    kind: &str,
+    extension: &str,
+) -> Result<PathBuf, String> {
+    let data_directory = state.config.read().await.agent.data_directory.clone();
+    let dir = diagnostics_dir(&data_directory, &cfg.id);
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .map_err(|e| format!("Failed to create '{}': {}", dir.display(), e))?;
+    let dir = tokio::fs::canonicalize(&dir)
+        .await
+        .map_err(|e| format!("Failed to resolve '{}': {}", dir.display(), e))?;
+    let timestamp = chrono::Local::now().format("%Y-%m-%d_%H-%M-%S");
+    Ok(dir.join(format!("{}-{}.{}", kind, timestamp, extension)))
+}
+
+/// Saves a thread dump of the server's JVM.
+pub async fn thread_dump(state: &AppState, cfg: &ServerConfig) -> Result<DiagnosticFile, String> {
+    let instance = running(state, cfg)?;
+    let dump = match jcmd(state, cfg, instance.pid, &["Thread.print", "-l"]).await {
+        Ok(dump) => dump,
+        Err(e) => {
+            tracing::debug!("Thread dump of '{}' through jcmd failed: {}", cfg.id, e);
+            console_thread_dump(&instance).await?
+        }
+    };
+    let path = new_file(state, cfg, "thread-dump", "txt").await?;
+    tokio::fs::write(&path, dump)
+        .await
+        .map_err(|e| format!("Failed to write '{}': {}", path.display(), e))?;
+    tracing::info!("Saved a thread dump of '{}' to {:?}", cfg.id, path);
+    file_info(&path).await.ok_or_else(|| format!("'{}' vanished", path.display()))
+}
+
+/// Saves a heap dump of the server's live objects, which pauses it while
+/// the heap is written out.
+pub async fn heap_dump(state: &AppState, cfg: &ServerConfig) -> Result<DiagnosticFile, String> {
+    let instance = running(state, cfg)?;
+    let path = new_file(state, cfg, "heap-dump", "hprof").await?;
+    let target = path.to_string_lossy().into_owned();
+    jcmd(state, cfg, instance.pid, &["GC.heap_dump", &target]).await?;
+    tracing::info!("Saved a heap dump of '{}' to {:?}", cfg.id, path);
+    file_info(&path)
+        .await
+        .ok_or_else(|| format!("The JVM wrote no heap dump to '{}'", path.display()))
+}
+
+/// Starts a flight recording, written to the diagnostics folder when it
+/// ends. jcmd reports a refused command in its output only.
+pub async fn start_recording(
+    state: &AppState,
+    cfg: &ServerConfig,
+    request: JfrRequest,
+) -> Result<JfrRecording, String> {
+    if request.duration_secs.is_some_and(|d| d == 0 || d > MAX_JFR_DURATION_SECS) {
+        return Err(format!("duration_secs must be between 1 and {}", MAX_JFR_DURATION_SECS));
+    }
+    let instance = running(state, cfg)?;
+    let name = format!("name={}", JFR_RECORDING);
+    let check = jcmd(state, cfg, instance.pid, &["JFR.check", &name]).await?;
+    if check.contains("(running)") {
+        return Err(format!("'{}' is already recording", cfg.id));
+    }
+
+    let path = new_file(state, cfg, "recording", "jfr").await?;
+    let mut args = vec![
+        "JFR.start".to_string(),
+        name,
+        format!("settings={}", request.settings.name()),
+        format!("filename={}", path.display()),
+        "dumponexit=true".to_string(),
+    ];
+    if let Some(duration) = request.duration_secs {
+        args.push(format!("duration={}s", duration));
+    }
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let output = jcmd(state, cfg, instance.pid, &args).await?;
+    if !output.contains("Started recording") {
+        return Err(output.trim().to_string());
+    }
+    tracing::info!("Started a flight recording of '{}' to {:?}", cfg.id, path);
+    let recording = JfrRecording {
+        file: path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+        settings: request.settings,
+        duration_secs: request.duration_secs,
+    };
+    state.recordings.insert(cfg.id.clone(), recording.clone());
+    Ok(recording)
+}
+
+/// Stops the running flight recording, returning the file it was saved to.
+pub async fn stop_recording(
+    state: &AppState,
+    cfg: &ServerConfig,
+) -> Result<DiagnosticFile, String> {
+    let instance = running(state, cfg)?;
+    // The JVM keeps a recording the agent lost track of, say by restarting
+    let path = match state.recordings.get(&cfg.id) {
+        Some(recording) => {
+            let data_directory = state.config.read().await.agent.data_directory.clone();
+            tokio::fs::canonicalize(diagnostics_dir(&data_directory, &cfg.id))
+                .await
+                .map_err(|e| e.to_string())?
+                .join(&recording.file)
+        }
+        None => new_file(state, cfg, "recording", "jfr").await?,
+    };
+    let args = [
+        "JFR.stop".to_string(),
+        format!("name={}", JFR_RECORDING),
+        format!("filename={}", path.display()),
+    ];
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let output = jcmd(state, cfg, instance.pid, &args).await?;
+    if !output.contains("Stopped recording") {
+        return Err(format!("'{}' is not recording", cfg.id));
+    }
+    state.recordings.remove(&cfg.id);
+    tracing::info!("Saved the flight recording of '{}' to {:?}", cfg.id, path);
+    file_info(&path)
+        .await
+        .ok_or_else(|| format!("The JVM wrote no recording to '{}'", path.display()))
+}
+
+/// The saved dumps, newest first.
+pub async fn list(data_directory: &str, cfg: &ServerConfig) -> Vec<DiagnosticFile> {
+    let mut files = Vec::new();
+    let Ok(mut entries) = tokio::fs::read_dir(diagnostics_dir(data_directory, &cfg.id)).await else {
+        return files;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if let Some(file) = file_info(&entry.path()).await {
+            files.push(file);
+        }
+    }
+    files.sort_by_key(|f| std::cmp::Reverse(f.created_ms));
+    files
+}
+
+/// Path of the saved dump `name`, if there is one.
+pub fn file_path(data_directory: &str, cfg: &ServerConfig, name: &str) -> Option<PathBuf> {
+    let valid_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_');
+    if name.starts_with('.') || !name.chars().all(valid_char) {
+        return None;
+    }
+    let path = diagnostics_dir(data_directory, &cfg.id).join(name);
+    path.is_file().then_some(path)
+}
@@ -0,0 +1,402 @@
+//! Two-way chat bridge between a server and a Discord channel. Chat, joins
+//! and leaves read from the console are posted to the channel, and what
+//! people write in the channel is shown in game. The bot talks to Discord's
+//! gateway and REST API directly, and only while the server runs; messages
+//! sent while it is down or reconnecting are not relayed.
+
+use std::{sync::Arc, sync::OnceLock, time::Duration};
+
+use futures_util::{SinkExt, StreamExt};
+use regex::Regex;
+use serde::Deserialize;
+use tokio::sync::{broadcast::error::RecvError, mpsc};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{
+    config::{BroadcastCommand, DiscordBridgeConfig},
+    console,
+    playtime::{self, Presence},
+    process::send_command,
+    state::{AppState, ServerInstance},
+};
+
+const API: &str = "https://discord.com/api/v10";
+const GATEWAY: &str = "wss://gateway.discord.gg/?v=10&encoding=json";
+/// GUILD_MESSAGES and the privileged MESSAGE_CONTENT.
+const INTENTS: u64 = (1 << 9) | (1 << 15);
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+/// Lines waiting to be posted; more are dropped while Discord rate limits.
+const OUTBOX_LINES: usize = 256;
+/// Discord's limit for one message.
+const MAX_MESSAGE_LEN: usize = 2000;
+/// Chat is limited to this much in game, so Discord messages are cut to it.
+const MAX_CHAT_LEN: usize = 256;
+/// Gateway close codes that reconnecting won't fix: a bad token, or
+/// intents the bot isn't allowed.
+const FATAL_CLOSE_CODES: &[u16] = &[4004, 4010, 4011, 4012, 4013, 4014];
+
+fn chat_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    // Anchored on the log prefix so a player can't fake another's message
+    RE.get_or_init(|| {
+        Regex::new(&format!(
+            r"{}(?:\[Not Secure\] )?<([A-Za-z0-9_]{{1,16}})> (.+)$",
+            console::INFO_PREFIX
+        ))
+        .expect("valid regex")
+    })
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '~' | '`' | '|' | '>') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// What a console line becomes in the channel, if anything.
+fn channel_line(text: &str, announce_joins: bool) -> Option<String> {
+    if let Some(caps) = chat_regex().captures(text) {
+        return Some(format!("**{}**: {}", escape_markdown(&caps[1]), escape_markdown(&caps[2])));
+    }
+    let (name, presence) = playtime::presence(text).filter(|_| announce_joins)?;
+    let verb = match presence {
+        Presence::Joined => "joined",
+        Presence::Left => "left",
+    };
+    Some(format!("_{} {} the game_", escape_markdown(name), verb))
+}
+
+/// The console command that shows a Discord message in game.
+fn game_command(relay: BroadcastCommand, author: &str, content: &str) -> String {
+    let flatten = |text: &str| -> String {
+        text.chars().map(|c| if c.is_control() { ' ' } else { c }).collect()
+    };
+    let content: String = flatten(content).chars().take(MAX_CHAT_LEN).collect();
+    let author = flatten(author);
+    match relay {
+        BroadcastCommand::Say => format!("say [Discord] <{}> {}", author, content),
+        BroadcastCommand::Tellraw => {
+            let text = serde_json::json!([
+                "",
+                { "text": "[Discord] ", "color": "blue" },
+                { "text": format!("<{}> {}", author, content) },
+            ]);
+            format!("tellraw @a {}", text)
+        }
+    }
+}
+
+/// Starts the bridge for a server that has one configured. It stops when
+/// the server exits.
+pub fn spawn_bridge(
+    state: AppState,
+    server_id: String,
+    instance: Arc<ServerInstance>,
+    cfg: Option<DiscordBridgeConfig>,
+) {
+    let Some(cfg) = cfg else {
+        return;
+    };
+    let (tx, rx) = mpsc::channel(OUTBOX_LINES);
+    tokio::spawn(post_lines(cfg.token.clone(), cfg.channel_id.clone(), rx));
+
+    let mut console_rx = instance.console_tx.subscribe();
+    let mut exited_rx = instance.exited.subscribe();
+    let announce_joins = cfg.announce_joins;
+    tokio::spawn(async move {
+        loop {
+            let line = tokio::select! {
+                msg = console_rx.recv() => match msg {
+                    Ok(line) => line,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+                _ = exited_rx.wait_for(|exited| *exited) => break,
+            };
+            let text = crate::ansi::strip(&line.text);
+            if let Some(message) = channel_line(&text, announce_joins) {
+                let _ = tx.try_send(message);
+            }
+        }
+    });
+
+    let mut exited_rx = instance.exited.subscribe();
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = run_gateway(&state, &server_id, &cfg) => {}
+            _ = exited_rx.wait_for(|exited| *exited) => {}
+        }
+    });
+}
+
+/// Posts queued lines, joining those that piled up into one message.
+async fn post_lines(token: String, channel_id: String, mut rx: mpsc::Receiver<String>) {
+    let mut carried = None;
+    loop {
+        let mut content = match carried.take() {
+            Some(line) => line,
+            None => match rx.recv().await {
+                Some(line) => line,
+                None => break,
+            },
+        };
+        while let Ok(line) = rx.try_recv() {
+            if content.len() + 1 + line.len() > MAX_MESSAGE_LEN {
+                carried = Some(line);
+                break;
+            }
+            content.push('\n');
+            content.push_str(&line);
+        }
+        if content.len() > MAX_MESSAGE_LEN {
+            let end = (0..=MAX_MESSAGE_LEN).rev().find(|&i| content.is_char_boundary(i));
+            content.truncate(end.unwrap_or(0));
+        }
+        if let Err(e) = send_message(&token, &channel_id, &content).await {
+            tracing::warn!("Failed to post to Discord channel {}: {}", channel_id, e);
+        }
+    }
+}
+
+async fn send_message(token: &str, channel_id: &str, content: &str) -> Result<(), String> {
+    #[derive(Deserialize)]
+    struct RateLimited {
+        retry_after: f64,
+    }
+    let body = serde_json::json!({
+        "content": content,
+        // Posted text never pings anyone
+        "allowed_mentions": { "parse": [] },
+    });
+    loop {
+        let resp = crate::nodes::client()
+            .post(format!("{}/channels/{}/messages", API, channel_id))
+            .header("authorization", format!("Bot {}", token))
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            let wait = resp.json::<RateLimited>().await.map_or(1.0, |r| r.retry_after);
+            tokio::time::sleep(Duration::from_secs_f64(wait.clamp(0.0, 60.0))).await;
+            continue;
+        }
+        return resp.error_for_status().map(|_| ()).map_err(|e| e.to_string());
+    }
+}
+
+#[derive(Deserialize)]
+struct GatewayPayload {
+    op: u8,
+    #[serde(default)]
+    d: serde_json::Value,
+    #[serde(default)]
+    s: Option<u64>,
+    #[serde(default)]
+    t: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChannelMessage {
+    channel_id: String,
+    content: String,
+    author: Author,
+    #[serde(default)]
+    member: Option<Member>,
+    /// Set for webhook posts, such as another bridge's.
+    #[serde(default)]
+    webhook_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Author {
+    username: String,
+    #[serde(default)]
+    global_name: Option<String>,
+    #[serde(default)]
+    bot: bool,
+}
+
+#[derive(Deserialize)]
+struct Member {
+    #[serde(default)]
+    nick: Option<String>,
+}
+
+enum SessionEnd {
+    Reconnect(String),
+    Fatal(String),
+}
+
+/// Keeps a gateway session open, reconnecting until Discord refuses the bot.
+async fn run_gateway(state: &AppState, server_id: &str, cfg: &DiscordBridgeConfig) {
+    loop {
+        match gateway_session(state, server_id, cfg).await {
+            SessionEnd::Reconnect(reason) => {
+                tracing::warn!("Discord bridge of '{}' reconnecting: {}", server_id, reason);
+            }
+            SessionEnd::Fatal(reason) => {
+                tracing::error!(
+                    "Discord bridge of '{}' stopped relaying to the game: {}",
+                    server_id,
+                    reason
+                );
+                return;
+            }
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn gateway_session(
+    state: &AppState,
+    server_id: &str,
+    cfg: &DiscordBridgeConfig,
+) -> SessionEnd {
+    let (socket, _) = match tokio_tungstenite::connect_async(GATEWAY).await {
+        Ok(connected) => connected,
+        Err(e) => return SessionEnd::Reconnect(e.to_string()),
+    };
+    let (mut sink, mut stream) = socket.split();
+    let identify = serde_json::json!({
+        "op": 2,
+        "d": {
+            "token": cfg.token,
+            "intents": INTENTS,
+            "properties": {
+                "os": std::env::consts::OS,
+                "browser": "mc-node-agent",
+                "device": "mc-node-agent",
+            },
+        },
+    });
+    if let Err(e) = sink.send(Message::Text(identify.to_string().into())).await {
+        return SessionEnd::Reconnect(e.to_string());
+    }
+
+    let mut seq: Option<u64> = None;
+    // Replaced by the interval from Discord's hello
+    let mut heartbeat = tokio::time::interval(Duration::from_secs(3600));
+    heartbeat.reset();
+    let mut acked = true;
+    loop {
+        let beat = tokio::select! {
+            _ = heartbeat.tick() => {
+                if !acked {
+                    return SessionEnd::Reconnect("heartbeat was not acknowledged".to_string());
+                }
+                acked = false;
+                true
+            }
+            msg = stream.next() => {
+                let text = match msg {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(frame))) => {
+                        let (code, reason) = frame
+                            .map(|f| (u16::from(f.code), f.reason.to_string()))
+                            .unwrap_or((1000, String::new()));
+                        let reason = format!("closed with {} {}", code, reason);
+                        if FATAL_CLOSE_CODES.contains(&code) {
+                            return SessionEnd::Fatal(reason);
+                        }
+                        return SessionEnd::Reconnect(reason);
+                    }
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return SessionEnd::Reconnect(e.to_string()),
+                    None => return SessionEnd::Reconnect("connection closed".to_string()),
+                };
+                let Ok(payload) = serde_json::from_str::<GatewayPayload>(&text) else {
+                    continue;
+                };
+                if payload.s.is_some() {
+                    seq = payload.s;
+                }
+                match payload.op {
+                    0 if payload.t.as_deref() == Some("MESSAGE_CREATE") => {
+                        if let Ok(message) = serde_json::from_value(payload.d) {
+                            relay_to_game(state, server_id, cfg, message).await;
+                        }
+                        false
+                    }
+                    1 => true,
+                    7 => return SessionEnd::Reconnect("Discord asked for a reconnect".to_string()),
+                    9 => return SessionEnd::Reconnect("session invalidated".to_string()),
+                    10 => {
+                        let interval = payload.d["heartbeat_interval"].as_u64().unwrap_or(41_250);
+                        let period = Duration::from_millis(interval);
+                        heartbeat = tokio::time::interval_at(
+                            tokio::time::Instant::now() + period,
+                            period,
+                        );
+                        false
+                    }
+                    11 => {
+                        acked = true;
+                        false
+                    }
+                    _ => false,
+                }
+            }
+        };
+        if beat {
+            let payload = serde_json::json!({ "op": 1, "d": seq });
+            if let Err(e) = sink.send(Message::Text(payload.to_string().into())).await {
+                return SessionEnd::Reconnect(e.to_string());
+            }
+        }
+    }
+}
+
+async fn relay_to_game(
+    state: &AppState,
+    server_id: &str,
+    cfg: &DiscordBridgeConfig,
+    message: ChannelMessage,
+) {
+    if message.channel_id != cfg.channel_id
+        || message.author.bot
+        || message.webhook_id.is_some()
+        || message.content.trim().is_empty()
+    {
+        return;
+    }
+    let author = message
+        .member
+        .and_then(|m| m.nick)
+        .or(message.author.global_name)
+        .unwrap_or(message.author.username);
+    let command = game_command(cfg.relay_command, &author, &message.content);
+    if let Err(e) = send_command(state, server_id, &command).await {
+        tracing::warn!("Failed to relay a Discord message to '{}': {}", server_id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chat_is_posted_as_its_sender() {
+        let line = "[12:00:00] [Server thread/INFO]: <Bob> hi *all*";
+        assert_eq!(channel_line(line, false).as_deref(), Some("**Bob**: hi \\*all\\*"));
+        let line = "[12:00:00 INFO]: [Not Secure] <Bob> hi";
+        assert_eq!(channel_line(line, false).as_deref(), Some("**Bob**: hi"));
+    }
+
+    #[test]
+    fn quoted_prefixes_do_not_spoof_a_sender() {
+        for line in [
+            "[12:00:00 INFO]: * Bob ]: <Admin> hi",
+            "[12:00:00 INFO]: Bob issued server command: /say ]: <Admin> hi",
+            "[12:00:00 INFO]: <Bob> ]: <Admin> hi",
+        ] {
+            let posted = channel_line(line, false);
+            assert!(!posted.as_deref().is_some_and(|p| p.starts_with("**Admin**")), "{}", line);
+        }
+    }
+}
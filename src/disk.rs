@@ -0,0 +1,106 @@
+//! Per-server disk usage, measured in the background with `du` (by walking
+//! the tree where GNU `du` isn't available) and cached in
+//! `AppState::disk_usage` so status and metrics reads never touch the disk.
+
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::state::AppState;
+
+#[derive(Debug, Clone, Default, Serialize, utoipa::ToSchema)]
+pub struct DiskUsage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub directory_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub world_bytes: Option<u64>,
+}
+
+#[cfg(target_os = "linux")]
+pub async fn du_bytes(path: &Path) -> Result<u64, String> {
+    let output = tokio::process::Command::new("du")
+        .arg("-sb")
+        .arg(path)
+        .output()
+        .await
+        .map_err(|e| format!("Failed to execute du: {}", e))?;
+    // du exits non-zero on files that vanish mid-walk; the total is still usable
+    String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .next()
+        .and_then(|n| n.parse().ok())
+        .ok_or_else(|| {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            format!("du failed for '{}': {}", path.display(), stderr.trim())
+        })
+}
+
+/// BSD and macOS `du` can't count bytes and Windows has none, so the
+/// tree is walked instead.
+#[cfg(not(target_os = "linux"))]
+pub async fn du_bytes(path: &Path) -> Result<u64, String> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || tree_bytes(&path))
+        .await
+        .map_err(|e| format!("Disk usage walk failed: {}", e))?
+}
+
+#[cfg(not(target_os = "linux"))]
+fn tree_bytes(path: &Path) -> Result<u64, String> {
+    let meta = std::fs::symlink_metadata(path)
+        .map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+    if !meta.is_dir() {
+        return Ok(meta.len());
+    }
+    let entries = std::fs::read_dir(path)
+        .map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+    // Entries that vanish mid-walk are skipped, as du does
+    Ok(meta.len()
+        + entries
+            .flatten()
+            .filter_map(|entry| tree_bytes(&entry.path()).ok())
+            .sum::<u64>())
+}
+
+/// Measures every configured server once.
+pub async fn refresh(state: &AppState) {
+    let servers = state.config.read().await.servers.clone();
+    state
+        .disk_usage
+        .retain(|id, _| servers.iter().any(|s| &s.id == id));
+    for server in servers {
+        let directory_bytes = du_bytes(Path::new(&server.directory))
+            .await
+            .map_err(|e| tracing::debug!("Disk usage of '{}': {}", server.id, e))
+            .ok();
+        let world_bytes = match crate::world::world_dir(&server).await {
+            Ok(dir) if dir.is_dir() => du_bytes(&dir).await.ok(),
+            _ => None,
+        };
+        state.disk_usage.insert(
+            server.id.clone(),
+            DiskUsage {
+                directory_bytes,
+                world_bytes,
+            },
+        );
+    }
+}
+
+pub fn spawn_sampler(state: AppState) {
+    tokio::spawn(async move {
+        loop {
+            refresh(&state).await;
+            let interval = state.config.read().await.agent.disk_usage_interval_secs;
+            tokio::time::sleep(std::time::Duration::from_secs(interval.max(10))).await;
+        }
+    });
+}
+
+pub fn cached(state: &AppState, server_id: &str) -> DiskUsage {
+    state
+        .disk_usage
+        .get(server_id)
+        .map(|u| u.value().clone())
+        .unwrap_or_default()
+}
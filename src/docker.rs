@@ -0,0 +1,287 @@
+//! Container backend: servers with a `docker` section run in a container
+//! instead of as a plain child process. The container uses host networking
+//! and mounts the server directory at the same path, so ports, files and
+//! the host PID (for metrics and signals) behave as they do for a child.
+
+use std::process::ExitStatus;
+
+use bollard::{
+    container::LogOutput,
+    models::{ContainerCreateBody, HostConfig},
+    query_parameters::{
+        AttachContainerOptionsBuilder, CreateContainerOptionsBuilder, CreateImageOptionsBuilder,
+        InspectContainerOptions, KillContainerOptionsBuilder, RemoveContainerOptionsBuilder,
+        StartContainerOptions, WaitContainerOptions,
+    },
+    Docker,
+};
+use futures_util::StreamExt;
+use tokio::io::AsyncWriteExt;
+
+use crate::config::{DockerConfig, ServerConfig};
+use crate::console::Pipe;
+
+/// Buffer between the attach stream and the console readers.
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+pub struct Container {
+    docker: Docker,
+    name: String,
+}
+
+pub struct Spawned {
+    pub container: Container,
+    /// Host PID of the container's main process.
+    pub pid: u32,
+    pub console: Pipe,
+}
+
+fn container_name(server_id: &str) -> String {
+    format!("mc-{}", server_id)
+}
+
+/// Maps a container exit code back to what a child process would report;
+/// the runtime encodes death by signal N as 128 + N.
+#[cfg(unix)]
+fn exit_status(code: i64) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    match code {
+        129..=192 => ExitStatus::from_raw((code - 128) as i32),
+        _ => ExitStatus::from_raw(((code as i32) & 0xff) << 8),
+    }
+}
+
+#[cfg(windows)]
+fn exit_status(code: i64) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code as u32)
+}
+
+/// Runs the container as the owner of the server directory so files the
+/// server writes keep that owner.
+#[cfg(unix)]
+fn directory_owner(directory: &str) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+    let meta = std::fs::metadata(directory).ok()?;
+    Some(format!("{}:{}", meta.uid(), meta.gid()))
+}
+
+#[cfg(not(unix))]
+fn directory_owner(_directory: &str) -> Option<String> {
+    None
+}
+
+async fn pull_image(docker: &Docker, image: &str) -> Result<(), String> {
+    // Without a tag the API pulls every tag of the repository
+    let has_tag = image
+        .rsplit('/')
+        .next()
+        .is_some_and(|last| last.contains(':'));
+    let reference = if has_tag {
+        image.to_string()
+    } else {
+        format!("{}:latest", image)
+    };
+    tracing::info!("Pulling image {}", reference);
+    let options = CreateImageOptionsBuilder::new()
+        .from_image(&reference)
+        .build();
+    let mut progress = docker.create_image(Some(options), None, None);
+    while let Some(step) = progress.next().await {
+        step.map_err(|e| format!("Failed to pull image '{}': {}", reference, e))?;
+    }
+    Ok(())
+}
+
+/// The server directory and the configured mounts as binds. Mount sources
+/// are taken relative to the server directory and, symlinks resolved, must
+/// stay inside it, so a config can't hand the container the rest of the host.
+async fn binds(cfg: &ServerConfig, docker_cfg: &DockerConfig) -> Result<Vec<String>, String> {
+    let root = tokio::fs::canonicalize(&cfg.directory)
+        .await
+        .map_err(|e| format!("Failed to resolve '{}': {}", cfg.directory, e))?;
+    let mut binds = vec![format!("{}:{}", cfg.directory, cfg.directory)];
+    for mount in &docker_cfg.mounts {
+        let (host, target) = mount
+            .split_once(':')
+            .ok_or_else(|| format!("Docker mount '{}' is not 'host:container'", mount))?;
+        let source = tokio::fs::canonicalize(root.join(host))
+            .await
+            .map_err(|e| format!("Failed to resolve docker mount '{}': {}", host, e))?;
+        if !source.starts_with(&root) {
+            return Err(format!("Docker mount '{}' is outside the server directory", host));
+        }
+        binds.push(format!("{}:{}", source.display(), target));
+    }
+    Ok(binds)
+}
+
+fn create_body(
+    cfg: &ServerConfig,
+    docker_cfg: &DockerConfig,
+    argv: Vec<String>,
+    memory_limit_bytes: u64,
+    binds: Vec<String>,
+) -> ContainerCreateBody {
+    let user = directory_owner(&cfg.directory);
+    ContainerCreateBody {
+        image: Some(docker_cfg.image.clone()),
+        cmd: Some(argv),
+        working_dir: Some(cfg.directory.clone()),
+        user,
+        attach_stdin: Some(true),
+        attach_stdout: Some(true),
+        attach_stderr: Some(true),
+        open_stdin: Some(true),
+        tty: Some(false),
+        host_config: Some(HostConfig {
+            binds: Some(binds),
+            network_mode: Some("host".to_string()),
+            memory: Some(memory_limit_bytes as i64),
+            memory_swap: Some(memory_limit_bytes as i64),
+            nano_cpus: cfg.cpu_limit.map(|cores| (cores as f64 * 1e9) as i64),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Creates, attaches to and starts the server's container. Any container
+/// left over from an earlier run is removed first.
+pub async fn spawn(
+    cfg: &ServerConfig,
+    docker_cfg: &DockerConfig,
+    argv: Vec<String>,
+    memory_limit_bytes: u64,
+) -> Result<Spawned, String> {
+    let docker = Docker::connect_with_local_defaults()
+        .map_err(|e| format!("Failed to connect to Docker: {}", e))?;
+    let name = container_name(&cfg.id);
+    let container = Container {
+        docker: docker.clone(),
+        name: name.clone(),
+    };
+    container.remove().await;
+
+    let options = CreateContainerOptionsBuilder::new().name(&name).build();
+    let binds = binds(cfg, docker_cfg).await?;
+    let body = create_body(cfg, docker_cfg, argv, memory_limit_bytes, binds);
+    let created = docker
+        .create_container(Some(options.clone()), body.clone())
+        .await;
+    match created {
+        Err(bollard::errors::Error::DockerResponseServerError {
+            status_code: 404, ..
+        }) => {
+            pull_image(&docker, &docker_cfg.image).await?;
+            docker
+                .create_container(Some(options), body)
+                .await
+                .map_err(|e| format!("Failed to create container: {}", e))?;
+        }
+        other => {
+            other.map_err(|e| format!("Failed to create container: {}", e))?;
+        }
+    }
+
+    // Attach before starting so no early output is lost
+    let attach = AttachContainerOptionsBuilder::new()
+        .stream(true)
+        .stdin(true)
+        .stdout(true)
+        .stderr(true)
+        .build();
+    let attached = match docker.attach_container(&name, Some(attach)).await {
+        Ok(attached) => attached,
+        Err(e) => {
+            container.remove().await;
+            return Err(format!("Failed to attach to container: {}", e));
+        }
+    };
+    let (mut stdout_tx, stdout) = tokio::io::duplex(PIPE_CAPACITY);
+    let (mut stderr_tx, stderr) = tokio::io::duplex(PIPE_CAPACITY);
+    let mut output = attached.output;
+    tokio::spawn(async move {
+        // Dropping the writers on stream end gives the readers their EOF
+        while let Some(Ok(chunk)) = output.next().await {
+            let written = match chunk {
+                LogOutput::StdErr { message } => stderr_tx.write_all(&message).await,
+                LogOutput::StdOut { message } | LogOutput::Console { message } => {
+                    stdout_tx.write_all(&message).await
+                }
+                LogOutput::StdIn { .. } => Ok(()),
+            };
+            if written.is_err() {
+                break;
+            }
+        }
+    });
+
+    if let Err(e) = docker
+        .start_container(&name, None::<StartContainerOptions>)
+        .await
+    {
+        container.remove().await;
+        return Err(format!("Failed to start container: {}", e));
+    }
+    let pid = docker
+        .inspect_container(&name, None::<InspectContainerOptions>)
+        .await
+        .ok()
+        .and_then(|info| info.state?.pid)
+        .filter(|pid| *pid > 0);
+    let Some(pid) = pid else {
+        container.remove().await;
+        return Err("Failed to get container PID".to_string());
+    };
+    let pid = pid as u32;
+
+    Ok(Spawned {
+        container,
+        pid,
+        console: Pipe::new(attached.input, stdout, stderr),
+    })
+}
+
+impl Container {
+    /// The exit status once the container has stopped, None while running.
+    pub async fn try_wait(&self) -> std::io::Result<Option<ExitStatus>> {
+        let info = self
+            .docker
+            .inspect_container(&self.name, None::<InspectContainerOptions>)
+            .await
+            .map_err(std::io::Error::other)?;
+        let state = info.state.unwrap_or_default();
+        if state.running.unwrap_or(false) {
+            return Ok(None);
+        }
+        Ok(Some(exit_status(state.exit_code.unwrap_or(0))))
+    }
+
+    pub async fn wait(&self) -> std::io::Result<ExitStatus> {
+        let mut wait = self
+            .docker
+            .wait_container(&self.name, None::<WaitContainerOptions>);
+        // Non-zero exits arrive as errors; the code is read back below
+        let _ = wait.next().await;
+        self.try_wait()
+            .await?
+            .ok_or_else(|| std::io::Error::other("container is still running"))
+    }
+
+    pub async fn kill(&self) -> std::io::Result<()> {
+        let options = KillContainerOptionsBuilder::new().signal("SIGKILL").build();
+        self.docker
+            .kill_container(&self.name, Some(options))
+            .await
+            .map_err(std::io::Error::other)
+    }
+
+    pub async fn remove(&self) {
+        let options = RemoveContainerOptionsBuilder::new().force(true).build();
+        let _ = self
+            .docker
+            .remove_container(&self.name, Some(options))
+            .await;
+    }
+}
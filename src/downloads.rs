@@ -0,0 +1,218 @@
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ring::digest;
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Content-addressed store for downloaded jars and mods, keyed by SHA-256.
+pub fn cache_dir(data_directory: &str) -> PathBuf {
+    Path::new(data_directory).join("cache").join("sha256")
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct CacheEntry {
+    pub sha256: String,
+    pub size_bytes: u64,
+}
+
+fn validate_sha256(sha256: &str) -> Result<String, String> {
+    let hash = sha256.to_ascii_lowercase();
+    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("sha256 must be 64 hex characters".to_string());
+    }
+    Ok(hash)
+}
+
+fn client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .user_agent(concat!("mc-node-agent/", env!("CARGO_PKG_VERSION")))
+            .connect_timeout(CONNECT_TIMEOUT)
+            // Jars can be large, so only a stalled transfer times out
+            .read_timeout(READ_TIMEOUT)
+            .build()
+            .expect("HTTP client configuration is valid")
+    })
+}
+
+pub async fn sha256_file(path: &Path) -> Result<String, String> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| format!("Failed to open '{}': {}", path.display(), e))?;
+    let mut context = digest::Context::new(&digest::SHA256);
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .await
+            .map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+        if n == 0 {
+            break;
+        }
+        context.update(&buf[..n]);
+    }
+    Ok(hex(context.finish().as_ref()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+async fn get(url: &str) -> Result<reqwest::Response, String> {
+    client()
+        .get(url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Request to '{}' failed: {}", url, e))
+}
+
+async fn download_to(url: &str, dest: &Path) -> Result<(), String> {
+    let mut response = get(url).await?;
+    let mut file = tokio::fs::File::create(dest)
+        .await
+        .map_err(|e| format!("Failed to create '{}': {}", dest.display(), e))?;
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| format!("Download of '{}' failed: {}", url, e))?
+    {
+        file.write_all(&chunk)
+            .await
+            .map_err(|e| format!("Failed to write '{}': {}", dest.display(), e))?;
+    }
+    file.flush()
+        .await
+        .map_err(|e| format!("Failed to write '{}': {}", dest.display(), e))
+}
+
+/// Fetches a small document such as an API response, bypassing the cache.
+pub async fn fetch_text(url: &str) -> Result<String, String> {
+    get(url)
+        .await?
+        .text()
+        .await
+        .map_err(|e| format!("Request to '{}' failed: {}", url, e))
+}
+
+/// Returns the cached file for `sha256`, downloading it from `url` on a miss.
+pub async fn fetch_cached(data_directory: &str, url: &str, sha256: &str) -> Result<PathBuf, String> {
+    let hash = validate_sha256(sha256)?;
+    let dir = cache_dir(data_directory);
+    let cached = dir.join(&hash);
+    if tokio::fs::try_exists(&cached).await.unwrap_or(false) {
+        tracing::debug!("Download cache hit for {}", hash);
+        return Ok(cached);
+    }
+
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .map_err(|e| format!("Failed to create download cache: {}", e))?;
+    let nonce = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let tmp = dir.join(format!(".{}.{}.part", hash, nonce));
+
+    tracing::info!("Downloading {} into cache", url);
+    if let Err(e) = download_to(url, &tmp).await {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(e);
+    }
+    let actual = match sha256_file(&tmp).await {
+        Ok(h) => h,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return Err(e);
+        }
+    };
+    if actual != hash {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(format!(
+            "Checksum mismatch for '{}': expected {}, got {}",
+            url, hash, actual
+        ));
+    }
+    tokio::fs::rename(&tmp, &cached)
+        .await
+        .map_err(|e| format!("Failed to move download into cache: {}", e))?;
+    Ok(cached)
+}
+
+/// Fetches through the cache and places a copy at `dest`.
+pub async fn install_cached(
+    data_directory: &str,
+    url: &str,
+    sha256: &str,
+    dest: &Path,
+) -> Result<(), String> {
+    let cached = fetch_cached(data_directory, url, sha256).await?;
+    if let Some(parent) = dest.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| format!("Failed to create '{}': {}", parent.display(), e))?;
+    }
+    // Copy next to the destination and rename so a running server keeps
+    // reading the old file rather than a half-written one
+    let tmp = dest.with_extension("download.tmp");
+    tokio::fs::copy(&cached, &tmp)
+        .await
+        .map_err(|e| format!("Failed to copy cached file to '{}': {}", tmp.display(), e))?;
+    tokio::fs::rename(&tmp, dest)
+        .await
+        .map_err(|e| format!("Failed to move download to '{}': {}", dest.display(), e))?;
+    Ok(())
+}
+
+pub async fn list_cache(data_directory: &str) -> Result<Vec<CacheEntry>, String> {
+    let mut entries = Vec::new();
+    let mut dir = match tokio::fs::read_dir(cache_dir(data_directory)).await {
+        Ok(d) => d,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(entries),
+        Err(e) => return Err(format!("Failed to read download cache: {}", e)),
+    };
+    while let Ok(Some(entry)) = dir.next_entry().await {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        let size_bytes = entry.metadata().await.map(|m| m.len()).unwrap_or(0);
+        entries.push(CacheEntry {
+            sha256: name,
+            size_bytes,
+        });
+    }
+    entries.sort_by(|a, b| a.sha256.cmp(&b.sha256));
+    Ok(entries)
+}
+
+pub async fn clear_cache(data_directory: &str) -> Result<(), String> {
+    match tokio::fs::remove_dir_all(cache_dir(data_directory)).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(format!("Failed to clear download cache: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn sha256_file_matches_a_known_digest() {
+        let path = std::env::temp_dir().join(format!("sha256-test-{}", std::process::id()));
+        tokio::fs::write(&path, b"abc").await.unwrap();
+        let hash = sha256_file(&path).await;
+        let _ = tokio::fs::remove_file(&path).await;
+        assert_eq!(
+            hash.unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -0,0 +1,183 @@
+//! `${VAR}` placeholders in config strings, resolved from the environment
+//! when the config is read so secrets can stay out of the file. `$${` is a
+//! literal `${`, and saving writes any `${` that isn't a placeholder that way.
+
+use serde_json::Value;
+
+/// Resolves the placeholders in one string. `path` names the field in errors.
+fn resolve(text: &str, path: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("$${") {
+            out.push_str("${");
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let end = after
+                .find('}')
+                .ok_or_else(|| format!("{}: unterminated '${{' placeholder", path))?;
+            let name = &after[..end];
+            if name.is_empty() {
+                return Err(format!("{}: empty '${{}}' placeholder", path));
+            }
+            let value = std::env::var(name).map_err(|_| {
+                format!("{}: environment variable '{}' is not set", path, name)
+            })?;
+            out.push_str(&value);
+            rest = &after[end + 1..];
+        } else {
+            out.push('$');
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn has_placeholder(text: &str) -> bool {
+    text.contains("${")
+}
+
+fn walk(value: &mut Value, path: &str) -> Result<(), String> {
+    match value {
+        Value::String(s) if has_placeholder(s) => *s = resolve(s, path)?,
+        Value::Array(items) => {
+            for (i, item) in items.iter_mut().enumerate() {
+                walk(item, &format!("{}[{}]", path, i))?;
+            }
+        }
+        Value::Object(map) => {
+            for (key, item) in map.iter_mut() {
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                walk(item, &path)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Resolves every placeholder in the tree, failing on the first unset
+/// variable.
+pub fn substitute(value: &mut Value) -> Result<(), String> {
+    walk(value, "")
+}
+
+/// Makes `text` load back as itself.
+fn escape(text: &str) -> String {
+    text.replace("${", "$${")
+}
+
+/// The entry of `raw` that `item` was read from: the one with the same
+/// `id` in lists of objects, which shift when an entry is removed, and
+/// otherwise the one at the same index.
+fn counterpart<'a>(item: &Value, index: usize, raw: &'a [Value]) -> Option<&'a Value> {
+    match item.get("id").and_then(Value::as_str) {
+        Some(id) => raw.iter().find(|r| r.get("id").and_then(Value::as_str) == Some(id)),
+        None => raw.get(index),
+    }
+}
+
+/// Puts back the placeholders of `file` wherever `value` still holds what
+/// they resolve to, so saving the config doesn't write secrets to disk.
+/// Any other `${` is escaped so the saved file loads back unchanged.
+pub fn restore(value: &mut Value, file: &Value) {
+    match (value, file) {
+        (Value::String(s), Value::String(raw))
+            if has_placeholder(raw) && resolve(raw, "").is_ok_and(|resolved| resolved == *s) =>
+        {
+            *s = raw.clone();
+        }
+        (Value::String(s), _) if has_placeholder(s) => *s = escape(s),
+        (Value::Array(items), raw) => {
+            let raw = raw.as_array().map(Vec::as_slice).unwrap_or_default();
+            for (i, item) in items.iter_mut().enumerate() {
+                let raw = counterpart(item, i, raw).unwrap_or(&Value::Null);
+                restore(item, raw);
+            }
+        }
+        (Value::Object(map), raw) => {
+            for (key, item) in map.iter_mut() {
+                restore(item, raw.get(key).unwrap_or(&Value::Null));
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn placeholders_resolve_from_the_environment() {
+        std::env::set_var("ENVSUBST_TEST_TOKEN", "abc");
+        assert_eq!(resolve("${ENVSUBST_TEST_TOKEN}", "t").unwrap(), "abc");
+        assert_eq!(resolve("x-${ENVSUBST_TEST_TOKEN}-$5", "t").unwrap(), "x-abc-$5");
+        assert_eq!(resolve("$${ENVSUBST_TEST_TOKEN}", "t").unwrap(), "${ENVSUBST_TEST_TOKEN}");
+    }
+
+    #[test]
+    fn bad_placeholders_name_the_field() {
+        let err = resolve("${ENVSUBST_TEST_UNSET}", "agent.token").unwrap_err();
+        assert!(err.starts_with("agent.token:"), "{}", err);
+        assert!(resolve("${", "t").is_err());
+        assert!(resolve("${}", "t").is_err());
+
+        let mut value = json!({"servers": [{"jar": "${ENVSUBST_TEST_UNSET}"}]});
+        let err = substitute(&mut value).unwrap_err();
+        assert!(err.starts_with("servers[0].jar:"), "{}", err);
+    }
+
+    #[test]
+    fn restore_puts_back_unchanged_placeholders() {
+        std::env::set_var("ENVSUBST_TEST_SECRET", "hunter2");
+        let file = json!({"a": "${ENVSUBST_TEST_SECRET}", "b": ["${ENVSUBST_TEST_SECRET}"]});
+        let mut value = file.clone();
+        substitute(&mut value).unwrap();
+        assert_eq!(value, json!({"a": "hunter2", "b": ["hunter2"]}));
+
+        // An edited value is saved as it now is
+        value["b"][0] = json!("changed");
+        restore(&mut value, &file);
+        assert_eq!(value, json!({"a": "${ENVSUBST_TEST_SECRET}", "b": ["changed"]}));
+    }
+
+    #[test]
+    fn saved_configs_load_back_unchanged() {
+        std::env::set_var("ENVSUBST_TEST_KEY", "k3y");
+        let file = json!({"servers": [
+            {"id": "a", "token": "${ENVSUBST_TEST_KEY}", "note": "$${HOME}"},
+            {"id": "b", "token": "${ENVSUBST_TEST_KEY}", "note": "$${HOME}"},
+        ]});
+        let mut loaded = file.clone();
+        substitute(&mut loaded).unwrap();
+
+        // "a" is deleted, so "b" shifts down, and a hook using ${...} is added
+        let mut config = json!({"servers": [loaded["servers"][1].clone()]});
+        config["servers"][0]["hook"] = json!("rsync ${MC_SERVER_DIR} /backup");
+        let mut saved = config.clone();
+        restore(&mut saved, &file);
+        assert_eq!(saved["servers"][0]["token"], json!("${ENVSUBST_TEST_KEY}"));
+        assert_eq!(saved["servers"][0]["hook"], json!("rsync $${MC_SERVER_DIR} /backup"));
+
+        substitute(&mut saved).unwrap();
+        assert_eq!(saved, config);
+    }
+
+    #[test]
+    fn saving_without_a_file_escapes_placeholders() {
+        let config = json!({"command": "say ${name} $${x}"});
+        let mut saved = config.clone();
+        restore(&mut saved, &Value::Null);
+        substitute(&mut saved).unwrap();
+        assert_eq!(saved, config);
+    }
+}
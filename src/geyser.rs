@@ -0,0 +1,227 @@
+//! Geyser, plus optionally Floodgate, installed as plugins on a Paper
+//! server or a proxy for Bedrock crossplay. The installed builds are kept
+//! next to the plugins so updates can be offered later.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+use crate::{
+    config::{BedrockConfig, ProxySoftware, ServerConfig},
+    downloads, yaml,
+};
+
+const GEYSER_API: &str = "https://download.geysermc.org/v2/projects";
+const INSTALL_FILE: &str = "plugins/.geyser-install.json";
+
+#[derive(Deserialize)]
+struct LatestBuild {
+    version: String,
+    build: u32,
+    downloads: HashMap<String, Download>,
+}
+
+#[derive(Deserialize)]
+struct Download {
+    sha256: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct PluginBuild {
+    pub version: String,
+    pub build: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct InstalledPlugins {
+    #[serde(default)]
+    pub geyser: Option<PluginBuild>,
+    #[serde(default)]
+    pub floodgate: Option<PluginBuild>,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct GeyserStatus {
+    pub installed: InstalledPlugins,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latest_geyser: Option<PluginBuild>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latest_floodgate: Option<PluginBuild>,
+    pub update_available: bool,
+}
+
+/// Where each plugin comes from and goes for one kind of server.
+struct Platform {
+    geyser_download: &'static str,
+    geyser_jar: &'static str,
+    floodgate_download: &'static str,
+    floodgate_jar: &'static str,
+    /// Geyser's data folder, named after the plugin id on each platform.
+    geyser_config: &'static str,
+}
+
+fn platform(cfg: &ServerConfig) -> Platform {
+    match cfg.proxy.as_ref().map(|p| p.software) {
+        Some(ProxySoftware::Velocity) => Platform {
+            geyser_download: "velocity",
+            geyser_jar: "Geyser-Velocity.jar",
+            floodgate_download: "velocity",
+            floodgate_jar: "floodgate-velocity.jar",
+            geyser_config: "plugins/geyser/config.yml",
+        },
+        Some(ProxySoftware::Bungeecord) => Platform {
+            geyser_download: "bungeecord",
+            geyser_jar: "Geyser-BungeeCord.jar",
+            floodgate_download: "bungee",
+            floodgate_jar: "floodgate-bungee.jar",
+            geyser_config: "plugins/Geyser-BungeeCord/config.yml",
+        },
+        None => Platform {
+            geyser_download: "spigot",
+            geyser_jar: "Geyser-Spigot.jar",
+            floodgate_download: "spigot",
+            floodgate_jar: "floodgate-spigot.jar",
+            geyser_config: "plugins/Geyser-Spigot/config.yml",
+        },
+    }
+}
+
+fn install_file(cfg: &ServerConfig) -> PathBuf {
+    Path::new(&cfg.directory).join(INSTALL_FILE)
+}
+
+pub async fn installed(cfg: &ServerConfig) -> InstalledPlugins {
+    match tokio::fs::read_to_string(install_file(cfg)).await {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_default(),
+        Err(_) => InstalledPlugins::default(),
+    }
+}
+
+async fn latest(project: &str) -> Result<LatestBuild, String> {
+    let url = format!("{}/{}/versions/latest/builds/latest", GEYSER_API, project);
+    let body = downloads::fetch_text(&url).await?;
+    serde_json::from_str(&body).map_err(|e| format!("Failed to parse {} build: {}", project, e))
+}
+
+async fn install_plugin(
+    data_directory: &str,
+    cfg: &ServerConfig,
+    project: &str,
+    download: &str,
+    jar: &str,
+) -> Result<PluginBuild, String> {
+    let build = latest(project).await?;
+    let file = build
+        .downloads
+        .get(download)
+        .ok_or_else(|| format!("{} has no '{}' download", project, download))?;
+    let url = format!(
+        "{}/{}/versions/{}/builds/{}/downloads/{}",
+        GEYSER_API, project, build.version, build.build, download
+    );
+    let dest = Path::new(&cfg.directory).join("plugins").join(jar);
+    downloads::install_cached(data_directory, &url, &file.sha256, &dest).await?;
+    Ok(PluginBuild {
+        version: build.version,
+        build: build.build,
+    })
+}
+
+/// Points Geyser at the Bedrock port and Floodgate authentication. Geyser
+/// fills in every other setting on its first start.
+async fn write_geyser_config(
+    cfg: &ServerConfig,
+    bedrock: &BedrockConfig,
+    platform: &Platform,
+) -> Result<(), String> {
+    let path = Path::new(&cfg.directory).join(platform.geyser_config);
+    let mut doc = yaml::load(&path).await?;
+    yaml::set(&mut doc, &["bedrock", "port"], Value::from(bedrock.port));
+    let auth_type = if bedrock.floodgate { "floodgate" } else { "online" };
+    yaml::set(&mut doc, &["remote", "auth-type"], Value::from(auth_type));
+    yaml::save(&path, &doc).await
+}
+
+/// Installs or updates Geyser (and Floodgate when enabled) to the latest
+/// builds. Takes effect on the server's next start.
+pub async fn install(data_directory: &str, cfg: &ServerConfig) -> Result<InstalledPlugins, String> {
+    let bedrock = cfg
+        .bedrock
+        .as_ref()
+        .ok_or_else(|| format!("Server '{}' has no bedrock section", cfg.id))?;
+    let platform = platform(cfg);
+    let geyser = install_plugin(
+        data_directory,
+        cfg,
+        "geyser",
+        platform.geyser_download,
+        platform.geyser_jar,
+    )
+    .await?;
+    let floodgate = if bedrock.floodgate {
+        Some(
+            install_plugin(
+                data_directory,
+                cfg,
+                "floodgate",
+                platform.floodgate_download,
+                platform.floodgate_jar,
+            )
+            .await?,
+        )
+    } else {
+        let _ = tokio::fs::remove_file(
+            Path::new(&cfg.directory).join("plugins").join(platform.floodgate_jar),
+        )
+        .await;
+        None
+    };
+    write_geyser_config(cfg, bedrock, &platform).await?;
+
+    let plugins = InstalledPlugins {
+        geyser: Some(geyser),
+        floodgate,
+    };
+    let json = serde_json::to_string_pretty(&plugins)
+        .map_err(|e| format!("Failed to serialize Geyser install state: {}", e))?;
+    tokio::fs::write(install_file(cfg), json)
+        .await
+        .map_err(|e| format!("Failed to write Geyser install state: {}", e))?;
+    tracing::info!("Installed Geyser build {:?} on '{}'", plugins.geyser, cfg.id);
+    Ok(plugins)
+}
+
+/// Compares the installed builds with the latest published ones.
+pub async fn status(cfg: &ServerConfig) -> GeyserStatus {
+    let installed = installed(cfg).await;
+    let floodgate = cfg.bedrock.as_ref().is_some_and(|b| b.floodgate);
+    let to_build = |b: LatestBuild| PluginBuild {
+        version: b.version,
+        build: b.build,
+    };
+    let latest_geyser = match latest("geyser").await {
+        Ok(b) => Some(to_build(b)),
+        Err(e) => {
+            tracing::warn!("Geyser update check failed: {}", e);
+            None
+        }
+    };
+    let latest_floodgate = if floodgate {
+        latest("floodgate").await.ok().map(to_build)
+    } else {
+        None
+    };
+    let outdated = |installed: &Option<PluginBuild>, latest: &Option<PluginBuild>| {
+        latest.is_some() && installed != latest
+    };
+    let update_available = outdated(&installed.geyser, &latest_geyser)
+        || outdated(&installed.floodgate, &latest_floodgate);
+    GeyserStatus {
+        installed,
+        latest_geyser,
+        latest_floodgate,
+        update_available,
+    }
+}
@@ -0,0 +1,368 @@
+//! gRPC control API (`proto/agent.proto`) covering the core operations for
+//! typed clients and the manager↔agent hop. It serves the same local
+//! servers and tokens as the REST API on its own address.
+
+use std::{pin::Pin, sync::Arc};
+
+use futures_util::Stream;
+use tokio::sync::{broadcast::error::RecvError, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+use crate::{
+    api,
+    config::DesiredState,
+    ipfilter, maintenance, nodeauth, process,
+    ratelimit::{self, Class},
+    reconcile,
+    state::{AppState, ServerInstance},
+};
+
+include!(concat!(env!("OUT_DIR"), "/mcagent.v1.Agent.rs"));
+
+use agent_server::{Agent, AgentServer};
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListServersRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListServersResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub servers: Vec<ServerSummary>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ServerSummary {
+    #[prost(string, tag = "1")]
+    pub id: String,
+    #[prost(string, tag = "2")]
+    pub name: String,
+    #[prost(string, tag = "3")]
+    pub status: String,
+    #[prost(uint32, tag = "4")]
+    pub port: u32,
+    #[prost(uint32, optional, tag = "5")]
+    pub pid: Option<u32>,
+    #[prost(uint64, optional, tag = "6")]
+    pub uptime_seconds: Option<u64>,
+    #[prost(string, tag = "7")]
+    pub flavor: String,
+    #[prost(string, repeated, tag = "8")]
+    pub tags: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ServerRequest {
+    #[prost(string, tag = "1")]
+    pub id: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CommandRequest {
+    #[prost(string, tag = "1")]
+    pub id: String,
+    #[prost(string, tag = "2")]
+    pub command: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ActionResponse {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ConsoleLine {
+    #[prost(string, tag = "1")]
+    pub line: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct MetricsSample {
+    #[prost(uint64, tag = "1")]
+    pub timestamp_ms: u64,
+    #[prost(float, tag = "2")]
+    pub cpu_percent: f32,
+    #[prost(uint64, tag = "3")]
+    pub memory_bytes: u64,
+    #[prost(double, optional, tag = "4")]
+    pub tps: Option<f64>,
+    #[prost(double, optional, tag = "5")]
+    pub mspt: Option<f64>,
+    #[prost(uint64, optional, tag = "6")]
+    pub players_online: Option<u64>,
+}
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// Lines or samples buffered per stream before a slow client is dropped.
+const STREAM_BUFFER: usize = 256;
+
+struct AgentService {
+    state: AppState,
+}
+
+impl AgentService {
+    /// Same rule as the REST middleware: open when no tokens or users are
+    /// configured. Returns the rate-limit key of whoever authenticated.
+    async fn authorize<T>(&self, req: &Request<T>) -> Result<Option<String>, Status> {
+        let (tokens, users) = {
+            let config = self.state.config.read().await;
+            (config.agent.manager_tokens.clone(), config.agent.users.clone())
+        };
+        if tokens.is_empty() && users.is_empty() {
+            return Ok(None);
+        }
+        let presented = req
+            .metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        if let Some(token) =
+            presented.and_then(|p| tokens.into_iter().find(|t| nodeauth::tokens_match(t, p)))
+        {
+            return Ok(Some(format!("token:{}", token)));
+        }
+        match presented.and_then(|p| self.state.sessions.verify(&users, p)) {
+            Some(session) => Ok(Some(format!("user:{}", session.username))),
+            None => Err(Status::unauthenticated(
+                "A valid manager token or session is required",
+            )),
+        }
+    }
+
+    /// Filters the caller's address, authorizes the call and counts it
+    /// against the caller's rate limit, all as for REST clients.
+    async fn admit<T>(&self, req: &Request<T>, class: Class) -> Result<(), Status> {
+        let client_ip = match req.remote_addr() {
+            Some(peer) => {
+                let forwarded_for = req
+                    .metadata()
+                    .get(ipfilter::FORWARDED_FOR)
+                    .and_then(|v| v.to_str().ok());
+                let config = self.state.config.read().await;
+                let cfg = &config.agent.ip_filter;
+                let ip = ipfilter::resolve(cfg, peer.ip(), forwarded_for);
+                if !ipfilter::permitted(cfg, ip) {
+                    return Err(Status::permission_denied("Your address is not allowed"));
+                }
+                Some(ip)
+            }
+            None => None,
+        };
+        let address = match client_ip {
+            Some(ip) => format!("ip:{}", ip),
+            None => "unknown".to_string(),
+        };
+        let limit = self.state.config.read().await.agent.rate_limits.rejected;
+        let limiter = &self.state.rate_limiter;
+        if let Err(wait) = limiter.peek(&address, Class::Rejected, limit) {
+            return Err(Status::resource_exhausted(ratelimit::too_many(Class::Rejected, wait)));
+        }
+        let authorized = match self.authorize(req).await {
+            Ok(key) => key,
+            Err(status) => {
+                let _ = limiter.check(&address, Class::Rejected, limit);
+                return Err(status);
+            }
+        };
+        let client = authorized.unwrap_or(address);
+        limiter
+            .check_configured(&self.state, &client, class)
+            .await
+            .map_err(Status::resource_exhausted)
+    }
+
+    /// Refuses calls that change anything while in maintenance mode.
+    async fn writable(&self) -> Result<(), Status> {
+        maintenance::check(&self.state).await.map_err(Status::unavailable)
+    }
+
+    fn instance(&self, id: &str) -> Result<Arc<ServerInstance>, Status> {
+        self.state
+            .servers
+            .get(id)
+            .map(|r| r.value().clone())
+            .ok_or_else(|| Status::failed_precondition("Server is not running"))
+    }
+}
+
+#[tonic::async_trait]
+impl Agent for AgentService {
+    async fn list_servers(
+        &self,
+        req: Request<ListServersRequest>,
+    ) -> Result<Response<ListServersResponse>, Status> {
+        self.admit(&req, Class::Api).await?;
+        let servers = api::local_server_statuses(&self.state)
+            .await
+            .into_iter()
+            .map(|s| ServerSummary {
+                id: s.config.id.clone(),
+                name: s.config.name.clone(),
+                status: s.status.to_string(),
+                port: s.config.port.into(),
+                pid: s.pid,
+                uptime_seconds: s.uptime_seconds,
+                flavor: s.config.flavor().as_str().to_string(),
+                tags: s.config.tags.clone(),
+            })
+            .collect();
+        Ok(Response::new(ListServersResponse { servers }))
+    }
+
+    async fn start_server(
+        &self,
+        req: Request<ServerRequest>,
+    ) -> Result<Response<ActionResponse>, Status> {
+        self.admit(&req, Class::Actions).await?;
+        self.writable().await?;
+        let id = req.into_inner().id;
+        self.state.restart_attempts.remove(&id);
+        reconcile::set_desired(&self.state, &id, DesiredState::Running).await;
+        process::start_server(self.state.clone(), &id)
+            .await
+            .map_err(Status::failed_precondition)?;
+        Ok(Response::new(ActionResponse {}))
+    }
+
+    async fn stop_server(
+        &self,
+        req: Request<ServerRequest>,
+    ) -> Result<Response<ActionResponse>, Status> {
+        self.admit(&req, Class::Actions).await?;
+        self.writable().await?;
+        let id = req.into_inner().id;
+        reconcile::set_desired(&self.state, &id, DesiredState::Stopped).await;
+        process::stop_server(self.state.clone(), &id)
+            .await
+            .map_err(Status::failed_precondition)?;
+        Ok(Response::new(ActionResponse {}))
+    }
+
+    async fn send_command(
+        &self,
+        req: Request<CommandRequest>,
+    ) -> Result<Response<ActionResponse>, Status> {
+        self.admit(&req, Class::Commands).await?;
+        self.writable().await?;
+        let cmd = req.into_inner();
+        process::send_command(&self.state, &cmd.id, &cmd.command)
+            .await
+            .map_err(Status::failed_precondition)?;
+        Ok(Response::new(ActionResponse {}))
+    }
+
+    type StreamConsoleStream = ResponseStream<ConsoleLine>;
+
+    async fn stream_console(
+        &self,
+        req: Request<ServerRequest>,
+    ) -> Result<Response<Self::StreamConsoleStream>, Status> {
+        self.admit(&req, Class::Api).await?;
+        let instance = self.instance(&req.into_inner().id)?;
+        let recent: Vec<String> = {
+            let buf = instance.console_buffer.lock().await;
+            let mut recent: Vec<String> = buf
+                .iter()
+                .rev()
+                .take(instance.console_replay_lines)
+                .map(|line| line.text.clone())
+                .collect();
+            recent.reverse();
+            recent
+        };
+        let mut console_rx = instance.console_tx.subscribe();
+        let mut exited_rx = instance.exited.subscribe();
+
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(async move {
+            for line in recent {
+                if tx.send(Ok(ConsoleLine { line })).await.is_err() {
+                    return;
+                }
+            }
+            loop {
+                tokio::select! {
+                    msg = console_rx.recv() => {
+                        let item = match msg {
+                            Ok(line) => Ok(ConsoleLine { line: line.text }),
+                            Err(RecvError::Lagged(n)) => Err(Status::resource_exhausted(
+                                format!("Console consumer fell behind by {} lines", n),
+                            )),
+                            Err(RecvError::Closed) => break,
+                        };
+                        let failed = item.is_err();
+                        if tx.send(item).await.is_err() || failed {
+                            break;
+                        }
+                    }
+                    _ = api::wait_for_exit(&mut exited_rx) => break,
+                    _ = tx.closed() => break,
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    type StreamMetricsStream = ResponseStream<MetricsSample>;
+
+    async fn stream_metrics(
+        &self,
+        req: Request<ServerRequest>,
+    ) -> Result<Response<Self::StreamMetricsStream>, Status> {
+        self.admit(&req, Class::Api).await?;
+        let instance = self.instance(&req.into_inner().id)?;
+        let mut metrics_rx = instance.metrics_tx.subscribe();
+        let mut exited_rx = instance.exited.subscribe();
+
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    msg = metrics_rx.recv() => {
+                        let item = match msg {
+                            Ok(m) => Ok(MetricsSample {
+                                timestamp_ms: m.timestamp_ms,
+                                cpu_percent: m.cpu_percent,
+                                memory_bytes: m.memory_bytes,
+                                tps: m.ticks.tps,
+                                mspt: m.ticks.mspt,
+                                players_online: m.players_online,
+                            }),
+                            Err(RecvError::Lagged(n)) => Err(Status::resource_exhausted(
+                                format!("Metrics consumer fell behind by {} samples", n),
+                            )),
+                            Err(RecvError::Closed) => break,
+                        };
+                        let failed = item.is_err();
+                        if tx.send(item).await.is_err() || failed {
+                            break;
+                        }
+                    }
+                    _ = api::wait_for_exit(&mut exited_rx) => break,
+                    _ = tx.closed() => break,
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+}
+
+/// Serves the gRPC API on `agent.grpc_bind_address` when one is set.
+pub fn spawn(state: AppState) {
+    tokio::spawn(async move {
+        let Some(address) = state.config.read().await.agent.grpc_bind_address.clone() else {
+            return;
+        };
+        let addr = match address.parse() {
+            Ok(addr) => addr,
+            Err(e) => {
+                tracing::error!("Invalid grpc_bind_address '{}': {}", address, e);
+                return;
+            }
+        };
+        tracing::info!("gRPC listening on {}", address);
+        let service = AgentServer::new(AgentService { state });
+        if let Err(e) = tonic::transport::Server::builder().add_service(service).serve(addr).await {
+            tracing::error!("gRPC server failed: {}", e);
+        }
+    });
+}
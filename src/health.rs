@@ -0,0 +1,112 @@
+//! Liveness and readiness reports for container healthchecks and monitors.
+
+use std::path::Path;
+
+use serde::Serialize;
+use sysinfo::Disks;
+
+use crate::state::AppState;
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct DiskReport {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mount_point: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub available_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_bytes: Option<u64>,
+    pub ok: bool,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct SupervisorReport {
+    pub id: String,
+    pub status: &'static str,
+    pub autostart: bool,
+    /// False for autostart servers that are not running and for crashes.
+    pub ok: bool,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct ReadinessReport {
+    pub ready: bool,
+    pub config_loaded: bool,
+    pub server_count: usize,
+    pub disk: DiskReport,
+    pub servers: Vec<SupervisorReport>,
+}
+
+/// Space left on the filesystem holding `path`, via the longest matching mount.
+fn disk_report(path: &str, min_free_mb: u64) -> DiskReport {
+    let resolved = std::fs::canonicalize(path).ok();
+    let disks = Disks::new_with_refreshed_list();
+    let disk = resolved.as_ref().and_then(|p| {
+        disks
+            .list()
+            .iter()
+            .filter(|d| p.starts_with(d.mount_point()))
+            .max_by_key(|d| d.mount_point().as_os_str().len())
+    });
+    let available_bytes = disk.map(|d| d.available_space());
+    DiskReport {
+        path: path.to_string(),
+        mount_point: disk.map(|d| d.mount_point().to_string_lossy().into_owned()),
+        available_bytes,
+        total_bytes: disk.map(|d| d.total_space()),
+        ok: Path::new(path).is_dir()
+            && available_bytes.is_none_or(|b| b >= min_free_mb * 1024 * 1024),
+    }
+}
+
+pub async fn readiness(state: &AppState) -> ReadinessReport {
+    let (data_directory, min_free_mb, servers) = {
+        let config = state.config.read().await;
+        (
+            config.agent.data_directory.clone(),
+            config.agent.min_free_disk_mb,
+            config.servers.clone(),
+        )
+    };
+
+    let mut reports = Vec::with_capacity(servers.len());
+    for cfg in &servers {
+        let instance = state.servers.get(&cfg.id).map(|r| r.value().clone());
+        let status = match instance {
+            Some(inst) => inst.phase.lock().await.as_str(),
+            None if state.last_exits.get(&cfg.id).is_some_and(|e| e.crashed) => "crashed",
+            None => "stopped",
+        };
+        let ok = match status {
+            "crashed" => false,
+            "stopped" => !cfg.autostart,
+            _ => true,
+        };
+        reports.push(SupervisorReport {
+            id: cfg.id.clone(),
+            status,
+            autostart: cfg.autostart,
+            ok,
+        });
+    }
+
+    let disk = tokio::task::spawn_blocking(move || disk_report(&data_directory, min_free_mb))
+        .await
+        .unwrap_or_else(|_| DiskReport {
+            path: String::new(),
+            mount_point: None,
+            available_bytes: None,
+            total_bytes: None,
+            ok: false,
+        });
+
+    ReadinessReport {
+        // Supervisor problems are reported but don't take the agent out of
+        // rotation; the agent is still needed to fix them
+        ready: disk.ok,
+        config_loaded: true,
+        server_count: servers.len(),
+        disk,
+        servers: reports,
+    }
+}
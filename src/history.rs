@@ -0,0 +1,243 @@
+//! Metrics history on disk: one JSON line per sample, in hourly segment
+//! files under `data_directory/metrics/<server id>/`, pruned by retention.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::state::Metrics;
+
+const HOUR_MS: u64 = 60 * 60 * 1000;
+/// Most points a query returns when no step is given.
+const DEFAULT_MAX_POINTS: u64 = 500;
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct Sample {
+    pub timestamp_ms: u64,
+    pub cpu_percent: f32,
+    pub memory_bytes: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heap_used_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tps: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mspt: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub players_online: Option<u64>,
+}
+
+/// Online players over one bucket of a player count query.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct PlayerCountPoint {
+    pub timestamp_ms: u64,
+    pub average: f64,
+    pub peak: u64,
+}
+
+impl From<&Metrics> for Sample {
+    fn from(m: &Metrics) -> Self {
+        Self {
+            timestamp_ms: m.timestamp_ms,
+            cpu_percent: m.cpu_percent,
+            memory_bytes: m.memory_bytes,
+            heap_used_bytes: m.gc.heap_used_bytes,
+            tps: m.ticks.tps,
+            mspt: m.ticks.mspt,
+            players_online: m.players_online,
+        }
+    }
+}
+
+fn server_dir(data_directory: &str, server_id: &str) -> PathBuf {
+    Path::new(data_directory).join("metrics").join(server_id)
+}
+
+fn segment_hour(timestamp_ms: u64) -> u64 {
+    timestamp_ms / HOUR_MS
+}
+
+/// Appends samples for one running server.
+pub struct Writer {
+    dir: PathBuf,
+    retention_hours: u64,
+    segment: Option<(u64, tokio::fs::File)>,
+}
+
+impl Writer {
+    pub fn new(data_directory: &str, server_id: &str, retention_hours: u64) -> Self {
+        Self {
+            dir: server_dir(data_directory, server_id),
+            retention_hours,
+            segment: None,
+        }
+    }
+
+    pub async fn append(&mut self, metrics: &Metrics) {
+        if self.retention_hours == 0 {
+            return;
+        }
+        let hour = segment_hour(metrics.timestamp_ms);
+        if self.segment.as_ref().map(|(h, _)| *h) != Some(hour) {
+            self.segment = None;
+            if let Err(e) = tokio::fs::create_dir_all(&self.dir).await {
+                tracing::warn!("Failed to create metrics directory {:?}: {}", self.dir, e);
+                return;
+            }
+            self.prune(hour).await;
+            let path = self.dir.join(format!("{}.jsonl", hour));
+            match tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await
+            {
+                Ok(file) => self.segment = Some((hour, file)),
+                Err(e) => {
+                    tracing::warn!("Failed to open metrics segment {:?}: {}", path, e);
+                    return;
+                }
+            }
+        }
+        let Some((_, file)) = self.segment.as_mut() else {
+            return;
+        };
+        if let Ok(mut line) = serde_json::to_string(&Sample::from(metrics)) {
+            line.push('\n');
+            let _ = file.write_all(line.as_bytes()).await;
+        }
+    }
+
+    async fn prune(&self, current_hour: u64) {
+        let oldest = current_hour.saturating_sub(self.retention_hours);
+        for (hour, path) in segments(&self.dir).await {
+            if hour < oldest {
+                let _ = tokio::fs::remove_file(path).await;
+            }
+        }
+    }
+}
+
+async fn segments(dir: &Path) -> Vec<(u64, PathBuf)> {
+    let mut found = Vec::new();
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return found;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if let Some(hour) = name.strip_suffix(".jsonl").and_then(|h| h.parse().ok()) {
+            found.push((hour, entry.path()));
+        }
+    }
+    found.sort();
+    found
+}
+
+fn mean<I: Iterator<Item = f64>>(values: I) -> Option<f64> {
+    let (sum, count) = values.fold((0.0, 0u32), |(s, c), v| (s + v, c + 1));
+    (count > 0).then(|| sum / count as f64)
+}
+
+/// Stored samples in `[from_ms, to_ms]`, oldest first.
+pub async fn samples(
+    data_directory: &str,
+    server_id: &str,
+    from_ms: u64,
+    to_ms: u64,
+) -> Vec<Sample> {
+    let mut samples = Vec::new();
+    let (first, last) = (segment_hour(from_ms), segment_hour(to_ms));
+    for (hour, path) in segments(&server_dir(data_directory, server_id)).await {
+        if hour < first || hour > last {
+            continue;
+        }
+        let Ok(contents) = tokio::fs::read_to_string(&path).await else {
+            continue;
+        };
+        samples.extend(
+            contents
+                .lines()
+                .filter_map(|l| serde_json::from_str::<Sample>(l).ok())
+                .filter(|s| s.timestamp_ms >= from_ms && s.timestamp_ms <= to_ms),
+        );
+    }
+    samples
+}
+
+/// Stored samples in `[from_ms, to_ms]`, grouped into buckets of `step_ms`.
+async fn buckets(
+    data_directory: &str,
+    server_id: &str,
+    from_ms: u64,
+    to_ms: u64,
+    step_ms: Option<u64>,
+) -> std::collections::BTreeMap<u64, Vec<Sample>> {
+    let step_ms = step_ms
+        .filter(|&s| s > 0)
+        .unwrap_or_else(|| ((to_ms.saturating_sub(from_ms)) / DEFAULT_MAX_POINTS).max(1000));
+
+    let mut buckets: std::collections::BTreeMap<u64, Vec<Sample>> = Default::default();
+    for sample in samples(data_directory, server_id, from_ms, to_ms).await {
+        let bucket = from_ms + (sample.timestamp_ms - from_ms) / step_ms * step_ms;
+        buckets.entry(bucket).or_default().push(sample);
+    }
+    buckets
+}
+
+/// Samples in `[from_ms, to_ms]`, averaged into buckets of `step_ms`.
+pub async fn query(
+    data_directory: &str,
+    server_id: &str,
+    from_ms: u64,
+    to_ms: u64,
+    step_ms: Option<u64>,
+) -> Vec<Sample> {
+    buckets(data_directory, server_id, from_ms, to_ms, step_ms)
+        .await
+        .into_iter()
+        .map(|(timestamp_ms, samples)| Sample {
+            timestamp_ms,
+            cpu_percent: mean(samples.iter().map(|s| s.cpu_percent as f64)).unwrap_or(0.0) as f32,
+            memory_bytes: mean(samples.iter().map(|s| s.memory_bytes as f64)).unwrap_or(0.0) as u64,
+            heap_used_bytes: mean(
+                samples
+                    .iter()
+                    .filter_map(|s| s.heap_used_bytes)
+                    .map(|v| v as f64),
+            )
+            .map(|v| v as u64),
+            tps: mean(samples.iter().filter_map(|s| s.tps)),
+            mspt: mean(samples.iter().filter_map(|s| s.mspt)),
+            players_online: mean(
+                samples
+                    .iter()
+                    .filter_map(|s| s.players_online)
+                    .map(|v| v as f64),
+            )
+            .map(|v| v.round() as u64),
+        })
+        .collect()
+}
+
+/// Average and peak online players in `[from_ms, to_ms]`, per bucket of
+/// `step_ms`. Buckets without a known count are left out.
+pub async fn player_counts(
+    data_directory: &str,
+    server_id: &str,
+    from_ms: u64,
+    to_ms: u64,
+    step_ms: Option<u64>,
+) -> Vec<PlayerCountPoint> {
+    buckets(data_directory, server_id, from_ms, to_ms, step_ms)
+        .await
+        .into_iter()
+        .filter_map(|(timestamp_ms, samples)| {
+            let counts = || samples.iter().filter_map(|s| s.players_online);
+            Some(PlayerCountPoint {
+                timestamp_ms,
+                average: mean(counts().map(|v| v as f64))?,
+                peak: counts().max()?,
+            })
+        })
+        .collect()
+}
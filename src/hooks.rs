@@ -0,0 +1,98 @@
+//! Operator shell commands run around lifecycle events, e.g. pausing a
+//! dynmap render before a stop or syncing a backup off-host afterwards.
+//! Each runs through the shell in the server directory with `MC_*` variables set.
+
+use std::time::Duration;
+
+use crate::{config::ServerConfig, state::AppState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    PreStart,
+    PostStart,
+    PreStop,
+    PostStop,
+    PostBackup,
+}
+
+impl Hook {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Hook::PreStart => "pre_start",
+            Hook::PostStart => "post_start",
+            Hook::PreStop => "pre_stop",
+            Hook::PostStop => "post_stop",
+            Hook::PostBackup => "post_backup",
+        }
+    }
+
+    fn command(self, cfg: &ServerConfig) -> Option<&str> {
+        let hooks = &cfg.hooks;
+        match self {
+            Hook::PreStart => hooks.pre_start.as_deref(),
+            Hook::PostStart => hooks.post_start.as_deref(),
+            Hook::PreStop => hooks.pre_stop.as_deref(),
+            Hook::PostStop => hooks.post_stop.as_deref(),
+            Hook::PostBackup => hooks.post_backup.as_deref(),
+        }
+    }
+}
+
+/// Runs `hook` for `cfg` if one is configured. `extra_env` is added on top
+/// of the standard variables, e.g. `MC_BACKUP_PATH` for `post_backup`.
+pub async fn run(
+    cfg: &ServerConfig,
+    hook: Hook,
+    pid: Option<u32>,
+    extra_env: &[(&str, String)],
+) -> Result<(), String> {
+    let Some(command) = hook.command(cfg) else {
+        return Ok(());
+    };
+    let mut cmd = crate::platform::shell_command(command);
+    cmd.current_dir(&cfg.directory)
+        .env("MC_HOOK", hook.as_str())
+        .env("MC_SERVER_ID", &cfg.id)
+        .env("MC_SERVER_NAME", &cfg.name)
+        .env("MC_SERVER_DIR", &cfg.directory)
+        .env("MC_SERVER_PORT", cfg.port.to_string())
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true);
+    if let Some(pid) = pid {
+        cmd.env("MC_SERVER_PID", pid.to_string());
+    }
+    for (key, value) in extra_env {
+        cmd.env(key, value);
+    }
+
+    let timeout = Duration::from_secs(cfg.hooks.timeout_secs);
+    let output = tokio::time::timeout(timeout, cmd.output())
+        .await
+        .map_err(|_| format!("{} hook timed out after {}s", hook.as_str(), timeout.as_secs()))?
+        .map_err(|e| format!("Failed to run {} hook: {}", hook.as_str(), e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!(
+            "{} hook exited with {}: {}",
+            hook.as_str(),
+            output.status,
+            stderr.trim()
+        ));
+    }
+    tracing::info!("Ran {} hook for '{}'", hook.as_str(), cfg.id);
+    Ok(())
+}
+
+/// Like `run`, but looks the server up and only logs failures.
+pub async fn run_logged(state: &AppState, server_id: &str, hook: Hook, pid: Option<u32>) {
+    let cfg = {
+        let config = state.config.read().await;
+        config.servers.iter().find(|s| s.id == server_id).cloned()
+    };
+    let Some(cfg) = cfg else {
+        return;
+    };
+    if let Err(e) = run(&cfg, hook, pid, &[]).await {
+        tracing::warn!("Server '{}': {}", server_id, e);
+    }
+}
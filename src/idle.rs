@@ -0,0 +1,59 @@
+//! Stops servers that have had no players for `idle_shutdown_minutes`,
+//! judged from the player counts the SLP poller caches.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::{
+    process::stop_server,
+    state::{AppState, ServerPhase},
+};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+pub fn spawn_monitor(state: AppState) {
+    tokio::spawn(async move {
+        // When each server (by id and PID) was first seen empty
+        let mut empty_since: HashMap<(String, u32), Instant> = HashMap::new();
+        loop {
+            tokio::time::sleep(CHECK_INTERVAL).await;
+            let servers = state.config.read().await.servers.clone();
+            empty_since.retain(|(id, pid), _| state.servers.get(id).is_some_and(|i| i.pid == *pid));
+            for server in servers {
+                let Some(minutes) = server.idle_shutdown_minutes else {
+                    continue;
+                };
+                let Some(instance) = state.servers.get(&server.id).map(|i| i.value().clone())
+                else {
+                    continue;
+                };
+                let key = (server.id.clone(), instance.pid);
+                if *instance.phase.lock().await != ServerPhase::Running {
+                    empty_since.remove(&key);
+                    continue;
+                }
+                // No SLP answer means the count is unknown, not zero
+                let players = state.slp.get(&server.id).map(|s| s.players_online);
+                if players != Some(0) {
+                    empty_since.remove(&key);
+                    continue;
+                }
+                let since = *empty_since.entry(key.clone()).or_insert_with(Instant::now);
+                if since.elapsed() >= Duration::from_secs(minutes as u64 * 60) {
+                    tracing::info!(
+                        "Stopping '{}' after {} minutes without players",
+                        server.id,
+                        minutes
+                    );
+                    empty_since.remove(&key);
+                    let state = state.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = stop_server(state, &server.id).await {
+                            tracing::error!("Idle shutdown of '{}' failed: {}", server.id, e);
+                        }
+                    });
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,124 @@
+//! `!panel <action>` chat commands from trusted in-game operators.
+
+use std::sync::{Arc, OnceLock};
+
+use regex::Regex;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    ansi, console,
+    process::{backup_server, restart_server, send_command},
+    state::{AppState, ServerInstance},
+};
+
+fn tell(player: &str, message: &str) -> String {
+    let text = serde_json::json!({ "text": format!("[Panel] {}", message), "color": "gold" });
+    format!("tellraw {} {}", player, text)
+}
+
+fn panel_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    // Anchored on the log prefix so another player can't quote an operator
+    RE.get_or_init(|| {
+        Regex::new(&format!(r"{}<([A-Za-z0-9_]{{1,16}})> !panel (\w+)\s*$", console::INFO_PREFIX))
+            .expect("valid regex")
+    })
+}
+
+/// The player and action of a `!panel` chat line, without colour codes.
+fn panel_request(line: &str) -> Option<(String, String)> {
+    let caps = panel_regex().captures(line)?;
+    Some((caps[1].to_string(), caps[2].to_string()))
+}
+
+/// Watches the console for `<player> !panel backup|restart` chat lines.
+pub fn spawn_listener(
+    state: AppState,
+    server_id: String,
+    instance: Arc<ServerInstance>,
+    operators: Vec<String>,
+) {
+    if operators.is_empty() {
+        return;
+    }
+    let mut console_rx = instance.console_tx.subscribe();
+    let mut exited_rx = instance.exited.subscribe();
+    tokio::spawn(async move {
+        loop {
+            let line = tokio::select! {
+                msg = console_rx.recv() => match msg {
+                    Ok(line) => line,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+                _ = exited_rx.wait_for(|exited| *exited) => break,
+            };
+            let Some((player, action)) = panel_request(&ansi::strip(&line.text)) else {
+                continue;
+            };
+            if !operators.iter().any(|op| op.eq_ignore_ascii_case(&player)) {
+                let reply = tell(&player, "You are not a panel operator");
+                let _ = send_command(&state, &server_id, &reply).await;
+                continue;
+            }
+            tracing::info!(
+                "In-game operator '{}' requested '{}' on '{}'",
+                player,
+                action,
+                server_id
+            );
+            match action.as_str() {
+                "backup" => {
+                    let _ = send_command(&state, &server_id, &tell(&player, "Backup started")).await;
+                    let state2 = state.clone();
+                    let sid = server_id.clone();
+                    tokio::spawn(async move {
+                        let reply = match backup_server(state2.clone(), &sid).await {
+                            Ok(()) => "Backup finished".to_string(),
+                            Err(e) => format!("Backup failed: {}", e),
+                        };
+                        let _ = send_command(&state2, &sid, &tell(&player, &reply)).await;
+                    });
+                }
+                "restart" => {
+                    let _ = send_command(&state, &server_id, &tell(&player, "Restarting")).await;
+                    let state2 = state.clone();
+                    let sid = server_id.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = restart_server(state2, &sid).await {
+                            tracing::error!("In-game restart of '{}' failed: {}", sid, e);
+                        }
+                    });
+                    break;
+                }
+                _ => {
+                    let _ = send_command(
+                        &state,
+                        &server_id,
+                        &tell(&player, "Unknown action; use !panel backup or !panel restart"),
+                    )
+                    .await;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panel_requests_are_read_from_chat() {
+        let line = "[12:00:00] [Server thread/INFO]: <Op> !panel restart";
+        assert_eq!(panel_request(line), Some(("Op".to_string(), "restart".to_string())));
+        let line = "\x1b[0m[12:00:00 INFO]: <Op> !panel backup \x1b[0m";
+        assert_eq!(panel_request(&ansi::strip(line)).map(|r| r.1).as_deref(), Some("backup"));
+    }
+
+    #[test]
+    fn quoted_requests_are_ignored() {
+        assert_eq!(panel_request("[12:00:00 INFO]: <Bob> ]: <Op> !panel restart"), None);
+        assert_eq!(panel_request("[12:00:00 INFO]: * Bob ]: <Op> !panel restart"), None);
+    }
+}
@@ -0,0 +1,132 @@
+//! Source-address allow and deny lists for agents exposed directly on the
+//! internet. Behind a reverse proxy the client address is taken from
+//! `X-Forwarded-For`, but only when the proxy is a trusted one.
+
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use ipnet::IpNet;
+
+use crate::{config::IpFilterConfig, state::AppState};
+
+pub const FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// The address a request came from once trusted proxies are looked past,
+/// stored in the request extensions for later layers.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+/// Accepts "10.0.0.0/8" as well as a bare address.
+pub fn parse_range(entry: &str) -> Option<IpNet> {
+    let entry = entry.trim();
+    entry
+        .parse::<IpNet>()
+        .ok()
+        .or_else(|| entry.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+fn contains(ranges: &[String], ip: IpAddr) -> bool {
+    ranges.iter().filter_map(|r| parse_range(r)).any(|net| net.contains(&ip))
+}
+
+/// Walks `X-Forwarded-For` from the nearest hop outwards while the hops are
+/// trusted proxies. Without a trusted peer the header is ignored, since
+/// anyone can send it.
+pub fn resolve(cfg: &IpFilterConfig, peer: IpAddr, forwarded_for: Option<&str>) -> IpAddr {
+    let mut client = peer.to_canonical();
+    if !contains(&cfg.trusted_proxies, client) {
+        return client;
+    }
+    let Some(forwarded_for) = forwarded_for else {
+        return client;
+    };
+    for hop in forwarded_for.rsplit(',') {
+        let Ok(hop) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+        client = hop.to_canonical();
+        if !contains(&cfg.trusted_proxies, client) {
+            break;
+        }
+    }
+    client
+}
+
+pub fn permitted(cfg: &IpFilterConfig, ip: IpAddr) -> bool {
+    (cfg.allow.is_empty() || contains(&cfg.allow, ip)) && !contains(&cfg.deny, ip)
+}
+
+pub async fn filter(State(state): State<AppState>, mut req: Request, next: Next) -> Response {
+    let Some(ConnectInfo(peer)) = req.extensions().get::<ConnectInfo<SocketAddr>>().copied() else {
+        return next.run(req).await;
+    };
+    let forwarded_for = req.headers().get(FORWARDED_FOR).and_then(|v| v.to_str().ok());
+    let (client, allowed) = {
+        let config = state.config.read().await;
+        let cfg = &config.agent.ip_filter;
+        let client = resolve(cfg, peer.ip(), forwarded_for);
+        (client, permitted(cfg, client))
+    };
+    if !allowed {
+        tracing::debug!("Refused {} {} from {}", req.method(), req.uri().path(), client);
+        return (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({ "error": "Your address is not allowed" })),
+        )
+            .into_response();
+    }
+    req.extensions_mut().insert(ClientIp(client));
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn behind(trusted_proxies: &[&str]) -> IpFilterConfig {
+        IpFilterConfig {
+            trusted_proxies: trusted_proxies.iter().map(|p| p.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_from_untrusted_peers() {
+        let cfg = behind(&["10.0.0.1"]);
+        assert_eq!(resolve(&cfg, ip("203.0.113.9"), Some("198.51.100.7")), ip("203.0.113.9"));
+    }
+
+    #[test]
+    fn trusted_proxies_are_looked_past() {
+        let cfg = behind(&["10.0.0.0/8"]);
+        let header = "198.51.100.7, 203.0.113.9, 10.0.0.2";
+        assert_eq!(resolve(&cfg, ip("10.0.0.1"), Some(header)), ip("203.0.113.9"));
+        assert_eq!(resolve(&cfg, ip("10.0.0.1"), None), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn a_malformed_hop_stops_the_walk() {
+        let cfg = behind(&["10.0.0.1"]);
+        let header = "198.51.100.7, not-an-address";
+        assert_eq!(resolve(&cfg, ip("10.0.0.1"), Some(header)), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn mapped_ipv4_peers_are_canonical() {
+        let cfg = behind(&["10.0.0.1"]);
+        assert_eq!(
+            resolve(&cfg, ip("::ffff:10.0.0.1"), Some("198.51.100.7")),
+            ip("198.51.100.7")
+        );
+    }
+}
@@ -0,0 +1,212 @@
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+use crate::config::{JvmProfile, ServerConfig};
+use crate::state::GcStats;
+
+/// Unified GC logging to stdout with a fixed decoration the console reader
+/// recognises. Requires Java 9 or newer.
+pub const GC_LOG_ARG: &str = "-Xlog:gc:stdout:uptime,tags";
+
+// https://docs.papermc.io/paper/aikars-flags
+const AIKAR_FLAGS: &[&str] = &[
+    "-XX:+UseG1GC",
+    "-XX:+ParallelRefProcEnabled",
+    "-XX:MaxGCPauseMillis=200",
+    "-XX:+UnlockExperimentalVMOptions",
+    "-XX:+DisableExplicitGC",
+    "-XX:+AlwaysPreTouch",
+    "-XX:G1HeapWastePercent=5",
+    "-XX:G1MixedGCCountTarget=4",
+    "-XX:InitiatingHeapOccupancyPercent=15",
+    "-XX:G1MixedGCLiveThresholdPercent=90",
+    "-XX:G1RSetUpdatingPauseTimePercent=5",
+    "-XX:SurvivorRatio=32",
+    "-XX:+PerfDisableSharedMem",
+    "-XX:MaxTenuringThreshold=1",
+    "-Dusing.aikars.flags=https://mcflags.emc.gs",
+    "-Daikars.new.flags=true",
+];
+
+// Aikar recommends larger young generation settings above 12GB of heap.
+const AIKAR_SMALL_HEAP_FLAGS: &[&str] = &[
+    "-XX:G1NewSizePercent=30",
+    "-XX:G1MaxNewSizePercent=40",
+    "-XX:G1HeapRegionSize=8M",
+    "-XX:G1ReservePercent=20",
+];
+const AIKAR_LARGE_HEAP_FLAGS: &[&str] = &[
+    "-XX:G1NewSizePercent=40",
+    "-XX:G1MaxNewSizePercent=50",
+    "-XX:G1HeapRegionSize=16M",
+    "-XX:G1ReservePercent=15",
+];
+
+const ZGC_FLAGS: &[&str] = &[
+    "-XX:+UseZGC",
+    "-XX:+AlwaysPreTouch",
+    "-XX:+DisableExplicitGC",
+    "-XX:+PerfDisableSharedMem",
+];
+
+/// Heap and GC flags generated for the configured profile. User supplied
+/// `jvm_args` are appended after these so they can override any of them.
+pub fn profile_flags(profile: JvmProfile, min_memory_mb: u32, memory_mb: u32) -> Vec<String> {
+    let mut flags = Vec::new();
+    if profile != JvmProfile::Custom {
+        flags.push(format!("-Xms{}M", min_memory_mb));
+        flags.push(format!("-Xmx{}M", memory_mb));
+    }
+    match profile {
+        JvmProfile::Default | JvmProfile::Custom => {}
+        JvmProfile::Aikar => {
+            flags.extend(AIKAR_FLAGS.iter().map(|f| f.to_string()));
+            let sized = if memory_mb > 12 * 1024 {
+                AIKAR_LARGE_HEAP_FLAGS
+            } else {
+                AIKAR_SMALL_HEAP_FLAGS
+            };
+            flags.extend(sized.iter().map(|f| f.to_string()));
+        }
+        JvmProfile::Zgc => flags.extend(ZGC_FLAGS.iter().map(|f| f.to_string())),
+    }
+    flags
+}
+
+/// Every JVM argument that goes before `-jar` for this server.
+pub fn jvm_arguments(cfg: &ServerConfig) -> Vec<String> {
+    let mut args = profile_flags(cfg.jvm_profile, cfg.min_memory_mb(), cfg.memory_mb);
+    args.extend(cfg.jvm_args.iter().cloned());
+    args
+}
+
+/// The `-Xmx` the server's JVM is started with, in MB: the last one given,
+/// as the JVM takes it. None when the JVM picks its own default.
+pub fn max_heap_mb(cfg: &ServerConfig) -> Option<u64> {
+    let args = cfg.command.clone().unwrap_or_else(|| jvm_arguments(cfg));
+    let value = args.iter().rev().find_map(|a| a.strip_prefix("-Xmx"))?;
+    let (digits, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => value.split_at(i),
+        None => (value, ""),
+    };
+    let amount: u64 = digits.parse().ok()?;
+    let scale: u64 = match unit.to_ascii_lowercase().as_str() {
+        "" => 1,
+        "k" => 1024,
+        "m" => 1024 * 1024,
+        "g" => 1024 * 1024 * 1024,
+        "t" => 1024 * 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    Some(amount.checked_mul(scale)? / (1024 * 1024))
+}
+
+fn gc_line_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"^\[\d+\.\d+s\]\[gc\s*\]").expect("valid regex"))
+}
+
+fn gc_pause_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r"Pause .* (\d+)M->(\d+)M\((\d+)M\) (\d+(?:\.\d+)?)ms$").expect("valid regex")
+    })
+}
+
+fn zgc_cycle_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r"(?:Garbage|Minor|Major) Collection \(.*\) \d+M\(\d+%\)->(\d+)M\(\d+%\)")
+            .expect("valid regex")
+    })
+}
+
+/// Returns true when `line` came from `GC_LOG_ARG` and should be kept out of
+/// the console, folding any pause it reports into `stats`.
+pub fn record_gc_line(line: &str, stats: &mut GcStats) -> bool {
+    if !gc_line_regex().is_match(line) {
+        return false;
+    }
+    // ZGC collects concurrently and logs
+    // "Garbage Collection (Warmup) 226M(88%)->86M(34%)", or "Minor" or
+    // "Major Collection" when generational: the heap after it, no pause
+    if let Some(caps) = zgc_cycle_regex().captures(line) {
+        stats.heap_used_bytes = caps[1].parse::<u64>().ok().map(|v| v * 1024 * 1024);
+        return true;
+    }
+    // G1, Parallel and Serial log "Pause ... 120M->30M(512M) 5.123ms"
+    if let Some(caps) = gc_pause_regex().captures(line) {
+        let mb = |i: usize| caps[i].parse::<u64>().ok().map(|v| v * 1024 * 1024);
+        let pause_ms = caps[4].parse::<f64>().unwrap_or(0.0);
+        stats.heap_used_bytes = mb(2);
+        stats.heap_committed_bytes = mb(3);
+        stats.gc_pause_count += 1;
+        stats.gc_pause_total_ms += pause_ms;
+        stats.last_gc_pause_ms = Some(pause_ms);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pauses_are_folded_into_stats() {
+        let mut stats = GcStats::default();
+        let line = "[0.066s][gc] GC(0) Pause Young (Allocation Failure) 25M->3M(90M) 2.650ms";
+        assert!(record_gc_line(line, &mut stats));
+        assert_eq!(stats.heap_used_bytes, Some(3 * 1024 * 1024));
+        assert_eq!(stats.heap_committed_bytes, Some(90 * 1024 * 1024));
+        assert_eq!(stats.gc_pause_count, 1);
+        assert_eq!(stats.last_gc_pause_ms, Some(2.65));
+    }
+
+    #[test]
+    fn zgc_cycles_report_the_heap_after_them() {
+        let mut stats = GcStats::default();
+        let line = "[0.207s][gc] GC(0) Garbage Collection (Warmup) 226M(88%)->86M(34%)";
+        assert!(record_gc_line(line, &mut stats));
+        assert_eq!(stats.heap_used_bytes, Some(86 * 1024 * 1024));
+        let line = "[9.100s][gc] GC(4) Major Collection (Proactive) 300M(30%)->120M(12%) 0.151s";
+        assert!(record_gc_line(line, &mut stats));
+        assert_eq!(stats.heap_used_bytes, Some(120 * 1024 * 1024));
+        assert_eq!(stats.gc_pause_count, 0);
+    }
+
+    #[test]
+    fn other_lines_stay_in_the_console() {
+        let mut stats = GcStats::default();
+        assert!(!record_gc_line("[12:00:00 INFO]: Done (3.2s)!", &mut stats));
+        assert!(record_gc_line("[0.014s][gc] Using The Z Garbage Collector", &mut stats));
+    }
+
+    fn server_with_command(command: &[&str]) -> ServerConfig {
+        serde_json::from_value(serde_json::json!({
+            "id": "s",
+            "name": "s",
+            "directory": "/srv/s",
+            "jar": "server.jar",
+            "memory_mb": 2048,
+            "autostart": false,
+            "command": command,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn max_heap_mb_reads_the_last_xmx() {
+        let cfg = server_with_command(&["java", "-Xmx1G", "-Xmx4g", "-jar", "server.jar"]);
+        assert_eq!(max_heap_mb(&cfg), Some(4096));
+        let cfg = server_with_command(&["java", "-Xmx524288k", "-jar", "server.jar"]);
+        assert_eq!(max_heap_mb(&cfg), Some(512));
+        assert_eq!(max_heap_mb(&server_with_command(&["java", "-jar", "server.jar"])), None);
+    }
+
+    #[test]
+    fn max_heap_mb_rejects_sizes_that_overflow() {
+        let cfg = server_with_command(&["java", "-Xmx99999999999t", "-jar", "server.jar"]);
+        assert_eq!(max_heap_mb(&cfg), None);
+    }
+}
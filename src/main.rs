@@ -1,72 +1,340 @@
 mod config;
 mod state;
 mod process;
+mod adopt;
+mod announce;
+mod ansi;
 mod api;
+mod auth;
+mod bans;
+mod cgroups;
+mod console;
+mod consolelog;
+mod cors;
+mod crash;
+#[cfg(unix)]
+mod detach;
+mod diagnostics;
+mod discord;
+mod disk;
+mod docker;
+mod downloads;
+mod envsubst;
+mod geyser;
+mod grpc;
+mod health;
+mod history;
+mod hooks;
+mod idle;
+mod ingame;
+mod ipfilter;
+mod jvm;
+mod maintenance;
+mod motd;
+mod nbt;
+mod nodeauth;
+mod nodes;
+mod openapi;
+mod packs;
+mod pidfile;
+mod platform;
+mod playtime;
+mod portmap;
+mod preflight;
+mod pregen;
+mod ports;
+mod procstats;
+mod properties;
+mod proxy;
+mod query;
+mod ratelimit;
+mod reconcile;
+mod redact;
+mod reload;
+mod resources;
+mod rollout;
+mod runtimes;
+mod sampler;
+mod schema;
+mod sessions;
+mod shaping;
+mod sizing;
+mod slp;
+mod systemd;
+mod telemetry;
+mod ticks;
+mod triggers;
+mod wake;
+mod watchdog;
+mod whitelist;
+mod world;
+mod yaml;
+
+use std::net::SocketAddr;
 
 use axum::{
+    extract::DefaultBodyLimit,
     routing::{delete, get, post, put},
     Router,
 };
-use tower_http::{cors::CorsLayer, services::ServeDir};
+use tower_http::{services::ServeDir, trace::TraceLayer};
+
+const USAGE: &str = "\
+Usage: mc-node-agent [options]
+
+Options:
+  --config <path>    Config file (MCM_CONFIG); default: the first of
+                     config.json, config.toml, config.yaml, config.yml
+  --data-dir <path>  Overrides agent.data_directory (MCM_DATA_DIR)
+  --bind <address>   Overrides agent.bind_address (MCM_BIND)
+  --hash-password    Reads a password from stdin and prints its hash for
+                     agent.users";
+
+/// Flags win over the `MCM_*` variables. Returns `None` after printing help
+/// or a password hash.
+fn parse_args(
+    mut args: impl Iterator<Item = String>,
+) -> anyhow::Result<Option<config::Overrides>> {
+    let env = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+    let mut overrides = config::Overrides {
+        config_path: env("MCM_CONFIG"),
+        data_directory: env("MCM_DATA_DIR"),
+        bind_address: env("MCM_BIND"),
+    };
+    while let Some(flag) = args.next() {
+        if flag == "--help" || flag == "-h" {
+            println!("{}", USAGE);
+            return Ok(None);
+        }
+        if flag == "--hash-password" {
+            let mut password = String::new();
+            std::io::stdin().read_line(&mut password)?;
+            let password = password.trim_end_matches(['\r', '\n']);
+            anyhow::ensure!(!password.is_empty(), "No password was given on stdin");
+            println!("{}", sessions::hash_password(password).map_err(anyhow::Error::msg)?);
+            return Ok(None);
+        }
+        let (flag, inline) = match flag.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (flag, None),
+        };
+        let target = match flag.as_str() {
+            "--config" => &mut overrides.config_path,
+            "--data-dir" => &mut overrides.data_directory,
+            "--bind" => &mut overrides.bind_address,
+            _ => anyhow::bail!("Unknown option {}\n\n{}", flag, USAGE),
+        };
+        let Some(value) = inline.or_else(|| args.next()) else {
+            anyhow::bail!("{} needs a value\n\n{}", flag, USAGE);
+        };
+        *target = Some(value);
+    }
+    Ok(Some(overrides))
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
-        )
-        .init();
+    let Some(overrides) = parse_args(std::env::args().skip(1))? else {
+        return Ok(());
+    };
+    config::set_overrides(overrides);
 
-    let cfg = config::load_config().await?;
-    let bind_address = cfg.agent.bind_address.clone();
+    let mut telemetry = telemetry::init_logging();
 
-    // Kill any orphaned servers from a previous crash
-    process::kill_orphaned_servers(&cfg).await;
-
-    let state = state::AppState::new(cfg.clone());
-
-    // Autostart servers
-    for server in &cfg.servers {
-        if server.autostart {
-            let state2 = state.clone();
-            let id = server.id.clone();
-            tokio::spawn(async move {
-                if let Err(e) = process::start_server(state2, &id).await {
-                    tracing::error!("Autostart failed for '{}': {}", id, e);
-                }
-            });
+    let cfg = config::load_config().await?;
+    if let Some(ref otlp) = cfg.agent.otlp {
+        if let Err(e) = telemetry.enable_otlp(otlp) {
+            tracing::error!("Failed to set up OTLP export: {:#}", e);
         }
     }
+    let bind_address = cfg.agent.bind_address.clone();
+    let cors = cors::layer(&cfg.agent.cors)?;
+
+    let rollout = rollout::load_rollout(&cfg.agent.data_directory).await;
+    let state = state::AppState::new(cfg.clone(), rollout);
+    *state.maintenance.write().await = maintenance::load(&cfg.agent.data_directory).await;
+
+    #[cfg(unix)]
+    process::reattach_servers(&state).await;
+    // Adopt or kill servers left running by a crashed agent
+    process::handle_orphaned_servers(&state).await;
+
+    reconcile::spawn(state.clone());
+    rollout::spawn_scheduler(state.clone());
+    disk::spawn_sampler(state.clone());
+    sampler::spawn(state.clone());
+    slp::spawn_poller(state.clone());
+    idle::spawn_monitor(state.clone());
+    wake::spawn_supervisor(state.clone());
+    watchdog::spawn_monitor(state.clone());
+    announce::spawn_scheduler(state.clone());
+    whitelist::spawn_sync(state.clone());
+    bans::spawn_sync(state.clone());
+    portmap::spawn_renewer(state.clone());
+    nodes::spawn_heartbeat(state.clone());
+    grpc::spawn(state.clone());
+    reload::spawn_watcher(state.clone());
+    ratelimit::spawn_pruner(state.clone());
 
     let app = Router::new()
+        .route("/healthz", get(api::healthz))
+        .route("/readyz", get(api::readyz))
         .route("/api/servers", get(api::list_servers))
         .route("/api/servers", post(api::create_server))
+        .route("/api/servers/actions", post(api::bulk_action))
+        .route("/api/servers/validate", post(api::validate_server))
         .route("/api/servers/{id}", put(api::update_server))
         .route("/api/servers/{id}", delete(api::delete_server))
         .route("/api/servers/{id}/start", post(api::start_server_handler))
         .route("/api/servers/{id}/stop", post(api::stop_server_handler))
         .route("/api/servers/{id}/restart", post(api::restart_server_handler))
+        .route("/api/servers/{id}/desired-state", put(api::set_desired_state))
         .route("/api/servers/{id}/backup", post(api::backup_server_handler))
+        .route("/api/servers/{id}/chat", post(api::chat_handler))
+        .route("/api/servers/{id}/prompt", post(api::prompt_handler))
+        .route("/api/servers/{id}/macros/{name}", post(api::macro_handler))
+        .route("/api/servers/{id}/announcements", get(api::list_announcements))
+        .route("/api/servers/{id}/announcements", post(api::create_announcement))
+        .route("/api/servers/{id}/announcements/{announcement}", put(api::update_announcement))
+        .route("/api/servers/{id}/announcements/{announcement}", delete(api::delete_announcement))
+        .route("/api/servers/{id}/downloads", post(api::download_handler))
+        .route("/api/servers/{id}/last-exit", get(api::last_exit))
+        .route("/api/servers/{id}/players/stats", get(api::player_stats))
+        .route("/api/servers/{id}/players/history", get(api::player_history))
+        .route("/api/servers/{id}/java", get(api::java_selection))
+        .route("/api/servers/{id}/query", get(api::server_query))
+        .route("/api/servers/{id}/world/seed", get(api::world_seed))
+        .route("/api/servers/{id}/worlds", get(api::list_worlds))
+        .route("/api/servers/{id}/worlds/{name}/info", get(api::world_info))
+        .route("/api/servers/{id}/worlds/{name}/trim", post(api::trim_world))
+        .route("/api/servers/{id}/worlds/reset", post(api::reset_world))
+        .route("/api/servers/{id}/worlds/reset/{dimension}", post(api::reset_dimension))
+        .route("/api/servers/{id}/pregen", get(api::pregen_status))
+        .route("/api/servers/{id}/pregen", post(api::start_pregen))
+        .route("/api/servers/{id}/pregen", delete(api::cancel_pregen))
+        .route("/api/servers/{id}/diagnostics", get(api::list_diagnostics))
+        .route("/api/servers/{id}/diagnostics/thread-dump", post(api::thread_dump))
+        .route("/api/servers/{id}/diagnostics/heap-dump", post(api::heap_dump))
+        .route("/api/servers/{id}/diagnostics/jfr", post(api::start_recording))
+        .route("/api/servers/{id}/diagnostics/jfr", delete(api::stop_recording))
+        .route("/api/servers/{id}/diagnostics/{file}", get(api::download_diagnostic))
+        .route("/api/servers/{id}/diagnostics/{file}", delete(api::delete_diagnostic))
+        .route("/api/servers/{id}/worlds/{name}/activate", post(api::activate_world))
+        .route("/api/servers/{id}/motd", get(api::get_motd))
+        .route("/api/servers/{id}/motd", put(api::set_motd))
+        .route("/api/servers/{id}/icon", get(api::get_icon))
+        .route("/api/servers/{id}/icon", put(api::set_icon))
+        .route("/api/servers/{id}/icon", delete(api::delete_icon))
+        .route("/api/servers/{id}/resource-pack", get(api::get_resource_pack))
+        .route(
+            "/api/servers/{id}/resource-pack",
+            put(api::upload_resource_pack).layer(DefaultBodyLimit::max(packs::MAX_PACK_BYTES)),
+        )
+        .route("/api/servers/{id}/resource-pack", delete(api::delete_resource_pack))
+        .route("/api/servers/{id}/property-profiles/{profile}", get(api::preview_property_profile))
+        .route("/api/servers/{id}/property-profiles/{profile}", post(api::apply_property_profile))
+        .route("/api/servers/{id}/world/locate", get(api::world_locate))
+        .route("/api/servers/{id}/geyser", get(api::geyser_status))
+        .route("/api/servers/{id}/geyser", post(api::install_geyser))
+        .route("/api/servers/{id}/proxy", get(api::proxy_status))
+        .route("/api/servers/{id}/proxy/provision", post(api::provision_proxy))
+        .route("/api/servers/{id}/proxy/backends", post(api::add_proxy_backend))
+        .route("/api/servers/{id}/proxy/backends/{name}", delete(api::remove_proxy_backend))
+        .route("/api/auth/can", get(api::auth_can))
+        .route("/api/node/resources", get(api::node_resources))
+        .route("/api/node/memory-advice", get(api::memory_advice))
+        .route("/api/maintenance", get(api::maintenance_status))
+        .route("/api/maintenance", post(api::enter_maintenance))
+        .route("/api/maintenance", delete(api::leave_maintenance))
+        .route("/api/config/reload", post(api::reload_config))
+        .route("/api/nodes", get(api::list_nodes))
+        .route("/api/nodes", post(api::register_node))
+        .route("/api/nodes/{id}", delete(api::remove_node))
+        .route("/api/nodes/{id}/rotate-token", post(api::rotate_node_token))
+        .route("/api/ws-tickets", post(api::issue_ws_ticket))
+        .route("/api/login", post(api::login))
+        .route("/api/logout", post(api::logout))
+        .route("/api/session/refresh", post(api::refresh_session))
+        .route("/api/node/token", post(api::add_manager_token))
+        .route("/api/node/challenge", get(api::node_challenge))
+        .route("/api/node/token/previous", delete(api::retire_manager_tokens))
+        .route("/api/runtimes", get(api::list_runtimes))
+        .route("/api/runtimes/{version}", post(api::install_runtime))
+        .route("/api/runtimes/{version}", delete(api::remove_runtime))
+        .route("/api/updates", get(api::list_updates))
+        .route("/api/updates/check", post(api::check_updates))
+        .route("/api/updates/{version}/{build}/promote", post(api::promote_update))
+        .route("/api/cache", get(api::list_cache))
+        .route("/api/cache", delete(api::clear_cache))
+        .route("/api/property-profiles", get(api::list_property_profiles))
+        .route("/api/whitelist-groups/{group}", get(api::get_whitelist_group))
+        .route("/api/whitelist-groups/{group}/players", post(api::add_whitelist_player))
+        .route(
+            "/api/whitelist-groups/{group}/players/{player}",
+            delete(api::remove_whitelist_player),
+        )
+        .route("/api/ban-groups/{group}", get(api::get_ban_group))
+        .route("/api/ban-groups/{group}/bans", post(api::ban_player))
+        .route("/api/ban-groups/{group}/bans/{player}", delete(api::pardon_player))
+        .route("/api/ban-groups/{group}/audit", get(api::ban_audit))
+        .route("/api/events/ws", get(api::events_ws))
         .route("/api/servers/{id}/console/ws", get(api::console_ws))
+        .route("/api/servers/{id}/console/sse", get(api::console_sse))
+        .route("/api/servers/{id}/metrics", get(api::metrics_history))
         .route("/api/servers/{id}/metrics/ws", get(api::metrics_ws))
-        .layer(CorsLayer::permissive())
+        .route("/api/servers/{id}/metrics/sse", get(api::metrics_sse));
+    #[cfg(not(feature = "swagger-ui"))]
+    let app = app.route("/api/openapi.json", get(openapi::openapi_json));
+    // Inside auth, so clients are told apart by the token they present
+    let app = app
+        .layer(axum::middleware::from_fn_with_state(state.clone(), maintenance::guard))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), ratelimit::limit))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            nodeauth::require_manager,
+        ))
+        // Outside auth, where requests are only known by their address
+        .layer(axum::middleware::from_fn_with_state(state.clone(), ratelimit::limit_rejected));
+    // Fetched by game clients, which present no credentials
+    let packs_dir = packs::packs_dir(&cfg.agent.data_directory);
+    let app = app.nest_service("/packs", ServeDir::new(packs_dir));
+    // The UI is static and fetches the document, so it stays outside auth
+    #[cfg(feature = "swagger-ui")]
+    let app = app.merge(
+        utoipa_swagger_ui::SwaggerUi::new("/api/docs")
+            .url("/api/openapi.json", <openapi::ApiDoc as utoipa::OpenApi>::openapi()),
+    );
+    let app = app
+        .layer(TraceLayer::new_for_http())
+        .layer(cors)
         .with_state(state.clone())
-        .fallback_service(ServeDir::new("public"));
+        .fallback_service(ServeDir::new("public"))
+        // Outermost, so refused addresses get nothing, not even the UI
+        .layer(axum::middleware::from_fn_with_state(state.clone(), ipfilter::filter));
 
     let listener = tokio::net::TcpListener::bind(&bind_address).await?;
     tracing::info!("Listening on {}", bind_address);
-    
-    axum::serve(listener, app)
+    systemd::notify("READY=1");
+    systemd::spawn_watchdog();
+
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
         .with_graceful_shutdown(shutdown_signal())
         .await?;
 
     tracing::info!("Shutting down servers...");
-    let server_ids: Vec<String> = state.servers.iter().map(|s| s.key().clone()).collect();
-    for id in server_ids {
+    systemd::notify("STOPPING=1");
+    let instances: Vec<_> =
+        state.servers.iter().map(|s| (s.key().clone(), s.value().clone())).collect();
+    for (id, instance) in instances {
+        if instance.child.lock().await.is_detached() {
+            tracing::info!("Leaving detached server '{}' running", id);
+            continue;
+        }
+        systemd::notify(&format!("STATUS=Stopping server '{}'", id));
         let _ = process::stop_server(state.clone(), &id).await;
     }
 
+    telemetry.shutdown();
     Ok(())
 }
 
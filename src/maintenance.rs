@@ -0,0 +1,108 @@
+//! Read-only mode for host maintenance and migrations. While it is on,
+//! servers can be listed and their consoles watched, but nothing is
+//! started, stopped or changed, whether by a client or by the agent's own
+//! autostart and restart logic. The mode survives agent restarts.
+
+use std::path::PathBuf;
+
+use axum::{
+    extract::{Request, State},
+    http::{Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::state::{AgentEvent, AppState};
+
+const MAINTENANCE_FILE: &str = "maintenance.json";
+
+/// Requests that change nothing on the node, or that are needed to leave
+/// maintenance again.
+const ALLOWED_PATHS: &[&str] = &[
+    "/api/maintenance",
+    "/api/servers/validate",
+    "/api/ws-tickets",
+    "/api/login",
+    "/api/logout",
+    "/api/session/refresh",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct Maintenance {
+    pub reason: String,
+    pub since_ms: u64,
+}
+
+fn maintenance_path(data_directory: &str) -> PathBuf {
+    PathBuf::from(data_directory).join(MAINTENANCE_FILE)
+}
+
+pub async fn load(data_directory: &str) -> Option<Maintenance> {
+    let contents = tokio::fs::read_to_string(maintenance_path(data_directory)).await.ok()?;
+    match serde_json::from_str(&contents) {
+        Ok(maintenance) => Some(maintenance),
+        Err(e) => {
+            tracing::warn!("Ignoring unreadable {}: {}", MAINTENANCE_FILE, e);
+            None
+        }
+    }
+}
+
+async fn save(data_directory: &str, maintenance: Option<&Maintenance>) -> Result<(), String> {
+    let path = maintenance_path(data_directory);
+    let Some(maintenance) = maintenance else {
+        return match tokio::fs::remove_file(&path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(format!("Failed to remove {}: {}", MAINTENANCE_FILE, e))
+            }
+            _ => Ok(()),
+        };
+    };
+    let json = serde_json::to_string_pretty(maintenance).map_err(|e| e.to_string())?;
+    tokio::fs::create_dir_all(data_directory)
+        .await
+        .map_err(|e| format!("Failed to create data directory: {}", e))?;
+    tokio::fs::write(&path, json)
+        .await
+        .map_err(|e| format!("Failed to write {}: {}", MAINTENANCE_FILE, e))
+}
+
+/// Turns the mode on, or off with `None`, and tells event subscribers.
+pub async fn set(state: &AppState, maintenance: Option<Maintenance>) -> Result<(), String> {
+    let data_directory = state.config.read().await.agent.data_directory.clone();
+    let mut current = state.maintenance.write().await;
+    save(&data_directory, maintenance.as_ref()).await?;
+    match &maintenance {
+        Some(m) => tracing::warn!("Maintenance mode on: {}", m.reason),
+        None => tracing::info!("Maintenance mode off"),
+    }
+    *current = maintenance.clone();
+    let _ = state.events_tx.send(AgentEvent::MaintenanceChanged { maintenance });
+    Ok(())
+}
+
+/// Fails with the reason while maintenance mode is on.
+pub async fn check(state: &AppState) -> Result<(), String> {
+    match &*state.maintenance.read().await {
+        Some(m) => Err(format!("The agent is in maintenance mode: {}", m.reason)),
+        None => Ok(()),
+    }
+}
+
+/// Answers 503 to every request that would change something.
+pub async fn guard(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let reads = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    if reads || ALLOWED_PATHS.contains(&req.uri().path()) {
+        return next.run(req).await;
+    }
+    if let Err(message) = check(&state).await {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "error": message })),
+        )
+            .into_response();
+    }
+    next.run(req).await
+}
@@ -0,0 +1,183 @@
+//! How a server looks in the multiplayer list: the MOTD in
+//! server.properties and the 64x64 `server-icon.png`. Both are read once
+//! at startup, so a running server shows changes after its next restart.
+
+use std::io::Cursor;
+
+/// Side of the square icon the client shows.
+pub const ICON_SIZE: u32 = 64;
+pub const ICON_FILE: &str = "server-icon.png";
+/// Uploads larger than this on either side are refused before decoding.
+const MAX_SOURCE_SIDE: u32 = 4096;
+const MAX_MOTD_LINES: usize = 2;
+const MAX_MOTD_LEN: usize = 256;
+
+fn is_format_code(c: char) -> bool {
+    matches!(c.to_ascii_lowercase(), '0'..='9' | 'a'..='f' | 'k'..='o' | 'r')
+}
+
+/// Turns `&` color codes such as `&6` into the `§` codes the server reads,
+/// and checks the result: at most two lines, and every `§` followed by a
+/// valid code. A `&` not followed by a code is kept as is.
+pub fn format(input: &str) -> Result<String, String> {
+    let input = input.replace("\r\n", "\n");
+    let mut motd = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' if chars.peek().copied().is_some_and(is_format_code) => motd.push('§'),
+            '§' if !chars.peek().copied().is_some_and(is_format_code) => {
+                return Err("'§' must be followed by a color or format code".to_string());
+            }
+            '\n' => motd.push('\n'),
+            c if c.is_control() => return Err("motd must not contain control characters".into()),
+            c => motd.push(c),
+        }
+    }
+    if motd.lines().count() > MAX_MOTD_LINES {
+        return Err(format!("motd must have at most {} lines", MAX_MOTD_LINES));
+    }
+    if motd.chars().count() > MAX_MOTD_LEN {
+        return Err(format!("motd must be at most {} characters", MAX_MOTD_LEN));
+    }
+    Ok(motd)
+}
+
+/// The MOTD as the player reads it, without format codes.
+pub fn plain(motd: &str) -> String {
+    let mut plain = String::with_capacity(motd.len());
+    let mut chars = motd.chars();
+    while let Some(c) = chars.next() {
+        if c == '§' {
+            chars.next();
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+
+/// Part of an uploaded image to use as the icon, in source pixels.
+#[derive(Debug, Clone, Copy)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub size: u32,
+}
+
+/// Decodes a PNG into RGBA8, returning it with its width and height.
+fn decode(png_bytes: &[u8]) -> Result<(Vec<u8>, u32, u32), String> {
+    let mut decoder = png::Decoder::new(Cursor::new(png_bytes));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|e| format!("Not a valid PNG: {}", e))?;
+    let (width, height) = (reader.info().width, reader.info().height);
+    if width > MAX_SOURCE_SIDE || height > MAX_SOURCE_SIDE {
+        return Err(format!("Image must be at most {0}x{0}", MAX_SOURCE_SIDE));
+    }
+    let size = reader.output_buffer_size().ok_or("Image is too large")?;
+    let mut buf = vec![0; size];
+    let frame = reader
+        .next_frame(&mut buf)
+        .map_err(|e| format!("Not a valid PNG: {}", e))?;
+    buf.truncate(frame.buffer_size());
+    let rgba = match frame.color_type {
+        png::ColorType::Rgba => buf,
+        png::ColorType::Rgb => buf.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => {
+            buf.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect()
+        }
+        png::ColorType::Grayscale => buf.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+        png::ColorType::Indexed => return Err("Unsupported PNG color type".to_string()),
+    };
+    Ok((rgba, width, height))
+}
+
+/// Scales the square `crop` of an RGBA image to `ICON_SIZE`, averaging the
+/// source pixels behind each icon pixel, weighted by their alpha.
+fn scale(rgba: &[u8], width: u32, crop: Crop) -> Vec<u8> {
+    let mut icon = Vec::with_capacity((ICON_SIZE * ICON_SIZE * 4) as usize);
+    let span = |i: u32| {
+        let start = crop.size * i / ICON_SIZE;
+        let end = (crop.size * (i + 1) / ICON_SIZE).max(start + 1);
+        start..end
+    };
+    for ty in 0..ICON_SIZE {
+        for tx in 0..ICON_SIZE {
+            let (mut sum, mut alpha, mut count) = ([0u64; 3], 0u64, 0u64);
+            for sy in span(ty) {
+                for sx in span(tx) {
+                    let i = (((crop.y + sy) * width + crop.x + sx) * 4) as usize;
+                    let a = u64::from(rgba[i + 3]);
+                    for (c, total) in sum.iter_mut().enumerate() {
+                        *total += u64::from(rgba[i + c]) * a;
+                    }
+                    alpha += a;
+                    count += 1;
+                }
+            }
+            for total in sum {
+                icon.push(total.checked_div(alpha).unwrap_or(0) as u8);
+            }
+            icon.push((alpha / count) as u8);
+        }
+    }
+    icon
+}
+
+/// Crops an uploaded PNG to a square, the centered one unless `crop` is
+/// given, and scales it to the 64x64 PNG the server expects.
+pub fn make_icon(png_bytes: &[u8], crop: Option<Crop>) -> Result<Vec<u8>, String> {
+    let (rgba, width, height) = decode(png_bytes)?;
+    let crop = crop.unwrap_or_else(|| {
+        let size = width.min(height);
+        Crop {
+            x: (width - size) / 2,
+            y: (height - size) / 2,
+            size,
+        }
+    });
+    let fits = |offset: u32, side: u32| offset.checked_add(crop.size).is_some_and(|e| e <= side);
+    if crop.size == 0 || !fits(crop.x, width) || !fits(crop.y, height) {
+        return Err(format!(
+            "Crop must be a non-empty square inside the {}x{} image",
+            width, height
+        ));
+    }
+    let icon = scale(&rgba, width, crop);
+
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, ICON_SIZE, ICON_SIZE);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&icon).map_err(|e| e.to_string())?;
+    writer.finish().map_err(|e| e.to_string())?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ampersand_codes_become_section_signs() {
+        assert_eq!(format("&6Gold &lbold").unwrap(), "§6Gold §lbold");
+        assert_eq!(format("Tom & Jerry &z").unwrap(), "Tom & Jerry &z");
+        assert_eq!(format("§aok\r\nline two").unwrap(), "§aok\nline two");
+    }
+
+    #[test]
+    fn invalid_motds_are_refused() {
+        assert!(format("§zbad").is_err());
+        assert!(format("trailing §").is_err());
+        assert!(format("one\ntwo\nthree").is_err());
+        assert!(format("bell\x07").is_err());
+        assert!(format(&"x".repeat(MAX_MOTD_LEN + 1)).is_err());
+        assert!(format(&"x".repeat(MAX_MOTD_LEN)).is_ok());
+    }
+
+    #[test]
+    fn plain_drops_format_codes() {
+        assert_eq!(plain("§6Gold §lbold§r!"), "Gold bold!");
+    }
+}
@@ -0,0 +1,181 @@
+//! Just enough of the NBT format to read values out of `level.dat`.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub enum Tag {
+    Byte(i8),
+    Int(i32),
+    Long(i64),
+    String(String),
+    IntArray(Vec<i32>),
+    Compound(HashMap<String, Tag>),
+    /// Any tag type the agent has no use for; its payload is skipped.
+    Other,
+}
+
+impl Tag {
+    pub fn get(&self, key: &str) -> Option<&Tag> {
+        match self {
+            Tag::Compound(map) => map.get(key),
+            _ => None,
+        }
+    }
+
+    /// Follows a chain of compound keys, e.g. `["Data", "SpawnX"]`.
+    pub fn path(&self, keys: &[&str]) -> Option<&Tag> {
+        keys.iter().try_fold(self, |tag, key| tag.get(key))
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Tag::Byte(v) => Some(*v as i64),
+            Tag::Int(v) => Some(*v as i64),
+            Tag::Long(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Tag::String(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_int_array(&self) -> Option<&[i32]> {
+        match self {
+            Tag::IntArray(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let end = self.pos.checked_add(n).filter(|&e| e <= self.data.len());
+        let end = end.ok_or_else(|| "unexpected end of NBT data".to_string())?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        let b = self.take(4)?;
+        Ok(i32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn i64(&mut self) -> Result<i64, String> {
+        let b = self.take(8)?;
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(b);
+        Ok(i64::from_be_bytes(buf))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u16()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn array_len(&mut self) -> Result<usize, String> {
+        let len = self.i32()?;
+        usize::try_from(len).map_err(|_| "negative NBT array length".to_string())
+    }
+
+    fn payload(&mut self, kind: u8) -> Result<Tag, String> {
+        Ok(match kind {
+            1 => Tag::Byte(self.u8()? as i8),
+            2 => {
+                self.take(2)?;
+                Tag::Other
+            }
+            3 => Tag::Int(self.i32()?),
+            4 => Tag::Long(self.i64()?),
+            5 => {
+                self.take(4)?;
+                Tag::Other
+            }
+            6 => {
+                self.take(8)?;
+                Tag::Other
+            }
+            7 => {
+                let len = self.array_len()?;
+                self.take(len)?;
+                Tag::Other
+            }
+            8 => Tag::String(self.string()?),
+            9 => {
+                let item = self.u8()?;
+                let len = self.array_len()?;
+                for _ in 0..len {
+                    self.payload(item)?;
+                }
+                Tag::Other
+            }
+            10 => {
+                let mut map = HashMap::new();
+                loop {
+                    let child = self.u8()?;
+                    if child == 0 {
+                        break;
+                    }
+                    let name = self.string()?;
+                    map.insert(name, self.payload(child)?);
+                }
+                Tag::Compound(map)
+            }
+            11 => {
+                let len = self.array_len()?;
+                let bytes = self.take(len.saturating_mul(4))?;
+                let ints = bytes.chunks_exact(4);
+                Tag::IntArray(ints.map(|b| i32::from_be_bytes([b[0], b[1], b[2], b[3]])).collect())
+            }
+            12 => {
+                let len = self.array_len()?;
+                self.take(len.saturating_mul(8))?;
+                Tag::Other
+            }
+            other => return Err(format!("unknown NBT tag type {}", other)),
+        })
+    }
+}
+
+/// Parses an uncompressed NBT document and returns its root compound.
+pub fn parse(data: &[u8]) -> Result<Tag, String> {
+    let mut reader = Reader { data, pos: 0 };
+    let kind = reader.u8()?;
+    if kind != 10 {
+        return Err("NBT root is not a compound".to_string());
+    }
+    reader.string()?;
+    reader.payload(kind)
+}
+
+/// Reads a gzip-compressed NBT file such as `level.dat`.
+pub async fn read_gzip_file(path: &Path) -> Result<Tag, String> {
+    let compressed = tokio::fs::read(path)
+        .await
+        .map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+    let mut data = Vec::new();
+    flate2::read::MultiGzDecoder::new(compressed.as_slice())
+        .read_to_end(&mut data)
+        .map_err(|e| format!("Failed to decompress '{}': {}", path.display(), e))?;
+    parse(&data)
+}
@@ -0,0 +1,268 @@
+//! Shared-secret authentication between a manager and its node agents.
+//! The manager sends its token as a bearer token plus a random nonce; the
+//! agent only answers callers holding one of `agent.manager_tokens` and
+//! proves it knows the same token by returning an HMAC of the nonce.
+//! Before sending the token at all, the manager has the agent answer a
+//! challenge the same way, so an impostor at the agent's URL never sees it.
+//! People using the web UI log in instead and present a session token.
+
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{Request, State},
+    http::{
+        header::{AUTHORIZATION, SEC_WEBSOCKET_PROTOCOL, UPGRADE},
+        HeaderValue, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use rand::{distr::Alphanumeric, Rng};
+use ring::hmac;
+
+use crate::state::AppState;
+
+pub const NONCE_HEADER: &str = "x-manager-nonce";
+pub const PROOF_HEADER: &str = "x-agent-proof";
+
+/// Browsers can't set headers on a WebSocket, so a socket may carry its
+/// token as a `bearer.<token>` subprotocol, offered alongside this one for
+/// the agent to accept.
+pub const WS_PROTOCOL: &str = "bearer";
+const WS_TOKEN_PREFIX: &str = "bearer.";
+/// How long a ticket from `POST /api/ws-tickets` can be redeemed.
+pub const WS_TICKET_TTL: Duration = Duration::from_secs(30);
+
+pub const CHALLENGE_PATH: &str = "/api/node/challenge";
+/// Mixed into challenge nonces so that the open challenge endpoint can't
+/// be used to forge the proof on an authenticated answer.
+const CHALLENGE_CONTEXT: &str = "challenge:";
+
+/// Probes stay reachable for load balancers and service managers, the API
+/// description for tooling, the login form for people, and the identity
+/// challenge for managers that have not sent their token yet.
+const UNAUTHENTICATED_PATHS: &[&str] =
+    &["/healthz", "/readyz", "/api/openapi.json", "/api/login", CHALLENGE_PATH];
+
+/// The manager token a request was authenticated with.
+#[derive(Debug, Clone)]
+pub struct ManagerToken(pub String);
+
+pub fn random_token(len: usize) -> String {
+    rand::rng()
+        .sample_iter(Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+fn key(token: &str) -> hmac::Key {
+    hmac::Key::new(hmac::HMAC_SHA256, token.as_bytes())
+}
+
+pub fn proof(token: &str, nonce: &str) -> String {
+    hmac::sign(&key(token), nonce.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub fn verify_proof(token: &str, nonce: &str, proof: &str) -> bool {
+    let bytes: Option<Vec<u8>> = (0..proof.len())
+        .step_by(2)
+        .map(|i| proof.get(i..i + 2).and_then(|h| u8::from_str_radix(h, 16).ok()))
+        .collect();
+    bytes.is_some_and(|b| hmac::verify(&key(token), nonce.as_bytes(), &b).is_ok())
+}
+
+pub fn challenge_proof(token: &str, nonce: &str) -> String {
+    proof(token, &format!("{}{}", CHALLENGE_CONTEXT, nonce))
+}
+
+pub fn verify_challenge(token: &str, nonce: &str, proof: &str) -> bool {
+    verify_proof(token, &format!("{}{}", CHALLENGE_CONTEXT, nonce), proof)
+}
+
+/// Answers a challenge with one proof per configured manager token, since
+/// the agent can't tell which of them the manager holds.
+pub async fn answer_challenge(state: &AppState, nonce: &str) -> Result<String, String> {
+    if !(16..=128).contains(&nonce.len()) || !nonce.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err("the nonce must be 16 to 128 letters or digits".to_string());
+    }
+    let config = state.config.read().await;
+    let proofs: Vec<String> =
+        config.agent.manager_tokens.iter().map(|t| challenge_proof(t, nonce)).collect();
+    Ok(proofs.join(","))
+}
+
+/// A single-use stand-in for a token in a WebSocket URL's `ticket` query.
+pub struct WsTicket {
+    /// The manager or session token that asked for it, when authentication
+    /// is on.
+    pub token: Option<String>,
+    pub expires_at: Instant,
+}
+
+pub fn issue_ws_ticket(state: &AppState, token: Option<String>) -> String {
+    let now = Instant::now();
+    state.ws_tickets.retain(|_, t| t.expires_at > now);
+    let ticket = random_token(32);
+    state.ws_tickets.insert(
+        ticket.clone(),
+        WsTicket {
+            token,
+            expires_at: now + WS_TICKET_TTL,
+        },
+    );
+    ticket
+}
+
+fn is_websocket_upgrade(req: &Request) -> bool {
+    req.headers()
+        .get(UPGRADE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("websocket"))
+}
+
+/// The token a WebSocket upgrade carries as a ticket or a subprotocol.
+/// Tickets are spent here whether or not the upgrade goes on to succeed.
+fn websocket_token(state: &AppState, req: &Request) -> Option<String> {
+    if !is_websocket_upgrade(req) {
+        return None;
+    }
+    let ticket = req
+        .uri()
+        .query()
+        .and_then(|q| q.split('&').find_map(|p| p.strip_prefix("ticket=")));
+    if let Some(ticket) = ticket {
+        let (_, ticket) = state.ws_tickets.remove(ticket)?;
+        return (ticket.expires_at > Instant::now()).then_some(ticket.token).flatten();
+    }
+    req.headers()
+        .get_all(SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .find_map(|p| p.trim().strip_prefix(WS_TOKEN_PREFIX))
+        .map(str::to_string)
+}
+
+/// Compares tokens without leaking the position of the first mismatch.
+pub fn tokens_match(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub async fn require_manager(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let (tokens, users) = {
+        let config = state.config.read().await;
+        (config.agent.manager_tokens.clone(), config.agent.users.clone())
+    };
+    if tokens.is_empty() && users.is_empty() {
+        return next.run(req).await;
+    }
+    let presented = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::to_string)
+        .or_else(|| websocket_token(&state, &req));
+    // Rejected before the upgrade, so no socket task is ever started
+    let token = presented
+        .as_deref()
+        .and_then(|p| tokens.into_iter().find(|t| tokens_match(t, p)));
+    // Open paths still prove identity to a manager that authenticated
+    let Some(token) = token else {
+        let session = presented.and_then(|p| state.sessions.verify(&users, &p));
+        if let Some(session) = session {
+            req.extensions_mut().insert(session);
+            return next.run(req).await;
+        }
+        if UNAUTHENTICATED_PATHS.contains(&req.uri().path()) {
+            return next.run(req).await;
+        }
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(serde_json::json!({ "error": "A valid manager token or session is required" })),
+        )
+            .into_response();
+    };
+    let nonce = req
+        .headers()
+        .get(NONCE_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    req.extensions_mut().insert(ManagerToken(token.clone()));
+
+    let mut response = next.run(req).await;
+    if let Some(nonce) = nonce {
+        if let Ok(value) = HeaderValue::from_str(&proof(&token, &nonce)) {
+            response.headers_mut().insert(PROOF_HEADER, value);
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proofs_verify_only_with_the_same_token_and_nonce() {
+        let p = proof("secret-token-0123", "nonce");
+        assert!(verify_proof("secret-token-0123", "nonce", &p));
+        assert!(!verify_proof("other-token-01234", "nonce", &p));
+        assert!(!verify_proof("secret-token-0123", "other", &p));
+        assert!(!verify_proof("secret-token-0123", "nonce", "zz"));
+    }
+
+    #[test]
+    fn challenge_proofs_do_not_pass_as_answer_proofs() {
+        let p = challenge_proof("secret-token-0123", "nonce");
+        assert!(verify_challenge("secret-token-0123", "nonce", &p));
+        assert!(!verify_proof("secret-token-0123", "nonce", &p));
+    }
+
+    fn upgrade(uri: &str, protocols: Option<&str>) -> Request {
+        let mut req = Request::builder().uri(uri).header(UPGRADE, "websocket");
+        if let Some(protocols) = protocols {
+            req = req.header(SEC_WEBSOCKET_PROTOCOL, protocols);
+        }
+        req.body(axum::body::Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn websocket_tokens_come_from_the_subprotocol() {
+        let state = AppState::new(Default::default(), Default::default());
+        let req = upgrade("/api/servers/a/console/ws", Some("bearer, bearer.tok-123"));
+        assert_eq!(websocket_token(&state, &req).as_deref(), Some("tok-123"));
+        let req = upgrade("/api/servers/a/console/ws", Some("bearer"));
+        assert_eq!(websocket_token(&state, &req), None);
+    }
+
+    #[test]
+    fn websocket_tickets_are_single_use() {
+        let state = AppState::new(Default::default(), Default::default());
+        let ticket = issue_ws_ticket(&state, Some("tok-123".to_string()));
+        let uri = format!("/api/servers/a/console/ws?ticket={}", ticket);
+        assert_eq!(websocket_token(&state, &upgrade(&uri, None)).as_deref(), Some("tok-123"));
+        assert_eq!(websocket_token(&state, &upgrade(&uri, None)), None);
+    }
+
+    #[test]
+    fn plain_requests_carry_no_websocket_token() {
+        let state = AppState::new(Default::default(), Default::default());
+        let req = Request::builder()
+            .uri("/api/servers")
+            .header(SEC_WEBSOCKET_PROTOCOL, "bearer.tok-123")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        assert_eq!(websocket_token(&state, &req), None);
+    }
+}
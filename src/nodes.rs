@@ -0,0 +1,559 @@
+//! Node registry for running this agent as a manager: other agents are
+//! registered by URL and token, and their servers and capacity are pulled
+//! into this agent's API.
+
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::extract::ws::{self, WebSocket};
+use futures_util::{SinkExt, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest, protocol::CloseFrame};
+
+use crate::{
+    config::NodeConfig,
+    nodeauth,
+    resources::NodeResources,
+    state::{AgentEvent, AppState},
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Starting, stopping or backing up a server waits for it to finish.
+const ACTION_TIMEOUT: Duration = Duration::from_secs(300);
+const WS_RECONNECT_ATTEMPTS: u32 = 5;
+/// Reconnects over the life of one proxied socket, so a node that keeps
+/// accepting and then dropping it is given up on.
+const WS_MAX_RECONNECTS: u32 = 20;
+/// Close codes that end the stream on purpose, normally or going away, or
+/// after which reconnecting to the node would not help.
+const WS_FINAL_CLOSE_CODES: &[u16] = &[1000, 1001, 4001, 4004, 4010];
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct NodeInfo {
+    pub id: String,
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resources: Option<NodeResources>,
+    /// Why the node could not be reached.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Latest heartbeat outcome.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health: Option<NodeHealth>,
+}
+
+pub fn client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .expect("HTTP client configuration is valid")
+    })
+}
+
+/// Joins the node's base URL and an API path.
+pub fn url(node: &NodeConfig, path: &str) -> String {
+    format!("{}{}", node.url.trim_end_matches('/'), path)
+}
+
+/// Has the node prove it holds `token` before the token is sent to it, so
+/// something else answering at the node's URL never learns it.
+async fn verify_identity(node: &NodeConfig, token: &str) -> Result<(), String> {
+    let nonce = nodeauth::random_token(32);
+    let response = client()
+        .get(url(node, nodeauth::CHALLENGE_PATH))
+        .header(nodeauth::NONCE_HEADER, &nonce)
+        .send()
+        .await
+        .map_err(|e| format!("Node '{}' is unreachable: {}", node.id, e))?;
+    let proven = response
+        .headers()
+        .get(nodeauth::PROOF_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|p| nodeauth::verify_challenge(token, &nonce, p)));
+    if !proven {
+        return Err(format!("Node '{}' failed to prove its identity", node.id));
+    }
+    Ok(())
+}
+
+/// Sends an authenticated request and, when the node has a token, checks
+/// that the answer comes from an agent holding the same token. Any status
+/// other than 401 is handed back to the caller.
+async fn exchange(
+    node: &NodeConfig,
+    method: reqwest::Method,
+    path: &str,
+    body: Option<&serde_json::Value>,
+) -> Result<reqwest::Response, String> {
+    exchange_within(node, method, path, body, REQUEST_TIMEOUT).await
+}
+
+async fn exchange_within(
+    node: &NodeConfig,
+    method: reqwest::Method,
+    path: &str,
+    body: Option<&serde_json::Value>,
+    timeout: Duration,
+) -> Result<reqwest::Response, String> {
+    let mut builder = client().request(method, url(node, path)).timeout(timeout);
+    if let Some(body) = body {
+        builder = builder.json(body);
+    }
+    let nonce = nodeauth::random_token(32);
+    if let Some(ref token) = node.token {
+        verify_identity(node, token).await?;
+        builder = builder
+            .bearer_auth(token)
+            .header(nodeauth::NONCE_HEADER, &nonce);
+    }
+    let response = builder
+        .send()
+        .await
+        .map_err(|e| format!("Node '{}' is unreachable: {}", node.id, e))?;
+    if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+        return Err(format!("Node '{}' rejected the manager token", node.id));
+    }
+    if let Some(ref token) = node.token {
+        let proof = response
+            .headers()
+            .get(nodeauth::PROOF_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        if !nodeauth::verify_proof(token, &nonce, proof) {
+            return Err(format!("Node '{}' failed to prove its identity", node.id));
+        }
+    }
+    Ok(response)
+}
+
+/// Like `exchange`, but only successful answers count.
+pub async fn send(
+    node: &NodeConfig,
+    method: reqwest::Method,
+    path: &str,
+    body: Option<&serde_json::Value>,
+) -> Result<reqwest::Response, String> {
+    let response = exchange(node, method, path, body).await?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "Node '{}' answered {} for {}",
+            node.id,
+            response.status(),
+            path
+        ));
+    }
+    Ok(response)
+}
+
+async fn get_json<T: DeserializeOwned>(node: &NodeConfig, path: &str) -> Result<T, String> {
+    send(node, reqwest::Method::GET, path, None)
+        .await?
+        .json()
+        .await
+        .map_err(|e| format!("Node '{}' sent an invalid response: {}", node.id, e))
+}
+
+/// Like `get_json`, but a 404 is `Ok(None)`.
+pub async fn get_optional<T: DeserializeOwned>(
+    node: &NodeConfig,
+    path: &str,
+) -> Result<Option<T>, String> {
+    let response = exchange(node, reqwest::Method::GET, path, None).await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        return Err(format!(
+            "Node '{}' answered {} for {}",
+            node.id,
+            response.status(),
+            path
+        ));
+    }
+    response
+        .json()
+        .await
+        .map(Some)
+        .map_err(|e| format!("Node '{}' sent an invalid response: {}", node.id, e))
+}
+
+pub async fn resources(node: &NodeConfig) -> Result<NodeResources, String> {
+    get_json(node, "/api/node/resources").await
+}
+
+/// The node's own servers, each tagged with the node id.
+pub async fn servers(node: &NodeConfig) -> Result<Vec<serde_json::Value>, String> {
+    // Only the node's local servers, or two managers would recurse forever
+    let mut servers: Vec<serde_json::Value> = get_json(node, "/api/servers?scope=local").await?;
+    for server in &mut servers {
+        if let Some(obj) = server.as_object_mut() {
+            obj.insert("node".to_string(), node.id.clone().into());
+        }
+    }
+    Ok(servers)
+}
+
+/// Runs start, stop, restart or backup on a server the node owns, passing
+/// on the node's error message when it refuses.
+pub async fn server_action(node: &NodeConfig, server_id: &str, action: &str) -> Result<(), String> {
+    let path = format!("/api/servers/{}/{}", server_id, action);
+    let response =
+        exchange_within(node, reqwest::Method::POST, &path, None, ACTION_TIMEOUT).await?;
+    if response.status().is_success() {
+        return Ok(());
+    }
+    let status = response.status();
+    match response.json::<serde_json::Value>().await {
+        Ok(body) if body["error"].is_string() => {
+            Err(body["error"].as_str().unwrap_or_default().to_string())
+        }
+        _ => Err(format!("Node '{}' answered {} for {}", node.id, status, path)),
+    }
+}
+
+/// Replaces the node's token: the agent accepts the new token alongside
+/// the old one, the caller saves it, and the old one is then retired with a
+/// request that already uses the new token.
+pub async fn rotate_token(node: &NodeConfig, new_token: &str) -> Result<NodeConfig, String> {
+    let body = serde_json::json!({ "token": new_token });
+    send(node, reqwest::Method::POST, "/api/node/token", Some(&body)).await?;
+    Ok(NodeConfig {
+        token: Some(new_token.to_string()),
+        ..node.clone()
+    })
+}
+
+pub async fn retire_previous_token(node: &NodeConfig) -> Result<(), String> {
+    send(node, reqwest::Method::DELETE, "/api/node/token/previous", None)
+        .await
+        .map(drop)
+}
+
+pub async fn info(state: &AppState, node: &NodeConfig) -> NodeInfo {
+    let health = state.node_health.get(&node.id).map(|h| h.value().clone());
+    // Don't wait out a timeout for a node that is known to be down
+    let (resources, error) = if is_unreachable(state, &node.id) {
+        (None, health.as_ref().and_then(|h| h.error.clone()))
+    } else {
+        match resources(node).await {
+            Ok(r) => (Some(r), None),
+            Err(e) => (None, Some(e)),
+        }
+    };
+    NodeInfo {
+        id: node.id.clone(),
+        url: node.url.clone(),
+        resources,
+        error,
+        health,
+    }
+}
+
+/// Servers of the node, or when it cannot be reached, the ones it had
+/// last time with their status replaced by "unknown".
+pub async fn servers_or_last_known(state: &AppState, node: &NodeConfig) -> Vec<serde_json::Value> {
+    if !is_unreachable(state, &node.id) {
+        match servers(node).await {
+            Ok(list) => {
+                state.node_servers.insert(node.id.clone(), list.clone());
+                return list;
+            }
+            Err(e) => tracing::warn!("Using last known servers of node '{}': {}", node.id, e),
+        }
+    }
+    let mut list = state
+        .node_servers
+        .get(&node.id)
+        .map(|l| l.value().clone())
+        .unwrap_or_default();
+    for server in &mut list {
+        if let Some(obj) = server.as_object_mut() {
+            obj.insert("status".to_string(), "unknown".into());
+        }
+    }
+    list
+}
+
+fn owner(state: &AppState, server_id: &str) -> Option<String> {
+    state.node_servers.iter().find_map(|entry| {
+        entry
+            .value()
+            .iter()
+            .any(|s| s.get("id").and_then(|v| v.as_str()) == Some(server_id))
+            .then(|| entry.key().clone())
+    })
+}
+
+/// Finds the node that runs `server_id`, asking every node on a cache miss.
+pub async fn locate(state: &AppState, server_id: &str) -> Option<NodeConfig> {
+    let nodes = state.config.read().await.nodes.clone();
+    if let Some(node) = owner(state, server_id).and_then(|id| nodes.iter().find(|n| n.id == id)) {
+        return Some(node.clone());
+    }
+    for node in &nodes {
+        if let Ok(list) = servers(node).await {
+            state.node_servers.insert(node.id.clone(), list);
+        }
+    }
+    let id = owner(state, server_id)?;
+    nodes.into_iter().find(|n| n.id == id)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeState {
+    Online,
+    /// Reachable but not ready, or missed fewer than `UNREACHABLE_AFTER`
+    /// heartbeats in a row.
+    Degraded,
+    Unreachable,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct NodeHealth {
+    pub state: NodeState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_seen_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip)]
+    missed: u32,
+}
+
+const UNREACHABLE_AFTER: u32 = 3;
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+pub fn is_unreachable(state: &AppState, node_id: &str) -> bool {
+    state
+        .node_health
+        .get(node_id)
+        .is_some_and(|h| h.state == NodeState::Unreachable)
+}
+
+async fn heartbeat(state: &AppState, node: &NodeConfig) {
+    let previous = state.node_health.get(&node.id).map(|h| h.value().clone());
+    let last_seen_ms = previous.as_ref().and_then(|h| h.last_seen_ms);
+    let health = match exchange(node, reqwest::Method::GET, "/readyz", None).await {
+        Ok(response) if response.status().is_success() => NodeHealth {
+            state: NodeState::Online,
+            last_seen_ms: Some(now_ms()),
+            error: None,
+            missed: 0,
+        },
+        Ok(response) => NodeHealth {
+            state: NodeState::Degraded,
+            last_seen_ms: Some(now_ms()),
+            error: Some(format!("Node is not ready ({})", response.status())),
+            missed: 0,
+        },
+        Err(e) => {
+            let missed = previous.as_ref().map_or(0, |h| h.missed) + 1;
+            NodeHealth {
+                state: if missed >= UNREACHABLE_AFTER {
+                    NodeState::Unreachable
+                } else {
+                    NodeState::Degraded
+                },
+                last_seen_ms,
+                error: Some(e),
+                missed,
+            }
+        }
+    };
+    if previous.as_ref().is_none_or(|p| p.state != health.state) {
+        match health.state {
+            NodeState::Online => tracing::info!("Node '{}' is online", node.id),
+            _ => tracing::warn!(
+                "Node '{}' is {:?}: {}",
+                node.id,
+                health.state,
+                health.error.as_deref().unwrap_or_default()
+            ),
+        }
+        let _ = state.events_tx.send(AgentEvent::NodeHealthChanged {
+            node: node.id.clone(),
+            health: health.clone(),
+        });
+    }
+    state.node_health.insert(node.id.clone(), health);
+}
+
+/// Checks every registered node on `agent.node_heartbeat_secs`.
+pub fn spawn_heartbeat(state: AppState) {
+    tokio::spawn(async move {
+        loop {
+            let (interval, nodes) = {
+                let config = state.config.read().await;
+                (config.agent.node_heartbeat_secs, config.nodes.clone())
+            };
+            if interval == 0 {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                continue;
+            }
+            state.node_health.retain(|id, _| nodes.iter().any(|n| &n.id == id));
+            futures_util::future::join_all(nodes.iter().map(|n| heartbeat(&state, n))).await;
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+        }
+    });
+}
+
+type NodeSocket =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+async fn connect_ws(node: &NodeConfig, path: &str) -> Result<NodeSocket, String> {
+    let ws_url = url(node, path)
+        .replacen("https://", "wss://", 1)
+        .replacen("http://", "ws://", 1);
+    let mut request = ws_url
+        .as_str()
+        .into_client_request()
+        .map_err(|e| format!("Invalid node URL '{}': {}", ws_url, e))?;
+    let nonce = nodeauth::random_token(32);
+    if let Some(ref token) = node.token {
+        verify_identity(node, token).await?;
+        let headers = request.headers_mut();
+        let bearer = format!("Bearer {}", token);
+        headers.insert(
+            "authorization",
+            bearer.parse().map_err(|_| "Invalid node token".to_string())?,
+        );
+        headers.insert(
+            nodeauth::NONCE_HEADER,
+            nonce.parse().map_err(|_| "Invalid nonce".to_string())?,
+        );
+    }
+    let (socket, response) = tokio_tungstenite::connect_async(request)
+        .await
+        .map_err(|e| format!("Node '{}' websocket failed: {}", node.id, e))?;
+    if let Some(ref token) = node.token {
+        let proof = response
+            .headers()
+            .get(nodeauth::PROOF_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        if !nodeauth::verify_proof(token, &nonce, proof) {
+            return Err(format!("Node '{}' failed to prove its identity", node.id));
+        }
+    }
+    Ok(socket)
+}
+
+fn to_node_message(msg: ws::Message) -> tungstenite::Message {
+    match msg {
+        ws::Message::Text(t) => tungstenite::Message::text(t.as_str()),
+        ws::Message::Binary(b) => tungstenite::Message::Binary(b),
+        ws::Message::Ping(b) => tungstenite::Message::Ping(b),
+        ws::Message::Pong(b) => tungstenite::Message::Pong(b),
+        ws::Message::Close(frame) => tungstenite::Message::Close(frame.map(|f| CloseFrame {
+            code: f.code.into(),
+            reason: f.reason.as_str().into(),
+        })),
+    }
+}
+
+fn from_node_message(msg: tungstenite::Message) -> Option<ws::Message> {
+    Some(match msg {
+        tungstenite::Message::Text(t) => ws::Message::Text(t.as_str().into()),
+        tungstenite::Message::Binary(b) => ws::Message::Binary(b),
+        tungstenite::Message::Ping(b) => ws::Message::Ping(b),
+        tungstenite::Message::Pong(b) => ws::Message::Pong(b),
+        tungstenite::Message::Close(frame) => ws::Message::Close(frame.map(|f| ws::CloseFrame {
+            code: f.code.into(),
+            reason: f.reason.as_str().into(),
+        })),
+        tungstenite::Message::Frame(_) => return None,
+    })
+}
+
+/// Relays a client websocket to the same path on `node`. When the link to
+/// the node drops without a final close frame it is re-established with
+/// backoff; the node then replays its recent console lines.
+pub async fn proxy_ws(mut client: WebSocket, node: NodeConfig, path: String) {
+    let mut attempt = 0;
+    let mut reconnects = 0;
+    loop {
+        let upstream = match connect_ws(&node, &path).await {
+            Ok(upstream) => upstream,
+            Err(e) => {
+                attempt += 1;
+                if attempt > WS_RECONNECT_ATTEMPTS {
+                    tracing::warn!("Giving up on websocket to node '{}': {}", node.id, e);
+                    let _ = client
+                        .send(ws::Message::Close(Some(ws::CloseFrame {
+                            code: 1011,
+                            reason: "node unreachable".into(),
+                        })))
+                        .await;
+                    return;
+                }
+                tokio::time::sleep(Duration::from_secs(1 << attempt.min(4))).await;
+                continue;
+            }
+        };
+        attempt = 0;
+        let (mut up_tx, mut up_rx) = upstream.split();
+        loop {
+            tokio::select! {
+                msg = client.recv() => match msg {
+                    Some(Ok(ws::Message::Close(frame))) => {
+                        let _ = up_tx.send(to_node_message(ws::Message::Close(frame))).await;
+                        return;
+                    }
+                    Some(Ok(msg)) => {
+                        if up_tx.send(to_node_message(msg)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(Err(_)) | None => {
+                        let _ = up_tx.close().await;
+                        return;
+                    }
+                },
+                msg = up_rx.next() => match msg {
+                    Some(Ok(tungstenite::Message::Close(frame))) => {
+                        let last = frame
+                            .as_ref()
+                            .is_some_and(|f| WS_FINAL_CLOSE_CODES.contains(&f.code.into()));
+                        if last {
+                            let close = from_node_message(tungstenite::Message::Close(frame));
+                            if let Some(close) = close {
+                                let _ = client.send(close).await;
+                            }
+                            return;
+                        }
+                        break;
+                    }
+                    Some(Ok(msg)) => {
+                        if let Some(msg) = from_node_message(msg) {
+                            if client.send(msg).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Some(Err(_)) | None => break,
+                },
+            }
+        }
+        reconnects += 1;
+        if reconnects > WS_MAX_RECONNECTS {
+            tracing::warn!("Giving up on websocket to node '{}': it keeps dropping", node.id);
+            let _ = client
+                .send(ws::Message::Close(Some(ws::CloseFrame {
+                    code: 1011,
+                    reason: "node connection keeps dropping".into(),
+                })))
+                .await;
+            return;
+        }
+        tracing::debug!("Websocket to node '{}' dropped, reconnecting", node.id);
+    }
+}
@@ -0,0 +1,139 @@
+//! OpenAPI document for the REST API, generated from the handler
+//! annotations in `api` so it stays in step with the routes.
+
+use utoipa::{
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi,
+};
+
+use crate::api;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "mc-node-agent", description = "Minecraft server node agent API"),
+    paths(
+        api::healthz,
+        api::readyz,
+        api::list_servers,
+        api::list_nodes,
+        api::register_node,
+        api::rotate_node_token,
+        api::remove_node,
+        api::create_server,
+        api::update_server,
+        api::bulk_action,
+        api::validate_server,
+        api::delete_server,
+        api::start_server_handler,
+        api::stop_server_handler,
+        api::restart_server_handler,
+        api::set_desired_state,
+        api::backup_server_handler,
+        api::chat_handler,
+        api::macro_handler,
+        api::list_announcements,
+        api::create_announcement,
+        api::update_announcement,
+        api::delete_announcement,
+        api::download_handler,
+        api::proxy_status,
+        api::provision_proxy,
+        api::add_proxy_backend,
+        api::remove_proxy_backend,
+        api::geyser_status,
+        api::install_geyser,
+        api::issue_ws_ticket,
+        api::login,
+        api::logout,
+        api::refresh_session,
+        api::add_manager_token,
+        api::retire_manager_tokens,
+        api::node_challenge,
+        api::node_resources,
+        api::memory_advice,
+        api::maintenance_status,
+        api::enter_maintenance,
+        api::leave_maintenance,
+        api::java_selection,
+        api::last_exit,
+        api::player_stats,
+        api::player_history,
+        api::world_seed,
+        api::list_worlds,
+        api::world_info,
+        api::activate_world,
+        api::reset_world,
+        api::reset_dimension,
+        api::trim_world,
+        api::pregen_status,
+        api::start_pregen,
+        api::cancel_pregen,
+        api::list_diagnostics,
+        api::thread_dump,
+        api::heap_dump,
+        api::start_recording,
+        api::stop_recording,
+        api::download_diagnostic,
+        api::delete_diagnostic,
+        api::get_motd,
+        api::set_motd,
+        api::get_icon,
+        api::set_icon,
+        api::delete_icon,
+        api::get_resource_pack,
+        api::upload_resource_pack,
+        api::delete_resource_pack,
+        api::list_property_profiles,
+        api::preview_property_profile,
+        api::apply_property_profile,
+        api::server_query,
+        api::world_locate,
+        api::auth_can,
+        api::reload_config,
+        api::list_runtimes,
+        api::install_runtime,
+        api::remove_runtime,
+        api::list_updates,
+        api::check_updates,
+        api::promote_update,
+        api::list_cache,
+        api::clear_cache,
+        api::get_whitelist_group,
+        api::add_whitelist_player,
+        api::remove_whitelist_player,
+        api::get_ban_group,
+        api::ban_player,
+        api::pardon_player,
+        api::ban_audit,
+        api::prompt_handler,
+        api::metrics_history,
+        api::console_ws,
+        api::console_sse,
+        api::events_ws,
+        api::metrics_ws,
+        api::metrics_sse,
+    ),
+    modifiers(&ManagerAuth),
+    security(("manager_token" = []))
+)]
+pub struct ApiDoc;
+
+/// Documents the bearer token required when `agent.manager_tokens` or
+/// `agent.users` is set; a session token from `/api/login` also serves.
+struct ManagerAuth;
+
+impl Modify for ManagerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "manager_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+/// Served directly when the Swagger UI, which serves it too, is compiled out.
+#[cfg(not(feature = "swagger-ui"))]
+pub async fn openapi_json() -> axum::Json<utoipa::openapi::OpenApi> {
+    axum::Json(ApiDoc::openapi())
+}
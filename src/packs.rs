@@ -0,0 +1,182 @@
+//! Resource packs hosted by the agent itself. Each server has at most one,
+//! kept in `data_directory/packs/<server id>/` and served unauthenticated
+//! at `/packs/<server id>/<file>`. Uploading one points the server's
+//! `resource-pack` at that URL, below `agent.public_url`, along with its
+//! SHA-1 so clients re-download it whenever it changes.
+
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::{config::ServerConfig, properties::ServerProperties, state::AppState};
+
+/// The most recent clients refuse larger packs.
+pub const MAX_PACK_BYTES: usize = 250 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct ResourcePack {
+    pub file: String,
+    pub size_bytes: u64,
+    pub sha1: String,
+    pub url: String,
+    /// Whether server.properties points at this pack with this hash.
+    pub active: bool,
+    /// The server reads the pack settings at startup only.
+    pub restart_required: bool,
+}
+
+pub fn packs_dir(data_directory: &str) -> PathBuf {
+    Path::new(data_directory).join("packs")
+}
+
+fn server_dir(data_directory: &str, server_id: &str) -> PathBuf {
+    packs_dir(data_directory).join(server_id)
+}
+
+/// Letters, digits, '.', '-' and '_', ending in `.zip`.
+fn validate_file_name(file: &str) -> Result<(), String> {
+    let valid_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_');
+    if file.len() > 64 || file.starts_with('.') || !file.chars().all(valid_char) {
+        return Err("pack file names must be 1-64 letters, digits, '.', '-' or '_'".to_string());
+    }
+    if !file.ends_with(".zip") || file.len() == ".zip".len() {
+        return Err("pack file names must end in .zip".to_string());
+    }
+    Ok(())
+}
+
+fn sha1_hex(bytes: &[u8]) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY, bytes);
+    digest.as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+async fn pack_url(state: &AppState, server_id: &str, file: &str) -> Result<String, String> {
+    let public_url = state.config.read().await.agent.public_url.clone();
+    let public_url =
+        public_url.ok_or("Set agent.public_url to where players can reach the agent")?;
+    Ok(format!("{}/packs/{}/{}", public_url.trim_end_matches('/'), server_id, file))
+}
+
+/// The file of the server's pack, if it has one.
+async fn current_file(dir: &Path) -> Option<String> {
+    let mut entries = tokio::fs::read_dir(dir).await.ok()?;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if validate_file_name(&name).is_ok() {
+            return Some(name);
+        }
+    }
+    None
+}
+
+/// Deletes the packs in `dir` other than `file`.
+async fn remove_others(dir: &Path, file: &str) {
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name != file && validate_file_name(&name).is_ok() {
+            let _ = tokio::fs::remove_file(entry.path()).await;
+        }
+    }
+}
+
+/// The server's hosted pack, hashed afresh.
+pub async fn get(state: &AppState, cfg: &ServerConfig) -> Result<Option<ResourcePack>, String> {
+    let data_directory = state.config.read().await.agent.data_directory.clone();
+    let dir = server_dir(&data_directory, &cfg.id);
+    let Some(file) = current_file(&dir).await else {
+        return Ok(None);
+    };
+    let path = dir.join(&file);
+    let bytes = tokio::fs::read(&path)
+        .await
+        .map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+    let sha1 = tokio::task::spawn_blocking(move || sha1_hex(&bytes))
+        .await
+        .map_err(|e| e.to_string())?;
+    let url = pack_url(state, &cfg.id, &file).await?;
+    let properties = ServerProperties::load(&cfg.directory).await?;
+    let active = properties.get_text("resource-pack").as_deref() == Some(url.as_str())
+        && properties.get("resource-pack-sha1") == Some(sha1.as_str());
+    Ok(Some(ResourcePack {
+        size_bytes: tokio::fs::metadata(&path).await.map(|m| m.len()).unwrap_or_default(),
+        file,
+        sha1,
+        url,
+        active,
+        restart_required: false,
+    }))
+}
+
+/// Stores `bytes` as the server's pack, replacing any other, and points
+/// server.properties at it.
+pub async fn upload(
+    state: &AppState,
+    cfg: &ServerConfig,
+    file: &str,
+    bytes: axum::body::Bytes,
+) -> Result<ResourcePack, String> {
+    validate_file_name(file)?;
+    // A zip's local file header; catches uploads of the wrong file
+    if !bytes.starts_with(b"PK\x03\x04") {
+        return Err("A resource pack must be a zip file".to_string());
+    }
+    let url = pack_url(state, &cfg.id, file).await?;
+    let data_directory = state.config.read().await.agent.data_directory.clone();
+    let dir = server_dir(&data_directory, &cfg.id);
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .map_err(|e| format!("Failed to create '{}': {}", dir.display(), e))?;
+
+    let size_bytes = bytes.len() as u64;
+    let path = dir.join(file);
+    let tmp = dir.join(format!(".{}.tmp", file));
+    tokio::fs::write(&tmp, &bytes)
+        .await
+        .map_err(|e| format!("Failed to write '{}': {}", tmp.display(), e))?;
+    tokio::fs::rename(&tmp, &path)
+        .await
+        .map_err(|e| format!("Failed to replace '{}': {}", path.display(), e))?;
+    remove_others(&dir, file).await;
+    let sha1 = tokio::task::spawn_blocking(move || sha1_hex(&bytes))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut properties = ServerProperties::load(&cfg.directory).await?;
+    properties.set_text("resource-pack", &url);
+    properties.set("resource-pack-sha1", &sha1);
+    properties.save(&cfg.directory).await?;
+    tracing::info!("Hosting resource pack '{}' for '{}' ({})", file, cfg.id, sha1);
+    Ok(ResourcePack {
+        file: file.to_string(),
+        size_bytes,
+        sha1,
+        url,
+        active: true,
+        restart_required: state.servers.contains_key(&cfg.id),
+    })
+}
+
+/// Deletes the server's pack, clearing server.properties if it still
+/// points at it. Returns whether there was one.
+pub async fn remove(state: &AppState, cfg: &ServerConfig) -> Result<bool, String> {
+    let data_directory = state.config.read().await.agent.data_directory.clone();
+    let dir = server_dir(&data_directory, &cfg.id);
+    let Some(file) = current_file(&dir).await else {
+        return Ok(false);
+    };
+    let path = dir.join(&file);
+    tokio::fs::remove_file(&path)
+        .await
+        .map_err(|e| format!("Failed to delete '{}': {}", path.display(), e))?;
+    let mut properties = ServerProperties::load(&cfg.directory).await?;
+    let hosted = format!("/packs/{}/{}", cfg.id, file);
+    if properties.get_text("resource-pack").is_some_and(|url| url.ends_with(&hosted)) {
+        properties.set("resource-pack", "");
+        properties.set("resource-pack-sha1", "");
+        properties.save(&cfg.directory).await?;
+    }
+    Ok(true)
+}
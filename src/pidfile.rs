@@ -0,0 +1,115 @@
+//! PID files for running servers, at `<data_directory>/run/<id>/process.json`.
+//! Next to the PID they hold what the process looked like once spawned: its
+//! start time, working directory and command line. PIDs get reused, so a
+//! PID read back from a file, or one remembered while the server ran, is
+//! only acted on while the process behind it still matches.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sysinfo::{Pid, ProcessRefreshKind, System, UpdateKind};
+
+const RUN_DIR: &str = "run";
+const PID_FILE: &str = "process.json";
+
+/// Per-server directory for the PID file and a detached server's console.
+pub fn run_dir(data_directory: &str, server_id: &str) -> PathBuf {
+    Path::new(data_directory).join(RUN_DIR).join(server_id)
+}
+
+/// A process as the OS describes it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProcessIdentity {
+    pub pid: u32,
+    /// Unix seconds.
+    pub start_time: u64,
+    pub cwd: Option<PathBuf>,
+    pub cmd: Vec<String>,
+}
+
+impl ProcessIdentity {
+    /// The process that has this PID right now.
+    pub fn of(pid: u32) -> Option<Self> {
+        let mut sys = System::new();
+        let refresh = ProcessRefreshKind::new()
+            .with_cmd(UpdateKind::Always)
+            .with_cwd(UpdateKind::Always);
+        let sys_pid = Pid::from_u32(pid);
+        if !sys.refresh_process_specifics(sys_pid, refresh) {
+            return None;
+        }
+        sys.process(sys_pid).map(|process| Self::from_process(pid, process))
+    }
+
+    pub fn from_process(pid: u32, process: &sysinfo::Process) -> Self {
+        Self {
+            pid,
+            start_time: process.start_time(),
+            cwd: process.cwd().map(Path::to_path_buf),
+            cmd: process.cmd().to_vec(),
+        }
+    }
+
+    /// Whether the PID still belongs to this process. A start time one
+    /// second off is allowed for rounding in the boot time.
+    pub fn is_running(&self) -> bool {
+        Self::of(self.pid).is_some_and(|now| {
+            now.start_time.abs_diff(self.start_time) <= 1
+                && now.cwd == self.cwd
+                && now.cmd == self.cmd
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PidFile {
+    #[serde(flatten)]
+    pub process: ProcessIdentity,
+    /// When the agent spawned it, in Unix milliseconds.
+    pub started_at_ms: u64,
+}
+
+/// Records a just-spawned server. Fails if the process is already gone.
+pub async fn write(
+    data_directory: &str,
+    server_id: &str,
+    pid: u32,
+    started_at_ms: u64,
+) -> Result<PidFile, String> {
+    let process = ProcessIdentity::of(pid)
+        .ok_or_else(|| format!("Server process {} exited right away", pid))?;
+    let pid_file = PidFile {
+        process,
+        started_at_ms,
+    };
+    let dir = run_dir(data_directory, server_id);
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .map_err(|e| format!("Failed to create '{}': {}", dir.display(), e))?;
+    let json = serde_json::to_string_pretty(&pid_file).map_err(|e| e.to_string())?;
+    tokio::fs::write(dir.join(PID_FILE), json)
+        .await
+        .map_err(|e| format!("Failed to write the PID file: {}", e))?;
+    Ok(pid_file)
+}
+
+pub async fn read(data_directory: &str, server_id: &str) -> Option<PidFile> {
+    let path = run_dir(data_directory, server_id).join(PID_FILE);
+    let contents = tokio::fs::read_to_string(path).await.ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// The server's recorded process, if it is still the one running. A PID
+/// file for a process that is gone is removed.
+pub async fn running(data_directory: &str, server_id: &str) -> Option<PidFile> {
+    let pid_file = read(data_directory, server_id).await?;
+    if pid_file.process.is_running() {
+        return Some(pid_file);
+    }
+    remove(data_directory, server_id).await;
+    None
+}
+
+pub async fn remove(data_directory: &str, server_id: &str) {
+    let _ = tokio::fs::remove_file(run_dir(data_directory, server_id).join(PID_FILE)).await;
+}
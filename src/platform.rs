@@ -0,0 +1,142 @@
+//! OS-specific process control. Unix servers are stopped and probed with
+//! signals. Windows has no SIGTERM for console programs, so there servers
+//! run in their own process group (CTRL_BREAK makes the JVM print a thread
+//! dump) and inside a job object that takes them down with the agent, and
+//! `taskkill` is the fallback when the stop command is ignored.
+
+use std::process::ExitStatus;
+
+use tokio::process::{Child, Command};
+
+/// Platform setup applied to every server command before it is spawned.
+pub fn configure_command(cmd: &mut Command) {
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::System::Threading::CREATE_NEW_PROCESS_GROUP;
+        cmd.creation_flags(CREATE_NEW_PROCESS_GROUP);
+    }
+    #[cfg(not(windows))]
+    let _ = cmd;
+}
+
+/// Ties a freshly spawned server to the agent's lifetime where the OS
+/// needs help with that (a kill-on-close job object on Windows).
+pub fn adopt_child(child: &Child) {
+    #[cfg(windows)]
+    if let Some(handle) = child.raw_handle() {
+        job::assign(handle);
+    }
+    #[cfg(not(windows))]
+    let _ = child;
+}
+
+/// Asks the process to exit: SIGTERM on Unix, `taskkill` on Windows.
+pub async fn terminate(pid: u32) {
+    #[cfg(unix)]
+    {
+        use nix::sys::signal::{self, Signal};
+        use nix::unistd::Pid as NixPid;
+        let _ = signal::kill(NixPid::from_raw(pid as i32), Signal::SIGTERM);
+    }
+    #[cfg(windows)]
+    {
+        // Console programs ignore the WM_CLOSE a plain taskkill sends
+        let _ = Command::new("taskkill")
+            .args(["/PID", &pid.to_string(), "/T", "/F"])
+            .output()
+            .await;
+    }
+}
+
+/// Makes the JVM print a thread dump to its console.
+pub fn request_thread_dump(pid: u32) {
+    #[cfg(unix)]
+    {
+        use nix::sys::signal::{self, Signal};
+        use nix::unistd::Pid as NixPid;
+        let _ = signal::kill(NixPid::from_raw(pid as i32), Signal::SIGQUIT);
+    }
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::System::Console::{GenerateConsoleCtrlEvent, CTRL_BREAK_EVENT};
+        // The server leads its own process group, whose id is its PID
+        unsafe {
+            GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, pid);
+        }
+    }
+}
+
+/// The signal that ended the process; always None on Windows.
+pub fn exit_signal(status: ExitStatus) -> Option<i32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        status.signal()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = status;
+        None
+    }
+}
+
+/// A command that runs `script` through the platform shell.
+pub fn shell_command(script: &str) -> Command {
+    #[cfg(windows)]
+    {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(script);
+        cmd
+    }
+    #[cfg(not(windows))]
+    {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(script);
+        cmd
+    }
+}
+
+#[cfg(windows)]
+mod job {
+    use std::os::windows::io::RawHandle;
+    use std::sync::OnceLock;
+
+    use windows_sys::Win32::System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, JobObjectExtendedLimitInformation,
+        SetInformationJobObject, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+    };
+
+    // Never closed: the handle dying with the agent is what ends the servers
+    static JOB: OnceLock<usize> = OnceLock::new();
+
+    fn job() -> Option<usize> {
+        let job = *JOB.get_or_init(|| unsafe {
+            let job = CreateJobObjectW(std::ptr::null(), std::ptr::null());
+            if job.is_null() {
+                return 0;
+            }
+            let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = std::mem::zeroed();
+            info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+            SetInformationJobObject(
+                job,
+                JobObjectExtendedLimitInformation,
+                &info as *const _ as *const std::ffi::c_void,
+                std::mem::size_of_val(&info) as u32,
+            );
+            job as usize
+        });
+        (job != 0).then_some(job)
+    }
+
+    pub fn assign(process: RawHandle) {
+        let Some(job) = job() else {
+            tracing::warn!("Failed to create a job object for server processes");
+            return;
+        };
+        let assigned = unsafe { AssignProcessToJobObject(job as _, process as _) };
+        if assigned == 0 {
+            tracing::warn!("Failed to add server process to the agent's job object");
+        }
+    }
+}
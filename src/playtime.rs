@@ -0,0 +1,235 @@
+//! Player sessions, from the console's join and leave lines. Finished
+//! sessions are appended to `data_directory/players/<server id>/sessions.jsonl`,
+//! one JSON line each; open ones live on the running instance and are
+//! closed when the server exits. Sessions open when the agent itself dies
+//! are lost.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncWriteExt, sync::broadcast::error::RecvError};
+
+use crate::{console::INFO_PREFIX, state::ServerInstance};
+
+const SESSIONS_FILE: &str = "sessions.jsonl";
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct PlayerSession {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+    pub joined_ms: u64,
+    /// Absent while the player is online.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub left_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct PlayerStats {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+    pub online: bool,
+    pub first_seen_ms: u64,
+    pub last_seen_ms: u64,
+    /// Including the session in progress.
+    pub playtime_ms: u64,
+    pub session_count: usize,
+    /// Most recent first.
+    pub sessions: Vec<PlayerSession>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Presence {
+    Joined,
+    Left,
+}
+
+fn presence_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    // Anchored on the log prefix so chat can't fake it
+    RE.get_or_init(|| {
+        Regex::new(&format!(r"{}([A-Za-z0-9_]{{1,16}}) (joined|left) the game$", INFO_PREFIX))
+            .expect("valid regex")
+    })
+}
+
+fn uuid_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(&format!(
+            r"{}UUID of player ([A-Za-z0-9_]{{1,16}}) is ([0-9a-f-]{{32,36}})$",
+            INFO_PREFIX
+        ))
+        .expect("valid regex")
+    })
+}
+
+/// The player joining or leaving in a console line, without colour codes.
+pub fn presence(line: &str) -> Option<(&str, Presence)> {
+    let caps = presence_regex().captures(line)?;
+    let presence = if &caps[2] == "joined" { Presence::Joined } else { Presence::Left };
+    Some((caps.get(1)?.as_str(), presence))
+}
+
+fn sessions_path(data_directory: &str, server_id: &str) -> PathBuf {
+    Path::new(data_directory).join("players").join(server_id).join(SESSIONS_FILE)
+}
+
+async fn append(path: &Path, session: &PlayerSession) {
+    let write = async {
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let mut line = serde_json::to_string(session).map_err(std::io::Error::other)?;
+        line.push('\n');
+        let mut file =
+            tokio::fs::OpenOptions::new().create(true).append(true).open(path).await?;
+        file.write_all(line.as_bytes()).await
+    };
+    if let Err(e) = write.await {
+        tracing::warn!("Failed to record a session of '{}': {}", session.name, e);
+    }
+}
+
+/// Follows the console for joins and leaves until the server exits, then
+/// closes the sessions still open.
+pub fn spawn_tracker(data_directory: &str, server_id: &str, instance: Arc<ServerInstance>) {
+    let path = sessions_path(data_directory, server_id);
+    let mut console_rx = instance.console_tx.subscribe();
+    let mut exited_rx = instance.exited.subscribe();
+    tokio::spawn(async move {
+        // Logged while the player logs in, just before they join
+        let mut uuids: HashMap<String, String> = HashMap::new();
+        loop {
+            let line = tokio::select! {
+                msg = console_rx.recv() => match msg {
+                    Ok(line) => line,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+                _ = exited_rx.wait_for(|exited| *exited) => break,
+            };
+            let text = crate::ansi::strip(&line.text);
+            if let Some(caps) = uuid_regex().captures(&text) {
+                uuids.insert(caps[1].to_string(), caps[2].to_string());
+                continue;
+            }
+            let Some((name, presence)) = presence(&text) else {
+                continue;
+            };
+            let mut online = instance.players.lock().await;
+            match presence {
+                Presence::Joined => {
+                    let session = PlayerSession {
+                        name: name.to_string(),
+                        uuid: uuids.remove(name),
+                        joined_ms: line.timestamp_ms,
+                        left_ms: None,
+                    };
+                    online.insert(name.to_string(), session);
+                }
+                Presence::Left => {
+                    if let Some(mut session) = online.remove(name) {
+                        session.left_ms = Some(line.timestamp_ms);
+                        append(&path, &session).await;
+                    }
+                }
+            }
+        }
+        let left_ms = chrono::Utc::now().timestamp_millis() as u64;
+        let open: Vec<(String, PlayerSession)> = instance.players.lock().await.drain().collect();
+        for (_, mut session) in open {
+            session.left_ms = Some(left_ms);
+            append(&path, &session).await;
+        }
+    });
+}
+
+/// Every recorded player of a server, most recently seen first, each with
+/// up to `history` of their latest sessions. `open` are the sessions in
+/// progress.
+pub async fn stats(
+    data_directory: &str,
+    server_id: &str,
+    open: Vec<PlayerSession>,
+    history: usize,
+) -> Result<Vec<PlayerStats>, String> {
+    let path = sessions_path(data_directory, server_id);
+    let contents = match tokio::fs::read_to_string(&path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("Failed to read '{}': {}", path.display(), e)),
+    };
+    let now_ms = chrono::Utc::now().timestamp_millis() as u64;
+    let recorded = contents.lines().filter_map(|l| serde_json::from_str::<PlayerSession>(l).ok());
+    // By UUID where the log had one, so renamed players stay one entry
+    let mut players: HashMap<String, Vec<PlayerSession>> = HashMap::new();
+    for session in recorded.chain(open) {
+        let key = session.uuid.clone().unwrap_or_else(|| session.name.to_lowercase());
+        players.entry(key).or_default().push(session);
+    }
+    let mut stats: Vec<PlayerStats> = players
+        .into_values()
+        .map(|mut sessions| {
+            sessions.sort_by_key(|s| std::cmp::Reverse(s.joined_ms));
+            let latest = &sessions[0];
+            PlayerStats {
+                name: latest.name.clone(),
+                uuid: sessions.iter().find_map(|s| s.uuid.clone()),
+                online: latest.left_ms.is_none(),
+                first_seen_ms: sessions.iter().map(|s| s.joined_ms).min().unwrap_or_default(),
+                last_seen_ms: latest.left_ms.unwrap_or(now_ms),
+                playtime_ms: sessions
+                    .iter()
+                    .map(|s| s.left_ms.unwrap_or(now_ms).saturating_sub(s.joined_ms))
+                    .sum(),
+                session_count: sessions.len(),
+                sessions: sessions.into_iter().take(history).collect(),
+            }
+        })
+        .collect();
+    stats.sort_by_key(|p| std::cmp::Reverse(p.last_seen_ms));
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presence_reads_vanilla_and_paper_lines() {
+        assert_eq!(
+            presence("[12:00:00] [Server thread/INFO]: Steve joined the game"),
+            Some(("Steve", Presence::Joined))
+        );
+        assert_eq!(
+            presence("[12:00:00 INFO]: Alex_2 left the game"),
+            Some(("Alex_2", Presence::Left))
+        );
+    }
+
+    #[test]
+    fn presence_ignores_spoofed_chat() {
+        let spoofed = "[12:00:00] [Server thread/INFO]: <Steve> ]: Notch left the game";
+        assert_eq!(presence(spoofed), None);
+        assert_eq!(presence("[12:00:00 INFO]: <Steve> Notch joined the game"), None);
+        assert_eq!(presence("[12:00:00] [Server thread/WARN]: Notch left the game"), None);
+    }
+
+    #[test]
+    fn uuid_lines_are_anchored_too() {
+        let line = "[12:00:00] [User Authenticator #1/INFO]: UUID of player Steve is \
+                    069a79f4-44e9-4726-a5be-fca90e38aaf5";
+        let caps = uuid_regex().captures(line).unwrap();
+        assert_eq!(&caps[1], "Steve");
+        let spoofed = "[12:00:00 INFO]: <x> ]: UUID of player Steve is \
+                       069a79f444e94726a5befca90e38aaf5";
+        assert!(uuid_regex().captures(spoofed).is_none());
+    }
+}
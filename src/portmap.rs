@@ -0,0 +1,332 @@
+//! Router port forwarding for home hosting. When a `port_forward` server
+//! starts, its game port (and query port, if enabled) is mapped on the
+//! gateway via UPnP IGD, falling back to NAT-PMP on Linux, and unmapped
+//! when it exits. Mappings are leased and renewed while the server runs.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use igd_next::{aio::tokio::search_gateway, PortMappingProtocol, SearchOptions};
+use serde::Serialize;
+use tokio::{net::UdpSocket, sync::OwnedMutexGuard};
+
+use crate::{
+    config::ServerConfig,
+    ports::{Protocol, ServerPort},
+    properties::ServerProperties,
+    state::AppState,
+};
+
+const LEASE_SECS: u32 = 3600;
+const RENEW_INTERVAL: Duration = Duration::from_secs(LEASE_SECS as u64 / 2);
+const UPNP_SEARCH_TIMEOUT: Duration = Duration::from_secs(3);
+const NATPMP_PORT: u16 = 5351;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Method {
+    Upnp,
+    Natpmp,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct MappingStatus {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<Method>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_ip: Option<String>,
+    pub ports: Vec<ServerPort>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub updated_at_ms: u64,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Ports worth exposing to the internet; RCON never is.
+async fn forwarded_ports(cfg: &ServerConfig) -> Result<Vec<ServerPort>, String> {
+    let properties = ServerProperties::load(&cfg.directory).await?;
+    let ports = crate::ports::server_ports(cfg, &properties)?;
+    Ok(ports.into_iter().filter(|p| p.purpose != "rcon").collect())
+}
+
+/// The address this host uses to reach `gateway`, which is what the
+/// router must forward to.
+async fn local_ip_towards(gateway: IpAddr) -> Result<IpAddr, String> {
+    let socket = UdpSocket::bind(("0.0.0.0", 0))
+        .await
+        .map_err(|e| format!("Failed to open UDP socket: {}", e))?;
+    socket
+        .connect((gateway, 9))
+        .await
+        .map_err(|e| format!("No route to gateway {}: {}", gateway, e))?;
+    socket
+        .local_addr()
+        .map(|a| a.ip())
+        .map_err(|e| format!("Failed to read local address: {}", e))
+}
+
+fn upnp_protocol(protocol: Protocol) -> PortMappingProtocol {
+    match protocol {
+        Protocol::Tcp => PortMappingProtocol::TCP,
+        Protocol::Udp => PortMappingProtocol::UDP,
+    }
+}
+
+async fn upnp_map(cfg: &ServerConfig, ports: &[ServerPort]) -> Result<Option<String>, String> {
+    let options = SearchOptions {
+        timeout: Some(UPNP_SEARCH_TIMEOUT),
+        ..Default::default()
+    };
+    let gateway = search_gateway(options)
+        .await
+        .map_err(|e| format!("No UPnP gateway found: {}", e))?;
+    let local_ip = local_ip_towards(gateway.addr.ip()).await?;
+    let description = format!("mc-node-agent {}", cfg.id);
+    for p in ports {
+        gateway
+            .add_port(
+                upnp_protocol(p.protocol),
+                p.port,
+                SocketAddr::new(local_ip, p.port),
+                LEASE_SECS,
+                &description,
+            )
+            .await
+            .map_err(|e| format!("UPnP mapping of port {} failed: {}", p.port, e))?;
+    }
+    Ok(gateway.get_external_ip().await.ok().map(|ip| ip.to_string()))
+}
+
+async fn upnp_unmap(ports: &[ServerPort]) -> Result<(), String> {
+    let options = SearchOptions {
+        timeout: Some(UPNP_SEARCH_TIMEOUT),
+        ..Default::default()
+    };
+    let gateway = search_gateway(options)
+        .await
+        .map_err(|e| format!("No UPnP gateway found: {}", e))?;
+    for p in ports {
+        let _ = gateway.remove_port(upnp_protocol(p.protocol), p.port).await;
+    }
+    Ok(())
+}
+
+/// The default IPv4 gateway from the kernel routing table.
+#[cfg(target_os = "linux")]
+async fn default_gateway() -> Result<Ipv4Addr, String> {
+    let routes = tokio::fs::read_to_string("/proc/net/route")
+        .await
+        .map_err(|e| format!("Failed to read routing table: {}", e))?;
+    routes
+        .lines()
+        .skip(1)
+        .find_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.get(1) != Some(&"00000000") {
+                return None;
+            }
+            // Stored as a little-endian hex word
+            let raw = u32::from_str_radix(fields.get(2)?, 16).ok()?;
+            Some(Ipv4Addr::from(raw.to_le_bytes()))
+        })
+        .ok_or_else(|| "No default gateway".to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn default_gateway() -> Result<Ipv4Addr, String> {
+    Err("NAT-PMP is only supported on Linux".to_string())
+}
+
+/// Sends a NAT-PMP request with the RFC 6886 retry schedule.
+async fn natpmp_request(gateway: Ipv4Addr, request: &[u8]) -> Result<Vec<u8>, String> {
+    let socket = UdpSocket::bind(("0.0.0.0", 0))
+        .await
+        .map_err(|e| format!("Failed to open UDP socket: {}", e))?;
+    socket
+        .connect((gateway, NATPMP_PORT))
+        .await
+        .map_err(|e| format!("No route to gateway {}: {}", gateway, e))?;
+    let mut wait = Duration::from_millis(250);
+    let mut buf = [0u8; 16];
+    for _ in 0..4 {
+        socket
+            .send(request)
+            .await
+            .map_err(|e| format!("Failed to send NAT-PMP request: {}", e))?;
+        if let Ok(Ok(n)) = tokio::time::timeout(wait, socket.recv(&mut buf)).await {
+            // Responses echo the opcode with the high bit set
+            if n >= 8 && buf[1] == request[1] | 0x80 {
+                let result = u16::from_be_bytes([buf[2], buf[3]]);
+                if result != 0 {
+                    return Err(format!("NAT-PMP gateway returned result code {}", result));
+                }
+                return Ok(buf[..n].to_vec());
+            }
+        }
+        wait *= 2;
+    }
+    Err(format!("NAT-PMP gateway {} did not answer", gateway))
+}
+
+fn natpmp_mapping_request(p: &ServerPort, lifetime: u32) -> Vec<u8> {
+    let opcode = match p.protocol {
+        Protocol::Udp => 1,
+        Protocol::Tcp => 2,
+    };
+    let external = if lifetime == 0 { 0 } else { p.port };
+    let mut request = vec![0, opcode, 0, 0];
+    request.extend_from_slice(&p.port.to_be_bytes());
+    request.extend_from_slice(&external.to_be_bytes());
+    request.extend_from_slice(&lifetime.to_be_bytes());
+    request
+}
+
+async fn natpmp_map(ports: &[ServerPort]) -> Result<Option<String>, String> {
+    let gateway = default_gateway().await?;
+    for p in ports {
+        let response = natpmp_request(gateway, &natpmp_mapping_request(p, LEASE_SECS)).await?;
+        let mapped = response
+            .get(10..12)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .ok_or("Truncated NAT-PMP response")?;
+        if mapped != p.port {
+            return Err(format!(
+                "NAT-PMP gateway mapped port {} to {} instead",
+                p.port, mapped
+            ));
+        }
+    }
+    let external_ip = natpmp_request(gateway, &[0, 0])
+        .await
+        .ok()
+        .and_then(|r| r.get(8..12).map(|b| Ipv4Addr::new(b[0], b[1], b[2], b[3]).to_string()));
+    Ok(external_ip)
+}
+
+async fn natpmp_unmap(ports: &[ServerPort]) -> Result<(), String> {
+    let gateway = default_gateway().await?;
+    for p in ports {
+        let _ = natpmp_request(gateway, &natpmp_mapping_request(p, 0)).await;
+    }
+    Ok(())
+}
+
+/// Locks `ports` so a mapping and a removal of the same port, even for
+/// different servers, never interleave on the router.
+async fn lock_ports(state: &AppState, ports: &[ServerPort]) -> Vec<OwnedMutexGuard<()>> {
+    let mut numbers: Vec<u16> = ports.iter().map(|p| p.port).collect();
+    // Always taken in the same order
+    numbers.sort_unstable();
+    numbers.dedup();
+    let mut guards = Vec::with_capacity(numbers.len());
+    for port in numbers {
+        let lock = state.port_locks.entry(port).or_default().clone();
+        guards.push(lock.lock_owned().await);
+    }
+    guards
+}
+
+async fn remove_mappings(status: &MappingStatus) -> Result<(), String> {
+    match status.method {
+        Some(Method::Upnp) => upnp_unmap(&status.ports).await,
+        Some(Method::Natpmp) => natpmp_unmap(&status.ports).await,
+        None => Ok(()),
+    }
+}
+
+/// Maps (or renews) the server's ports and records the outcome.
+pub async fn map(state: &AppState, cfg: &ServerConfig) {
+    let ports = match forwarded_ports(cfg).await {
+        Ok(ports) => ports,
+        Err(e) => {
+            tracing::warn!("Cannot forward ports for '{}': {}", cfg.id, e);
+            return;
+        }
+    };
+    let _locks = lock_ports(state, &ports).await;
+    if !state.servers.contains_key(&cfg.id) {
+        return;
+    }
+    let (method, result) = match upnp_map(cfg, &ports).await {
+        Ok(ip) => (Some(Method::Upnp), Ok(ip)),
+        Err(upnp_err) => match natpmp_map(&ports).await {
+            Ok(ip) => (Some(Method::Natpmp), Ok(ip)),
+            Err(natpmp_err) => (None, Err(format!("{}; {}", upnp_err, natpmp_err))),
+        },
+    };
+    let status = match result {
+        Ok(external_ip) => MappingStatus {
+            ok: true,
+            method,
+            external_ip,
+            ports,
+            error: None,
+            updated_at_ms: now_ms(),
+        },
+        Err(e) => {
+            tracing::warn!("Port forwarding for '{}' failed: {}", cfg.id, e);
+            MappingStatus {
+                ok: false,
+                method: None,
+                external_ip: None,
+                ports,
+                error: Some(e),
+                updated_at_ms: now_ms(),
+            }
+        }
+    };
+    let first = state
+        .port_mappings
+        .get(&cfg.id)
+        .is_none_or(|s| !s.ok);
+    if status.ok && first {
+        tracing::info!(
+            "Forwarded ports of '{}' via {:?} (external address {:?})",
+            cfg.id,
+            method,
+            status.external_ip
+        );
+    }
+    state.port_mappings.insert(cfg.id.clone(), status);
+    // An exit while the mapping was made found nothing to remove
+    if !state.servers.contains_key(&cfg.id) {
+        if let Some((_, status)) = state.port_mappings.remove(&cfg.id) {
+            if let Err(e) = remove_mappings(&status).await {
+                tracing::warn!("Failed to remove port forwarding for '{}': {}", cfg.id, e);
+            }
+        }
+    }
+}
+
+/// Removes the server's mappings from the router.
+pub async fn unmap(state: &AppState, server_id: &str) {
+    let Some((_, status)) = state.port_mappings.remove(server_id) else {
+        return;
+    };
+    let _locks = lock_ports(state, &status.ports).await;
+    if let Err(e) = remove_mappings(&status).await {
+        tracing::warn!("Failed to remove port forwarding for '{}': {}", server_id, e);
+    }
+}
+
+/// Renews the leases of running servers before they expire.
+pub fn spawn_renewer(state: AppState) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(RENEW_INTERVAL).await;
+            let servers = state.config.read().await.servers.clone();
+            for cfg in servers {
+                if cfg.port_forward && state.servers.contains_key(&cfg.id) {
+                    map(&state, &cfg).await;
+                }
+            }
+        }
+    });
+}
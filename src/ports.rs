@@ -0,0 +1,146 @@
+//! Port bookkeeping: checks that the ports a server is about to bind are
+//! free, so a clash is reported up front instead of as a JVM that exits
+//! right after launch, and hands out ports to newly created servers.
+
+use serde::Serialize;
+use tokio::net::{TcpListener, UdpSocket};
+
+use crate::{
+    config::{Config, ServerConfig},
+    properties::ServerProperties,
+};
+
+const DEFAULT_RCON_PORT: u16 = 25575;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct ServerPort {
+    pub port: u16,
+    pub protocol: Protocol,
+    pub purpose: &'static str,
+}
+
+fn parse_port(properties: &ServerProperties, key: &str) -> Result<Option<u16>, String> {
+    properties
+        .get(key)
+        .map(|p| p.parse().map_err(|_| format!("Invalid {} '{}'", key, p)))
+        .transpose()
+}
+
+/// The game port, Geyser's Bedrock port, and the query and RCON ports
+/// enabled in server.properties.
+pub fn server_ports(
+    cfg: &ServerConfig,
+    properties: &ServerProperties,
+) -> Result<Vec<ServerPort>, String> {
+    let mut ports = vec![ServerPort {
+        port: cfg.port,
+        protocol: Protocol::Tcp,
+        purpose: "game",
+    }];
+    if properties.get("enable-query") == Some("true") && cfg.flavor().has_world() {
+        ports.push(ServerPort {
+            port: parse_port(properties, "query.port")?.unwrap_or(cfg.port),
+            protocol: Protocol::Udp,
+            purpose: "query",
+        });
+    }
+    if let Some(ref bedrock) = cfg.bedrock {
+        ports.push(ServerPort {
+            port: bedrock.port,
+            protocol: Protocol::Udp,
+            purpose: "bedrock",
+        });
+    }
+    if properties.get("enable-rcon") == Some("true") && cfg.flavor().has_world() {
+        ports.push(ServerPort {
+            port: parse_port(properties, "rcon.port")?.unwrap_or(DEFAULT_RCON_PORT),
+            protocol: Protocol::Tcp,
+            purpose: "rcon",
+        });
+    }
+    Ok(ports)
+}
+
+/// Fails with the first port another process already holds.
+pub async fn check_available(cfg: &ServerConfig) -> Result<(), String> {
+    let properties = ServerProperties::load(&cfg.directory).await?;
+    // The server binds `server-ip` when set, every interface otherwise
+    let host = properties
+        .get("server-ip")
+        .filter(|ip| !ip.is_empty())
+        .unwrap_or("0.0.0.0")
+        .to_string();
+    let ports = server_ports(cfg, &properties)?;
+    for (i, p) in ports.iter().enumerate() {
+        if let Some(other) = ports[..i]
+            .iter()
+            .find(|o| o.port == p.port && o.protocol == p.protocol)
+        {
+            return Err(format!(
+                "The {} and {} ports of '{}' are both {}",
+                other.purpose, p.purpose, cfg.id, p.port
+            ));
+        }
+        let bound = match p.protocol {
+            Protocol::Tcp => TcpListener::bind((host.as_str(), p.port)).await.map(drop),
+            Protocol::Udp => UdpSocket::bind((host.as_str(), p.port)).await.map(drop),
+        };
+        if let Err(e) = bound {
+            let protocol = match p.protocol {
+                Protocol::Tcp => "tcp",
+                Protocol::Udp => "udp",
+            };
+            return Err(format!(
+                "Port {}/{} ({}) for '{}' is not available: {}",
+                p.port, protocol, p.purpose, cfg.id, e
+            ));
+        }
+    }
+    Ok(())
+}
+
+async fn is_free(port: u16) -> bool {
+    TcpListener::bind(("0.0.0.0", port)).await.is_ok()
+        && UdpSocket::bind(("0.0.0.0", port)).await.is_ok()
+}
+
+/// Picks the lowest port in `agent.port_range` that no configured server
+/// uses and that nothing on the host has bound.
+pub async fn allocate(config: &Config) -> Result<u16, String> {
+    let range = config.agent.port_range;
+    for port in range.start..=range.end {
+        let taken = config
+            .servers
+            .iter()
+            .any(|s| {
+                s.port == port
+                    || s.shaped_ports.contains(&port)
+                    || s.bedrock.as_ref().is_some_and(|b| b.port == port)
+            });
+        if !taken && is_free(port).await {
+            return Ok(port);
+        }
+    }
+    Err(format!(
+        "No free port left in agent.port_range {}-{}",
+        range.start, range.end
+    ))
+}
+
+/// Points server.properties at the allocated port. Proxies get theirs
+/// written into their own config at start.
+pub async fn write_server_port(cfg: &ServerConfig) -> Result<(), String> {
+    if !cfg.flavor().has_world() {
+        return Ok(());
+    }
+    let mut properties = ServerProperties::load(&cfg.directory).await?;
+    properties.set("server-port", &cfg.port.to_string());
+    properties.save(&cfg.directory).await
+}
@@ -0,0 +1,164 @@
+//! Dry-run checks on a server config before it is saved: the same
+//! validation create and update apply, plus whether the server could
+//! actually start on this host right now.
+
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{
+    config::{validate_server_config, ServerConfig, ServerFlavor},
+    ports, process, proxy, resources, runtimes, schema,
+    state::AppState,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Create or update would reject the config, or the server can't start.
+    Error,
+    /// Accepted, but starting may fail or needs attention first.
+    Warning,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct Finding {
+    pub severity: Severity,
+    /// Which check raised it: config, conflict, jar, java, port or memory.
+    pub check: &'static str,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct ValidationReport {
+    /// True when there are no errors; warnings don't count.
+    pub valid: bool,
+    pub findings: Vec<Finding>,
+}
+
+#[derive(Default)]
+struct Findings(Vec<Finding>);
+
+impl Findings {
+    fn error(&mut self, check: &'static str, message: impl Into<String>) {
+        self.0.push(Finding {
+            severity: Severity::Error,
+            check,
+            message: message.into(),
+        });
+    }
+
+    fn warning(&mut self, check: &'static str, message: impl Into<String>) {
+        self.0.push(Finding {
+            severity: Severity::Warning,
+            check,
+            message: message.into(),
+        });
+    }
+}
+
+fn jar_exists(cfg: &ServerConfig) -> bool {
+    Path::new(&cfg.directory).join(&cfg.jar).is_file()
+}
+
+async fn check_jar(cfg: &ServerConfig, findings: &mut Findings) {
+    if cfg.command.is_some() || jar_exists(cfg) {
+        return;
+    }
+    let forge = cfg.flavor() == ServerFlavor::Forge;
+    if forge && process::forge_args_file(&cfg.directory).await.is_some() {
+        return;
+    }
+    findings.error("jar", format!("'{}' was not found in '{}'", cfg.jar, cfg.directory));
+}
+
+async fn check_java(state: &AppState, cfg: &ServerConfig, findings: &mut Findings) {
+    // Custom commands and container images bring their own java
+    if cfg.command.is_some() || cfg.docker.is_some() {
+        return;
+    }
+    let data_directory = state.config.read().await.agent.data_directory.clone();
+    match runtimes::select_java(cfg, &data_directory).await {
+        Ok(java) if java.java_version.is_none() && cfg.java_version.is_none() => {
+            findings.error("java", format!("'{}' could not be run", java.java_path));
+        }
+        Ok(java) if java.minecraft_version.is_none() && jar_exists(cfg) => findings.warning(
+            "java",
+            format!(
+                "The Minecraft version of '{}' is unknown, so {} was not checked against it",
+                cfg.jar, java.java_path
+            ),
+        ),
+        Ok(_) => {}
+        Err(e) => findings.error("java", e),
+    }
+}
+
+async fn check_memory(state: &AppState, cfg: &ServerConfig, findings: &mut Findings) {
+    let node = resources::node_resources(state).await;
+    let memory_mb = cfg.memory_mb as u64;
+    let usable_mb = node.total_memory_mb.saturating_sub(node.reserved_memory_mb);
+    if memory_mb > usable_mb {
+        findings.error(
+            "memory",
+            format!(
+                "Needs {} MB but this node has only {} MB outside its reserve",
+                memory_mb, usable_mb
+            ),
+        );
+    } else if !state.servers.contains_key(&cfg.id) && memory_mb > node.available_memory_mb {
+        findings.warning(
+            "memory",
+            format!(
+                "Needs {} MB but only {} MB is free while the running servers are up",
+                memory_mb, node.available_memory_mb
+            ),
+        );
+    }
+}
+
+/// Runs every check without saving or starting anything. A config with the
+/// id of an existing server is checked as an update of it.
+pub async fn validate(state: &AppState, cfg: &ServerConfig) -> ValidationReport {
+    let mut findings = Findings::default();
+    let mut candidate = state.config.read().await.clone();
+
+    // Port 0 is checked with the port create would hand out
+    let mut cfg = cfg.clone();
+    if cfg.port == 0 {
+        match ports::allocate(&candidate).await {
+            Ok(port) => cfg.port = port,
+            Err(e) => findings.error("port", e),
+        }
+    }
+    let cfg = &cfg;
+
+    if let Err(e) = validate_server_config(cfg) {
+        findings.error("config", e);
+    }
+    if let Err(e) = proxy::check_backends(&candidate, cfg) {
+        findings.error("config", e);
+    }
+    match candidate.servers.iter_mut().find(|s| s.id == cfg.id) {
+        Some(existing) => *existing = cfg.clone(),
+        None => candidate.servers.push(cfg.clone()),
+    }
+    for conflict in schema::conflicts(&candidate) {
+        findings.error("conflict", conflict);
+    }
+
+    check_jar(cfg, &mut findings).await;
+    check_java(state, cfg, &mut findings).await;
+    // A running server holds its own ports
+    if cfg.port != 0 && !state.servers.contains_key(&cfg.id) {
+        if let Err(e) = ports::check_available(cfg).await {
+            findings.warning("port", e);
+        }
+    }
+    check_memory(state, cfg, &mut findings).await;
+
+    ValidationReport {
+        valid: findings.0.iter().all(|f| f.severity != Severity::Error),
+        findings: findings.0,
+    }
+}
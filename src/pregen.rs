@@ -0,0 +1,413 @@
+//! World pregeneration with the Chunky plugin, driven through the console.
+//! A job's progress is read from the lines Chunky logs while it works and
+//! kept in `AppState::pregen_jobs` until the next job on that server. Jobs
+//! live only as long as the agent; one interrupted by a stop is picked up
+//! again in game with `chunky continue`.
+
+use std::{
+    path::Path,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+
+use crate::{
+    config::{ServerConfig, ServerFlavor},
+    console::{ConsoleLine, INFO_PREFIX},
+    downloads,
+    process::command_with_response,
+    state::{AgentEvent, AppState, ServerInstance},
+};
+
+const HANGAR_VERSIONS: &str = concat!(
+    "https://hangar.papermc.io/api/v1/projects/Chunky/versions",
+    "?limit=1&channel=Release&platform=PAPER"
+);
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_RADIUS: u32 = 100_000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PregenShape {
+    #[default]
+    Square,
+    Circle,
+}
+
+impl PregenShape {
+    fn as_str(self) -> &'static str {
+        match self {
+            PregenShape::Square => "square",
+            PregenShape::Circle => "circle",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PregenStatus {
+    Running,
+    Finished,
+    Cancelled,
+    /// Paused in game or stopped with the server; `chunky continue` resumes it.
+    Interrupted,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct PregenJob {
+    pub world: String,
+    pub shape: PregenShape,
+    pub center_x: i64,
+    pub center_z: i64,
+    /// In blocks.
+    pub radius: u32,
+    pub status: PregenStatus,
+    pub started_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_ms: Option<u64>,
+    pub chunks_processed: u64,
+    pub percent: f64,
+    /// As Chunky reports it, e.g. `0:12:34`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eta: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunks_per_second: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize, utoipa::ToSchema)]
+pub struct PregenRequest {
+    /// Defaults to the server's `level-name`.
+    #[serde(default)]
+    pub world: Option<String>,
+    #[serde(default)]
+    pub shape: PregenShape,
+    #[serde(default)]
+    pub center_x: i64,
+    #[serde(default)]
+    pub center_z: i64,
+    /// In blocks.
+    pub radius: u32,
+}
+
+#[derive(Deserialize)]
+struct HangarVersions {
+    result: Vec<HangarVersion>,
+}
+
+#[derive(Deserialize)]
+struct HangarVersion {
+    name: String,
+    downloads: std::collections::HashMap<String, HangarDownload>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HangarDownload {
+    file_info: HangarFile,
+    download_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HangarFile {
+    name: String,
+    sha256_hash: String,
+}
+
+/// Whether a Chunky jar sits in the server's plugins or mods.
+pub async fn installed(cfg: &ServerConfig) -> bool {
+    let Some(content_dir) = cfg.flavor().content_dir() else {
+        return false;
+    };
+    let Ok(mut entries) = tokio::fs::read_dir(Path::new(&cfg.directory).join(content_dir)).await
+    else {
+        return false;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().to_lowercase();
+        if name.starts_with("chunky") && name.ends_with(".jar") {
+            return true;
+        }
+    }
+    false
+}
+
+/// Installs the latest Chunky release from Hangar. Only Paper servers;
+/// the mod builds have to be put in `mods` by hand. Loads on the server's
+/// next start.
+pub async fn install(data_directory: &str, cfg: &ServerConfig) -> Result<String, String> {
+    if cfg.flavor() != ServerFlavor::Paper {
+        return Err(format!(
+            "Chunky can only be installed on Paper servers; put it in the mods of '{}' by hand",
+            cfg.id
+        ));
+    }
+    let body = downloads::fetch_text(HANGAR_VERSIONS).await?;
+    let versions: HangarVersions =
+        serde_json::from_str(&body).map_err(|e| format!("Failed to parse Chunky versions: {}", e))?;
+    let version = versions.result.into_iter().next().ok_or("Chunky has no release")?;
+    let download = version
+        .downloads
+        .get("PAPER")
+        .ok_or_else(|| format!("Chunky {} has no Paper download", version.name))?;
+    let url = download
+        .download_url
+        .as_deref()
+        .ok_or_else(|| format!("Chunky {} is not hosted on Hangar", version.name))?;
+    let dest = Path::new(&cfg.directory).join("plugins").join(&download.file_info.name);
+    downloads::install_cached(data_directory, url, &download.file_info.sha256_hash, &dest).await?;
+    tracing::info!("Installed Chunky {} on '{}'", version.name, cfg.id);
+    Ok(version.name)
+}
+
+fn reply_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r"\[Chunky\] .*|Unknown or incomplete command|Unknown command")
+            .expect("valid regex")
+    })
+}
+
+fn progress_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        // Anchored on the log prefix so chat can't fake progress
+        Regex::new(&format!(
+            concat!(
+                r"{}\[Chunky\] Task (running|finished) for (\S+)\. ",
+                r"Processed: ([\d,]+) chunks \(([\d.]+)%\)",
+                r"(?:, ETA: ([\d:]+))?(?:, Rate: ([\d.]+) cps)?",
+            ),
+            INFO_PREFIX
+        ))
+        .expect("valid regex")
+    })
+}
+
+fn ended_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(&format!(
+            r"{}\[Chunky\] Task (cancelled|paused|stopped) for (\S+)\.",
+            INFO_PREFIX
+        ))
+        .expect("valid regex")
+    })
+}
+
+fn now_ms() -> u64 {
+    chrono::Utc::now().timestamp_millis() as u64
+}
+
+fn end(job: &mut PregenJob, status: PregenStatus) {
+    job.status = status;
+    job.finished_ms = Some(now_ms());
+    job.eta = None;
+    job.chunks_per_second = None;
+}
+
+/// Folds one console line into `job`; true once the job is over.
+fn update(job: &mut PregenJob, text: &str) -> bool {
+    if let Some(caps) = progress_regex().captures(text) {
+        if caps[2] != job.world {
+            return false;
+        }
+        job.chunks_processed = caps[3].replace(',', "").parse().unwrap_or(job.chunks_processed);
+        job.percent = caps[4].parse().unwrap_or(job.percent);
+        job.eta = caps.get(5).map(|m| m.as_str().to_string());
+        job.chunks_per_second = caps.get(6).and_then(|m| m.as_str().parse().ok());
+        if &caps[1] == "finished" {
+            job.status = PregenStatus::Finished;
+            job.finished_ms = Some(now_ms());
+            return true;
+        }
+        return false;
+    }
+    match ended_regex().captures(text) {
+        Some(caps) if caps[2] == job.world => {
+            let status = if &caps[1] == "cancelled" {
+                PregenStatus::Cancelled
+            } else {
+                PregenStatus::Interrupted
+            };
+            end(job, status);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Follows Chunky's progress for the server's job until it ends or the
+/// server exits, announcing a finished job as an agent event.
+fn spawn_watcher(
+    state: AppState,
+    server_id: String,
+    instance: Arc<ServerInstance>,
+    mut console_rx: Receiver<ConsoleLine>,
+) {
+    let mut exited_rx = instance.exited.subscribe();
+    tokio::spawn(async move {
+        loop {
+            let line = tokio::select! {
+                msg = console_rx.recv() => match msg {
+                    Ok(line) => line,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+                _ = exited_rx.wait_for(|exited| *exited) => break,
+            };
+            let text = crate::ansi::strip(&line.text);
+            let Some(mut job) = state.pregen_jobs.get_mut(&server_id) else {
+                return;
+            };
+            if job.status != PregenStatus::Running {
+                return;
+            }
+            if !update(&mut job, &text) {
+                continue;
+            }
+            if job.status == PregenStatus::Finished {
+                tracing::info!("Pregeneration of '{}' on '{}' finished", job.world, server_id);
+                let job = job.clone();
+                let _ = state.events_tx.send(AgentEvent::PregenerationFinished {
+                    server: server_id.clone(),
+                    job,
+                });
+            }
+            return;
+        }
+        if let Some(mut job) = state.pregen_jobs.get_mut(&server_id) {
+            if job.status == PregenStatus::Running {
+                end(&mut job, PregenStatus::Interrupted);
+            }
+        }
+    });
+}
+
+fn valid_world(world: &str) -> bool {
+    !world.is_empty() && !world.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+/// Starts a Chunky task on a running server, replacing its last job.
+pub async fn start(
+    state: &AppState,
+    cfg: &ServerConfig,
+    request: PregenRequest,
+) -> Result<PregenJob, String> {
+    if request.radius == 0 || request.radius > MAX_RADIUS {
+        return Err(format!("radius must be between 1 and {}", MAX_RADIUS));
+    }
+    let world = match request.world {
+        Some(world) => world,
+        None => crate::properties::ServerProperties::load(&cfg.directory)
+            .await?
+            .level_name()
+            .to_string(),
+    };
+    if !valid_world(&world) {
+        return Err(format!("'{}' is not a valid world name", world));
+    }
+    let running = |id: &str| {
+        state.pregen_jobs.get(id).is_some_and(|j| j.status == PregenStatus::Running)
+    };
+    if running(&cfg.id) {
+        return Err(format!("'{}' is already pregenerating", cfg.id));
+    }
+    let instance = state
+        .servers
+        .get(&cfg.id)
+        .map(|r| r.value().clone())
+        .ok_or_else(|| format!("Server '{}' is not running", cfg.id))?;
+
+    // Subscribed first so the watcher sees the task's earliest lines
+    let console_rx = instance.console_tx.subscribe();
+    let command = format!(
+        "chunky start {} {} {} {} {}",
+        world,
+        request.shape.as_str(),
+        request.center_x,
+        request.center_z,
+        request.radius
+    );
+    let reply = command_with_response(state, &cfg.id, &command, reply_regex(), RESPONSE_TIMEOUT)
+        .await?;
+    let reply = crate::ansi::strip(&reply);
+    if !reply.contains("Task started") {
+        if reply.contains("Unknown") {
+            return Err(format!("Chunky is not loaded on '{}'; restart it first", cfg.id));
+        }
+        return Err(format!("Chunky refused the task: {}", reply));
+    }
+
+    let job = PregenJob {
+        world,
+        shape: request.shape,
+        center_x: request.center_x,
+        center_z: request.center_z,
+        radius: request.radius,
+        status: PregenStatus::Running,
+        started_ms: now_ms(),
+        finished_ms: None,
+        chunks_processed: 0,
+        percent: 0.0,
+        eta: None,
+        chunks_per_second: None,
+    };
+    tracing::info!("Started pregenerating '{}' on '{}'", job.world, cfg.id);
+    state.pregen_jobs.insert(cfg.id.clone(), job.clone());
+    spawn_watcher(state.clone(), cfg.id.clone(), instance, console_rx);
+    Ok(job)
+}
+
+/// Cancels the server's running job.
+pub async fn cancel(state: &AppState, server_id: &str) -> Result<PregenJob, String> {
+    let world = match state.pregen_jobs.get(server_id) {
+        Some(job) if job.status == PregenStatus::Running => job.world.clone(),
+        _ => return Err(format!("'{}' is not pregenerating", server_id)),
+    };
+    // Chunky asks every cancel to be confirmed
+    let command = format!("chunky cancel {}", world);
+    command_with_response(state, server_id, &command, reply_regex(), RESPONSE_TIMEOUT).await?;
+    let reply =
+        command_with_response(state, server_id, "chunky confirm", reply_regex(), RESPONSE_TIMEOUT)
+            .await?;
+    let reply = crate::ansi::strip(&reply);
+    let mut job = state
+        .pregen_jobs
+        .get_mut(server_id)
+        .ok_or_else(|| format!("'{}' is not pregenerating", server_id))?;
+    update(&mut job, &reply);
+    if job.status == PregenStatus::Running {
+        return Err(format!("Chunky did not cancel the task: {}", reply));
+    }
+    Ok(job.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_lines_are_read_from_server_output() {
+        let line = "[12:00:00 INFO]: [Chunky] Task running for world. Processed: 1,024 chunks \
+                    (12.50%), ETA: 0:05:00, Rate: 80.5 cps";
+        let caps = progress_regex().captures(line).unwrap();
+        assert_eq!(&caps[2], "world");
+        assert_eq!(&caps[3], "1,024");
+        assert_eq!(&caps[5], "0:05:00");
+        let line = "[12:00:00] [Server thread/INFO]: [Chunky] Task cancelled for world.";
+        assert_eq!(&ended_regex().captures(line).unwrap()[1], "cancelled");
+    }
+
+    #[test]
+    fn chat_cannot_fake_progress_or_an_end() {
+        let chat = "[12:00:00 INFO]: <Steve> [Chunky] Task finished for world. Processed: 9 \
+                    chunks (100.00%)";
+        assert!(progress_regex().captures(chat).is_none());
+        let chat = "[12:00:00] [Server thread/INFO]: <Steve> ]: [Chunky] Task cancelled for world.";
+        assert!(ended_regex().captures(chat).is_none());
+    }
+}
@@ -1,59 +1,245 @@
-use crate::config::validate_server_config;
-use crate::state::{AppState, Metrics, ServerInstance};
+use crate::config::{
+    validate_server_config, OrphanPolicy, RestartMode, ServerConfig, ServerFlavor, StopStep,
+};
+use crate::console::{ConsoleBackend, ConsoleKind, ConsoleLine, ConsoleStream, Pipe};
+use crate::hooks::{self, Hook};
+use crate::pidfile::{self, ProcessIdentity};
+use crate::state::{
+    AgentEvent, AppState, ExitInfo, GcStats, ServerInstance, ServerPhase,
+};
+use crate::ticks::TickStats;
+use regex::Regex;
 use std::collections::VecDeque;
-use std::sync::Arc;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
 use std::time::{SystemTime, UNIX_EPOCH};
-use sysinfo::{Pid, System};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::sync::{broadcast, Mutex};
+use sysinfo::System;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::sync::{broadcast, watch, Mutex};
 
-pub async fn kill_orphaned_servers(config: &crate::config::Config) {
+/// Deals with server processes a crashed agent left behind as each
+/// server's `orphan_policy` says. Servers that have been reattached are
+/// left alone.
+pub async fn handle_orphaned_servers(state: &AppState) {
+    let config = state.config.read().await.clone();
     let sys = System::new_all();
+    let mut killed = false;
     for server in &config.servers {
-        for (pid, process) in sys.processes() {
-            let cmd = process.cmd();
-            if cmd.iter().any(|c| c.contains("java")) && cmd.iter().any(|c| c.contains(&server.jar)) {
-                if let Some(cwd) = process.cwd() {
-                    if cwd.to_string_lossy() == server.directory {
-                        tracing::warn!("Found orphaned server '{}' (PID {}), killing it...", server.id, pid);
-                        process.kill();
+        if state.servers.contains_key(&server.id) {
+            continue;
+        }
+        // The PID file names the process exactly. Agents before it left
+        // none, so otherwise look for the server's jar running in its
+        // directory.
+        let orphans = match pidfile::running(&config.agent.data_directory, &server.id).await {
+            Some(pid_file) => vec![(pid_file.process, pid_file.started_at_ms)],
+            None => sys
+                .processes()
+                .iter()
+                .filter(|(_, process)| is_server_process(server, process))
+                .map(|(pid, process)| {
+                    let identity = ProcessIdentity::from_process(pid.as_u32(), process);
+                    (identity, process.start_time() * 1000)
+                })
+                .collect(),
+        };
+        for (i, (identity, started_at_ms)) in orphans.into_iter().enumerate() {
+            let pid = identity.pid;
+            match server.orphan_policy {
+                OrphanPolicy::Adopt if i == 0 => {
+                    let orphan = crate::adopt::adopt(server, identity).await;
+                    resume(state, server, orphan.into(), started_at_ms).await;
+                    tracing::warn!("Adopted orphaned server '{}' (PID {})", server.id, pid);
+                }
+                OrphanPolicy::Adopt | OrphanPolicy::Ignore => {
+                    tracing::warn!("Leaving orphaned server '{}' (PID {}) alone", server.id, pid);
+                }
+                OrphanPolicy::Kill => {
+                    tracing::warn!(
+                        "Found orphaned server '{}' (PID {}), killing it...",
+                        server.id,
+                        pid
+                    );
+                    if let Some(process) = sys.process(sysinfo::Pid::from_u32(pid)) {
+                        killed |= process.kill();
                     }
                 }
             }
         }
     }
-    // Give them a moment to exit
-    tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+    if killed {
+        // Give them a moment to exit
+        tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+    }
 }
 
-pub async fn start_server(state: AppState, server_id: &str) -> Result<(), String> {
-    if state.servers.contains_key(server_id) {
-        return Err(format!("Server '{}' is already running", server_id));
+fn is_server_process(server: &ServerConfig, process: &sysinfo::Process) -> bool {
+    let cmd = process.cmd();
+    cmd.iter().any(|c| c.contains("java"))
+        && cmd.iter().any(|c| c.contains(&server.jar))
+        && process.cwd().is_some_and(|cwd| cwd.to_string_lossy() == server.directory)
+}
+
+/// A running server's process: a direct child, a container, a detached
+/// process that outlives the agent, or one adopted from a crashed agent.
+pub enum ServerProcess {
+    Child(tokio::process::Child),
+    Container(crate::docker::Container),
+    Adopted(crate::adopt::AdoptedProcess),
+    #[cfg(unix)]
+    Detached(crate::detach::DetachedProcess),
+}
+
+impl ServerProcess {
+    pub async fn wait(&mut self) -> std::io::Result<std::process::ExitStatus> {
+        match self {
+            ServerProcess::Child(child) => child.wait().await,
+            ServerProcess::Container(container) => container.wait().await,
+            ServerProcess::Adopted(adopted) => adopted.wait().await,
+            #[cfg(unix)]
+            ServerProcess::Detached(detached) => detached.wait().await,
+        }
     }
 
-    let server_cfg = {
-        let config = state.config.read().await;
-        config
-            .servers
-            .iter()
-            .find(|s| s.id == server_id)
-            .cloned()
-            .ok_or_else(|| format!("Server '{}' not found in config", server_id))?
+    pub async fn try_wait(&mut self) -> std::io::Result<Option<std::process::ExitStatus>> {
+        match self {
+            ServerProcess::Child(child) => child.try_wait(),
+            ServerProcess::Container(container) => container.try_wait().await,
+            ServerProcess::Adopted(adopted) => Ok(adopted.try_wait()),
+            #[cfg(unix)]
+            ServerProcess::Detached(detached) => Ok(detached.try_wait()),
+        }
+    }
+
+    pub async fn kill(&mut self) -> std::io::Result<()> {
+        match self {
+            ServerProcess::Child(child) => child.kill().await,
+            ServerProcess::Container(container) => container.kill().await,
+            ServerProcess::Adopted(adopted) => adopted.kill(),
+            #[cfg(unix)]
+            ServerProcess::Detached(detached) => detached.kill(),
+        }
+    }
+
+    /// Cleans up after the process has exited and its status was read.
+    pub async fn release(&mut self) {
+        if let ServerProcess::Container(container) = self {
+            container.remove().await;
+        }
+    }
+
+    /// Whether the server's PID still refers to it, so it can be signalled.
+    /// A child's PID stays reserved until it has been waited for; other
+    /// processes are checked against what they were when spawned or found.
+    pub async fn owns_pid(&mut self) -> bool {
+        match self {
+            ServerProcess::Child(child) => matches!(child.try_wait(), Ok(None)),
+            ServerProcess::Container(container) => {
+                matches!(container.try_wait().await, Ok(None))
+            }
+            ServerProcess::Adopted(adopted) => adopted.owns_pid(),
+            #[cfg(unix)]
+            ServerProcess::Detached(detached) => detached.owns_pid(),
+        }
+    }
+
+    /// Whether the process is meant to keep running when the agent exits.
+    pub fn is_detached(&self) -> bool {
+        #[cfg(unix)]
+        if let ServerProcess::Detached(_) = self {
+            return true;
+        }
+        false
+    }
+}
+
+struct Spawned {
+    process: ServerProcess,
+    pid: u32,
+    console: Box<dyn ConsoleBackend>,
+}
+
+/// Program and arguments the server is launched with.
+async fn launch_command(
+    state: &AppState,
+    server_cfg: &ServerConfig,
+) -> Result<Vec<String>, String> {
+    if let Some(ref command) = server_cfg.command {
+        return Ok(command.clone());
+    }
+    let (java_path, java_version) = if server_cfg.docker.is_some() {
+        // The image provides java; only an explicit java_version is known
+        ("java".to_string(), server_cfg.java_version)
+    } else {
+        let data_directory = state.config.read().await.agent.data_directory.clone();
+        let java = crate::runtimes::select_java(server_cfg, &data_directory).await?;
+        (java.java_path, java.java_version)
     };
 
-    validate_server_config(&server_cfg).map_err(|e| format!("Invalid config: {}", e))?;
+    let mut argv = vec![java_path];
+    if server_cfg.gc_metrics && java_version.is_some_and(|v| v >= 9) {
+        argv.push(crate::jvm::GC_LOG_ARG.to_string());
+    }
+    argv.extend(crate::jvm::jvm_arguments(server_cfg));
+    match server_cfg.flavor() {
+        // Proxies have no GUI to turn off
+        ServerFlavor::Proxy => argv.extend(["-jar".to_string(), server_cfg.jar.clone()]),
+        ServerFlavor::Forge if !Path::new(&server_cfg.directory).join(&server_cfg.jar).exists() => {
+            let args_file = forge_args_file(&server_cfg.directory).await.ok_or_else(|| {
+                format!("Neither '{}' nor a Forge installation was found", server_cfg.jar)
+            })?;
+            argv.extend([format!("@{}", args_file), "nogui".to_string()]);
+        }
+        _ => argv.extend(["-jar".to_string(), server_cfg.jar.clone(), "nogui".to_string()]),
+    }
+    Ok(argv)
+}
 
-    let mut cmd = tokio::process::Command::new("java");
-    cmd.arg(format!("-Xmx{}M", server_cfg.memory_mb))
-        .arg("-jar")
-        .arg(&server_cfg.jar)
-        .arg("nogui")
+/// Forge and NeoForge 1.17+ install no server jar; they are launched with
+/// the argument file their installer leaves under `libraries/`.
+pub async fn forge_args_file(directory: &str) -> Option<String> {
+    let file = if cfg!(windows) { "win_args.txt" } else { "unix_args.txt" };
+    for loader in ["net/minecraftforge/forge", "net/neoforged/neoforge"] {
+        let dir = Path::new("libraries").join(loader);
+        let Ok(mut versions) = tokio::fs::read_dir(Path::new(directory).join(&dir)).await else {
+            continue;
+        };
+        let mut newest: Option<(Vec<u64>, String)> = None;
+        while let Ok(Some(entry)) = versions.next_entry().await {
+            let version = entry.file_name().to_string_lossy().into_owned();
+            let key = version_key(&version);
+            if entry.path().join(file).is_file() && newest.as_ref().is_none_or(|(n, _)| &key > n) {
+                newest = Some((key, version));
+            }
+        }
+        if let Some((_, version)) = newest {
+            return Some(dir.join(version).join(file).to_string_lossy().into_owned());
+        }
+    }
+    None
+}
+
+/// The numbers in a loader version such as `1.20.1-47.2.0`, so `47.10.0`
+/// sorts after `47.9.0`.
+fn version_key(version: &str) -> Vec<u64> {
+    version
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|part| part.parse().ok())
+        .collect()
+}
+
+fn spawn_child(server_cfg: &ServerConfig, argv: Vec<String>) -> Result<Spawned, String> {
+    let mut cmd = tokio::process::Command::new(&argv[0]);
+    cmd.args(&argv[1..])
         .current_dir(&server_cfg.directory)
         .stdin(std::process::Stdio::piped())
         .stdout(std::process::Stdio::piped())
         .stderr(std::process::Stdio::piped());
-
-    let mut child = cmd.spawn().map_err(|e| format!("Failed to spawn java: {}", e))?;
+    crate::platform::configure_command(&mut cmd);
+    let mut child = cmd
+        .spawn()
+        .map_err(|e| format!("Failed to spawn server process: {}", e))?;
+    crate::platform::adopt_child(&child);
 
     let pid = child.id().ok_or("Failed to get child PID")?;
     let stdin = child
@@ -68,204 +254,746 @@ pub async fn start_server(state: AppState, server_id: &str) -> Result<(), String
         .stderr
         .take()
         .ok_or("Failed to get child stderr")?;
+    Ok(Spawned {
+        process: ServerProcess::Child(child),
+        pid,
+        console: Box::new(Pipe::new(stdin, stdout, stderr)),
+    })
+}
+
+#[cfg(unix)]
+impl From<crate::detach::Detached> for Spawned {
+    fn from(detached: crate::detach::Detached) -> Self {
+        Spawned {
+            pid: detached.process.pid,
+            process: ServerProcess::Detached(detached.process),
+            console: Box::new(detached.console),
+        }
+    }
+}
+
+impl From<crate::adopt::Adopted> for Spawned {
+    fn from(adopted: crate::adopt::Adopted) -> Self {
+        Spawned {
+            pid: adopted.process.pid,
+            process: ServerProcess::Adopted(adopted.process),
+            console: Box::new(adopted.console),
+        }
+    }
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn start_server(state: AppState, server_id: &str) -> Result<(), String> {
+    if state.servers.contains_key(server_id) {
+        return Err(format!("Server '{}' is already running", server_id));
+    }
+    crate::maintenance::check(&state).await?;
+
+    let server_cfg = {
+        let config = state.config.read().await;
+        config
+            .servers
+            .iter()
+            .find(|s| s.id == server_id)
+            .cloned()
+            .ok_or_else(|| format!("Server '{}' not found in config", server_id))?
+    };
+
+    validate_server_config(&server_cfg).map_err(|e| format!("Invalid config: {}", e))?;
+    crate::resources::check_admission(&state, &server_cfg).await?;
+    if server_cfg.proxy.is_some() {
+        let config = state.config.read().await.clone();
+        crate::proxy::prepare_start(&config, &server_cfg).await?;
+    }
+
+    let argv = launch_command(&state, &server_cfg).await?;
+    hooks::run(&server_cfg, Hook::PreStart, None, &[]).await?;
+
+    // Held until the instance is registered so the wake supervisor can't
+    // grab the port back in between
+    let mut wake_listeners = state.wake_listeners.lock().await;
+    // Another start may have got here while the hook ran
+    if state.servers.contains_key(server_id) {
+        return Err(format!("Server '{}' is already running", server_id));
+    }
+    // Other starts may have taken the resources since; none can until this
+    // one is registered
+    crate::resources::check_admission(&state, &server_cfg).await?;
+    crate::wake::release(&mut wake_listeners, server_id).await;
+    crate::ports::check_available(&server_cfg).await?;
+
+    let agent = state.config.read().await.agent.clone();
+    let spawned = match server_cfg.docker {
+        Some(ref docker_cfg) => {
+            let memory_limit = crate::cgroups::memory_limit_bytes(
+                &server_cfg,
+                agent.cgroup_memory_overhead_percent,
+            );
+            let spawned =
+                crate::docker::spawn(&server_cfg, docker_cfg, argv, memory_limit).await?;
+            Spawned {
+                process: ServerProcess::Container(spawned.container),
+                pid: spawned.pid,
+                console: Box::new(spawned.console),
+            }
+        }
+        #[cfg(unix)]
+        None if agent.detach_servers => {
+            crate::detach::spawn(&agent.data_directory, &server_cfg, argv).await?.into()
+        }
+        None => {
+            let spawned = spawn_child(&server_cfg, argv)?;
+            let started_at_ms = chrono::Utc::now().timestamp_millis() as u64;
+            let written =
+                pidfile::write(&agent.data_directory, server_id, spawned.pid, started_at_ms).await;
+            if let Err(e) = written {
+                tracing::warn!("No PID file for '{}': {}", server_id, e);
+            }
+            spawned
+        }
+    };
+    let pid = spawned.pid;
+    let started_at = std::time::Instant::now();
+    attach(&state, &server_cfg, spawned, ServerPhase::Starting, started_at).await;
+    drop(wake_listeners);
+
+    // Containers get their limits from the container runtime
+    if server_cfg.docker.is_none() {
+        crate::cgroups::apply(
+            agent.cgroup_root.as_deref(),
+            &server_cfg,
+            agent.cgroup_memory_overhead_percent,
+            pid,
+        )
+        .await;
+    }
+    crate::shaping::apply(&agent, &server_cfg).await;
+    if server_cfg.port_forward {
+        let (state, server_cfg) = (state.clone(), server_cfg.clone());
+        tokio::spawn(async move { crate::portmap::map(&state, &server_cfg).await });
+    }
+
+    tracing::info!("Started server '{}' with PID {}", server_id, pid);
+    Ok(())
+}
 
+/// Registers a spawned or reattached process as the server's running
+/// instance and starts everything that follows its console.
+async fn attach(
+    state: &AppState,
+    server_cfg: &ServerConfig,
+    mut spawned: Spawned,
+    phase: ServerPhase,
+    started_at: std::time::Instant,
+) {
+    let server_id = &server_cfg.id;
     let (metrics_tx, _) = broadcast::channel(64);
     let (console_tx, _) = broadcast::channel(256);
 
+    let output = spawned.console.take_output();
+    let agent = state.config.read().await.agent.clone();
     let instance = Arc::new(ServerInstance {
-        pid,
-        child: Mutex::new(child),
-        stdin: Mutex::new(stdin),
-        metrics_tx: metrics_tx.clone(),
-        console_tx: console_tx.clone(),
-        started_at: std::time::Instant::now(),
-        console_buffer: Mutex::new(VecDeque::new()),
+        pid: spawned.pid,
+        child: Mutex::new(spawned.process),
+        console: Mutex::new(spawned.console),
+        metrics_tx,
+        console_tx,
+        started_at,
+        console_buffer: Mutex::new(VecDeque::with_capacity(server_cfg.console_buffer_lines)),
+        console_buffer_lines: server_cfg.console_buffer_lines,
+        console_replay_lines: server_cfg.console_replay_lines,
+        pending_prompt: Mutex::new(None),
+        exited: watch::channel(false).0,
+        gc_stats: Mutex::new(GcStats::default()),
+        tick_stats: Mutex::new(TickStats::default()),
+        phase: Mutex::new(phase),
+        last_output: Mutex::new(std::time::Instant::now()),
+        redactor: crate::redact::Redactor::load(server_cfg).await,
+        console_log: crate::consolelog::ConsoleLog::open(
+            &agent.data_directory,
+            server_id,
+            agent.console_log_max_mb.saturating_mul(1024 * 1024),
+            agent.console_log_files,
+        ),
+        players: Mutex::new(std::collections::HashMap::new()),
     });
 
+    state.last_exits.remove(server_id);
     state.servers.insert(server_id.to_string(), instance.clone());
 
-    // Spawn console reader for stdout
-    {
-        let state2 = state.clone();
-        let sid = server_id.to_string();
-        let console_tx2 = console_tx.clone();
-        let instance2 = instance.clone();
-        tokio::spawn(async move {
-            let mut reader = BufReader::new(stdout).lines();
-            while let Ok(Some(line)) = reader.next_line().await {
-                let _ = console_tx2.send(line.clone());
-                let mut buf = instance2.console_buffer.lock().await;
-                buf.push_back(line);
-                if buf.len() > 500 {
-                    buf.pop_front();
-                }
-            }
-            on_process_exit(&state2, &sid).await;
-        });
-    }
-
-    // Spawn console reader for stderr
-    {
-        let state2 = state.clone();
-        let sid = server_id.to_string();
-        let console_tx3 = console_tx.clone();
-        let instance3 = instance.clone();
-        tokio::spawn(async move {
-            let mut reader = BufReader::new(stderr).lines();
-            while let Ok(Some(line)) = reader.next_line().await {
-                let _ = console_tx3.send(line.clone());
-                let mut buf = instance3.console_buffer.lock().await;
-                buf.push_back(line);
-                if buf.len() > 500 {
-                    buf.pop_front();
-                }
-            }
-            on_process_exit(&state2, &sid).await;
-        });
-    }
-
-    // Spawn metrics sampler
-    {
-        let state2 = state.clone();
-        let sid = server_id.to_string();
-        let metrics_tx2 = metrics_tx.clone();
-        tokio::spawn(async move {
-            let mut sys = System::new();
-            loop {
-                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-                let alive = sys.refresh_process(Pid::from_u32(pid));
-                if !alive {
-                    on_process_exit(&state2, &sid).await;
+    for (stream, output) in output {
+        spawn_console_reader(
+            state.clone(),
+            server_id.to_string(),
+            instance.clone(),
+            stream,
+            output,
+        );
+    }
+    crate::ingame::spawn_listener(
+        state.clone(),
+        server_id.to_string(),
+        instance.clone(),
+        server_cfg.panel_operators.clone(),
+    );
+    crate::playtime::spawn_tracker(&agent.data_directory, server_id, instance.clone());
+    crate::triggers::spawn_listener(
+        state.clone(),
+        server_id.to_string(),
+        instance.clone(),
+        server_cfg.triggers.clone(),
+    );
+    crate::discord::spawn_bridge(
+        state.clone(),
+        server_id.to_string(),
+        instance.clone(),
+        server_cfg.discord.clone(),
+    );
+    crate::ticks::spawn_sampler(
+        state.clone(),
+        server_id.to_string(),
+        instance,
+        server_cfg.tps_commands.clone(),
+        server_cfg.tps_sample_secs,
+    );
+}
+
+/// Takes back the detached servers a previous agent left running. Their
+/// cgroups and traffic shaping are still in place; only port forwarding is
+/// renewed.
+#[cfg(unix)]
+pub async fn reattach_servers(state: &AppState) {
+    let (data_directory, servers) = {
+        let config = state.config.read().await;
+        (config.agent.data_directory.clone(), config.servers.clone())
+    };
+    for server_cfg in servers {
+        let Some(detached) = crate::detach::reattach(&data_directory, &server_cfg.id).await else {
+            continue;
+        };
+        let (pid, started_at_ms) = (detached.process.pid, detached.started_at_ms);
+        resume(state, &server_cfg, detached.into(), started_at_ms).await;
+        tracing::info!("Reattached server '{}' with PID {}", server_cfg.id, pid);
+    }
+}
+
+/// Attaches a server that was already running when the agent started.
+async fn resume(state: &AppState, server_cfg: &ServerConfig, spawned: Spawned, started_at_ms: u64) {
+    let age_ms = (chrono::Utc::now().timestamp_millis() as u64).saturating_sub(started_at_ms);
+    let started_at = std::time::Instant::now()
+        .checked_sub(std::time::Duration::from_millis(age_ms))
+        .unwrap_or_else(std::time::Instant::now);
+    attach(state, server_cfg, spawned, ServerPhase::Running, started_at).await;
+    if server_cfg.port_forward {
+        let (state, server_cfg) = (state.clone(), server_cfg.clone());
+        tokio::spawn(async move { crate::portmap::map(&state, &server_cfg).await });
+    }
+}
+
+// How long a partial line may sit without a newline during startup before it
+// is treated as an installer or first-run prompt waiting on stdin.
+const PROMPT_IDLE: std::time::Duration = std::time::Duration::from_millis(750);
+
+fn spawn_console_reader<R>(
+    state: AppState,
+    server_id: String,
+    instance: Arc<ServerInstance>,
+    stream: ConsoleStream,
+    output: R,
+) where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut reader = BufReader::new(output);
+        let mut pending: Vec<u8> = Vec::new();
+        loop {
+            match tokio::time::timeout(PROMPT_IDLE, reader.read_until(b'\n', &mut pending)).await {
+                Ok(Ok(0)) => {
+                    if !pending.is_empty() {
+                        let line = String::from_utf8_lossy(&pending).into_owned();
+                        instance.push_console_line(line, stream).await;
+                    }
                     break;
                 }
-                if let Some(proc) = sys.process(Pid::from_u32(pid)) {
-                    let cpu = proc.cpu_usage();
-                    let mem = proc.memory();
-                    let ts = SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap_or_default()
-                        .as_millis() as u64;
-                    let m = Metrics {
-                        cpu_percent: cpu,
-                        memory_bytes: mem,
-                        timestamp_ms: ts,
-                    };
-                    let _ = metrics_tx2.send(m);
+                Ok(Ok(_)) => {
+                    if pending.last() != Some(&b'\n') {
+                        // EOF reached mid-line; the next read reports it
+                        continue;
+                    }
+                    let line = String::from_utf8_lossy(&pending)
+                        .trim_end_matches(['\r', '\n'])
+                        .to_string();
+                    pending.clear();
+                    *instance.pending_prompt.lock().await = None;
+                    if crate::jvm::record_gc_line(&line, &mut *instance.gc_stats.lock().await) {
+                        continue;
+                    }
+                    crate::ticks::record_tick_line(&line, &mut *instance.tick_stats.lock().await);
+                    {
+                        let mut phase = instance.phase.lock().await;
+                        if *phase == ServerPhase::Starting && ready_line_regex().is_match(&line) {
+                            *phase = ServerPhase::Running;
+                            tracing::info!("Server '{}' is ready", server_id);
+                            let (state, server_id) = (state.clone(), server_id.clone());
+                            let pid = instance.pid;
+                            tokio::spawn(async move {
+                                hooks::run_logged(&state, &server_id, Hook::PostStart, Some(pid))
+                                    .await;
+                            });
+                        }
+                    }
+                    instance.push_console_line(line, stream).await;
+                }
+                Ok(Err(_)) => break,
+                Err(_) => {
+                    // Output stalled without a newline: surface it as a prompt, but only
+                    // before startup finishes; later a stall is just slow progress output
+                    if pending.is_empty() || *instance.phase.lock().await != ServerPhase::Starting
+                    {
+                        continue;
+                    }
+                    let prompt = String::from_utf8_lossy(&pending).trim_end().to_string();
+                    let prompt = instance.redactor.redact(prompt);
+                    pending.clear();
+                    if prompt.is_empty() {
+                        continue;
+                    }
+                    tracing::info!("Server '{}' is waiting for input: {}", server_id, prompt);
+                    *instance.pending_prompt.lock().await = Some(prompt.clone());
+                    instance.push_console_line(prompt, stream).await;
                 }
             }
-        });
+        }
+        on_process_exit(&state, &server_id).await;
+    });
+}
+
+// "Done (3.456s)! For help, type "help"" marks the end of startup
+fn ready_line_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"Done \([0-9.,]+m?s\)!").expect("valid regex"))
+}
+
+fn exit_info(status: Option<std::process::ExitStatus>, requested: bool) -> ExitInfo {
+    let exit_code = status.and_then(|s| s.code());
+    let signal = status.and_then(crate::platform::exit_signal);
+    ExitInfo {
+        exit_code,
+        signal,
+        requested,
+        crashed: !requested && (signal.is_some() || exit_code.is_some_and(|c| c != 0)),
+        exited_at_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64,
+        oom_killed: false,
+        crash_report: None,
     }
+}
 
-    tracing::info!("Started server '{}' with PID {}", server_id, pid);
-    Ok(())
+/// Attaches any crash report, stores the exit and announces it.
+async fn record_exit(
+    state: &AppState,
+    server_id: &str,
+    instance: &ServerInstance,
+    mut exit: ExitInfo,
+) {
+    let (directory, cgroup_root) = {
+        let config = state.config.read().await;
+        (
+            config
+                .servers
+                .iter()
+                .find(|s| s.id == server_id)
+                .map(|s| s.directory.clone()),
+            config.agent.cgroup_root.clone(),
+        )
+    };
+    exit.oom_killed = crate::cgroups::oom_killed(cgroup_root.as_deref(), server_id).await;
+    if let Some(directory) = directory {
+        let started = SystemTime::now() - instance.started_at.elapsed();
+        exit.crash_report = crate::crash::find_report(&directory, instance.pid, started).await;
+    }
+    state.last_exits.insert(server_id.to_string(), exit.clone());
+    let _ = state.events_tx.send(AgentEvent::ServerExited {
+        server: server_id.to_string(),
+        exit,
+    });
+    // The hook may run for a minute; exit handling and restarts don't wait
+    let (state, server_id) = (state.clone(), server_id.to_string());
+    tokio::spawn(async move {
+        hooks::run_logged(&state, &server_id, Hook::PostStop, None).await;
+    });
+}
+
+/// Undoes host-level setup made for a server when it started.
+async fn release_server_resources(state: &AppState, server_id: &str) {
+    let (interface, cgroup_root, data_directory, server_cfg) = {
+        let config = state.config.read().await;
+        (
+            config.agent.shaping_interface.clone(),
+            config.agent.cgroup_root.clone(),
+            config.agent.data_directory.clone(),
+            config.servers.iter().find(|s| s.id == server_id).cloned(),
+        )
+    };
+    if let Some(server_cfg) = server_cfg {
+        crate::shaping::clear(interface.as_deref(), &server_cfg).await;
+    }
+    crate::cgroups::clear(cgroup_root.as_deref(), server_id).await;
+    crate::portmap::unmap(state, server_id).await;
+    // Forgotten once it has exited, so no agent acts on whoever gets the
+    // PID next. A detached server's console log is kept.
+    pidfile::remove(&data_directory, server_id).await;
 }
 
-async fn on_process_exit(state: &AppState, server_id: &str) {
-    // Idempotent: only first removal triggers autostart
-    if state.servers.remove(server_id).is_none() {
+pub async fn on_process_exit(state: &AppState, server_id: &str) {
+    // Idempotent: only first removal triggers a restart
+    let Some(instance) = state.remove_instance(server_id) else {
         return;
+    };
+    let phase = *instance.phase.lock().await;
+    let requested = phase == ServerPhase::Stopping;
+    let status = {
+        let mut child = instance.child.lock().await;
+        let status = tokio::time::timeout(std::time::Duration::from_secs(10), child.wait())
+            .await
+            .ok()
+            .and_then(Result::ok);
+        child.release().await;
+        status
+    };
+    let exit = exit_info(status, requested);
+    if exit.crashed {
+        tracing::warn!(
+            "Server '{}' crashed (exit code {:?}, signal {:?})",
+            server_id,
+            exit.exit_code,
+            exit.signal
+        );
+        crate::rollout::record_crash(state, server_id).await;
+    } else {
+        tracing::info!("Server '{}' exited", server_id);
     }
-    tracing::info!("Server '{}' exited", server_id);
+    let crashed = exit.crashed;
+    record_exit(state, server_id, &instance, exit).await;
+    release_server_resources(state, server_id).await;
 
-    let autostart = {
+    let Some(policy) = ({
         let config = state.config.read().await;
         config
             .servers
             .iter()
             .find(|s| s.id == server_id)
-            .map(|s| s.autostart)
-            .unwrap_or(false)
+            .map(|s| s.effective_restart_policy())
+    }) else {
+        return;
     };
-
-    if autostart {
-        let state2 = state.clone();
-        let sid = server_id.to_string();
-        tokio::spawn(autostart_after_delay(state2, sid));
+    // A run that got through startup ends any crash loop
+    if phase == ServerPhase::Running {
+        state.restart_attempts.remove(server_id);
+    }
+    let restart = match policy.mode {
+        RestartMode::Never => false,
+        RestartMode::OnFailure => crashed,
+        RestartMode::Always => !requested,
+    };
+    if !restart {
+        return;
+    }
+    let attempts = {
+        let mut attempts = state.restart_attempts.entry(server_id.to_string()).or_insert(0);
+        *attempts += 1;
+        *attempts
+    };
+    if policy.max_retries.is_some_and(|max| attempts > max) {
+        tracing::warn!(
+            "Not restarting '{}': gave up after {} consecutive restarts",
+            server_id,
+            attempts - 1
+        );
+        return;
     }
+    tracing::info!(
+        "Restarting '{}' in {}s (attempt {})",
+        server_id,
+        policy.delay_secs,
+        attempts
+    );
+    tokio::spawn(restart_after_delay(
+        state.clone(),
+        server_id.to_string(),
+        std::time::Duration::from_secs(policy.delay_secs),
+    ));
 }
 
 // Separate non-async fn returning BoxFuture to break the opaque-type cycle
 // between start_server and on_process_exit.
-fn autostart_after_delay(
+fn restart_after_delay(
     state: AppState,
     server_id: String,
+    delay: std::time::Duration,
 ) -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>> {
     Box::pin(async move {
-        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+        tokio::time::sleep(delay).await;
+        // An operator may have started it in the meantime
+        if state.servers.contains_key(&server_id) {
+            return;
+        }
         if let Err(e) = start_server(state, &server_id).await {
-            tracing::error!("Autostart failed for '{}': {}", server_id, e);
+            tracing::error!("Restart failed for '{}': {}", server_id, e);
         }
     })
 }
 
+#[tracing::instrument(skip(state))]
 pub async fn stop_server(state: AppState, server_id: &str) -> Result<(), String> {
     let instance = state
         .servers
         .get(server_id)
         .map(|r| r.value().clone())
         .ok_or_else(|| format!("Server '{}' is not running", server_id))?;
+    *instance.phase.lock().await = ServerPhase::Stopping;
+    state.restart_attempts.remove(server_id);
+    hooks::run_logged(&state, server_id, Hook::PreStop, Some(instance.pid)).await;
 
-    // Send "stop" command
-    {
-        let mut stdin = instance.stdin.lock().await;
-        let _ = stdin.write_all(b"stop\n").await;
-        let _ = stdin.flush().await;
-    }
-
-    // Wait up to 15 seconds
+    let stop_steps = {
+        let config = state.config.read().await;
+        config
+            .servers
+            .iter()
+            .find(|s| s.id == server_id)
+            .map(|s| s.stop_steps.clone())
+            .unwrap_or_default()
+    };
+    // An adopted server can't be sent commands, but SIGTERM makes it save
+    // and shut down all the same
+    let read_only = instance.console.lock().await.kind() == ConsoleKind::ReadOnly;
     let mut stopped = false;
-    for _ in 0..30 {
-        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
-        let mut child = instance.child.lock().await;
-        match child.try_wait() {
-            Ok(Some(_)) => {
-                stopped = true;
-                break;
-            }
-            Ok(None) => {}
-            Err(e) => {
-                tracing::warn!("try_wait error for '{}': {}", server_id, e);
-            }
-        }
-    }
+    if !read_only {
+        run_stop_steps(&instance, server_id, &stop_steps).await;
 
-    if !stopped {
-        // SIGTERM
-        use nix::sys::signal::{self, Signal};
-        use nix::unistd::Pid as NixPid;
-        let _ = signal::kill(NixPid::from_raw(instance.pid as i32), Signal::SIGTERM);
-
-        // Wait up to 5 more seconds
-        for _ in 0..10 {
+        // Wait up to 15 seconds
+        for _ in 0..30 {
             tokio::time::sleep(std::time::Duration::from_millis(500)).await;
             let mut child = instance.child.lock().await;
-            match child.try_wait() {
+            match child.try_wait().await {
                 Ok(Some(_)) => {
                     stopped = true;
                     break;
                 }
-                _ => {}
+                Ok(None) => {}
+                Err(e) => {
+                    tracing::warn!("try_wait error for '{}': {}", server_id, e);
+                }
             }
         }
     }
 
     if !stopped {
-        let mut child = instance.child.lock().await;
-        let _ = child.kill().await;
+        if instance.child.lock().await.owns_pid().await {
+            crate::platform::terminate(instance.pid).await;
+        }
+
+        // Wait up to 5 more seconds, or 30 for a server that only got the
+        // signal and still has its worlds to save
+        for _ in 0..if read_only { 60 } else { 10 } {
+            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+            let mut child = instance.child.lock().await;
+            if let Ok(Some(_)) = child.try_wait().await {
+                stopped = true;
+                break;
+            }
+        }
     }
 
-    state.servers.remove(server_id);
+    let status = {
+        let mut child = instance.child.lock().await;
+        if !stopped {
+            let _ = child.kill().await;
+        }
+        let status = child.try_wait().await.ok().flatten();
+        child.release().await;
+        status
+    };
+
+    // The exit watcher may already have recorded this exit
+    if state.remove_instance(server_id).is_some() {
+        record_exit(&state, server_id, &instance, exit_info(status, true)).await;
+        release_server_resources(&state, server_id).await;
+    }
     tracing::info!("Stopped server '{}'", server_id);
     Ok(())
 }
 
+/// Waits for the next console line matching `pattern`.
+async fn wait_for_console_match(
+    console_rx: &mut broadcast::Receiver<ConsoleLine>,
+    pattern: &Regex,
+    timeout: std::time::Duration,
+) -> Option<String> {
+    tokio::time::timeout(timeout, async {
+        loop {
+            match console_rx.recv().await {
+                Ok(line) => {
+                    let text = crate::ansi::strip(&line.text);
+                    if pattern.is_match(&text) {
+                        return Some(text);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+/// Sends a console command and returns the first output line matching
+/// `pattern`, without colour codes.
+pub async fn command_with_response(
+    state: &AppState,
+    server_id: &str,
+    command: &str,
+    pattern: &Regex,
+    timeout: std::time::Duration,
+) -> Result<String, String> {
+    let instance = state
+        .servers
+        .get(server_id)
+        .map(|r| r.value().clone())
+        .ok_or_else(|| format!("Server '{}' is not running", server_id))?;
+    let mut console_rx = instance.console_tx.subscribe();
+    send_command(state, server_id, command).await?;
+    wait_for_console_match(&mut console_rx, pattern, timeout)
+        .await
+        .ok_or_else(|| format!("No response to '{}' from server '{}'", command, server_id))
+}
+
+async fn run_stop_steps(instance: &ServerInstance, server_id: &str, steps: &[StopStep]) {
+    if steps.is_empty() {
+        let _ = instance.send_line("stop").await;
+        return;
+    }
+
+    for step in steps {
+        // Subscribe before sending so a fast reply isn't missed
+        let mut console_rx = instance.console_tx.subscribe();
+        let _ = instance.send_line(&step.command).await;
+
+        if let Some(pattern) = step.expect.as_deref().and_then(|p| Regex::new(p).ok()) {
+            let timeout = std::time::Duration::from_millis(step.expect_timeout_ms);
+            if wait_for_console_match(&mut console_rx, &pattern, timeout).await.is_none() {
+                tracing::warn!(
+                    "Stop step '{}' for '{}' did not see '{}' in time",
+                    step.command,
+                    server_id,
+                    pattern
+                );
+            }
+        }
+
+        if step.wait_ms > 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(step.wait_ms)).await;
+        }
+    }
+}
+
+#[tracing::instrument(skip(state))]
 pub async fn restart_server(state: AppState, server_id: &str) -> Result<(), String> {
+    // Checked up front, so the server isn't left stopped
+    crate::maintenance::check(&state).await?;
     if state.servers.contains_key(server_id) {
         stop_server(state.clone(), server_id).await?;
     }
     start_server(state, server_id).await
 }
 
+pub async fn send_command(state: &AppState, server_id: &str, command: &str) -> Result<(), String> {
+    if command.contains('\n') || command.contains('\r') {
+        return Err("command must not contain line breaks".to_string());
+    }
+    let instance = state
+        .servers
+        .get(server_id)
+        .map(|r| r.value().clone())
+        .ok_or_else(|| format!("Server '{}' is not running", server_id))?;
+
+    instance
+        .send_line(command)
+        .await
+        .map_err(|e| format!("Failed to write to the server console: {}", e))
+}
+
+pub async fn answer_prompt(state: &AppState, server_id: &str, answer: &str) -> Result<(), String> {
+    let instance = state
+        .servers
+        .get(server_id)
+        .map(|r| r.value().clone())
+        .ok_or_else(|| format!("Server '{}' is not running", server_id))?;
+    if instance.pending_prompt.lock().await.is_none() {
+        return Err(format!("Server '{}' is not waiting for input", server_id));
+    }
+    send_command(state, server_id, answer).await?;
+    *instance.pending_prompt.lock().await = None;
+    Ok(())
+}
+
+pub async fn run_macro(state: AppState, server_id: &str, name: &str) -> Result<(), String> {
+    let steps = {
+        let config = state.config.read().await;
+        let server_cfg = config
+            .servers
+            .iter()
+            .find(|s| s.id == server_id)
+            .ok_or_else(|| format!("Server '{}' not found in config", server_id))?;
+        server_cfg
+            .macros
+            .iter()
+            .find(|m| m.name == name)
+            .map(|m| m.steps.clone())
+            .ok_or_else(|| format!("Macro '{}' not found for server '{}'", name, server_id))?
+    };
+    if !state.servers.contains_key(server_id) {
+        return Err(format!("Server '{}' is not running", server_id));
+    }
+
+    let sid = server_id.to_string();
+    let name = name.to_string();
+    tokio::spawn(async move {
+        for step in steps {
+            if step.delay_ms > 0 {
+                tokio::time::sleep(std::time::Duration::from_millis(step.delay_ms)).await;
+            }
+            if let Err(e) = send_command(&state, &sid, &step.command).await {
+                tracing::warn!("Macro '{}' on '{}' aborted: {}", name, sid, e);
+                return;
+            }
+        }
+        tracing::info!("Macro '{}' finished on '{}'", name, sid);
+    });
+    Ok(())
+}
+
+pub async fn send_chat(
+    state: &AppState,
+    server_id: &str,
+    sender: &str,
+    message: &str,
+) -> Result<(), String> {
+    if message.trim().is_empty() {
+        return Err("message must not be empty".to_string());
+    }
+    let components = serde_json::json!([
+        { "text": format!("[{} via Panel] ", sender), "color": "gold" },
+        { "text": message, "color": "white" },
+    ]);
+    send_command(state, server_id, &format!("tellraw @a {}", components)).await
+}
+
+#[tracing::instrument(skip(state))]
 pub async fn backup_server(state: AppState, server_id: &str) -> Result<(), String> {
     let server_cfg = {
         let config = state.config.read().await;
@@ -310,5 +1038,39 @@ pub async fn backup_server(state: AppState, server_id: &str) -> Result<(), Strin
     }
 
     tracing::info!("Created backup for server '{}' at {:?}", server_id, backup_path);
+    let backup_env = [("MC_BACKUP_PATH", backup_path.to_string_lossy().into_owned())];
+    if let Err(e) = hooks::run(&server_cfg, Hook::PostBackup, None, &backup_env).await {
+        tracing::warn!("Server '{}': {}", server_id, e);
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_key_compares_numerically() {
+        assert!(version_key("1.20.1-47.10.0") > version_key("1.20.1-47.9.0"));
+        assert!(version_key("1.20.10-1.0") > version_key("1.20.9-50.0"));
+        assert_eq!(version_key("21.0.167-beta"), vec![21, 0, 167]);
+    }
+
+    #[tokio::test]
+    async fn forge_args_file_picks_the_newest_install() {
+        let dir = std::env::temp_dir().join(format!("forge-test-{}", std::process::id()));
+        let file = if cfg!(windows) { "win_args.txt" } else { "unix_args.txt" };
+        let forge = dir.join("libraries/net/minecraftforge/forge");
+        for version in ["1.20.1-47.9.0", "1.20.1-47.10.0", "1.20.1-48.0.0"] {
+            tokio::fs::create_dir_all(forge.join(version)).await.unwrap();
+        }
+        // An installation without its argument file doesn't count
+        for version in ["1.20.1-47.9.0", "1.20.1-47.10.0"] {
+            tokio::fs::write(forge.join(version).join(file), "").await.unwrap();
+        }
+        let found = forge_args_file(&dir.to_string_lossy()).await;
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        let expected = Path::new("libraries/net/minecraftforge/forge/1.20.1-47.10.0").join(file);
+        assert_eq!(found, Some(expected.to_string_lossy().into_owned()));
+    }
+}
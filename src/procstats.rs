@@ -0,0 +1,149 @@
+//! Process figures sysinfo doesn't cover: thread count from /proc and TCP
+//! traffic from the kernel's per-socket counters as reported by `ss`, read
+//! once per sampler tick for every server. Both are Linux-only; elsewhere
+//! the figures are left out.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use regex::Regex;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ProcessStats {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threads: Option<u64>,
+    /// Bytes sent over TCP since the agent started sampling the process.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub net_sent_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub net_received_bytes: Option<u64>,
+}
+
+pub async fn thread_count(pid: u32) -> Option<u64> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+    let status = tokio::fs::read_to_string(format!("/proc/{}/status", pid))
+        .await
+        .ok()?;
+    status
+        .lines()
+        .find_map(|l| l.strip_prefix("Threads:"))
+        .and_then(|n| n.trim().parse().ok())
+}
+
+fn bytes_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"bytes_sent:(\d+).*bytes_received:(\d+)").expect("valid regex"))
+}
+
+fn pid_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"pid=(\d+),").expect("valid regex"))
+}
+
+/// Every TCP socket's byte counters from one `ss` run, by owning PID, so
+/// a sampler tick lists the sockets once however many servers it covers.
+#[derive(Debug, Default)]
+pub struct Sockets {
+    by_pid: HashMap<u32, HashMap<String, (u64, u64)>>,
+}
+
+impl Sockets {
+    /// None when `ss` is unavailable.
+    pub async fn read() -> Option<Self> {
+        if !cfg!(target_os = "linux") {
+            return None;
+        }
+        let output = tokio::process::Command::new("ss")
+            .args(["-tinpH"])
+            .output()
+            .await
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        Some(Self::parse(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    fn parse(stdout: &str) -> Self {
+        let mut sockets = Sockets::default();
+        let mut lines = stdout.lines();
+        // Each socket is a summary line followed by an indented info line
+        while let Some(line) = lines.next() {
+            let pids: Vec<u32> =
+                pid_regex().captures_iter(line).filter_map(|c| c[1].parse().ok()).collect();
+            if pids.is_empty() {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let key = format!(
+                "{} {}",
+                fields.nth(3).unwrap_or(""),
+                fields.next().unwrap_or("")
+            );
+            let Some(info) = lines.next() else { break };
+            let Some(caps) = bytes_regex().captures(info) else {
+                continue;
+            };
+            let counters = (caps[1].parse().unwrap_or(0), caps[2].parse().unwrap_or(0));
+            for pid in pids {
+                sockets.by_pid.entry(pid).or_default().insert(key.clone(), counters);
+            }
+        }
+        sockets
+    }
+}
+
+/// Turns per-connection socket counters into monotonic totals. Sockets
+/// come and go, so only the growth of each one between samples is added.
+#[derive(Default)]
+pub struct NetCounter {
+    connections: HashMap<String, (u64, u64)>,
+    sent: u64,
+    received: u64,
+}
+
+impl NetCounter {
+    /// Folds in the process' sockets from this tick's listing.
+    pub fn sample(&mut self, sockets: &Sockets, pid: u32) -> (u64, u64) {
+        let current = sockets.by_pid.get(&pid).cloned().unwrap_or_default();
+        for (key, &(sent, received)) in &current {
+            let (prev_sent, prev_received) = self.connections.get(key).copied().unwrap_or((0, 0));
+            self.sent += sent.saturating_sub(prev_sent);
+            self.received += received.saturating_sub(prev_received);
+        }
+        self.connections = current;
+        (self.sent, self.received)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SS: &str = "\
+ESTAB 0 0 10.0.0.2:25565 10.0.0.9:51234 users:((\"java\",pid=100,fd=40))
+\t cubic wscale:7,7 bytes_sent:5000 bytes_acked:5000 bytes_received:300 segs_out:10
+ESTAB 0 0 10.0.0.2:22 10.0.0.9:40000 users:((\"sshd\",pid=7,fd=3))
+\t cubic bytes_sent:10 bytes_received:20
+";
+
+    #[test]
+    fn sockets_are_grouped_by_pid() {
+        let sockets = Sockets::parse(SS);
+        assert_eq!(sockets.by_pid[&100].values().next(), Some(&(5000, 300)));
+        assert_eq!(sockets.by_pid[&7].len(), 1);
+    }
+
+    #[test]
+    fn totals_grow_by_what_each_socket_adds() {
+        let mut counter = NetCounter::default();
+        assert_eq!(counter.sample(&Sockets::parse(SS), 100), (5000, 300));
+        let later = SS.replace("bytes_sent:5000", "bytes_sent:5500");
+        assert_eq!(counter.sample(&Sockets::parse(&later), 100), (5500, 300));
+        // The socket closed; what it sent stays counted
+        assert_eq!(counter.sample(&Sockets::default(), 100), (5500, 300));
+    }
+}
@@ -0,0 +1,161 @@
+use std::{collections::BTreeMap, path::Path};
+
+use serde::Serialize;
+
+/// Settings the agent keeps in line with the server config itself.
+const MANAGED_KEYS: &[&str] = &["server-port"];
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct PropertyChange {
+    pub key: String,
+    /// Absent when the setting isn't in the file yet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    pub to: String,
+}
+
+/// Checks the name of a setting to be written with `set_text`.
+pub fn validate_setting(key: &str) -> Result<(), String> {
+    let valid_char = |c: char| c.is_ascii_graphic() && !matches!(c, '=' | ':' | '#' | '!');
+    if key.is_empty() || !key.chars().all(valid_char) {
+        return Err(format!("'{}' is not a valid property name", key));
+    }
+    if MANAGED_KEYS.contains(&key) {
+        return Err(format!("'{}' is set from the server config", key));
+    }
+    Ok(())
+}
+
+/// `server.properties`, kept line by line so comments survive a rewrite.
+#[derive(Debug, Clone, Default)]
+pub struct ServerProperties {
+    lines: Vec<String>,
+}
+
+impl ServerProperties {
+    pub async fn load(directory: &str) -> Result<Self, String> {
+        let path = Path::new(directory).join("server.properties");
+        match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => Ok(Self {
+                lines: contents.lines().map(str::to_string).collect(),
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("Failed to read server.properties: {}", e)),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.lines.iter().find_map(|line| {
+            let (k, v) = split_line(line)?;
+            (k == key).then_some(v)
+        })
+    }
+
+    /// Sets `key`, replacing its existing line or appending a new one.
+    pub fn set(&mut self, key: &str, value: &str) {
+        let line = format!("{}={}", key, value);
+        match self
+            .lines
+            .iter_mut()
+            .find(|l| split_line(l).is_some_and(|(k, _)| k == key))
+        {
+            Some(existing) => *existing = line,
+            None => self.lines.push(line),
+        }
+    }
+
+    /// `key` with the escapes Java writes into properties files, such as
+    /// `\u00A7` and `\n`, decoded.
+    pub fn get_text(&self, key: &str) -> Option<String> {
+        self.get(key).map(unescape)
+    }
+
+    /// Sets `key` to `value`, escaped the way Java would write it.
+    pub fn set_text(&mut self, key: &str, value: &str) {
+        self.set(key, &escape(value));
+    }
+
+    /// What setting each of `values` would change.
+    pub fn diff(&self, values: &BTreeMap<String, String>) -> Vec<PropertyChange> {
+        values
+            .iter()
+            .filter_map(|(key, value)| {
+                let current = self.get_text(key);
+                (current.as_deref() != Some(value.as_str())).then(|| PropertyChange {
+                    key: key.clone(),
+                    from: current,
+                    to: value.clone(),
+                })
+            })
+            .collect()
+    }
+
+    pub async fn save(&self, directory: &str) -> Result<(), String> {
+        let path = Path::new(directory).join("server.properties");
+        let mut contents = self.lines.join("\n");
+        contents.push('\n');
+        tokio::fs::write(&path, contents)
+            .await
+            .map_err(|e| format!("Failed to write server.properties: {}", e))
+    }
+
+    /// The world folder name, `level-name` in server.properties.
+    pub fn level_name(&self) -> &str {
+        self.get("level-name").filter(|v| !v.is_empty()).unwrap_or("world")
+    }
+}
+
+fn split_line(line: &str) -> Option<(&str, &str)> {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with('#') || trimmed.starts_with('!') {
+        return None;
+    }
+    let (k, v) = trimmed.split_once('=')?;
+    Some((k.trim(), v.trim()))
+}
+
+fn unescape(value: &str) -> String {
+    // UTF-16 units, since a `\u` escape may be half of a surrogate pair
+    let mut units: Vec<u16> = Vec::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            units.extend(c.encode_utf16(&mut [0; 2]).iter());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => units.push(u16::from(b'\n')),
+            Some('r') => units.push(u16::from(b'\r')),
+            Some('t') => units.push(u16::from(b'\t')),
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                if let Ok(unit) = u16::from_str_radix(&hex, 16) {
+                    units.push(unit);
+                }
+            }
+            Some(other) => units.extend(other.encode_utf16(&mut [0; 2]).iter()),
+            None => {}
+        }
+    }
+    String::from_utf16_lossy(&units)
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for (i, c) in value.chars().enumerate() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            ' ' if i == 0 => escaped.push_str("\\ "),
+            c if c.is_ascii() && !c.is_ascii_control() => escaped.push(c),
+            c => {
+                for unit in c.encode_utf16(&mut [0; 2]).iter() {
+                    escaped.push_str(&format!("\\u{:04X}", unit));
+                }
+            }
+        }
+    }
+    escaped
+}
@@ -0,0 +1,406 @@
+//! Velocity and BungeeCord proxies: provisioning the proxy, writing its
+//! backend list, and making sure every backend expects the proxy's
+//! player-info forwarding.
+
+use std::path::{Path, PathBuf};
+
+use rand::{distr::Alphanumeric, Rng};
+use serde::Serialize;
+use serde_yaml::{Mapping, Value};
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    config::{Config, ForwardingMode, ProxyConfig, ProxySoftware, ServerConfig},
+    downloads,
+    properties::ServerProperties,
+    rollout,
+    yaml,
+};
+
+const VELOCITY_CONFIG: &str = "velocity.toml";
+const BUNGEECORD_CONFIG: &str = "config.yml";
+const SECRET_FILE: &str = "forwarding.secret";
+const PAPER_GLOBAL_CONFIG: &str = "config/paper-global.yml";
+const SPIGOT_CONFIG: &str = "spigot.yml";
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct Provisioned {
+    pub version: String,
+    pub build: u32,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct BackendStatus {
+    pub name: String,
+    pub server_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// Why the backend would reject players from this proxy, if it would.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub problem: Option<String>,
+}
+
+pub async fn read_secret(proxy_dir: &str) -> Option<String> {
+    tokio::fs::read_to_string(Path::new(proxy_dir).join(SECRET_FILE))
+        .await
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+async fn ensure_secret(proxy_dir: &str) -> Result<String, String> {
+    if let Some(secret) = read_secret(proxy_dir).await {
+        return Ok(secret);
+    }
+    let secret: String = rand::rng()
+        .sample_iter(Alphanumeric)
+        .take(24)
+        .map(char::from)
+        .collect();
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    // Anyone who can read it can forward players to the backends as anyone
+    #[cfg(unix)]
+    options.mode(0o600);
+    let write = async {
+        let mut file = options.open(Path::new(proxy_dir).join(SECRET_FILE)).await?;
+        file.write_all(secret.as_bytes()).await?;
+        file.flush().await
+    };
+    write.await.map_err(|e| format!("Failed to write {}: {}", SECRET_FILE, e))?;
+    Ok(secret)
+}
+
+async fn latest_velocity_version() -> Result<String, String> {
+    #[derive(serde::Deserialize)]
+    struct Project {
+        versions: Vec<String>,
+    }
+    let body =
+        downloads::fetch_text(&format!("{}/velocity", rollout::PAPERMC_API)).await?;
+    let project: Project = serde_json::from_str(&body)
+        .map_err(|e| format!("Failed to parse Velocity version list: {}", e))?;
+    project
+        .versions
+        .last()
+        .cloned()
+        .ok_or_else(|| "Velocity has no published versions".to_string())
+}
+
+/// Downloads the proxy jar into the server directory and generates the
+/// forwarding secret. Only Velocity publishes checksummed builds, so a
+/// BungeeCord jar has to be placed with the downloads endpoint instead.
+pub async fn provision(
+    data_directory: &str,
+    cfg: &ServerConfig,
+    proxy: &ProxyConfig,
+) -> Result<Provisioned, String> {
+    if proxy.software != ProxySoftware::Velocity {
+        return Err("Only Velocity can be provisioned; upload the BungeeCord jar instead".into());
+    }
+    let version = match proxy.version {
+        Some(ref v) => v.clone(),
+        None => latest_velocity_version().await?,
+    };
+    let build = rollout::latest_build("velocity", &version)
+        .await?
+        .ok_or_else(|| format!("No stable Velocity build for {}", version))?;
+    let app = build
+        .downloads
+        .get("application")
+        .ok_or("Velocity build has no application download")?;
+    let url = format!(
+        "{}/velocity/versions/{}/builds/{}/downloads/{}",
+        rollout::PAPERMC_API,
+        version,
+        build.build,
+        app.name
+    );
+    let dest = Path::new(&cfg.directory).join(&cfg.jar);
+    downloads::install_cached(data_directory, &url, &app.sha256, &dest).await?;
+    if proxy.forwarding_mode() == ForwardingMode::Modern {
+        ensure_secret(&cfg.directory).await?;
+    }
+    tracing::info!("Provisioned Velocity {} build {} for '{}'", version, build.build, cfg.id);
+    Ok(Provisioned {
+        version,
+        build: build.build,
+    })
+}
+
+fn backend_address(backend: &ServerConfig) -> String {
+    format!("127.0.0.1:{}", backend.port)
+}
+
+/// Resolves the proxy's backends to their server configs.
+fn resolve_backends<'a>(
+    config: &'a Config,
+    proxy: &ProxyConfig,
+) -> Result<Vec<(String, &'a ServerConfig)>, String> {
+    proxy
+        .backends
+        .iter()
+        .map(|b| {
+            let server = config
+                .servers
+                .iter()
+                .find(|s| s.id == b.server_id)
+                .ok_or_else(|| format!("proxy backend server '{}' not found", b.server_id))?;
+            if server.proxy.is_some() {
+                return Err(format!("proxy backend '{}' is itself a proxy", b.server_id));
+            }
+            Ok((b.name.clone(), server))
+        })
+        .collect()
+}
+
+/// Checks backend references against the rest of the config.
+pub fn check_backends(config: &Config, cfg: &ServerConfig) -> Result<(), String> {
+    match cfg.proxy {
+        Some(ref proxy) => resolve_backends(config, proxy).map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+async fn write_velocity_config(
+    cfg: &ServerConfig,
+    proxy: &ProxyConfig,
+    backends: &[(String, &ServerConfig)],
+) -> Result<(), String> {
+    use toml_edit::{value, Array, DocumentMut, Item, Table};
+
+    let path = Path::new(&cfg.directory).join(VELOCITY_CONFIG);
+    let contents = match tokio::fs::read_to_string(&path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("Failed to read {}: {}", VELOCITY_CONFIG, e)),
+    };
+    let mut doc: DocumentMut = contents
+        .parse()
+        .map_err(|e| format!("Failed to parse {}: {}", VELOCITY_CONFIG, e))?;
+
+    doc["bind"] = value(format!("0.0.0.0:{}", cfg.port));
+    doc["player-info-forwarding-mode"] = value(proxy.forwarding_mode().as_str());
+    doc["forwarding-secret-file"] = value(SECRET_FILE);
+
+    let mut servers = Table::new();
+    for (name, backend) in backends {
+        servers[name.as_str()] = value(backend_address(backend));
+    }
+    let mut try_order = Array::new();
+    for (name, _) in backends {
+        try_order.push(name.as_str());
+    }
+    servers["try"] = value(try_order);
+    doc["servers"] = Item::Table(servers);
+
+    // Velocity refuses to start when a forced host names an unknown server
+    if let Some(forced) = doc.get_mut("forced-hosts").and_then(Item::as_table_mut) {
+        forced.retain(|_, targets| {
+            targets.as_array().is_some_and(|a| {
+                a.iter().all(|t| {
+                    t.as_str()
+                        .is_some_and(|t| backends.iter().any(|(name, _)| name == t))
+                })
+            })
+        });
+    }
+
+    tokio::fs::write(&path, doc.to_string())
+        .await
+        .map_err(|e| format!("Failed to write {}: {}", VELOCITY_CONFIG, e))
+}
+
+async fn write_bungeecord_config(
+    cfg: &ServerConfig,
+    backends: &[(String, &ServerConfig)],
+) -> Result<(), String> {
+    let path = Path::new(&cfg.directory).join(BUNGEECORD_CONFIG);
+    let mut doc = yaml::load(&path).await?;
+    yaml::set(&mut doc, &["ip_forward"], Value::Bool(true));
+
+    let mut servers = Mapping::new();
+    for (name, backend) in backends {
+        let mut entry = Mapping::new();
+        entry.insert("address".into(), backend_address(backend).into());
+        entry.insert("motd".into(), backend.name.clone().into());
+        entry.insert("restricted".into(), false.into());
+        servers.insert(name.as_str().into(), Value::Mapping(entry));
+    }
+    yaml::set(&mut doc, &["servers"], Value::Mapping(servers));
+
+    let priorities: Vec<Value> = backends.iter().map(|(name, _)| name.as_str().into()).collect();
+    let mut listeners = match yaml::get(&doc, &["listeners"]) {
+        Some(Value::Sequence(seq)) if !seq.is_empty() => seq.clone(),
+        _ => vec![Value::Mapping(Mapping::new())],
+    };
+    yaml::set(&mut listeners[0], &["host"], format!("0.0.0.0:{}", cfg.port).into());
+    yaml::set(&mut listeners[0], &["priorities"], Value::Sequence(priorities));
+    yaml::set(&mut doc, &["listeners"], Value::Sequence(listeners));
+
+    yaml::save(&path, &doc).await
+}
+
+/// Rewrites the proxy's own config so its listener and backend list match
+/// the agent config.
+pub async fn write_config(config: &Config, cfg: &ServerConfig) -> Result<(), String> {
+    let Some(ref proxy) = cfg.proxy else {
+        return Ok(());
+    };
+    let backends = resolve_backends(config, proxy)?;
+    match proxy.software {
+        ProxySoftware::Velocity => {
+            if proxy.forwarding_mode() == ForwardingMode::Modern {
+                ensure_secret(&cfg.directory).await?;
+            }
+            write_velocity_config(cfg, proxy, &backends).await
+        }
+        ProxySoftware::Bungeecord => write_bungeecord_config(cfg, &backends).await,
+    }
+}
+
+fn paper_global(dir: &str) -> PathBuf {
+    Path::new(dir).join(PAPER_GLOBAL_CONFIG)
+}
+
+/// The modern forwarding secret a backend expects, from its paper-global.yml.
+pub async fn backend_secret(backend_dir: &str) -> Option<String> {
+    let paper = yaml::load(&paper_global(backend_dir)).await.ok()?;
+    yaml::get(&paper, &["proxies", "velocity", "secret"])
+        .and_then(Value::as_str)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+}
+
+fn spigot(dir: &str) -> PathBuf {
+    Path::new(dir).join(SPIGOT_CONFIG)
+}
+
+/// Sets a backend up to accept players forwarded by `proxy_cfg`.
+pub async fn configure_backend(
+    proxy_cfg: &ServerConfig,
+    backend: &ServerConfig,
+) -> Result<(), String> {
+    let Some(ref proxy) = proxy_cfg.proxy else {
+        return Err(format!("Server '{}' is not a proxy", proxy_cfg.id));
+    };
+    let mut properties = ServerProperties::load(&backend.directory).await?;
+    properties.set("online-mode", "false");
+    properties.save(&backend.directory).await?;
+
+    let modern = proxy.forwarding_mode() == ForwardingMode::Modern;
+    let mut paper = yaml::load(&paper_global(&backend.directory)).await?;
+    if modern {
+        let secret = ensure_secret(&proxy_cfg.directory).await?;
+        yaml::set(&mut paper, &["proxies", "velocity", "enabled"], Value::Bool(true));
+        yaml::set(&mut paper, &["proxies", "velocity", "online-mode"], Value::Bool(true));
+        yaml::set(&mut paper, &["proxies", "velocity", "secret"], secret.into());
+        yaml::save(&paper_global(&backend.directory), &paper).await?;
+    } else if yaml::get(&paper, &["proxies", "velocity", "enabled"]).is_some() {
+        yaml::set(&mut paper, &["proxies", "velocity", "enabled"], Value::Bool(false));
+        yaml::save(&paper_global(&backend.directory), &paper).await?;
+    }
+
+    let mut spigot_doc = yaml::load(&spigot(&backend.directory)).await?;
+    yaml::set(&mut spigot_doc, &["settings", "bungeecord"], Value::Bool(!modern));
+    yaml::save(&spigot(&backend.directory), &spigot_doc).await?;
+    tracing::info!("Configured '{}' as a backend of proxy '{}'", backend.id, proxy_cfg.id);
+    Ok(())
+}
+
+/// Why `backend` would reject players forwarded by the proxy, if it would.
+pub async fn forwarding_problem(
+    proxy: &ProxyConfig,
+    secret: Option<&str>,
+    backend: &ServerConfig,
+) -> Option<String> {
+    let properties = match ServerProperties::load(&backend.directory).await {
+        Ok(p) => p,
+        Err(e) => return Some(e),
+    };
+    if properties.get("online-mode") != Some("false") {
+        return Some("online-mode must be false behind a proxy".to_string());
+    }
+    match proxy.forwarding_mode() {
+        ForwardingMode::Modern => {
+            let paper = match yaml::load(&paper_global(&backend.directory)).await {
+                Ok(doc) => doc,
+                Err(e) => return Some(e),
+            };
+            let enabled = yaml::get(&paper, &["proxies", "velocity", "enabled"])
+                .and_then(Value::as_bool)
+                .unwrap_or(false);
+            if !enabled {
+                return Some(format!(
+                    "modern forwarding needs proxies.velocity.enabled in {}",
+                    PAPER_GLOBAL_CONFIG
+                ));
+            }
+            let backend_secret =
+                yaml::get(&paper, &["proxies", "velocity", "secret"]).and_then(Value::as_str);
+            if backend_secret.is_none() || backend_secret != secret {
+                return Some(format!("forwarding secret differs from the proxy's {}", SECRET_FILE));
+            }
+        }
+        ForwardingMode::Legacy => {
+            let doc = match yaml::load(&spigot(&backend.directory)).await {
+                Ok(doc) => doc,
+                Err(e) => return Some(e),
+            };
+            let enabled = yaml::get(&doc, &["settings", "bungeecord"])
+                .and_then(Value::as_bool)
+                .unwrap_or(false);
+            if !enabled {
+                return Some(format!(
+                    "legacy forwarding needs settings.bungeecord in {}",
+                    SPIGOT_CONFIG
+                ));
+            }
+        }
+    }
+    None
+}
+
+pub async fn backend_statuses(config: &Config, cfg: &ServerConfig) -> Vec<BackendStatus> {
+    let Some(ref proxy) = cfg.proxy else {
+        return Vec::new();
+    };
+    let secret = read_secret(&cfg.directory).await;
+    let mut statuses = Vec::with_capacity(proxy.backends.len());
+    for b in &proxy.backends {
+        let server = config.servers.iter().find(|s| s.id == b.server_id);
+        let problem = match server {
+            Some(server) => forwarding_problem(proxy, secret.as_deref(), server).await,
+            None => Some(format!("server '{}' not found", b.server_id)),
+        };
+        statuses.push(BackendStatus {
+            name: b.name.clone(),
+            server_id: b.server_id.clone(),
+            address: server.map(backend_address),
+            problem,
+        });
+    }
+    statuses
+}
+
+/// Writes the proxy config and refuses to start a proxy whose backends
+/// would reject its forwarded players.
+pub async fn prepare_start(config: &Config, cfg: &ServerConfig) -> Result<(), String> {
+    let Some(ref proxy) = cfg.proxy else {
+        return Ok(());
+    };
+    write_config(config, cfg).await?;
+    if let Some(status) = backend_statuses(config, cfg)
+        .await
+        .into_iter()
+        .find(|s| s.problem.is_some())
+    {
+        return Err(format!(
+            "Backend '{}' is not set up for {} forwarding: {}",
+            status.server_id,
+            proxy.forwarding_mode().as_str(),
+            status.problem.unwrap_or_default()
+        ));
+    }
+    Ok(())
+}
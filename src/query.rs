@@ -0,0 +1,157 @@
+//! GameSpy4 "full stat" query over UDP, available when `enable-query=true`.
+//! Unlike Server List Ping it returns every player name and the plugin list.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::net::UdpSocket;
+
+use crate::{config::ServerConfig, properties::ServerProperties};
+
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+const MAGIC: [u8; 2] = [0xfe, 0xfd];
+const TYPE_HANDSHAKE: u8 = 0x09;
+const TYPE_STAT: u8 = 0x00;
+// Only the low nibble of each byte is used by the server
+const SESSION_ID: [u8; 4] = [0x01, 0x02, 0x03, 0x04];
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct QueryStatus {
+    pub motd: String,
+    pub game_type: String,
+    pub version: String,
+    /// Server software as reported in the `plugins` field, e.g. "Paper on Bukkit 1.21".
+    pub server_mod: Option<String>,
+    pub plugins: Vec<String>,
+    pub map: String,
+    pub players_online: u64,
+    pub players_max: u64,
+    pub players: Vec<String>,
+}
+
+async fn exchange(socket: &UdpSocket, request: &[u8], kind: u8) -> Result<Vec<u8>, String> {
+    socket
+        .send(request)
+        .await
+        .map_err(|e| format!("Failed to send query: {}", e))?;
+    let mut buf = vec![0u8; 65535];
+    let len = socket
+        .recv(&mut buf)
+        .await
+        .map_err(|e| format!("Failed to receive query response: {}", e))?;
+    buf.truncate(len);
+    if buf.len() < 5 || buf[0] != kind || buf[1..5] != SESSION_ID {
+        return Err("Malformed query response".to_string());
+    }
+    Ok(buf.split_off(5))
+}
+
+/// Splits a NUL-separated payload, keeping empty strings as terminators.
+fn nul_strings(data: &[u8]) -> Vec<String> {
+    data.split(|&b| b == 0)
+        .map(|s| String::from_utf8_lossy(s).into_owned())
+        .collect()
+}
+
+/// Parses "Paper on Bukkit 1.21: WorldEdit 7.3; LuckPerms 5.4".
+fn parse_plugins(field: &str) -> (Option<String>, Vec<String>) {
+    if field.is_empty() {
+        return (None, Vec::new());
+    }
+    match field.split_once(": ") {
+        Some((server_mod, list)) => (
+            Some(server_mod.to_string()),
+            list.split("; ")
+                .filter(|p| !p.is_empty())
+                .map(str::to_string)
+                .collect(),
+        ),
+        None => (Some(field.to_string()), Vec::new()),
+    }
+}
+
+async fn query_inner(port: u16) -> Result<QueryStatus, String> {
+    let socket = UdpSocket::bind("127.0.0.1:0")
+        .await
+        .map_err(|e| format!("Failed to bind UDP socket: {}", e))?;
+    socket
+        .connect(("127.0.0.1", port))
+        .await
+        .map_err(|e| format!("Failed to connect to query port {}: {}", port, e))?;
+
+    let mut handshake = MAGIC.to_vec();
+    handshake.push(TYPE_HANDSHAKE);
+    handshake.extend_from_slice(&SESSION_ID);
+    let token = exchange(&socket, &handshake, TYPE_HANDSHAKE).await?;
+    let token: i32 = nul_strings(&token)
+        .first()
+        .and_then(|t| t.trim().parse().ok())
+        .ok_or("Invalid challenge token")?;
+
+    // Full stat is a basic stat request padded with four zero bytes
+    let mut request = MAGIC.to_vec();
+    request.push(TYPE_STAT);
+    request.extend_from_slice(&SESSION_ID);
+    request.extend_from_slice(&token.to_be_bytes());
+    request.extend_from_slice(&[0; 4]);
+    let body = exchange(&socket, &request, TYPE_STAT).await?;
+
+    // 11 bytes of constant padding ("splitnum\0\x80\0") precede the K/V section
+    let body = body.get(11..).ok_or("Truncated query response")?;
+    let players_marker = b"\x01player_\x00\x00";
+    let split = body
+        .windows(players_marker.len())
+        .position(|w| w == players_marker)
+        .ok_or("Query response has no player section")?;
+
+    let mut fields = HashMap::new();
+    let kv = nul_strings(&body[..split]);
+    for pair in kv.chunks(2) {
+        if let [k, v] = pair {
+            if k.is_empty() {
+                break;
+            }
+            fields.insert(k.clone(), v.clone());
+        }
+    }
+    let players = nul_strings(&body[split + players_marker.len()..])
+        .into_iter()
+        .filter(|p| !p.is_empty())
+        .collect();
+
+    let field = |k: &str| fields.get(k).cloned().unwrap_or_default();
+    let (server_mod, plugins) = parse_plugins(&field("plugins"));
+    Ok(QueryStatus {
+        motd: field("hostname"),
+        game_type: field("gametype"),
+        version: field("version"),
+        server_mod,
+        plugins,
+        map: field("map"),
+        players_online: field("numplayers").parse().unwrap_or(0),
+        players_max: field("maxplayers").parse().unwrap_or(0),
+        players,
+    })
+}
+
+/// Queries a server, reading `enable-query` and `query.port` from its
+/// server.properties.
+pub async fn query(cfg: &ServerConfig) -> Result<QueryStatus, String> {
+    let properties = ServerProperties::load(&cfg.directory).await?;
+    if properties.get("enable-query") != Some("true") {
+        return Err(format!(
+            "Query is not enabled for '{}' (enable-query=false)",
+            cfg.id
+        ));
+    }
+    let port = match properties.get("query.port") {
+        Some(p) => p
+            .parse()
+            .map_err(|_| format!("Invalid query.port '{}'", p))?,
+        None => cfg.port,
+    };
+    tokio::time::timeout(QUERY_TIMEOUT, query_inner(port))
+        .await
+        .map_err(|_| format!("Query to port {} timed out", port))?
+}
@@ -0,0 +1,288 @@
+//! Token-bucket limits per client and kind of request, so a runaway script
+//! or a hostile caller can't thrash server processes or flood a console.
+
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, Request, State},
+    http::{header::RETRY_AFTER, request::Parts, Extensions, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use dashmap::DashMap;
+
+use crate::{
+    config::RateLimit, ipfilter::ClientIp, nodeauth::ManagerToken, sessions::Session,
+    state::AppState,
+};
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Class {
+    Api,
+    Actions,
+    Commands,
+    Login,
+    Rejected,
+}
+
+impl Class {
+    fn limit(self, limits: &crate::config::RateLimits) -> RateLimit {
+        match self {
+            Class::Api => limits.api,
+            Class::Actions => limits.actions,
+            Class::Commands => limits.commands,
+            Class::Login => limits.login,
+            Class::Rejected => limits.rejected,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Class::Api => "API requests",
+            Class::Actions => "server actions",
+            Class::Commands => "console commands",
+            Class::Login => "login attempts",
+            Class::Rejected => "failed authentication attempts",
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    per_minute: u32,
+    burst: u32,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let refilled = now.duration_since(self.updated).as_secs_f64() * self.per_minute as f64 / 60.0;
+        self.tokens = (self.tokens + refilled).min(self.burst as f64);
+        self.updated = now;
+    }
+}
+
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: DashMap<(String, Class), Bucket>,
+}
+
+impl RateLimiter {
+    /// Takes one token from the client's bucket, or says how long until the
+    /// next one is due.
+    pub fn check(&self, client: &str, class: Class, limit: RateLimit) -> Result<(), Duration> {
+        self.take(client, class, limit, true)
+    }
+
+    /// Like `check`, but leaves the token in the bucket.
+    pub fn peek(&self, client: &str, class: Class, limit: RateLimit) -> Result<(), Duration> {
+        self.take(client, class, limit, false)
+    }
+
+    fn take(
+        &self,
+        client: &str,
+        class: Class,
+        limit: RateLimit,
+        spend: bool,
+    ) -> Result<(), Duration> {
+        if limit.per_minute == 0 {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let burst = limit.burst.max(1);
+        let mut bucket = self.buckets.entry((client.to_string(), class)).or_insert(Bucket {
+            tokens: burst as f64,
+            updated: now,
+            per_minute: limit.per_minute,
+            burst,
+        });
+        bucket.per_minute = limit.per_minute;
+        bucket.burst = burst;
+        bucket.refill(now);
+        if bucket.tokens >= 1.0 {
+            if spend {
+                bucket.tokens -= 1.0;
+            }
+            return Ok(());
+        }
+        let missing = 1.0 - bucket.tokens;
+        Err(Duration::from_secs_f64(missing * 60.0 / limit.per_minute as f64))
+    }
+
+    /// Checks against the limit currently configured for the class.
+    pub async fn check_configured(
+        &self,
+        state: &AppState,
+        client: &str,
+        class: Class,
+    ) -> Result<(), String> {
+        let limit = class.limit(&state.config.read().await.agent.rate_limits);
+        self.check(client, class, limit).map_err(|wait| too_many(class, wait))
+    }
+
+    /// Forgets buckets that have filled back up; they'd start full anyway.
+    fn prune(&self) {
+        let now = Instant::now();
+        self.buckets.retain(|_, bucket| {
+            bucket.refill(now);
+            bucket.tokens < bucket.burst as f64
+        });
+    }
+}
+
+pub fn too_many(class: Class, wait: Duration) -> String {
+    format!("Too many {}; retry in {}s", class.name(), wait.as_secs().max(1))
+}
+
+/// The manager token or user a request was authenticated with, or else its
+/// source address.
+pub fn client_key(extensions: &Extensions) -> String {
+    if let Some(ManagerToken(token)) = extensions.get::<ManagerToken>() {
+        return format!("token:{}", token);
+    }
+    if let Some(session) = extensions.get::<Session>() {
+        return format!("user:{}", session.username);
+    }
+    if let Some(ClientIp(ip)) = extensions.get::<ClientIp>() {
+        return format!("ip:{}", ip);
+    }
+    match extensions.get::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(addr)) => format!("ip:{}", addr.ip()),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Who is calling, for handlers that count messages after the request,
+/// such as console WebSockets.
+pub struct ClientKey(pub String);
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientKey {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        Ok(ClientKey(client_key(&parts.extensions)))
+    }
+}
+
+fn classify(method: &Method, path: &str) -> Class {
+    if *method != Method::POST {
+        return Class::Api;
+    }
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    match segments.as_slice() {
+        ["api", "login"] => Class::Login,
+        ["api", "servers", "actions"] => Class::Actions,
+        ["api", "servers", _, "start" | "stop" | "restart" | "backup"] => Class::Actions,
+        ["api", "servers", _, "chat" | "prompt"] => Class::Commands,
+        ["api", "servers", _, "macros", _] => Class::Commands,
+        _ => Class::Api,
+    }
+}
+
+fn too_many_response(class: Class, wait: Duration) -> Response {
+    let retry_after = wait.as_secs().max(1).to_string();
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, retry_after)],
+        Json(serde_json::json!({ "error": too_many(class, wait) })),
+    )
+        .into_response()
+}
+
+/// Answers 429 with `Retry-After` once a client runs out of requests of
+/// the kind it is making.
+pub async fn limit(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let class = classify(req.method(), req.uri().path());
+    let limit = class.limit(&state.config.read().await.agent.rate_limits);
+    let client = client_key(req.extensions());
+    if let Err(wait) = state.rate_limiter.check(&client, class, limit) {
+        return too_many_response(class, wait);
+    }
+    next.run(req).await
+}
+
+/// Runs outside authentication and counts the requests it refuses per
+/// address; once an address runs out, it is refused before its credentials
+/// are even looked at.
+pub async fn limit_rejected(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let limit = Class::Rejected.limit(&state.config.read().await.agent.rate_limits);
+    let client = client_key(req.extensions());
+    if let Err(wait) = state.rate_limiter.peek(&client, Class::Rejected, limit) {
+        return too_many_response(Class::Rejected, wait);
+    }
+    let response = next.run(req).await;
+    if response.status() == StatusCode::UNAUTHORIZED {
+        let _ = state.rate_limiter.check(&client, Class::Rejected, limit);
+    }
+    response
+}
+
+pub fn spawn_pruner(state: AppState) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(PRUNE_INTERVAL).await;
+            state.rate_limiter.prune();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: RateLimit = RateLimit {
+        per_minute: 60,
+        burst: 2,
+    };
+
+    #[test]
+    fn bucket_allows_the_burst_then_refuses() {
+        let limiter = RateLimiter::default();
+        assert!(limiter.check("ip:1", Class::Api, LIMIT).is_ok());
+        assert!(limiter.check("ip:1", Class::Api, LIMIT).is_ok());
+        let wait = limiter.check("ip:1", Class::Api, LIMIT).unwrap_err();
+        assert!(wait <= Duration::from_secs(1));
+        // Other clients and classes have buckets of their own
+        assert!(limiter.check("ip:2", Class::Api, LIMIT).is_ok());
+        assert!(limiter.check("ip:1", Class::Login, LIMIT).is_ok());
+    }
+
+    #[test]
+    fn peek_leaves_the_token() {
+        let limiter = RateLimiter::default();
+        for _ in 0..5 {
+            assert!(limiter.peek("ip:1", Class::Rejected, LIMIT).is_ok());
+        }
+        limiter.check("ip:1", Class::Rejected, LIMIT).unwrap();
+        limiter.check("ip:1", Class::Rejected, LIMIT).unwrap();
+        assert!(limiter.peek("ip:1", Class::Rejected, LIMIT).is_err());
+    }
+
+    #[test]
+    fn zero_per_minute_turns_the_limit_off() {
+        let limiter = RateLimiter::default();
+        let off = RateLimit {
+            per_minute: 0,
+            burst: 0,
+        };
+        for _ in 0..100 {
+            assert!(limiter.check("ip:1", Class::Api, off).is_ok());
+        }
+    }
+
+    #[test]
+    fn requests_are_classified_by_path() {
+        assert_eq!(classify(&Method::POST, "/api/login"), Class::Login);
+        assert_eq!(classify(&Method::POST, "/api/servers/a/start"), Class::Actions);
+        assert_eq!(classify(&Method::POST, "/api/servers/a/chat"), Class::Commands);
+        assert_eq!(classify(&Method::GET, "/api/servers/a/start"), Class::Api);
+    }
+}
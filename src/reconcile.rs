@@ -0,0 +1,154 @@
+//! Converges each server towards its `desired_state`. On boot every server
+//! that should run is started (plain `autostart` servers included); after
+//! that only servers with an explicit `desired_state` are watched, and
+//! start failures are retried with backoff.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::{
+    config::{save_config, DesiredState},
+    process::{start_server, stop_server},
+    state::{AppState, ServerPhase},
+};
+
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(10);
+/// How long a server must stay down before it is started, leaving room for
+/// restarts and crash-restart delays that are already under way.
+const START_GRACE: Duration = Duration::from_secs(15);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+struct Backoff {
+    failures: u32,
+    next_attempt: Instant,
+}
+
+fn backoff_delay(failures: u32) -> Duration {
+    (RECONCILE_INTERVAL * 2u32.saturating_pow(failures.min(16) - 1)).min(MAX_BACKOFF)
+}
+
+/// Records an explicit start or stop as the server's new desired state so
+/// the reconciler doesn't undo it. Servers without an explicit desired
+/// state, and wake-on-demand servers, are left alone.
+pub async fn set_desired(state: &AppState, server_id: &str, desired: DesiredState) {
+    let mut config = state.config.write().await;
+    let Some(server) = config.servers.iter_mut().find(|s| s.id == server_id) else {
+        return;
+    };
+    if server.wake_on_demand || server.desired_state.is_none_or(|d| d == desired) {
+        return;
+    }
+    server.desired_state = Some(desired);
+    if let Err(e) = save_config(&config).await {
+        tracing::error!("Failed to save desired state of '{}': {}", server_id, e);
+    }
+}
+
+async fn boot(state: &AppState) {
+    if let Err(e) = crate::maintenance::check(state).await {
+        tracing::warn!("Skipping autostart: {}", e);
+        return;
+    }
+    let servers = state.config.read().await.servers.clone();
+    for server in servers {
+        if server.desired_state() != Some(DesiredState::Running) {
+            continue;
+        }
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = start_server(state, &server.id).await {
+                tracing::error!("Autostart failed for '{}': {}", server.id, e);
+            }
+        });
+    }
+}
+
+/// Per-server bookkeeping between passes.
+#[derive(Default)]
+struct Reconciler {
+    down_since: HashMap<String, Instant>,
+    backoff: HashMap<String, Backoff>,
+}
+
+impl Reconciler {
+    fn settled(&mut self, server_id: &str) {
+        self.down_since.remove(server_id);
+        self.backoff.remove(server_id);
+    }
+
+    async fn start(&mut self, state: &AppState, server_id: &str) {
+        let now = Instant::now();
+        let since = *self.down_since.entry(server_id.to_string()).or_insert(now);
+        let waiting = self.backoff.get(server_id).is_some_and(|b| b.next_attempt > now);
+        if since.elapsed() < START_GRACE || waiting {
+            return;
+        }
+        tracing::info!("Starting '{}' to match its desired state", server_id);
+        let result = start_server(state.clone(), server_id).await;
+        // Counted until a later pass sees the server up, so crash loops back off too
+        let backoff = self.backoff.entry(server_id.to_string()).or_insert(Backoff {
+            failures: 0,
+            next_attempt: now,
+        });
+        backoff.failures += 1;
+        let delay = backoff_delay(backoff.failures);
+        backoff.next_attempt = Instant::now() + delay;
+        if let Err(e) = result {
+            tracing::warn!(
+                "Could not start '{}' (attempt {}), retrying in {}s: {}",
+                server_id,
+                backoff.failures,
+                delay.as_secs(),
+                e
+            );
+        }
+    }
+}
+
+pub fn spawn(state: AppState) {
+    tokio::spawn(async move {
+        boot(&state).await;
+        let mut reconciler = Reconciler::default();
+        loop {
+            tokio::time::sleep(RECONCILE_INTERVAL).await;
+            if crate::maintenance::check(&state).await.is_err() {
+                continue;
+            }
+            let servers = state.config.read().await.servers.clone();
+            let sleeping: Vec<String> = state.wake_listeners.lock().await.keys().cloned().collect();
+            reconciler.down_since.retain(|id, _| servers.iter().any(|s| &s.id == id));
+            reconciler.backoff.retain(|id, _| servers.iter().any(|s| &s.id == id));
+
+            for server in servers {
+                let Some(desired) = server.desired_state else {
+                    continue;
+                };
+                let instance = state.servers.get(&server.id).map(|i| i.value().clone());
+                match (desired, instance) {
+                    (DesiredState::Running, Some(instance)) => {
+                        // Only a fully started server clears the backoff
+                        if *instance.phase.lock().await == ServerPhase::Running {
+                            reconciler.settled(&server.id);
+                        }
+                    }
+                    (DesiredState::Stopped, None) => reconciler.settled(&server.id),
+                    // Held by the wake listener, which starts it on demand
+                    (DesiredState::Running, None) if sleeping.contains(&server.id) => {}
+                    (DesiredState::Running, None) => reconciler.start(&state, &server.id).await,
+                    (DesiredState::Stopped, Some(instance)) => {
+                        if *instance.phase.lock().await == ServerPhase::Stopping {
+                            continue;
+                        }
+                        tracing::info!("Stopping '{}' to match its desired state", server.id);
+                        let state = state.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = stop_server(state, &server.id).await {
+                                tracing::error!("Reconciling '{}' failed: {}", server.id, e);
+                            }
+                        });
+                    }
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,129 @@
+//! Masks secrets in console output before it reaches subscribers, the
+//! replay buffer or anything kept from them: settings echoed as
+//! `key=value`, bearer credentials, passwords players type into login
+//! commands, and the secrets the server's own files hold.
+
+use std::{borrow::Cow, sync::OnceLock};
+
+use regex::Regex;
+
+use crate::{config::ServerConfig, properties::ServerProperties, proxy};
+
+pub const MASK: &str = "********";
+
+/// Shorter values would mask ordinary words wherever they appear.
+const MIN_SECRET_LEN: usize = 4;
+
+fn patterns() -> &'static [(Regex, String)] {
+    static PATTERNS: OnceLock<Vec<(Regex, String)>> = OnceLock::new();
+    PATTERNS.get_or_init(|| {
+        [
+            // rcon.password=..., "secret": "...", api-key: ...
+            (
+                concat!(
+                    r#"(?i)\b((?:rcon\.)?password|passwd|secret|token|api[-_]?key)"#,
+                    r#"(["']?\s*[=:]\s*["']?)[^\s"',;]+"#,
+                ),
+                "${1}${2}",
+            ),
+            (r"(?i)\b(bearer|basic)(\s+)[A-Za-z0-9._~+/=-]{8,}", "${1}${2}"),
+            // AuthMe and similar: "Steve issued server command: /login hunter2"
+            (
+                concat!(
+                    r"(?i)(issued server command: ",
+                    r"/(?:login|l|register|reg|changepassword|changepw|cpw)\b).*",
+                ),
+                "${1} ",
+            ),
+        ]
+        .into_iter()
+        .map(|(pattern, kept)| {
+            (Regex::new(pattern).expect("valid regex"), format!("{}{}", kept, MASK))
+        })
+        .collect()
+    })
+}
+
+#[derive(Debug, Default)]
+pub struct Redactor {
+    /// Exact values to mask wherever they show up.
+    secrets: Vec<String>,
+}
+
+impl Redactor {
+    /// Collects the secrets in the server's files as they are when it
+    /// starts: its RCON password and any proxy forwarding secret.
+    pub async fn load(cfg: &ServerConfig) -> Self {
+        let mut secrets = Vec::new();
+        if let Ok(properties) = ServerProperties::load(&cfg.directory).await {
+            secrets.extend(properties.get("rcon.password").map(str::to_string));
+        }
+        secrets.extend(proxy::read_secret(&cfg.directory).await);
+        secrets.extend(proxy::backend_secret(&cfg.directory).await);
+        secrets.retain(|s| s.len() >= MIN_SECRET_LEN);
+        secrets.sort();
+        secrets.dedup();
+        Self { secrets }
+    }
+
+    pub fn redact(&self, mut line: String) -> String {
+        for secret in &self.secrets {
+            if line.contains(secret.as_str()) {
+                line = line.replace(secret.as_str(), MASK);
+            }
+        }
+        for (pattern, replacement) in patterns() {
+            if let Cow::Owned(replaced) = pattern.replace_all(&line, replacement.as_str()) {
+                line = replaced;
+            }
+        }
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redact(line: &str) -> String {
+        Redactor::default().redact(line.to_string())
+    }
+
+    #[test]
+    fn settings_keep_their_key() {
+        assert_eq!(redact("rcon.password=hunter22"), format!("rcon.password={}", MASK));
+        assert_eq!(redact(r#"{"secret": "abc123"}"#), format!(r#"{{"secret": "{}"}}"#, MASK));
+        assert_eq!(redact("api_key: xyz, next"), format!("api_key: {}, next", MASK));
+    }
+
+    #[test]
+    fn bearer_credentials_are_masked() {
+        assert_eq!(
+            redact("Authorization: Bearer abcdefgh12345678"),
+            format!("Authorization: Bearer {}", MASK)
+        );
+        // Too short to be a credential
+        assert_eq!(redact("basic setup"), "basic setup");
+    }
+
+    #[test]
+    fn login_commands_lose_their_arguments() {
+        assert_eq!(
+            redact("Steve issued server command: /login hunter2"),
+            format!("Steve issued server command: /login {}", MASK)
+        );
+        let line = "Steve issued server command: /logout";
+        assert_eq!(redact(line), line);
+    }
+
+    #[test]
+    fn known_secrets_are_masked_anywhere() {
+        let redactor = Redactor {
+            secrets: vec!["s3cr3tvalue".to_string()],
+        };
+        assert_eq!(
+            redactor.redact("forwarding with s3cr3tvalue now".to_string()),
+            format!("forwarding with {} now", MASK)
+        );
+    }
+}
@@ -0,0 +1,236 @@
+//! Picks up hand edits to the config file, on SIGHUP or when the file
+//! changes, without restarting the agent. New and stopped servers take their
+//! new config at once; running servers keep going and are flagged when a
+//! change only applies on their next start.
+
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+
+use crate::{
+    config::{
+        config_path, read_config, validate_node_config, validate_server_config, DesiredState,
+        ServerConfig,
+    },
+    process::start_server,
+    state::{AgentEvent, AppState},
+};
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Agent settings that are only read at startup.
+const AGENT_RESTART_FIELDS: &[&str] = &["bind_address", "grpc_bind_address", "otlp", "cors"];
+
+/// Server settings read afresh whenever they are used, so changing them
+/// doesn't call for a restart of a running server.
+const LIVE_SERVER_FIELDS: &[&str] = &[
+    "name",
+    "autostart",
+    "desired_state",
+    "tags",
+    "restart_policy",
+    "idle_shutdown_minutes",
+    "watchdog",
+    "macros",
+    "hooks",
+    "stop_steps",
+    "announcements",
+    "whitelist_group",
+    "ban_group",
+];
+
+#[derive(Debug, Clone, Default, Serialize, utoipa::ToSchema)]
+pub struct ReloadReport {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+    /// Running servers whose change applies on their next start.
+    pub restart_required: Vec<String>,
+    /// Agent settings that only apply after the agent restarts.
+    pub agent_restart_required: Vec<String>,
+    /// Edits that were not applied, and why.
+    pub rejected: Vec<String>,
+}
+
+impl ReloadReport {
+    fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && self.agent_restart_required.is_empty()
+            && self.rejected.is_empty()
+    }
+}
+
+fn without(value: serde_json::Value, fields: &[&str]) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(mut map) => {
+            for field in fields {
+                map.remove(*field);
+            }
+            serde_json::Value::Object(map)
+        }
+        other => other,
+    }
+}
+
+fn same(a: &impl Serialize, b: &impl Serialize) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+/// Folds the file's servers into the running config, keeping the old entry
+/// wherever the new one can't be applied.
+fn merge_servers(
+    state: &AppState,
+    current: &[ServerConfig],
+    desired: Vec<ServerConfig>,
+    report: &mut ReloadReport,
+) -> Vec<ServerConfig> {
+    let mut merged = Vec::with_capacity(desired.len());
+    for server in desired {
+        let running = state.servers.contains_key(&server.id);
+        let Some(old) = current.iter().find(|s| s.id == server.id) else {
+            match validate_server_config(&server) {
+                Ok(()) => {
+                    report.added.push(server.id.clone());
+                    merged.push(server);
+                }
+                Err(e) => report.rejected.push(format!("{}: {}", server.id, e)),
+            }
+            continue;
+        };
+        if same(old, &server) {
+            merged.push(server);
+            continue;
+        }
+        if let Err(e) = validate_server_config(&server) {
+            report.rejected.push(format!("{}: {}", server.id, e));
+            merged.push(old.clone());
+            continue;
+        }
+        if running && (old.directory != server.directory || old.port != server.port) {
+            report.rejected.push(format!(
+                "{}: directory and port cannot change while it is running",
+                server.id
+            ));
+            merged.push(old.clone());
+            continue;
+        }
+        let launch_changed = !same(
+            &without(serde_json::to_value(old).unwrap_or_default(), LIVE_SERVER_FIELDS),
+            &without(serde_json::to_value(&server).unwrap_or_default(), LIVE_SERVER_FIELDS),
+        );
+        if running && launch_changed {
+            report.restart_required.push(server.id.clone());
+        }
+        report.changed.push(server.id.clone());
+        merged.push(server);
+    }
+    for old in current {
+        if merged.iter().any(|s| s.id == old.id) {
+            continue;
+        }
+        if state.servers.contains_key(&old.id) {
+            report
+                .rejected
+                .push(format!("{}: stop the server before removing it", old.id));
+            merged.push(old.clone());
+        } else {
+            report.removed.push(old.id.clone());
+        }
+    }
+    merged
+}
+
+/// Re-reads the config file and applies it. An unreadable file changes nothing.
+pub async fn reload(state: &AppState) -> Result<ReloadReport, String> {
+    let file = read_config().await.map_err(|e| format!("{:#}", e))?;
+
+    let mut report = ReloadReport::default();
+    let mut config = state.config.write().await;
+    let old_agent = serde_json::to_value(&config.agent).unwrap_or_default();
+    let new_agent = serde_json::to_value(&file.agent).unwrap_or_default();
+    for field in AGENT_RESTART_FIELDS {
+        if old_agent.get(field) != new_agent.get(field) {
+            report.agent_restart_required.push(field.to_string());
+        }
+    }
+    config.agent = file.agent;
+
+    match file.nodes.iter().try_for_each(validate_node_config) {
+        Ok(()) => config.nodes = file.nodes,
+        Err(e) => report.rejected.push(format!("nodes: {}", e)),
+    }
+
+    config.servers = merge_servers(state, &config.servers, file.servers, &mut report);
+    let to_start: Vec<String> = config
+        .servers
+        .iter()
+        .filter(|s| report.added.contains(&s.id))
+        .filter(|s| s.desired_state() == Some(DesiredState::Running))
+        .map(|s| s.id.clone())
+        .collect();
+    drop(config);
+
+    for id in to_start {
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = start_server(state, &id).await {
+                tracing::error!("Autostart failed for '{}': {}", id, e);
+            }
+        });
+    }
+    if !report.is_empty() {
+        tracing::info!("Reloaded {}: {:?}", config_path().display(), report);
+        for rejected in &report.rejected {
+            tracing::warn!("Config reload skipped {}", rejected);
+        }
+        let _ = state.events_tx.send(AgentEvent::ConfigReloaded {
+            report: report.clone(),
+        });
+    }
+    Ok(report)
+}
+
+async fn modified() -> Option<SystemTime> {
+    tokio::fs::metadata(config_path()).await.and_then(|m| m.modified()).ok()
+}
+
+async fn reload_logged(state: &AppState) {
+    if let Err(e) = reload(state).await {
+        tracing::error!("Config reload failed: {}", e);
+    }
+}
+
+/// Reloads when the config file's modification time changes, which
+/// includes the agent's own saves; those find nothing to apply.
+pub fn spawn_watcher(state: AppState) {
+    let watched = state.clone();
+    tokio::spawn(async move {
+        let mut last = modified().await;
+        loop {
+            tokio::time::sleep(WATCH_INTERVAL).await;
+            let current = modified().await;
+            if current.is_some() && current != last {
+                last = current;
+                reload_logged(&watched).await;
+            }
+        }
+    });
+
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                tracing::warn!("Failed to install SIGHUP handler: {}", e);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            tracing::info!("SIGHUP received, reloading {}", config_path().display());
+            reload_logged(&state).await;
+        }
+    });
+}
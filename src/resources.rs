@@ -0,0 +1,93 @@
+use serde::{Deserialize, Serialize};
+use sysinfo::System;
+
+use crate::{config::ServerConfig, state::AppState};
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct NodeResources {
+    pub total_memory_mb: u64,
+    pub reserved_memory_mb: u64,
+    /// Sum of `memory_mb` over running servers.
+    pub allocated_memory_mb: u64,
+    pub available_memory_mb: u64,
+    pub total_cpu_cores: f32,
+    pub reserved_cpu_cores: f32,
+    pub allocatable_cpu_cores: f32,
+    /// Sum of `cpu_limit` over running servers that set one.
+    pub allocated_cpu_cores: f32,
+    pub available_cpu_cores: f32,
+}
+
+pub async fn node_resources(state: &AppState) -> NodeResources {
+    let mut sys = System::new();
+    sys.refresh_memory();
+    sys.refresh_cpu();
+    let total_memory_mb = sys.total_memory() / (1024 * 1024);
+    let total_cpu_cores = sys.cpus().len() as f32;
+
+    let config = state.config.read().await;
+    let reserved_memory_mb = config.agent.reserved_memory_mb as u64;
+    let reserved_cpu_cores = config.agent.reserved_cpu_cores;
+    let allocated_memory_mb: u64 = config
+        .servers
+        .iter()
+        .filter(|s| state.servers.contains_key(&s.id))
+        .map(|s| s.memory_mb as u64)
+        .sum();
+    let allocated_cpu_cores: f32 = config
+        .servers
+        .iter()
+        .filter(|s| state.servers.contains_key(&s.id))
+        .filter_map(|s| s.cpu_limit)
+        .fold(0.0, |total, cores| total + cores);
+    let allocatable_cpu_cores = (total_cpu_cores - reserved_cpu_cores).max(0.0);
+
+    NodeResources {
+        total_memory_mb,
+        reserved_memory_mb,
+        allocated_memory_mb,
+        available_memory_mb: total_memory_mb
+            .saturating_sub(reserved_memory_mb)
+            .saturating_sub(allocated_memory_mb),
+        total_cpu_cores,
+        reserved_cpu_cores,
+        allocatable_cpu_cores,
+        allocated_cpu_cores,
+        available_cpu_cores: (allocatable_cpu_cores - allocated_cpu_cores).max(0.0),
+    }
+}
+
+/// Refuses to start a server whose heap or CPU limit would eat into the
+/// reserved slice. Only as good as the moment it runs; `start_server` runs
+/// it again while it holds the start lock.
+pub async fn check_admission(state: &AppState, server_cfg: &ServerConfig) -> Result<(), String> {
+    let resources = node_resources(state).await;
+    if server_cfg.memory_mb as u64 > resources.available_memory_mb {
+        return Err(format!(
+            "Not enough memory to start '{}': needs {} MB but only {} MB is available \
+             ({} MB total, {} MB reserved, {} MB allocated)",
+            server_cfg.id,
+            server_cfg.memory_mb,
+            resources.available_memory_mb,
+            resources.total_memory_mb,
+            resources.reserved_memory_mb,
+            resources.allocated_memory_mb,
+        ));
+    }
+    // Servers without a limit may use any core, so only limits are counted
+    if let Some(cpu_limit) = server_cfg.cpu_limit {
+        if cpu_limit > resources.available_cpu_cores {
+            return Err(format!(
+                "Not enough CPU to start '{}': needs {} cores but only {} are available \
+                 ({} total, {} reserved, {} allocated)",
+                server_cfg.id,
+                cpu_limit,
+                resources.available_cpu_cores,
+                resources.total_cpu_cores,
+                resources.reserved_cpu_cores,
+                resources.allocated_cpu_cores,
+            ));
+        }
+    }
+    Ok(())
+}
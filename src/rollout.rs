@@ -0,0 +1,370 @@
+//! Staged Paper updates: new builds go to beta-channel servers first, soak
+//! there, and are promoted to stable-channel servers only if they held up.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{ServerConfig, UpdateChannel},
+    downloads,
+    process::restart_server,
+    runtimes::detect_minecraft_version,
+    state::AppState,
+};
+
+const ROLLOUT_FILE: &str = "paper-rollout.json";
+pub(crate) const PAPERMC_API: &str = "https://api.papermc.io/v2/projects";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildStage {
+    /// Running on beta-channel servers, waiting out the soak period.
+    Testing,
+    /// Cleared for stable-channel servers.
+    Promoted,
+    /// Crashed too often on beta; never offered to stable.
+    Rejected,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct BuildStatus {
+    pub minecraft_version: String,
+    pub build: u32,
+    pub file_name: String,
+    pub sha256: String,
+    pub stage: BuildStage,
+    pub discovered_at_ms: u64,
+    #[serde(default)]
+    pub promoted_at_ms: Option<u64>,
+    /// Unexpected exits of beta servers while on this build.
+    #[serde(default)]
+    pub crashes: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct InstalledBuild {
+    pub minecraft_version: String,
+    pub build: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct RolloutState {
+    #[serde(default)]
+    pub builds: Vec<BuildStatus>,
+    /// Build each managed server was last updated to, by server id.
+    #[serde(default)]
+    pub installed: HashMap<String, InstalledBuild>,
+}
+
+impl RolloutState {
+    fn build_mut(&mut self, version: &str, build: u32) -> Option<&mut BuildStatus> {
+        self.builds
+            .iter_mut()
+            .find(|b| b.minecraft_version == version && b.build == build)
+    }
+
+    fn latest(&self, version: &str, stage: BuildStage) -> Option<&BuildStatus> {
+        self.builds
+            .iter()
+            .filter(|b| b.minecraft_version == version && b.stage == stage)
+            .max_by_key(|b| b.build)
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn rollout_path(data_directory: &str) -> std::path::PathBuf {
+    Path::new(data_directory).join(ROLLOUT_FILE)
+}
+
+pub async fn load_rollout(data_directory: &str) -> RolloutState {
+    match tokio::fs::read_to_string(rollout_path(data_directory)).await {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+            tracing::warn!("Ignoring unreadable {}: {}", ROLLOUT_FILE, e);
+            RolloutState::default()
+        }),
+        Err(_) => RolloutState::default(),
+    }
+}
+
+async fn save_rollout(data_directory: &str, rollout: &RolloutState) -> anyhow::Result<()> {
+    let path = rollout_path(data_directory);
+    let tmp = path.with_extension("json.tmp");
+    let json =
+        serde_json::to_string_pretty(rollout).context("Failed to serialize rollout state")?;
+    tokio::fs::create_dir_all(data_directory)
+        .await
+        .context("Failed to create data directory")?;
+    tokio::fs::write(&tmp, json)
+        .await
+        .context("Failed to write rollout state")?;
+    tokio::fs::rename(&tmp, &path)
+        .await
+        .context("Failed to rename rollout state")?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct PaperBuilds {
+    builds: Vec<PaperBuild>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PaperBuild {
+    pub build: u32,
+    channel: String,
+    pub downloads: HashMap<String, PaperDownload>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PaperDownload {
+    pub name: String,
+    pub sha256: String,
+}
+
+/// Latest stable build of a PaperMC `project` ("paper", "velocity") for `version`.
+pub(crate) async fn latest_build(
+    project: &str,
+    version: &str,
+) -> Result<Option<PaperBuild>, String> {
+    let url = format!("{}/{}/versions/{}/builds", PAPERMC_API, project, version);
+    let body = downloads::fetch_text(&url).await?;
+    let builds: PaperBuilds = serde_json::from_str(&body)
+        .map_err(|e| format!("Failed to parse {} build list: {}", project, e))?;
+    Ok(builds
+        .builds
+        .into_iter()
+        .filter(|b| b.channel == "default")
+        .max_by_key(|b| b.build))
+}
+
+async fn managed_servers(state: &AppState) -> Vec<(ServerConfig, UpdateChannel)> {
+    let config = state.config.read().await;
+    config
+        .servers
+        .iter()
+        .filter_map(|s| s.update_channel.map(|c| (s.clone(), c)))
+        .collect()
+}
+
+/// Installs `build` as the server's jar and restarts it if it was running.
+async fn apply_build(
+    state: &AppState,
+    server: &ServerConfig,
+    build: &BuildStatus,
+) -> Result<(), String> {
+    let data_directory = state.config.read().await.agent.data_directory.clone();
+    let url = format!(
+        "{}/paper/versions/{}/builds/{}/downloads/{}",
+        PAPERMC_API, build.minecraft_version, build.build, build.file_name
+    );
+    let dest = Path::new(&server.directory).join(&server.jar);
+    downloads::install_cached(&data_directory, &url, &build.sha256, &dest).await?;
+
+    {
+        let mut rollout = state.rollout.lock().await;
+        rollout.installed.insert(
+            server.id.clone(),
+            InstalledBuild {
+                minecraft_version: build.minecraft_version.clone(),
+                build: build.build,
+            },
+        );
+        if let Err(e) = save_rollout(&data_directory, &rollout).await {
+            tracing::warn!("Failed to save rollout state: {:#}", e);
+        }
+    }
+    tracing::info!(
+        "Updated '{}' to Paper {} build {}",
+        server.id,
+        build.minecraft_version,
+        build.build
+    );
+
+    if state.servers.contains_key(&server.id) {
+        restart_server(state.clone(), &server.id).await?;
+    }
+    Ok(())
+}
+
+/// Polls the Paper API for new builds and rolls them through the channels.
+///
+/// Checks started while another is running wait for it, then see the builds it installed.
+pub async fn run_rollout_cycle(state: &AppState) {
+    let _cycle = state.rollout_cycle.lock().await;
+    let servers = managed_servers(state).await;
+    let (data_directory, soak_ms, max_crashes) = {
+        let config = state.config.read().await;
+        (
+            config.agent.data_directory.clone(),
+            config.agent.update_soak_secs * 1000,
+            config.agent.update_max_crashes,
+        )
+    };
+
+    let mut versions: HashMap<String, Vec<(ServerConfig, UpdateChannel)>> = HashMap::new();
+    for (server, channel) in servers {
+        match detect_minecraft_version(&server).await {
+            Some((version, _)) => versions.entry(version).or_default().push((server, channel)),
+            None => tracing::warn!("Cannot update '{}': Minecraft version unknown", server.id),
+        }
+    }
+
+    for (version, servers) in versions {
+        match latest_build("paper", &version).await {
+            Ok(Some(latest)) => {
+                let mut rollout = state.rollout.lock().await;
+                if !rollout
+                    .builds
+                    .iter()
+                    .any(|b| b.minecraft_version == version && b.build == latest.build)
+                {
+                    if let Some(app) = latest.downloads.get("application") {
+                        tracing::info!("New Paper build {} for {}", latest.build, version);
+                        rollout.builds.push(BuildStatus {
+                            minecraft_version: version.clone(),
+                            build: latest.build,
+                            file_name: app.name.clone(),
+                            sha256: app.sha256.clone(),
+                            stage: BuildStage::Testing,
+                            discovered_at_ms: now_ms(),
+                            promoted_at_ms: None,
+                            crashes: 0,
+                        });
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Paper update check for {} failed: {}", version, e),
+        }
+
+        // Promote or reject builds whose soak period is over
+        {
+            let mut rollout = state.rollout.lock().await;
+            for build in rollout
+                .builds
+                .iter_mut()
+                .filter(|b| b.minecraft_version == version)
+            {
+                if build.stage != BuildStage::Testing {
+                    continue;
+                }
+                if build.crashes > max_crashes {
+                    tracing::warn!(
+                        "Rejecting Paper {} build {} after {} crashes",
+                        version,
+                        build.build,
+                        build.crashes
+                    );
+                    build.stage = BuildStage::Rejected;
+                } else if now_ms().saturating_sub(build.discovered_at_ms) >= soak_ms {
+                    tracing::info!(
+                        "Promoting Paper {} build {} to stable",
+                        version,
+                        build.build
+                    );
+                    build.stage = BuildStage::Promoted;
+                    build.promoted_at_ms = Some(now_ms());
+                }
+            }
+            if let Err(e) = save_rollout(&data_directory, &rollout).await {
+                tracing::warn!("Failed to save rollout state: {:#}", e);
+            }
+        }
+
+        for (server, channel) in servers {
+            let target = {
+                let rollout = state.rollout.lock().await;
+                let stage = match channel {
+                    UpdateChannel::Beta => BuildStage::Testing,
+                    UpdateChannel::Stable => BuildStage::Promoted,
+                };
+                let candidate = rollout.latest(&version, stage).cloned();
+                // Beta servers also pick up promoted builds newer than anything in testing
+                let candidate = match (channel, candidate) {
+                    (UpdateChannel::Beta, c) => {
+                        let promoted = rollout.latest(&version, BuildStage::Promoted).cloned();
+                        match (c, promoted) {
+                            (Some(t), Some(p)) => Some(if p.build > t.build { p } else { t }),
+                            (t, p) => t.or(p),
+                        }
+                    }
+                    (_, c) => c,
+                };
+                let current = rollout.installed.get(&server.id).map(|i| i.build);
+                candidate.filter(|c| current.is_none_or(|cur| c.build > cur))
+            };
+            if let Some(build) = target {
+                if let Err(e) = apply_build(state, &server, &build).await {
+                    tracing::error!("Failed to update '{}': {}", server.id, e);
+                }
+            }
+        }
+    }
+}
+
+/// Counts an unexpected exit against the build a beta server is running.
+pub async fn record_crash(state: &AppState, server_id: &str) {
+    let is_beta = {
+        let config = state.config.read().await;
+        config
+            .servers
+            .iter()
+            .any(|s| s.id == server_id && s.update_channel == Some(UpdateChannel::Beta))
+    };
+    if !is_beta {
+        return;
+    }
+    let data_directory = state.config.read().await.agent.data_directory.clone();
+    let mut rollout = state.rollout.lock().await;
+    let Some(installed) = rollout.installed.get(server_id).cloned() else {
+        return;
+    };
+    if let Some(build) = rollout.build_mut(&installed.minecraft_version, installed.build) {
+        if build.stage == BuildStage::Testing {
+            build.crashes += 1;
+            if let Err(e) = save_rollout(&data_directory, &rollout).await {
+                tracing::warn!("Failed to save rollout state: {:#}", e);
+            }
+        }
+    }
+}
+
+/// Manually clears a build for stable servers ahead of its soak period.
+pub async fn promote_build(
+    state: &AppState,
+    version: &str,
+    build: u32,
+) -> Result<BuildStatus, String> {
+    let data_directory = state.config.read().await.agent.data_directory.clone();
+    let mut rollout = state.rollout.lock().await;
+    let status = rollout
+        .build_mut(version, build)
+        .ok_or_else(|| format!("Paper {} build {} is not tracked", version, build))?;
+    status.stage = BuildStage::Promoted;
+    status.promoted_at_ms = Some(now_ms());
+    let status = status.clone();
+    save_rollout(&data_directory, &rollout)
+        .await
+        .map_err(|e| format!("{:#}", e))?;
+    Ok(status)
+}
+
+pub fn spawn_scheduler(state: AppState) {
+    tokio::spawn(async move {
+        loop {
+            let interval = state.config.read().await.agent.update_check_interval_secs;
+            run_rollout_cycle(&state).await;
+            tokio::time::sleep(std::time::Duration::from_secs(interval.max(60))).await;
+        }
+    });
+}
@@ -0,0 +1,282 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::ServerConfig, downloads};
+
+/// Java major versions the agent can download and manage.
+pub const SUPPORTED_JAVA_VERSIONS: &[u32] = &[8, 17, 21];
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct RuntimeInfo {
+    pub java_version: u32,
+    pub installed: bool,
+    pub path: String,
+}
+
+pub fn runtimes_dir(data_directory: &str) -> PathBuf {
+    Path::new(data_directory).join("runtimes")
+}
+
+fn runtime_home(data_directory: &str, java_version: u32) -> PathBuf {
+    runtimes_dir(data_directory).join(format!("temurin-{}", java_version))
+}
+
+pub fn runtime_java(data_directory: &str, java_version: u32) -> PathBuf {
+    runtime_home(data_directory, java_version).join("bin").join("java")
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct JavaSelection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub minecraft_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub required_java_version: Option<u32>,
+    pub java_path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub java_version: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct JarVersionInfo {
+    id: String,
+    #[serde(default)]
+    java_version: Option<u32>,
+}
+
+/// Reads `version.json` from the server jar, as shipped by vanilla 1.14+
+/// and the Paper/Purpur bundlers.
+pub async fn detect_minecraft_version(cfg: &ServerConfig) -> Option<(String, u32)> {
+    let jar = Path::new(&cfg.directory).join(&cfg.jar);
+    let output = tokio::process::Command::new("unzip")
+        .arg("-p")
+        .arg(&jar)
+        .arg("version.json")
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let info: JarVersionInfo = serde_json::from_slice(&output.stdout).ok()?;
+    let required = info
+        .java_version
+        .or_else(|| required_java_for(&info.id))?;
+    Some((info.id, required))
+}
+
+/// Minimum Java major version for a Minecraft release id like "1.20.4".
+fn required_java_for(minecraft_version: &str) -> Option<u32> {
+    let mut parts = minecraft_version.split('.').map(|p| p.parse::<u32>().ok());
+    let major = parts.next()??;
+    let minor = parts.next()??;
+    let patch = parts.next().flatten().unwrap_or(0);
+    if major != 1 {
+        return Some(21);
+    }
+    Some(match (minor, patch) {
+        (m, _) if m < 17 => 8,
+        (17, _) => 16,
+        (m, _) if m < 20 => 17,
+        (20, p) if p < 5 => 17,
+        _ => 21,
+    })
+}
+
+/// Major version reported by `java -version`, e.g. 8 for "1.8.0_392".
+pub async fn java_major_version(java: &Path) -> Option<u32> {
+    let output = tokio::process::Command::new(java)
+        .arg("-version")
+        .output()
+        .await
+        .ok()?;
+    let text = String::from_utf8_lossy(&output.stderr);
+    let version = text.split('"').nth(1)?;
+    let mut parts = version.split(['.', '_', '-', '+']);
+    match parts.next()?.parse::<u32>().ok()? {
+        1 => parts.next()?.parse().ok(),
+        major => Some(major),
+    }
+}
+
+/// Resolves the java executable for a server and checks it can run the
+/// server's Minecraft version. When no runtime is configured and the
+/// system java is too old, an installed managed runtime is picked instead.
+pub async fn select_java(cfg: &ServerConfig, data_directory: &str) -> Result<JavaSelection, String> {
+    let configured = java_binary(cfg, data_directory)?;
+    let detected = detect_minecraft_version(cfg).await;
+    let (minecraft_version, required) = match detected {
+        Some((id, required)) => (Some(id), Some(required)),
+        None => (None, None),
+    };
+
+    let mut java = configured;
+    let mut java_version = match cfg.java_version {
+        Some(v) if cfg.java_path.is_none() => Some(v),
+        _ => java_major_version(&java).await,
+    };
+
+    if let Some(required) = required {
+        let too_old = java_version.is_some_and(|v| v < required);
+        if too_old && cfg.java_path.is_none() && cfg.java_version.is_none() {
+            let managed = SUPPORTED_JAVA_VERSIONS
+                .iter()
+                .copied()
+                .filter(|&v| v >= required)
+                .find(|&v| runtime_java(data_directory, v).exists());
+            if let Some(v) = managed {
+                java = runtime_java(data_directory, v);
+                java_version = Some(v);
+            }
+        }
+        if let Some(found) = java_version {
+            if found < required {
+                return Err(format!(
+                    "Minecraft {} requires Java {} or newer but {} is Java {}; \
+                     set java_version or install a runtime with POST /api/runtimes/{}",
+                    minecraft_version.as_deref().unwrap_or("?"),
+                    required,
+                    java.display(),
+                    found,
+                    SUPPORTED_JAVA_VERSIONS
+                        .iter()
+                        .find(|&&v| v >= required)
+                        .unwrap_or(&21),
+                ));
+            }
+        }
+    }
+
+    Ok(JavaSelection {
+        minecraft_version,
+        required_java_version: required,
+        java_path: java.to_string_lossy().into_owned(),
+        java_version,
+    })
+}
+
+/// The java executable a server is configured to launch with.
+pub fn java_binary(cfg: &ServerConfig, data_directory: &str) -> Result<PathBuf, String> {
+    if let Some(ref path) = cfg.java_path {
+        return Ok(PathBuf::from(path));
+    }
+    if let Some(version) = cfg.java_version {
+        let java = runtime_java(data_directory, version);
+        if !java.exists() {
+            return Err(format!(
+                "Java {} runtime is not installed; install it with POST /api/runtimes/{}",
+                version, version
+            ));
+        }
+        return Ok(java);
+    }
+    Ok(PathBuf::from("java"))
+}
+
+pub fn list_runtimes(data_directory: &str) -> Vec<RuntimeInfo> {
+    SUPPORTED_JAVA_VERSIONS
+        .iter()
+        .map(|&java_version| {
+            let java = runtime_java(data_directory, java_version);
+            RuntimeInfo {
+                java_version,
+                installed: java.exists(),
+                path: java.to_string_lossy().into_owned(),
+            }
+        })
+        .collect()
+}
+
+fn adoptium_arch() -> Result<&'static str, String> {
+    match std::env::consts::ARCH {
+        "x86_64" => Ok("x64"),
+        "aarch64" => Ok("aarch64"),
+        other => Err(format!("No managed Java runtimes for architecture '{}'", other)),
+    }
+}
+
+#[derive(Deserialize)]
+struct AdoptiumRelease {
+    binary: AdoptiumBinary,
+}
+
+#[derive(Deserialize)]
+struct AdoptiumBinary {
+    package: AdoptiumPackage,
+}
+
+#[derive(Deserialize)]
+struct AdoptiumPackage {
+    link: String,
+    checksum: String,
+}
+
+/// Downloads the latest Temurin JRE for `java_version` into the runtimes directory.
+pub async fn install_runtime(data_directory: &str, java_version: u32) -> Result<RuntimeInfo, String> {
+    if !SUPPORTED_JAVA_VERSIONS.contains(&java_version) {
+        return Err(format!(
+            "Unsupported Java version {}; supported versions are {:?}",
+            java_version, SUPPORTED_JAVA_VERSIONS
+        ));
+    }
+    let url = format!(
+        "https://api.adoptium.net/v3/assets/latest/{}/hotspot?architecture={}&image_type=jre&os=linux&vendor=eclipse",
+        java_version,
+        adoptium_arch()?
+    );
+    let body = downloads::fetch_text(&url).await?;
+    let releases: Vec<AdoptiumRelease> = serde_json::from_str(&body)
+        .map_err(|e| format!("Failed to parse Adoptium release list: {}", e))?;
+    let package = releases
+        .into_iter()
+        .next()
+        .map(|r| r.binary.package)
+        .ok_or_else(|| format!("No Temurin {} JRE release found", java_version))?;
+
+    let archive = downloads::fetch_cached(data_directory, &package.link, &package.checksum).await?;
+
+    let home = runtime_home(data_directory, java_version);
+    let staging = runtimes_dir(data_directory).join(format!(".temurin-{}.staging", java_version));
+    let _ = tokio::fs::remove_dir_all(&staging).await;
+    tokio::fs::create_dir_all(&staging)
+        .await
+        .map_err(|e| format!("Failed to create runtime directory: {}", e))?;
+
+    let output = tokio::process::Command::new("tar")
+        .arg("-xzf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(&staging)
+        .arg("--strip-components=1")
+        .output()
+        .await
+        .map_err(|e| format!("Failed to execute tar command: {}", e))?;
+    if !output.status.success() {
+        let _ = tokio::fs::remove_dir_all(&staging).await;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Tar command failed: {}", stderr));
+    }
+
+    let _ = tokio::fs::remove_dir_all(&home).await;
+    tokio::fs::rename(&staging, &home)
+        .await
+        .map_err(|e| format!("Failed to install runtime: {}", e))?;
+
+    tracing::info!("Installed Temurin {} JRE at {:?}", java_version, home);
+    let java = runtime_java(data_directory, java_version);
+    Ok(RuntimeInfo {
+        java_version,
+        installed: java.exists(),
+        path: java.to_string_lossy().into_owned(),
+    })
+}
+
+pub async fn remove_runtime(data_directory: &str, java_version: u32) -> Result<(), String> {
+    match tokio::fs::remove_dir_all(runtime_home(data_directory, java_version)).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Err(format!("Java {} runtime is not installed", java_version))
+        }
+        Err(e) => Err(format!("Failed to remove runtime: {}", e)),
+    }
+}
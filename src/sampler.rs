@@ -0,0 +1,148 @@
+//! One task samples every running server: a single sysinfo refresh per tick
+//! covers all tracked PIDs, and each server's sample goes to its `metrics_tx`.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use sysinfo::{Pid, ProcessStatus, System};
+
+use crate::{
+    cgroups::{self, CgroupStats},
+    history,
+    procstats::{self, NetCounter, ProcessStats},
+    state::{AppState, Metrics, ServerInstance},
+};
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Per-process sampling state, dropped when the process goes away.
+struct Tracked {
+    pid: u32,
+    history: history::Writer,
+    net: NetCounter,
+    /// Previous cgroup `usage_usec` reading and when it was taken.
+    cgroup_cpu: Option<(u64, Instant)>,
+}
+
+/// Reads the server's cgroup figures, turning CPU time into a percentage.
+async fn cgroup_stats(root: Option<&str>, id: &str, tracked: &mut Tracked) -> CgroupStats {
+    let Some(root) = root else {
+        return CgroupStats::default();
+    };
+    let (memory, limit) = cgroups::memory_usage(root, id).await;
+    let usage = cgroups::cpu_usage_usec(root, id).await;
+    let now = Instant::now();
+    let cpu_percent = match (usage, tracked.cgroup_cpu) {
+        (Some(usage), Some((prev, at))) => {
+            let elapsed = now.duration_since(at).as_micros().max(1) as f64;
+            Some((usage.saturating_sub(prev) as f64 / elapsed * 100.0) as f32)
+        }
+        _ => None,
+    };
+    tracked.cgroup_cpu = usage.map(|u| (u, now));
+    CgroupStats {
+        cgroup_memory_bytes: memory,
+        cgroup_memory_limit_bytes: limit,
+        cgroup_cpu_percent: cpu_percent,
+    }
+}
+
+pub fn spawn(state: AppState) {
+    tokio::spawn(async move {
+        let mut sys = System::new();
+        let mut tracked: HashMap<String, Tracked> = HashMap::new();
+        // PIDs already handed to on_process_exit, so they're reported once
+        let mut exited: HashSet<u32> = HashSet::new();
+        loop {
+            tokio::time::sleep(SAMPLE_INTERVAL).await;
+
+            let instances: Vec<(String, Arc<ServerInstance>)> = state
+                .servers
+                .iter()
+                .map(|e| (e.key().clone(), e.value().clone()))
+                .filter(|(_, inst)| !exited.contains(&inst.pid))
+                .collect();
+            exited.retain(|pid| state.servers.iter().any(|e| e.value().pid == *pid));
+            tracked.retain(|id, t| {
+                instances
+                    .iter()
+                    .any(|(i, inst)| i == id && inst.pid == t.pid)
+            });
+
+            let pids: Vec<Pid> = instances
+                .iter()
+                .map(|(_, i)| Pid::from_u32(i.pid))
+                .collect();
+            sys.refresh_pids(&pids);
+
+            let (data_directory, retention_hours, cgroup_root) = {
+                let config = state.config.read().await;
+                (
+                    config.agent.data_directory.clone(),
+                    config.agent.metrics_retention_hours,
+                    config.agent.cgroup_root.clone(),
+                )
+            };
+
+            let sockets = if instances.is_empty() {
+                None
+            } else {
+                procstats::Sockets::read().await
+            };
+
+            for (id, instance) in instances {
+                let pid = instance.pid;
+                let proc = sys
+                    .process(Pid::from_u32(pid))
+                    .filter(|p| p.status() != ProcessStatus::Zombie);
+                let Some(proc) = proc else {
+                    exited.insert(pid);
+                    tracked.remove(&id);
+                    let state = state.clone();
+                    tokio::spawn(async move {
+                        crate::process::on_process_exit(&state, &id).await;
+                    });
+                    continue;
+                };
+                let (cpu_percent, memory_bytes) = (proc.cpu_usage(), proc.memory());
+
+                let entry = tracked.entry(id.clone()).or_insert_with(|| Tracked {
+                    pid,
+                    history: history::Writer::new(&data_directory, &id, retention_hours),
+                    net: NetCounter::default(),
+                    cgroup_cpu: None,
+                });
+                let net_bytes = sockets.as_ref().map(|s| entry.net.sample(s, pid));
+                let cgroup = cgroup_stats(cgroup_root.as_deref(), &id, entry).await;
+                // Counted from join and leave lines while SLP has no answer
+                let pinged = state.slp.get(&id).map(|s| s.players_online);
+                let players_online = match pinged {
+                    Some(count) => count,
+                    None => instance.players.lock().await.len() as u64,
+                };
+                let m = Metrics {
+                    cpu_percent,
+                    memory_bytes,
+                    timestamp_ms: SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_millis() as u64,
+                    gc: instance.gc_stats.lock().await.clone(),
+                    ticks: instance.tick_stats.lock().await.clone(),
+                    process: ProcessStats {
+                        threads: procstats::thread_count(pid).await,
+                        net_sent_bytes: net_bytes.map(|(sent, _)| sent),
+                        net_received_bytes: net_bytes.map(|(_, received)| received),
+                    },
+                    disk: crate::disk::cached(&state, &id),
+                    cgroup,
+                    players_online: Some(players_online),
+                };
+                crate::telemetry::record_metrics(&id, &m);
+                entry.history.append(&m).await;
+                let _ = instance.metrics_tx.send(m);
+            }
+        }
+    });
+}
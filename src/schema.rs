@@ -0,0 +1,170 @@
+//! Strict reading of the config file. Type errors name the field and where
+//! it is, unknown fields are rejected instead of silently dropped, and
+//! settings that clash between servers are caught before anything starts.
+
+use std::path::{Component, Path, PathBuf};
+
+use serde_json::Value;
+
+use crate::config::{Config, ConfigFormat};
+
+/// Deserializes the file, naming the offending field on failure. The
+/// parsers' own messages carry the line and column.
+fn deserialize(format: ConfigFormat, contents: &str) -> Result<Config, String> {
+    fn located<E: std::fmt::Display>(e: serde_path_to_error::Error<E>) -> String {
+        let path = e.path().to_string();
+        let inner = e.into_inner().to_string();
+        if path == "." || inner.starts_with(&path) {
+            inner
+        } else {
+            format!("{}: {}", path, inner)
+        }
+    }
+    match format {
+        ConfigFormat::Json => {
+            let de = &mut serde_json::Deserializer::from_str(contents);
+            serde_path_to_error::deserialize(de).map_err(located)
+        }
+        ConfigFormat::Toml => {
+            let de = toml::Deserializer::new(contents);
+            serde_path_to_error::deserialize(de).map_err(located)
+        }
+        ConfigFormat::Yaml => {
+            let de = serde_yaml::Deserializer::from_str(contents);
+            serde_path_to_error::deserialize(de).map_err(located)
+        }
+    }
+}
+
+fn raw_value(format: ConfigFormat, contents: &str) -> Option<Value> {
+    match format {
+        ConfigFormat::Json => serde_json::from_str(contents).ok(),
+        ConfigFormat::Toml => toml::from_str(contents).ok(),
+        ConfigFormat::Yaml => serde_yaml::from_str(contents).ok(),
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (above + 1).min(row[j] + 1).min(diagonal + usize::from(ca != *cb));
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Every config field is serialized, so a key in the file with no
+/// counterpart in the parsed config is one serde ignored.
+fn unknown_fields(file: &Value, known: &Value, path: &str, errors: &mut Vec<String>) {
+    match (file, known) {
+        (Value::Object(file), Value::Object(known)) => {
+            for (key, value) in file {
+                match known.get(key) {
+                    Some(known) => unknown_fields(value, known, &join(path, key), errors),
+                    None => {
+                        let hint = known
+                            .keys()
+                            .map(|k| (edit_distance(key, k), k))
+                            .filter(|(d, _)| *d <= 2)
+                            .min()
+                            .map(|(_, k)| format!(", did you mean '{}'?", k))
+                            .unwrap_or_default();
+                        errors.push(format!("{}: unknown field{}", join(path, key), hint));
+                    }
+                }
+            }
+        }
+        (Value::Array(file), Value::Array(known)) => {
+            for (i, (value, known)) in file.iter().zip(known).enumerate() {
+                unknown_fields(value, known, &format!("{}[{}]", path, i), errors);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Parses the file and rejects fields the agent doesn't know.
+pub fn parse(format: ConfigFormat, contents: &str) -> Result<Config, Vec<String>> {
+    let config = deserialize(format, contents).map_err(|e| vec![e])?;
+    let mut errors = Vec::new();
+    if let (Some(file), Ok(known)) = (raw_value(format, contents), serde_json::to_value(&config)) {
+        unknown_fields(&file, &known, "", &mut errors);
+    }
+    if errors.is_empty() {
+        Ok(config)
+    } else {
+        Err(errors)
+    }
+}
+
+/// The path with `.` and trailing separators dropped, for comparing
+/// directories as written.
+fn normalized(directory: &str) -> PathBuf {
+    Path::new(directory)
+        .components()
+        .filter(|c| *c != Component::CurDir)
+        .collect()
+}
+
+/// Clashes between entries that each look valid on their own: repeated
+/// ids, two servers on one port, and server directories that are the
+/// same or nested.
+pub fn conflicts(config: &Config) -> Vec<String> {
+    let mut errors = Vec::new();
+    for (i, server) in config.servers.iter().enumerate() {
+        let earlier = &config.servers[..i];
+        if earlier.iter().any(|s| s.id == server.id) {
+            errors.push(format!("Server id '{}' is used twice", server.id));
+            continue;
+        }
+        let ports = |s: &crate::config::ServerConfig| {
+            let bedrock = s.bedrock.as_ref().map(|b| (b.port, "udp"));
+            std::iter::once((s.port, "tcp")).chain(bedrock).collect::<Vec<_>>()
+        };
+        for (port, protocol) in ports(server) {
+            if let Some(other) = earlier.iter().find(|s| ports(s).contains(&(port, protocol))) {
+                errors.push(format!(
+                    "Port {}/{} of '{}' is also used by '{}'",
+                    port, protocol, server.id, other.id
+                ));
+            }
+        }
+        let directory = normalized(&server.directory);
+        for other in earlier {
+            let other_directory = normalized(&other.directory);
+            if directory == other_directory {
+                errors.push(format!(
+                    "'{}' and '{}' share the directory '{}'",
+                    other.id, server.id, server.directory
+                ));
+            } else if directory.starts_with(&other_directory)
+                || other_directory.starts_with(&directory)
+            {
+                errors.push(format!(
+                    "The directories of '{}' and '{}' are nested",
+                    other.id, server.id
+                ));
+            }
+        }
+    }
+    for (i, node) in config.nodes.iter().enumerate() {
+        if config.nodes[..i].iter().any(|n| n.id == node.id) {
+            errors.push(format!("Node id '{}' is used twice", node.id));
+        }
+    }
+    errors
+}
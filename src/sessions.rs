@@ -0,0 +1,213 @@
+//! Logins for people using the bundled web UI. Accounts are listed in
+//! `agent.users` with argon2 password hashes; logging in hands out a
+//! session token signed with a key the agent makes at startup, so sessions
+//! end when the agent restarts. A token is also void once its user is
+//! removed or given a new password.
+
+use std::sync::OnceLock;
+
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use dashmap::DashMap;
+use rand::Rng;
+use ring::hmac;
+
+use crate::{config::UserConfig, nodeauth::random_token};
+
+const TOKEN_PREFIX: &str = "session.";
+
+/// The account a request was authenticated with.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub username: String,
+    pub id: String,
+    /// Unix seconds.
+    pub expires_at: i64,
+    /// The token itself, so a WebSocket ticket can stand in for it.
+    pub token: String,
+}
+
+pub struct SessionStore {
+    key: hmac::Key,
+    /// Logged-out session ids, kept until they would have expired anyway.
+    revoked: DashMap<String, i64>,
+}
+
+impl Default for SessionStore {
+    fn default() -> Self {
+        let secret: [u8; 32] = rand::rng().random();
+        Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, &secret),
+            revoked: DashMap::new(),
+        }
+    }
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    (0..text.len())
+        .step_by(2)
+        .map(|i| text.get(i..i + 2).and_then(|h| u8::from_str_radix(h, 16).ok()))
+        .collect()
+}
+
+/// Signed together with the user's password hash, which is what voids the
+/// token when the password changes.
+fn signed_message(payload: &str, user: &UserConfig) -> String {
+    format!("{}\n{}", payload, user.password_hash)
+}
+
+impl SessionStore {
+    /// Starts a session for a user who has just proven their password.
+    pub fn issue(&self, user: &UserConfig, hours: u64) -> Session {
+        let id = random_token(16);
+        let expires_at = now() + (hours.max(1) * 3600) as i64;
+        let payload = format!("{}.{}.{}", user.username, expires_at, id);
+        let tag = hmac::sign(&self.key, signed_message(&payload, user).as_bytes());
+        Session {
+            username: user.username.clone(),
+            id,
+            expires_at,
+            token: format!("{}{}.{}", TOKEN_PREFIX, payload, hex(tag.as_ref())),
+        }
+    }
+
+    /// The live session a token belongs to, if it is one.
+    pub fn verify(&self, users: &[UserConfig], token: &str) -> Option<Session> {
+        let (payload, tag) = token.strip_prefix(TOKEN_PREFIX)?.rsplit_once('.')?;
+        let mut parts = payload.splitn(3, '.');
+        let (username, expires_at, id) = (parts.next()?, parts.next()?, parts.next()?);
+        let user = users.iter().find(|u| u.username == username)?;
+        let message = signed_message(payload, user);
+        hmac::verify(&self.key, message.as_bytes(), &unhex(tag)?).ok()?;
+        let expires_at: i64 = expires_at.parse().ok()?;
+        if expires_at <= now() || self.revoked.contains_key(id) {
+            return None;
+        }
+        Some(Session {
+            username: username.to_string(),
+            id: id.to_string(),
+            expires_at,
+            token: token.to_string(),
+        })
+    }
+
+    pub fn revoke(&self, session: &Session) {
+        let now = now();
+        self.revoked.retain(|_, expires_at| *expires_at > now);
+        self.revoked.insert(session.id.clone(), session.expires_at);
+    }
+}
+
+/// Hashes a password for `agent.users`. Slow on purpose; keep it off the
+/// async workers.
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt: [u8; 16] = rand::rng().random();
+    let salt = SaltString::encode_b64(&salt).map_err(|e| e.to_string())?;
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// Checked against a throwaway hash when the username is unknown, so the
+/// answer takes as long either way.
+fn decoy_hash() -> &'static str {
+    static DECOY: OnceLock<String> = OnceLock::new();
+    DECOY.get_or_init(|| hash_password(&random_token(16)).unwrap_or_default())
+}
+
+/// The user, if the password is theirs. Slow on purpose, like
+/// `hash_password`.
+pub fn check_password<'a>(
+    users: &'a [UserConfig],
+    username: &str,
+    password: &str,
+) -> Option<&'a UserConfig> {
+    let user = users.iter().find(|u| u.username == username);
+    let hash = match user {
+        Some(user) => &user.password_hash,
+        None => decoy_hash(),
+    };
+    let matches = PasswordHash::new(hash)
+        .is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok());
+    user.filter(|_| matches)
+}
+
+pub fn validate_user(user: &UserConfig) -> Result<(), String> {
+    let valid_name = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if user.username.is_empty() || !user.username.chars().all(valid_name) {
+        return Err(format!(
+            "username '{}' must be non-empty letters, digits, '-' or '_'",
+            user.username
+        ));
+    }
+    if PasswordHash::new(&user.password_hash).is_err() {
+        return Err(format!(
+            "password_hash of '{}' is not a PHC string; make one with --hash-password",
+            user.username
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(username: &str, password_hash: &str) -> UserConfig {
+        UserConfig {
+            username: username.to_string(),
+            password_hash: password_hash.to_string(),
+        }
+    }
+
+    #[test]
+    fn issued_tokens_verify() {
+        let store = SessionStore::default();
+        let users = [user("alice", "hash-a"), user("bob", "hash-b")];
+        let session = store.issue(&users[0], 1);
+        let verified = store.verify(&users, &session.token).unwrap();
+        assert_eq!(verified.username, "alice");
+        assert_eq!(verified.id, session.id);
+    }
+
+    #[test]
+    fn tampered_tokens_are_refused() {
+        let store = SessionStore::default();
+        let users = [user("alice", "hash-a"), user("bob", "hash-b")];
+        let token = store.issue(&users[0], 1).token;
+        assert!(store.verify(&users, &token.replacen("alice", "bob", 1)).is_none());
+        assert!(store.verify(&users, &token[..token.len() - 2]).is_none());
+        assert!(store.verify(&users, "session.alice.1.x.zz").is_none());
+        // Another agent's key signs different tokens
+        assert!(SessionStore::default().verify(&users, &token).is_none());
+    }
+
+    #[test]
+    fn password_changes_and_removal_void_tokens() {
+        let store = SessionStore::default();
+        let token = store.issue(&user("alice", "hash-a"), 1).token;
+        assert!(store.verify(&[user("alice", "hash-a2")], &token).is_none());
+        assert!(store.verify(&[user("bob", "hash-b")], &token).is_none());
+    }
+
+    #[test]
+    fn revoked_sessions_are_refused() {
+        let store = SessionStore::default();
+        let users = [user("alice", "hash-a")];
+        let session = store.issue(&users[0], 1);
+        store.revoke(&session);
+        assert!(store.verify(&users, &session.token).is_none());
+        assert!(store.verify(&users, &store.issue(&users[0], 1).token).is_some());
+    }
+}
@@ -0,0 +1,160 @@
+//! Egress bandwidth limits per server using `tc` HTB classes on the agent's
+//! uplink interface. Traffic is classified by source port, so the server
+//! port plus any `shaped_ports` (e.g. a dynmap web server) share one limit.
+
+use crate::config::{AgentConfig, ServerConfig};
+
+// Unshaped traffic falls into this class; port 1 can never be a server port.
+const DEFAULT_CLASS: &str = "1:1";
+
+fn class_id(port: u16) -> String {
+    format!("1:{:x}", port)
+}
+
+async fn tc(args: &[&str]) -> Result<(), String> {
+    let output = tokio::process::Command::new("tc")
+        .args(args)
+        .output()
+        .await
+        .map_err(|e| format!("Failed to execute tc: {}", e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("tc {} failed: {}", args.join(" "), stderr.trim()));
+    }
+    Ok(())
+}
+
+/// The kind and handle of the interface's root qdisc, from a
+/// `qdisc fq_codel 0: root refcnt 2 ...` line.
+fn root_qdisc(show: &str) -> Option<(&str, &str)> {
+    let mut fields = show.lines().next()?.split_whitespace().skip(1);
+    Some((fields.next()?, fields.next()?))
+}
+
+/// Installs the root HTB qdisc if the interface doesn't have it yet. The
+/// kernel's default root, with handle `0:`, is replaced freely; any other
+/// was set up by someone and is only replaced with `replace_root`.
+async fn ensure_root(interface: &str, replace_root: bool) -> Result<(), String> {
+    let output = tokio::process::Command::new("tc")
+        .args(["qdisc", "show", "dev", interface, "root"])
+        .output()
+        .await
+        .map_err(|e| format!("Failed to execute tc: {}", e))?;
+    let show = String::from_utf8_lossy(&output.stdout);
+    match root_qdisc(&show) {
+        Some(("htb", "1:")) => return Ok(()),
+        Some((_, "0:")) | None => {}
+        Some((kind, handle)) if !replace_root => {
+            return Err(format!(
+                "{} already has a {} {} root qdisc; set agent.shaping_replace_root to replace it",
+                interface, kind, handle
+            ));
+        }
+        Some(_) => {}
+    }
+    tc(&[
+        "qdisc", "replace", "dev", interface, "root", "handle", "1:", "htb", "default", "1",
+    ])
+    .await?;
+    tc(&[
+        "class",
+        "replace",
+        "dev",
+        interface,
+        "parent",
+        "1:",
+        "classid",
+        DEFAULT_CLASS,
+        "htb",
+        "rate",
+        "10gbit",
+    ])
+    .await
+}
+
+/// Applies `egress_limit_kbit` for a server that is about to run.
+pub async fn apply(agent: &AgentConfig, cfg: &ServerConfig) {
+    let Some(limit) = cfg.egress_limit_kbit else {
+        return;
+    };
+    let Some(ref interface) = agent.shaping_interface else {
+        tracing::warn!(
+            "Server '{}' has egress_limit_kbit but agent.shaping_interface is not set",
+            cfg.id
+        );
+        return;
+    };
+    if let Err(e) = apply_limit(interface, agent.shaping_replace_root, cfg, limit).await {
+        tracing::warn!("Failed to shape egress for '{}': {}", cfg.id, e);
+    }
+}
+
+async fn apply_limit(
+    interface: &str,
+    replace_root: bool,
+    cfg: &ServerConfig,
+    limit_kbit: u32,
+) -> Result<(), String> {
+    ensure_root(interface, replace_root).await?;
+    clear_port(interface, cfg.port).await;
+
+    let class = class_id(cfg.port);
+    let rate = format!("{}kbit", limit_kbit);
+    tc(&[
+        "class", "replace", "dev", interface, "parent", "1:", "classid", &class, "htb", "rate",
+        &rate, "ceil", &rate,
+    ])
+    .await?;
+
+    let prio = cfg.port.to_string();
+    let ports = std::iter::once(cfg.port).chain(cfg.shaped_ports.iter().copied());
+    for port in ports {
+        let sport = port.to_string();
+        for (protocol, matcher) in [("ip", "ip"), ("ipv6", "ip6")] {
+            tc(&[
+                "filter", "add", "dev", interface, "parent", "1:", "protocol", protocol, "prio",
+                &prio, "u32", "match", matcher, "sport", &sport, "0xffff", "flowid", &class,
+            ])
+            .await?;
+        }
+    }
+    tracing::info!(
+        "Limited egress of '{}' to {} kbit/s on {}",
+        cfg.id,
+        limit_kbit,
+        interface
+    );
+    Ok(())
+}
+
+async fn clear_port(interface: &str, port: u16) {
+    let prio = port.to_string();
+    for protocol in ["ip", "ipv6"] {
+        let _ = tc(&[
+            "filter", "del", "dev", interface, "parent", "1:", "protocol", protocol, "prio", &prio,
+        ])
+        .await;
+    }
+    let _ = tc(&["class", "del", "dev", interface, "classid", &class_id(port)]).await;
+}
+
+/// Removes a server's class and filters once it has stopped.
+pub async fn clear(interface: Option<&str>, cfg: &ServerConfig) {
+    if let (Some(interface), Some(_)) = (interface, cfg.egress_limit_kbit) {
+        clear_port(interface, cfg.port).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn root_qdisc_reads_kind_and_handle() {
+        let show = "qdisc fq_codel 0: root refcnt 2 limit 10240p flows 1024\n";
+        assert_eq!(root_qdisc(show), Some(("fq_codel", "0:")));
+        let show = "qdisc cake 8001: root refcnt 2 bandwidth 100Mbit";
+        assert_eq!(root_qdisc(show), Some(("cake", "8001:")));
+        assert_eq!(root_qdisc(""), None);
+    }
+}
@@ -0,0 +1,200 @@
+//! Memory sizing advice: each server's `-Xmx` set against the heap it
+//! actually keeps live and the memory its process holds, over the stored
+//! metrics history, to show which `memory_mb` to raise and which to give
+//! back on a crowded node.
+//!
+//! The heap figure is the heap in use right after a collection, which is
+//! the live set the heap has to fit; it is only known for servers with
+//! `gc_metrics`. Without it a server can still be found oversized from its
+//! resident memory, but never undersized.
+
+use serde::Serialize;
+use sysinfo::System;
+
+use crate::{config::ServerConfig, history, state::AppState};
+
+const MB: u64 = 1024 * 1024;
+/// Fewer samples than this in the window say too little to advise on.
+const MIN_SAMPLES: usize = 30;
+/// Recommended heap as a multiple of the peak live set, leaving the
+/// collector room to work without running back to back.
+const HEADROOM: f64 = 1.6;
+/// A live set above this share of the heap leaves the collector too little.
+const UNDER_RATIO: f64 = 0.85;
+/// A live set below this share of the heap leaves most of it unused.
+const OVER_RATIO: f64 = 0.4;
+/// Smallest difference worth advising a change for.
+const MIN_CHANGE_MB: u64 = 1024;
+const MIN_RECOMMENDED_MB: u64 = 1024;
+/// The host is short of memory when less than this share of it is free.
+const PRESSURE_RATIO: f64 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MemoryFit {
+    /// Too few figures in the window to tell.
+    Unknown,
+    UnderProvisioned,
+    Adequate,
+    OverProvisioned,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct ServerMemoryAdvice {
+    pub server: String,
+    pub memory_mb: u32,
+    /// The `-Xmx` the server starts with; None when the JVM picks it.
+    pub max_heap_mb: Option<u64>,
+    pub samples: usize,
+    /// Highest heap in use after a collection.
+    pub heap_peak_mb: Option<u64>,
+    /// Highest resident memory of the process.
+    pub rss_peak_mb: Option<u64>,
+    pub fit: MemoryFit,
+    /// Heap to size the server to: its `memory_mb`, or the `-Xmx` in its
+    /// `jvm_args` or `command` where one overrides it.
+    pub recommended_heap_mb: Option<u64>,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct MemoryAdvice {
+    pub window_hours: u64,
+    pub total_memory_mb: u64,
+    /// Memory the host has free right now, whatever the servers are allotted.
+    pub host_available_memory_mb: u64,
+    /// Sum of `memory_mb` over running servers.
+    pub allocated_memory_mb: u64,
+    /// Whether the host is short of free memory.
+    pub memory_pressure: bool,
+    /// Memory the over-provisioned servers would give back if resized.
+    pub reclaimable_memory_mb: u64,
+    pub servers: Vec<ServerMemoryAdvice>,
+}
+
+/// `mb` rounded up to a multiple of 512 MB, at least `MIN_RECOMMENDED_MB`.
+fn round_up(mb: f64) -> u64 {
+    ((mb / 512.0).ceil() as u64 * 512).max(MIN_RECOMMENDED_MB)
+}
+
+fn advise(cfg: &ServerConfig, samples: &[history::Sample]) -> ServerMemoryAdvice {
+    let max_heap_mb = crate::jvm::max_heap_mb(cfg);
+    let heap_peak_mb = samples.iter().filter_map(|s| s.heap_used_bytes).max().map(|b| b / MB);
+    let rss_peak_mb = samples.iter().map(|s| s.memory_bytes).max().map(|b| b / MB);
+    let heap_samples = samples.iter().filter(|s| s.heap_used_bytes.is_some()).count();
+    let mut advice = ServerMemoryAdvice {
+        server: cfg.id.clone(),
+        memory_mb: cfg.memory_mb,
+        max_heap_mb,
+        samples: samples.len(),
+        heap_peak_mb,
+        rss_peak_mb,
+        fit: MemoryFit::Unknown,
+        recommended_heap_mb: None,
+        reason: String::new(),
+    };
+
+    let Some(max_heap) = max_heap_mb.filter(|&m| m > 0) else {
+        advice.reason = "No -Xmx is set, so the JVM sizes its own heap".to_string();
+        return advice;
+    };
+    if samples.len() < MIN_SAMPLES {
+        advice.reason = format!("Only {} samples in the window", samples.len());
+        return advice;
+    }
+    let (fit, recommended, reason) = match heap_peak_mb.filter(|_| heap_samples >= MIN_SAMPLES) {
+        Some(peak) => {
+            let recommended = round_up(peak as f64 * HEADROOM);
+            let share = peak as f64 / max_heap as f64;
+            if share > UNDER_RATIO {
+                (
+                    MemoryFit::UnderProvisioned,
+                    Some(recommended.max(max_heap + 512)),
+                    format!(
+                        "Up to {} MB stays live after collections, {:.0}% of the {} MB heap",
+                        peak,
+                        share * 100.0,
+                        max_heap
+                    ),
+                )
+            } else if share < OVER_RATIO && max_heap.saturating_sub(recommended) >= MIN_CHANGE_MB {
+                (
+                    MemoryFit::OverProvisioned,
+                    Some(recommended),
+                    format!(
+                        "At most {} MB stays live after collections, {:.0}% of the {} MB heap",
+                        peak,
+                        share * 100.0,
+                        max_heap
+                    ),
+                )
+            } else {
+                (
+                    MemoryFit::Adequate,
+                    None,
+                    format!("Up to {} MB of the {} MB heap stays live", peak, max_heap),
+                )
+            }
+        }
+        // The process holds at least the heap it has touched
+        None => {
+            let recommended = rss_peak_mb.map(|rss| round_up(rss as f64 * HEADROOM));
+            match (rss_peak_mb, recommended) {
+                (Some(rss), Some(recommended))
+                    if (rss as f64) < max_heap as f64 * OVER_RATIO
+                        && max_heap.saturating_sub(recommended) >= MIN_CHANGE_MB =>
+                {
+                    (
+                        MemoryFit::OverProvisioned,
+                        Some(recommended),
+                        format!(
+                            "The process never held more than {} MB of its {} MB heap",
+                            rss, max_heap
+                        ),
+                    )
+                }
+                _ => (MemoryFit::Unknown, None, "No heap figures; enable gc_metrics".to_string()),
+            }
+        }
+    };
+    advice.fit = fit;
+    advice.recommended_heap_mb = recommended;
+    advice.reason = reason;
+    advice
+}
+
+/// Advice for every server from the last `window_hours` of metrics.
+pub async fn advise_all(state: &AppState, window_hours: u64) -> MemoryAdvice {
+    let (data_directory, servers) = {
+        let config = state.config.read().await;
+        (config.agent.data_directory.clone(), config.servers.clone())
+    };
+    let to_ms = chrono::Utc::now().timestamp_millis().max(0) as u64;
+    let from_ms = to_ms.saturating_sub(window_hours * 60 * 60 * 1000);
+
+    let mut advice = Vec::with_capacity(servers.len());
+    for cfg in &servers {
+        let samples = history::samples(&data_directory, &cfg.id, from_ms, to_ms).await;
+        advice.push(advise(cfg, &samples));
+    }
+    let reclaimable_memory_mb = advice
+        .iter()
+        .filter(|a| a.fit == MemoryFit::OverProvisioned)
+        .filter_map(|a| Some(a.max_heap_mb?.saturating_sub(a.recommended_heap_mb?)))
+        .sum();
+
+    let node = crate::resources::node_resources(state).await;
+    let mut sys = System::new();
+    sys.refresh_memory();
+    let host_available_memory_mb = sys.available_memory() / MB;
+    MemoryAdvice {
+        window_hours,
+        total_memory_mb: node.total_memory_mb,
+        host_available_memory_mb,
+        allocated_memory_mb: node.allocated_memory_mb,
+        memory_pressure: (host_available_memory_mb as f64)
+            < node.total_memory_mb as f64 * PRESSURE_RATIO,
+        reclaimable_memory_mb,
+        servers: advice,
+    }
+}
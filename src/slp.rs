@@ -0,0 +1,212 @@
+//! Server List Ping: the status request vanilla clients send for the
+//! multiplayer screen, used to read MOTD, version and player counts.
+
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::state::{AppState, ServerPhase};
+
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+// Largest status response we accept; favicons make them a few KB
+const MAX_PACKET_LEN: usize = 1 << 20;
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct SlpStatus {
+    pub motd: String,
+    pub version: String,
+    pub protocol: i64,
+    pub players_online: u64,
+    pub players_max: u64,
+    pub latency_ms: u64,
+}
+
+pub fn write_varint(buf: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+    loop {
+        if value & !0x7f == 0 {
+            buf.push(value as u8);
+            return;
+        }
+        buf.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+}
+
+/// Decodes a VarInt at the start of `data`, returning it and its length.
+pub fn decode_varint(data: &[u8]) -> Option<(i32, usize)> {
+    let mut value = 0u32;
+    for (i, &byte) in data.iter().take(5).enumerate() {
+        value |= ((byte & 0x7f) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value as i32, i + 1));
+        }
+    }
+    None
+}
+
+pub fn write_string(buf: &mut Vec<u8>, value: &str) {
+    write_varint(buf, value.len() as i32);
+    buf.extend_from_slice(value.as_bytes());
+}
+
+/// Decodes a length-prefixed string, returning it and the bytes consumed.
+pub fn decode_string(data: &[u8]) -> Option<(String, usize)> {
+    let (len, offset) = decode_varint(data)?;
+    let end = offset.checked_add(usize::try_from(len).ok()?)?;
+    let bytes = data.get(offset..end)?;
+    Some((String::from_utf8_lossy(bytes).into_owned(), end))
+}
+
+async fn read_varint(stream: &mut TcpStream) -> Result<i32, String> {
+    let mut value = 0u32;
+    for i in 0..5 {
+        let byte = stream
+            .read_u8()
+            .await
+            .map_err(|e| format!("Failed to read packet: {}", e))?;
+        value |= ((byte & 0x7f) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value as i32);
+        }
+    }
+    Err("VarInt too long".to_string())
+}
+
+pub async fn send_packet(stream: &mut TcpStream, id: i32, body: &[u8]) -> Result<(), String> {
+    let mut payload = Vec::with_capacity(body.len() + 5);
+    write_varint(&mut payload, id);
+    payload.extend_from_slice(body);
+    let mut packet = Vec::with_capacity(payload.len() + 5);
+    write_varint(&mut packet, payload.len() as i32);
+    packet.extend_from_slice(&payload);
+    stream
+        .write_all(&packet)
+        .await
+        .map_err(|e| format!("Failed to write packet: {}", e))
+}
+
+/// Reads one packet, returning its id and body.
+pub async fn read_packet(stream: &mut TcpStream) -> Result<(i32, Vec<u8>), String> {
+    let len = read_varint(stream).await?;
+    if len <= 0 || len as usize > MAX_PACKET_LEN {
+        return Err(format!("Invalid packet length {}", len));
+    }
+    let mut data = vec![0u8; len as usize];
+    stream
+        .read_exact(&mut data)
+        .await
+        .map_err(|e| format!("Failed to read packet: {}", e))?;
+    let (id, pos) = decode_varint(&data).ok_or("Invalid packet id")?;
+    Ok((id, data.split_off(pos)))
+}
+
+/// Flattens a chat component (string, object with text/extra, or array).
+fn component_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Array(parts) => parts.iter().map(component_text).collect(),
+        serde_json::Value::Object(obj) => {
+            let mut text = obj.get("text").map(component_text).unwrap_or_default();
+            if let Some(extra) = obj.get("extra") {
+                text.push_str(&component_text(extra));
+            }
+            text
+        }
+        _ => String::new(),
+    }
+}
+
+async fn ping_inner(port: u16) -> Result<SlpStatus, String> {
+    let host = "127.0.0.1";
+    let mut stream = TcpStream::connect((host, port))
+        .await
+        .map_err(|e| format!("Failed to connect to port {}: {}", port, e))?;
+
+    // Handshake: protocol -1 (unknown), address, port, next state 1 (status)
+    let mut handshake = Vec::new();
+    write_varint(&mut handshake, -1);
+    write_string(&mut handshake, host);
+    handshake.extend_from_slice(&port.to_be_bytes());
+    write_varint(&mut handshake, 1);
+    send_packet(&mut stream, 0x00, &handshake).await?;
+    send_packet(&mut stream, 0x00, &[]).await?;
+
+    let (id, body) = read_packet(&mut stream).await?;
+    if id != 0x00 {
+        return Err(format!("Unexpected status packet id {}", id));
+    }
+    let (json, _) = decode_string(&body).ok_or("Truncated status response")?;
+    let json: serde_json::Value =
+        serde_json::from_str(&json).map_err(|e| format!("Invalid status JSON: {}", e))?;
+
+    let sent = Instant::now();
+    // The payload is echoed back verbatim; any value works
+    send_packet(&mut stream, 0x01, &1i64.to_be_bytes()).await?;
+    let latency_ms = match read_packet(&mut stream).await {
+        Ok((0x01, _)) => sent.elapsed().as_millis() as u64,
+        // Some proxies close after the status response; fall back to zero
+        _ => 0,
+    };
+
+    Ok(SlpStatus {
+        motd: json
+            .get("description")
+            .map(component_text)
+            .unwrap_or_default(),
+        version: json["version"]["name"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        protocol: json["version"]["protocol"].as_i64().unwrap_or(-1),
+        players_online: json["players"]["online"].as_u64().unwrap_or(0),
+        players_max: json["players"]["max"].as_u64().unwrap_or(0),
+        latency_ms,
+    })
+}
+
+pub async fn ping(port: u16) -> Result<SlpStatus, String> {
+    tokio::time::timeout(PING_TIMEOUT, ping_inner(port))
+        .await
+        .map_err(|_| format!("Status ping to port {} timed out", port))?
+}
+
+/// Pings every running server on an interval and caches the result in
+/// `AppState::slp`. Servers that stop answering lose their entry.
+pub fn spawn_poller(state: AppState) {
+    tokio::spawn(async move {
+        loop {
+            let (interval, servers) = {
+                let config = state.config.read().await;
+                (config.agent.slp_interval_secs, config.servers.clone())
+            };
+            if interval == 0 {
+                state.slp.clear();
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                continue;
+            }
+            state.slp.retain(|id, _| state.servers.contains_key(id));
+            for server in servers {
+                let Some(instance) = state.servers.get(&server.id).map(|i| i.value().clone())
+                else {
+                    continue;
+                };
+                if *instance.phase.lock().await != ServerPhase::Running {
+                    continue;
+                }
+                match ping(server.port).await {
+                    Ok(status) => {
+                        state.slp.insert(server.id.clone(), status);
+                    }
+                    Err(e) => {
+                        tracing::debug!("Status ping of '{}' failed: {}", server.id, e);
+                        state.slp.remove(&server.id);
+                    }
+                }
+            }
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+        }
+    });
+}
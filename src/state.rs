@@ -1,37 +1,223 @@
 use dashmap::DashMap;
 use serde::Serialize;
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
-use tokio::sync::{broadcast, Mutex, RwLock};
+use tokio::sync::{broadcast, watch, Mutex, RwLock};
 
 #[derive(Debug, Clone, Serialize)]
 pub struct Metrics {
     pub cpu_percent: f32,
     pub memory_bytes: u64,
     pub timestamp_ms: u64,
+    #[serde(flatten)]
+    pub gc: GcStats,
+    #[serde(flatten)]
+    pub ticks: crate::ticks::TickStats,
+    #[serde(flatten)]
+    pub process: crate::procstats::ProcessStats,
+    #[serde(flatten)]
+    pub disk: crate::disk::DiskUsage,
+    #[serde(flatten)]
+    pub cgroup: crate::cgroups::CgroupStats,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub players_online: Option<u64>,
+}
+
+/// Heap and pause figures parsed from the JVM's `-Xlog:gc` output.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GcStats {
+    /// Heap in use right after the most recent collection.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heap_used_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heap_committed_bytes: Option<u64>,
+    pub gc_pause_count: u64,
+    pub gc_pause_total_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_gc_pause_ms: Option<f64>,
+}
+
+/// Lifecycle of a running process; exits are tracked separately as `ExitInfo`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerPhase {
+    /// Spawned but the "Done (...)!" line hasn't been logged yet.
+    Starting,
+    Running,
+    /// A stop was requested and the stop sequence is in progress.
+    Stopping,
+}
+
+impl ServerPhase {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ServerPhase::Starting => "starting",
+            ServerPhase::Running => "running",
+            ServerPhase::Stopping => "stopping",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct ExitInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+    /// The exit followed a stop requested through the agent.
+    pub requested: bool,
+    /// Unrequested exit with a non-zero code or a signal.
+    pub crashed: bool,
+    pub exited_at_ms: u64,
+    /// The kernel killed the process for exceeding its cgroup memory limit.
+    pub oom_killed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crash_report: Option<crate::crash::CrashReport>,
+}
+
+/// Agent-wide notifications pushed to `/api/events/ws` subscribers.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AgentEvent {
+    ServerExited { server: String, exit: ExitInfo },
+    NodeHealthChanged { node: String, health: crate::nodes::NodeHealth },
+    ConfigReloaded { report: crate::reload::ReloadReport },
+    MaintenanceChanged { maintenance: Option<crate::maintenance::Maintenance> },
+    PregenerationFinished { server: String, job: crate::pregen::PregenJob },
 }
 
 pub struct ServerInstance {
     pub pid: u32,
-    pub child: Mutex<tokio::process::Child>,
-    pub stdin: Mutex<tokio::process::ChildStdin>,
+    pub child: Mutex<crate::process::ServerProcess>,
+    pub console: Mutex<Box<dyn crate::console::ConsoleBackend>>,
     pub metrics_tx: broadcast::Sender<Metrics>,
-    pub console_tx: broadcast::Sender<String>,
+    pub console_tx: broadcast::Sender<crate::console::ConsoleLine>,
     pub started_at: std::time::Instant,
-    pub console_buffer: Mutex<VecDeque<String>>,
+    pub console_buffer: Mutex<VecDeque<crate::console::ConsoleLine>>,
+    pub console_buffer_lines: usize,
+    pub console_replay_lines: usize,
+    /// Trailing output the process left without a newline during startup, i.e. a stdin prompt.
+    pub pending_prompt: Mutex<Option<String>>,
+    /// Flipped to true once the instance is removed from `AppState::servers`.
+    pub exited: watch::Sender<bool>,
+    pub gc_stats: Mutex<GcStats>,
+    pub tick_stats: Mutex<crate::ticks::TickStats>,
+    pub phase: Mutex<ServerPhase>,
+    /// When the process last wrote anything, for the hang watchdog.
+    pub last_output: Mutex<std::time::Instant>,
+    pub redactor: crate::redact::Redactor,
+    /// Where every console line is kept on disk, unless that is disabled.
+    pub console_log: Option<crate::consolelog::ConsoleLog>,
+    /// The session of each player online, by name.
+    pub players: Mutex<HashMap<String, crate::playtime::PlayerSession>>,
+}
+
+impl ServerInstance {
+    /// Writes one command line to the server's console.
+    pub async fn send_line(&self, line: &str) -> std::io::Result<()> {
+        use tokio::io::AsyncWriteExt;
+        let mut console = self.console.lock().await;
+        let input = console.input().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::Unsupported, "the console is read-only")
+        })?;
+        input.write_all(format!("{}\n", line).as_bytes()).await?;
+        input.flush().await
+    }
+
+    /// Secrets are masked here, before the line goes anywhere.
+    pub async fn push_console_line(&self, line: String, stream: crate::console::ConsoleStream) {
+        *self.last_output.lock().await = std::time::Instant::now();
+        let text = self.redactor.redact(line);
+        if let Some(log) = &self.console_log {
+            log.append(&text);
+        }
+        let line = crate::console::ConsoleLine {
+            level: crate::console::LogLevel::parse(&text),
+            text,
+            stream,
+            timestamp_ms: chrono::Utc::now().timestamp_millis() as u64,
+        };
+        let _ = self.console_tx.send(line.clone());
+        let mut buf = self.console_buffer.lock().await;
+        buf.push_back(line);
+        if buf.len() > self.console_buffer_lines {
+            buf.pop_front();
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct AppState {
     pub config: Arc<RwLock<crate::config::Config>>,
     pub servers: Arc<DashMap<String, Arc<ServerInstance>>>,
+    pub rollout: Arc<Mutex<crate::rollout::RolloutState>>,
+    /// Held for a whole update check, so builds are only downloaded and applied once.
+    pub rollout_cycle: Arc<Mutex<()>>,
+    /// How each server last exited, kept until it is started again.
+    pub last_exits: Arc<DashMap<String, ExitInfo>>,
+    /// Latest directory sizes per server, refreshed by `disk::spawn_sampler`.
+    pub disk_usage: Arc<DashMap<String, crate::disk::DiskUsage>>,
+    /// Latest Server List Ping answer per running server.
+    pub slp: Arc<DashMap<String, crate::slp::SlpStatus>>,
+    /// Port holders for stopped `wake_on_demand` servers.
+    pub wake_listeners: Arc<Mutex<HashMap<String, crate::wake::WakeListener>>>,
+    /// Consecutive automatic restarts per server, checked against `max_retries`.
+    pub restart_attempts: Arc<DashMap<String, u32>>,
+    pub events_tx: broadcast::Sender<AgentEvent>,
+    /// Router port forwarding state per `port_forward` server.
+    pub port_mappings: Arc<DashMap<String, crate::portmap::MappingStatus>>,
+    /// Held while a port is mapped or unmapped on the router, by port.
+    pub port_locks: Arc<DashMap<u16, Arc<Mutex<()>>>>,
+    /// Last server list fetched from each node, by node id.
+    pub node_servers: Arc<DashMap<String, Vec<serde_json::Value>>>,
+    /// Heartbeat outcome per node, by node id.
+    pub node_health: Arc<DashMap<String, crate::nodes::NodeHealth>>,
+    pub rate_limiter: Arc<crate::ratelimit::RateLimiter>,
+    /// Outstanding WebSocket tickets, by ticket.
+    pub ws_tickets: Arc<DashMap<String, crate::nodeauth::WsTicket>>,
+    pub sessions: Arc<crate::sessions::SessionStore>,
+    /// Set while the agent is in maintenance mode.
+    pub maintenance: Arc<RwLock<Option<crate::maintenance::Maintenance>>>,
+    pub whitelists: Arc<Mutex<crate::whitelist::SyncState>>,
+    pub bans: Arc<Mutex<crate::bans::SyncState>>,
+    /// Latest Chunky pregeneration job per server.
+    pub pregen_jobs: Arc<DashMap<String, crate::pregen::PregenJob>>,
+    /// Latest flight recording started per server.
+    pub recordings: Arc<DashMap<String, crate::diagnostics::JfrRecording>>,
 }
 
 impl AppState {
-    pub fn new(config: crate::config::Config) -> Self {
+    pub fn new(config: crate::config::Config, rollout: crate::rollout::RolloutState) -> Self {
         Self {
             config: Arc::new(RwLock::new(config)),
             servers: Arc::new(DashMap::new()),
+            rollout: Arc::new(Mutex::new(rollout)),
+            rollout_cycle: Arc::new(Mutex::new(())),
+            last_exits: Arc::new(DashMap::new()),
+            disk_usage: Arc::new(DashMap::new()),
+            slp: Arc::new(DashMap::new()),
+            wake_listeners: Arc::new(Mutex::new(HashMap::new())),
+            restart_attempts: Arc::new(DashMap::new()),
+            events_tx: broadcast::channel(64).0,
+            port_mappings: Arc::new(DashMap::new()),
+            port_locks: Arc::new(DashMap::new()),
+            node_servers: Arc::new(DashMap::new()),
+            node_health: Arc::new(DashMap::new()),
+            rate_limiter: Arc::new(crate::ratelimit::RateLimiter::default()),
+            ws_tickets: Arc::new(DashMap::new()),
+            sessions: Arc::new(crate::sessions::SessionStore::default()),
+            maintenance: Arc::new(RwLock::new(None)),
+            whitelists: Arc::new(Mutex::new(Default::default())),
+            bans: Arc::new(Mutex::new(Default::default())),
+            pregen_jobs: Arc::new(DashMap::new()),
+            recordings: Arc::new(DashMap::new()),
         }
     }
+
+    /// Removes a running instance and notifies anyone streaming from it.
+    pub fn remove_instance(&self, server_id: &str) -> Option<Arc<ServerInstance>> {
+        let (_, instance) = self.servers.remove(server_id)?;
+        instance.exited.send_replace(true);
+        Some(instance)
+    }
 }
@@ -0,0 +1,62 @@
+//! sd_notify support for running under systemd with `Type=notify`: readiness,
+//! status text, shutdown and watchdog keep-alives. Everything is a no-op
+//! when `NOTIFY_SOCKET` is not set, and on platforms other than Linux.
+
+#[cfg(target_os = "linux")]
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+fn socket_addr() -> Option<SocketAddr> {
+    let path = std::env::var_os("NOTIFY_SOCKET")?;
+    let path = path.to_string_lossy();
+    // A leading '@' names a socket in the abstract namespace
+    if let Some(name) = path.strip_prefix('@') {
+        use std::os::linux::net::SocketAddrExt;
+        SocketAddr::from_abstract_name(name.as_bytes()).ok()
+    } else {
+        SocketAddr::from_pathname(path.as_ref()).ok()
+    }
+}
+
+/// Sends one notification, e.g. "READY=1" or "STATUS=...".
+#[cfg(target_os = "linux")]
+pub fn notify(message: &str) {
+    let Some(addr) = socket_addr() else {
+        return;
+    };
+    let sent =
+        UnixDatagram::unbound().and_then(|socket| socket.send_to_addr(message.as_bytes(), &addr));
+    if let Err(e) = sent {
+        tracing::debug!("sd_notify '{}' failed: {}", message, e);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn notify(_message: &str) {}
+
+/// How often systemd expects `WATCHDOG=1`, if the unit sets `WatchdogSec`.
+fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// Pings the systemd watchdog at half its timeout for as long as the
+/// runtime is alive, so a wedged agent gets restarted.
+pub fn spawn_watchdog() {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval / 2);
+        loop {
+            ticker.tick().await;
+            notify("WATCHDOG=1");
+        }
+    });
+}
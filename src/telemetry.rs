@@ -0,0 +1,152 @@
+//! Logging setup plus optional OTLP/HTTP export of traces and metrics.
+
+use opentelemetry::{global, metrics::Gauge, trace::TracerProvider as _, KeyValue};
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    metrics::{PeriodicReader, SdkMeterProvider},
+    trace::SdkTracerProvider,
+    Resource,
+};
+use std::sync::OnceLock;
+use tracing_subscriber::{layer::SubscriberExt, reload, util::SubscriberInitExt, Layer, Registry};
+
+use crate::{config::OtlpConfig, state::Metrics};
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Lets the OpenTelemetry layer be attached once the config has been read,
+/// without losing log output emitted while loading it.
+pub struct TelemetryHandle {
+    otel_layer: reload::Handle<Option<BoxedLayer>, Registry>,
+    tracer_provider: Option<SdkTracerProvider>,
+    meter_provider: Option<SdkMeterProvider>,
+}
+
+pub fn init_logging() -> TelemetryHandle {
+    let (otel_layer, handle) = reload::Layer::new(None::<BoxedLayer>);
+    tracing_subscriber::registry()
+        .with(otel_layer)
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+        )
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+    TelemetryHandle {
+        otel_layer: handle,
+        tracer_provider: None,
+        meter_provider: None,
+    }
+}
+
+impl TelemetryHandle {
+    /// Starts exporting spans and metrics to `cfg.endpoint` over OTLP/HTTP.
+    pub fn enable_otlp(&mut self, cfg: &OtlpConfig) -> anyhow::Result<()> {
+        let endpoint = cfg.endpoint.trim_end_matches('/');
+        let resource = Resource::builder()
+            .with_service_name(cfg.service_name.clone())
+            .build();
+
+        if cfg.traces {
+            let exporter = SpanExporter::builder()
+                .with_http()
+                .with_endpoint(format!("{}/v1/traces", endpoint))
+                .build()?;
+            let provider = SdkTracerProvider::builder()
+                .with_batch_exporter(exporter)
+                .with_resource(resource.clone())
+                .build();
+            let layer: BoxedLayer = Box::new(
+                tracing_opentelemetry::layer().with_tracer(provider.tracer("mc-node-agent")),
+            );
+            self.otel_layer.modify(|l| *l = Some(layer))?;
+            self.tracer_provider = Some(provider);
+        }
+
+        if cfg.metrics {
+            let exporter = MetricExporter::builder()
+                .with_http()
+                .with_endpoint(format!("{}/v1/metrics", endpoint))
+                .build()?;
+            let reader = PeriodicReader::builder(exporter)
+                .with_interval(std::time::Duration::from_secs(
+                    cfg.metrics_interval_secs.max(1),
+                ))
+                .build();
+            let provider = SdkMeterProvider::builder()
+                .with_reader(reader)
+                .with_resource(resource)
+                .build();
+            global::set_meter_provider(provider.clone());
+            self.meter_provider = Some(provider);
+        }
+
+        tracing::info!("Exporting telemetry to {}", endpoint);
+        Ok(())
+    }
+
+    /// Flushes anything still buffered; call before the process exits.
+    pub fn shutdown(self) {
+        if let Some(provider) = self.tracer_provider {
+            if let Err(e) = provider.shutdown() {
+                tracing::warn!("Failed to flush traces: {}", e);
+            }
+        }
+        if let Some(provider) = self.meter_provider {
+            if let Err(e) = provider.shutdown() {
+                tracing::warn!("Failed to flush metrics: {}", e);
+            }
+        }
+    }
+}
+
+struct ServerGauges {
+    cpu_percent: Gauge<f64>,
+    memory_bytes: Gauge<u64>,
+    heap_used_bytes: Gauge<u64>,
+    tps: Gauge<f64>,
+    mspt: Gauge<f64>,
+}
+
+fn gauges() -> &'static ServerGauges {
+    static GAUGES: OnceLock<ServerGauges> = OnceLock::new();
+    GAUGES.get_or_init(|| {
+        let meter = global::meter("mc-node-agent");
+        ServerGauges {
+            cpu_percent: meter
+                .f64_gauge("minecraft.server.cpu")
+                .with_unit("%")
+                .build(),
+            memory_bytes: meter
+                .u64_gauge("minecraft.server.memory")
+                .with_unit("By")
+                .build(),
+            heap_used_bytes: meter
+                .u64_gauge("minecraft.server.heap.used")
+                .with_unit("By")
+                .build(),
+            tps: meter.f64_gauge("minecraft.server.tps").build(),
+            mspt: meter
+                .f64_gauge("minecraft.server.mspt")
+                .with_unit("ms")
+                .build(),
+        }
+    })
+}
+
+/// Records a metrics sample; a no-op unless OTLP metrics are enabled.
+pub fn record_metrics(server_id: &str, metrics: &Metrics) {
+    let g = gauges();
+    let attrs = [KeyValue::new("server.id", server_id.to_string())];
+    g.cpu_percent.record(metrics.cpu_percent as f64, &attrs);
+    g.memory_bytes.record(metrics.memory_bytes, &attrs);
+    if let Some(heap) = metrics.gc.heap_used_bytes {
+        g.heap_used_bytes.record(heap, &attrs);
+    }
+    if let Some(tps) = metrics.ticks.tps {
+        g.tps.record(tps, &attrs);
+    }
+    if let Some(mspt) = metrics.ticks.mspt {
+        g.mspt.record(mspt, &attrs);
+    }
+}
@@ -0,0 +1,103 @@
+//! Tick performance parsed from `tps`/`mspt` style command output.
+
+use std::sync::{Arc, OnceLock};
+
+use regex::Regex;
+use serde::Serialize;
+
+use crate::{
+    process::send_command,
+    state::{AppState, ServerInstance},
+};
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TickStats {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tps: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mspt: Option<f64>,
+}
+
+fn strip_formatting(line: &str) -> String {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let re = RE.get_or_init(|| Regex::new(r"\x1b\[[0-9;]*m|§.").expect("valid regex"));
+    re.replace_all(line, "").into_owned()
+}
+
+struct TickPatterns {
+    paper_tps: Regex,
+    paper_mspt: Regex,
+    forge: Regex,
+    vanilla_mspt: Regex,
+}
+
+fn patterns() -> &'static TickPatterns {
+    static RE: OnceLock<TickPatterns> = OnceLock::new();
+    RE.get_or_init(|| TickPatterns {
+        // Paper: "TPS from last 1m, 5m, 15m: *20.0, 20.0, 20.0"
+        paper_tps: Regex::new(r"TPS from last 1m, 5m, 15m: \*?(\d+(?:\.\d+)?)")
+            .expect("valid regex"),
+        // Paper `mspt`: "◴ 2.1/1.0/5.2, ..." with 5s/10s/1m avg/min/max
+        paper_mspt: Regex::new(r"◴ (\d+(?:\.\d+)?)/").expect("valid regex"),
+        // Forge: "Overall: Mean tick time: 12.345 ms. Mean TPS: 20.000"
+        forge: Regex::new(
+            r"Overall: Mean tick time: (\d+(?:\.\d+)?) ms\. Mean TPS: (\d+(?:\.\d+)?)",
+        )
+        .expect("valid regex"),
+        // Vanilla 1.20.3+ `tick query`: "Average time per tick: 2.3ms"
+        vanilla_mspt: Regex::new(r"Average time per tick: (\d+(?:\.\d+)?)ms").expect("valid regex"),
+    })
+}
+
+/// Folds any tick figures reported on `line` into `stats`.
+pub fn record_tick_line(line: &str, stats: &mut TickStats) {
+    if !line.contains("TPS") && !line.contains('◴') && !line.contains("tick") {
+        return;
+    }
+    let line = strip_formatting(line);
+    let p = patterns();
+    let num = |caps: &regex::Captures, i: usize| caps[i].parse::<f64>().ok();
+    if let Some(caps) = p.paper_tps.captures(&line) {
+        stats.tps = num(&caps, 1);
+    } else if let Some(caps) = p.paper_mspt.captures(&line) {
+        stats.mspt = num(&caps, 1);
+    } else if let Some(caps) = p.forge.captures(&line) {
+        stats.mspt = num(&caps, 1);
+        stats.tps = num(&caps, 2);
+    } else if let Some(caps) = p.vanilla_mspt.captures(&line) {
+        stats.mspt = num(&caps, 1);
+        stats.tps = stats
+            .mspt
+            .map(|mspt| (1000.0 / mspt.max(50.0) * 100.0).round() / 100.0);
+    }
+}
+
+/// Issues the configured tick commands every `interval_secs` while running.
+pub fn spawn_sampler(
+    state: AppState,
+    server_id: String,
+    instance: Arc<ServerInstance>,
+    commands: Vec<String>,
+    interval_secs: u64,
+) {
+    if interval_secs == 0 || commands.is_empty() {
+        return;
+    }
+    let mut exited_rx = instance.exited.subscribe();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+        // Skip the immediate first tick; the server is still booting
+        interval.tick().await;
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = exited_rx.wait_for(|exited| *exited) => break,
+            }
+            for command in &commands {
+                if send_command(&state, &server_id, command).await.is_err() {
+                    return;
+                }
+            }
+        }
+    });
+}
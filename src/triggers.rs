@@ -0,0 +1,181 @@
+//! Console trigger rules: an action run whenever a line matches a pattern,
+//! such as answering "Can't keep up!" spam or restarting on a mod's
+//! deadlock message. Each rule has its own cooldown, so a burst of matching
+//! lines fires it once.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use regex::Regex;
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    config::{TriggerAction, TriggerRule},
+    process::{backup_server, restart_server, send_command},
+    state::{AppState, ServerInstance},
+};
+
+struct Trigger {
+    pattern: Regex,
+    rule: TriggerRule,
+    last_fired: Option<Instant>,
+}
+
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    server_id: &'a str,
+    pattern: &'a str,
+    line: &'a str,
+    timestamp_ms: u64,
+}
+
+/// Whether the pattern's captures can go into a console command. They may
+/// be text a player typed, so only name-like captures are let through.
+fn safe_captures(caps: &regex::Captures) -> bool {
+    caps.iter()
+        .skip(1)
+        .flatten()
+        .all(|m| m.as_str().chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+}
+
+/// Watches the console for lines matching the server's trigger rules.
+pub fn spawn_listener(
+    state: AppState,
+    server_id: String,
+    instance: Arc<ServerInstance>,
+    rules: Vec<TriggerRule>,
+) {
+    // Rules were validated with the config, so only bad hand edits drop out
+    let mut triggers: Vec<Trigger> = rules
+        .into_iter()
+        .filter_map(|rule| {
+            let pattern = Regex::new(&rule.pattern).ok()?;
+            Some(Trigger {
+                pattern,
+                rule,
+                last_fired: None,
+            })
+        })
+        .collect();
+    if triggers.is_empty() {
+        return;
+    }
+    let mut console_rx = instance.console_tx.subscribe();
+    let mut exited_rx = instance.exited.subscribe();
+    tokio::spawn(async move {
+        loop {
+            let line = tokio::select! {
+                msg = console_rx.recv() => match msg {
+                    Ok(line) => line,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+                _ = exited_rx.wait_for(|exited| *exited) => break,
+            };
+            let text = crate::ansi::strip(&line.text);
+            let mut restarting = false;
+            for trigger in &mut triggers {
+                let cooldown = Duration::from_secs(trigger.rule.cooldown_secs);
+                if trigger.last_fired.is_some_and(|at| at.elapsed() < cooldown) {
+                    continue;
+                }
+                let Some(caps) = trigger.pattern.captures(&text) else {
+                    continue;
+                };
+                trigger.last_fired = Some(Instant::now());
+                tracing::info!(
+                    "Trigger '{}' fired on '{}': {}",
+                    trigger.rule.pattern,
+                    server_id,
+                    text
+                );
+                // Only webhooks leave the server alone
+                let changes = !matches!(trigger.rule.action, TriggerAction::Webhook { .. });
+                if changes {
+                    if let Err(e) = crate::maintenance::check(&state).await {
+                        tracing::info!("Trigger on '{}' skipped: {}", server_id, e);
+                        continue;
+                    }
+                }
+                match trigger.rule.action {
+                    TriggerAction::Command { ref command } => {
+                        if command.contains('$') && !safe_captures(&caps) {
+                            tracing::warn!(
+                                "Trigger command on '{}' skipped: captures from '{}' are not \
+                                 plain names",
+                                server_id,
+                                text
+                            );
+                            continue;
+                        }
+                        let mut expanded = String::new();
+                        caps.expand(command, &mut expanded);
+                        if let Err(e) = send_command(&state, &server_id, &expanded).await {
+                            tracing::warn!("Trigger command on '{}' failed: {}", server_id, e);
+                        }
+                    }
+                    TriggerAction::Webhook { ref url } => {
+                        let payload = WebhookPayload {
+                            server_id: &server_id,
+                            pattern: &trigger.rule.pattern,
+                            line: &text,
+                            timestamp_ms: line.timestamp_ms,
+                        };
+                        let request = crate::nodes::client().post(url).json(&payload);
+                        let (url, sid) = (url.clone(), server_id.clone());
+                        tokio::spawn(async move {
+                            let sent = request.send().await.and_then(|r| r.error_for_status());
+                            if let Err(e) = sent {
+                                tracing::warn!(
+                                    "Trigger webhook '{}' for '{}' failed: {}",
+                                    url,
+                                    sid,
+                                    e
+                                );
+                            }
+                        });
+                    }
+                    TriggerAction::Backup => {
+                        let (state, sid) = (state.clone(), server_id.clone());
+                        tokio::spawn(async move {
+                            if let Err(e) = backup_server(state, &sid).await {
+                                tracing::error!("Triggered backup of '{}' failed: {}", sid, e);
+                            }
+                        });
+                    }
+                    TriggerAction::Restart => {
+                        let (state, sid) = (state.clone(), server_id.clone());
+                        tokio::spawn(async move {
+                            if let Err(e) = restart_server(state, &sid).await {
+                                tracing::error!("Triggered restart of '{}' failed: {}", sid, e);
+                            }
+                        });
+                        restarting = true;
+                        break;
+                    }
+                }
+            }
+            // The restarted server gets a listener of its own
+            if restarting {
+                break;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_name_like_captures_are_expanded() {
+        let pattern = Regex::new(r"\]: (\w+) joined the game|<(\S+)> (.+)$").unwrap();
+        let caps = pattern.captures("[12:00:00 INFO]: Steve joined the game").unwrap();
+        assert!(safe_captures(&caps));
+        let caps = pattern.captures("[12:00:00 INFO]: <Steve> hi; op Steve").unwrap();
+        assert!(!safe_captures(&caps));
+    }
+}
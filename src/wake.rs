@@ -0,0 +1,175 @@
+//! Wake-on-demand: while a `wake_on_demand` server is stopped the agent
+//! holds its port, answers list pings with a sleeping MOTD and starts the
+//! server when someone tries to join. The port is released to the JVM
+//! before it is spawned.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use crate::{
+    config::ServerConfig,
+    slp::{decode_string, decode_varint, read_packet, send_packet, write_string},
+    state::AppState,
+};
+
+const SUPERVISE_INTERVAL: Duration = Duration::from_secs(5);
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct WakeListener {
+    stop: watch::Sender<bool>,
+    task: JoinHandle<()>,
+}
+
+/// Handshake `next_state` values.
+const STATE_STATUS: i32 = 1;
+const STATE_LOGIN: i32 = 2;
+
+fn chat_json(text: &str) -> String {
+    serde_json::json!({ "text": text }).to_string()
+}
+
+/// Serves one connection; returns true when it was a join attempt.
+async fn handle_client(mut stream: TcpStream, cfg: &ServerConfig) -> Result<bool, String> {
+    let (id, body) = read_packet(&mut stream).await?;
+    if id != 0x00 {
+        return Err(format!("Unexpected handshake packet id {}", id));
+    }
+    let (protocol, mut pos) = decode_varint(&body).ok_or("Truncated handshake")?;
+    let (_, len) = decode_string(&body[pos..]).ok_or("Truncated handshake")?;
+    pos += len + 2; // server address, then the u16 port
+    let (next_state, _) =
+        decode_varint(body.get(pos..).unwrap_or(&[])).ok_or("Truncated handshake")?;
+
+    match next_state {
+        STATE_STATUS => {
+            let (id, _) = read_packet(&mut stream).await?;
+            if id != 0x00 {
+                return Ok(false);
+            }
+            let status = serde_json::json!({
+                // Echo the client's protocol so it isn't shown as incompatible
+                "version": { "name": "Sleeping", "protocol": protocol },
+                "players": { "online": 0, "max": 0 },
+                "description": {
+                    "text": format!("{} is sleeping. Join to start it.", cfg.name),
+                    "color": "gray",
+                },
+            });
+            let mut response = Vec::new();
+            write_string(&mut response, &status.to_string());
+            send_packet(&mut stream, 0x00, &response).await?;
+            // Ping: echo the payload back as the pong
+            if let Ok((0x01, payload)) = read_packet(&mut stream).await {
+                send_packet(&mut stream, 0x01, &payload).await?;
+            }
+            Ok(false)
+        }
+        STATE_LOGIN => {
+            let mut reason = Vec::new();
+            write_string(
+                &mut reason,
+                &chat_json(&format!(
+                    "{} is starting up. Reconnect in a moment.",
+                    cfg.name
+                )),
+            );
+            send_packet(&mut stream, 0x00, &reason).await?;
+            Ok(true)
+        }
+        other => Err(format!("Unknown handshake state {}", other)),
+    }
+}
+
+fn spawn_listener(state: AppState, cfg: ServerConfig, listener: TcpListener) -> WakeListener {
+    let (stop, mut stopped) = watch::channel(false);
+    let task = tokio::spawn(async move {
+        loop {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = stopped.changed() => return,
+            };
+            let Ok((stream, peer)) = accepted else {
+                continue;
+            };
+            match tokio::time::timeout(CLIENT_TIMEOUT, handle_client(stream, &cfg)).await {
+                Ok(Ok(true)) => {
+                    tracing::info!("Join attempt from {} woke server '{}'", peer, cfg.id);
+                    break;
+                }
+                Ok(Ok(false)) => {}
+                Ok(Err(e)) => tracing::debug!("Wake listener for '{}': {}", cfg.id, e),
+                Err(_) => tracing::debug!("Wake listener for '{}': client timed out", cfg.id),
+            }
+        }
+        // Free the port before start_server hands it to the JVM
+        drop(listener);
+        let id = cfg.id.clone();
+        tokio::spawn(async move {
+            if let Err(e) = crate::process::start_server(state, &id).await {
+                tracing::error!("Failed to wake server '{}': {}", id, e);
+            }
+        });
+    });
+    WakeListener { stop, task }
+}
+
+async fn shutdown(listener: WakeListener) {
+    listener.stop.send_replace(true);
+    let _ = listener.task.await;
+}
+
+/// Stops the server's wake listener, if any, so its port can be bound.
+/// Callers hold `listeners` until the server is registered as running.
+pub async fn release(listeners: &mut HashMap<String, WakeListener>, server_id: &str) {
+    if let Some(listener) = listeners.remove(server_id) {
+        shutdown(listener).await;
+    }
+}
+
+/// Keeps a listener on the port of every stopped `wake_on_demand` server.
+pub fn spawn_supervisor(state: AppState) {
+    tokio::spawn(async move {
+        loop {
+            let servers = state.config.read().await.servers.clone();
+            {
+                let mut listeners = state.wake_listeners.lock().await;
+                let wanted: Vec<&ServerConfig> = servers
+                    .iter()
+                    .filter(|s| s.wake_on_demand && !state.servers.contains_key(&s.id))
+                    .collect();
+
+                let stale: Vec<String> = listeners
+                    .iter()
+                    .filter(|(id, l)| l.task.is_finished() || !wanted.iter().any(|s| &&s.id == id))
+                    .map(|(id, _)| id.clone())
+                    .collect();
+                for id in stale {
+                    release(&mut listeners, &id).await;
+                }
+
+                for cfg in wanted {
+                    if listeners.contains_key(&cfg.id) {
+                        continue;
+                    }
+                    match TcpListener::bind(("0.0.0.0", cfg.port)).await {
+                        Ok(listener) => {
+                            tracing::info!("Listening on port {} to wake '{}'", cfg.port, cfg.id);
+                            listeners.insert(
+                                cfg.id.clone(),
+                                spawn_listener(state.clone(), cfg.clone(), listener),
+                            );
+                        }
+                        Err(e) => {
+                            tracing::debug!("Cannot hold port {} for '{}': {}", cfg.port, cfg.id, e)
+                        }
+                    }
+                }
+            }
+            tokio::time::sleep(SUPERVISE_INTERVAL).await;
+        }
+    });
+}
@@ -0,0 +1,86 @@
+//! Detects servers that are alive but frozen. A quiet console gets a probe
+//! command; if nothing at all is printed by `timeout_secs`, the JVM is asked
+//! for a thread dump and, depending on the action, restarted.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::{
+    config::WatchdogAction,
+    process::{restart_server, send_command},
+    state::{AppState, ServerPhase},
+};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// Time given to the JVM to print the thread dump before it is restarted.
+const THREAD_DUMP_GRACE: Duration = Duration::from_secs(2);
+
+pub fn spawn_monitor(state: AppState) {
+    tokio::spawn(async move {
+        // When each server (by id and PID) was last probed, and when it was
+        // last reported hung so each quiet spell is only acted on once
+        let mut probed: HashMap<(String, u32), Instant> = HashMap::new();
+        let mut tripped: HashMap<(String, u32), Instant> = HashMap::new();
+        loop {
+            tokio::time::sleep(CHECK_INTERVAL).await;
+            let servers = state.config.read().await.servers.clone();
+            let alive = |(id, pid): &(String, u32)| {
+                state.servers.get(id).is_some_and(|i| i.pid == *pid)
+            };
+            probed.retain(|key, _| alive(key));
+            tripped.retain(|key, _| alive(key));
+            for server in servers {
+                let Some(watchdog) = server.watchdog else {
+                    continue;
+                };
+                let Some(instance) = state.servers.get(&server.id).map(|i| i.value().clone())
+                else {
+                    continue;
+                };
+                let key = (server.id.clone(), instance.pid);
+                // Startup (world generation) and shutdown may legitimately stall
+                if *instance.phase.lock().await != ServerPhase::Running {
+                    probed.remove(&key);
+                    continue;
+                }
+                let last_output = *instance.last_output.lock().await;
+                let quiet = last_output.elapsed();
+                let timeout = Duration::from_secs(watchdog.timeout_secs);
+                if tripped.get(&key).is_some_and(|at| *at > last_output) {
+                    continue;
+                }
+                if quiet < timeout / 2 {
+                    continue;
+                }
+                if probed.get(&key).is_none_or(|at| *at < last_output) {
+                    probed.insert(key.clone(), Instant::now());
+                    let _ = send_command(&state, &server.id, &watchdog.probe_command).await;
+                    continue;
+                }
+                if quiet < timeout {
+                    continue;
+                }
+
+                tracing::warn!(
+                    "Server '{}' has not written any output for {}s; requesting a thread dump",
+                    server.id,
+                    quiet.as_secs()
+                );
+                tripped.insert(key.clone(), Instant::now());
+                if instance.child.lock().await.owns_pid().await {
+                    crate::platform::request_thread_dump(instance.pid);
+                }
+                if watchdog.action == WatchdogAction::Restart {
+                    let state = state.clone();
+                    tokio::spawn(async move {
+                        tokio::time::sleep(THREAD_DUMP_GRACE).await;
+                        tracing::warn!("Watchdog is restarting hung server '{}'", server.id);
+                        if let Err(e) = restart_server(state, &server.id).await {
+                            tracing::error!("Watchdog restart of '{}' failed: {}", server.id, e);
+                        }
+                    });
+                }
+            }
+        }
+    });
+}
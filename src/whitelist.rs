@@ -0,0 +1,231 @@
+//! Whitelist groups: servers sharing a `whitelist_group` keep the same
+//! `whitelist.json`. The group's list lives in
+//! `data_directory/whitelists/<group>.json`; edits made on any member, in
+//! game or in the file, are merged into it and pushed to the others, which
+//! are told to `whitelist reload` while running.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{process::send_command, state::AppState};
+
+const SYNC_INTERVAL: Duration = Duration::from_secs(5);
+const WHITELIST_FILE: &str = "whitelist.json";
+
+/// One `whitelist.json` entry, in the server's own format.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct WhitelistEntry {
+    #[serde(default)]
+    pub uuid: String,
+    pub name: String,
+}
+
+impl WhitelistEntry {
+    fn key(&self) -> String {
+        if self.uuid.is_empty() {
+            self.name.to_lowercase()
+        } else {
+            self.uuid.to_lowercase()
+        }
+    }
+
+    /// Whether `player` is this entry's name or UUID.
+    pub fn is(&self, player: &str) -> bool {
+        self.name.eq_ignore_ascii_case(player) || self.uuid.eq_ignore_ascii_case(player)
+    }
+}
+
+/// A change requested through the API, applied on top of the members' edits.
+pub enum Change {
+    Add(WhitelistEntry),
+    Remove(String),
+}
+
+/// The list each group member had after its last sync, by server id, so
+/// the next sync can tell what was edited there since.
+#[derive(Default)]
+pub struct SyncState {
+    synced: HashMap<String, Vec<WhitelistEntry>>,
+}
+
+fn group_path(data_directory: &str, group: &str) -> PathBuf {
+    Path::new(data_directory).join("whitelists").join(format!("{}.json", group))
+}
+
+/// Parses a JSON list such as `whitelist.json`; `Ok(None)` when it
+/// doesn't exist.
+pub async fn read_list<T: DeserializeOwned>(path: &Path) -> Result<Option<Vec<T>>, String> {
+    match tokio::fs::read_to_string(path).await {
+        // A server may leave the file empty before its first write
+        Ok(contents) if contents.trim().is_empty() => Ok(Some(Vec::new())),
+        Ok(contents) => serde_json::from_str(&contents)
+            .map(Some)
+            .map_err(|e| format!("Failed to parse '{}': {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Failed to read '{}': {}", path.display(), e)),
+    }
+}
+
+/// Replaces a JSON list in one rename, so servers never read half of it.
+pub async fn write_list<T: Serialize>(path: &Path, list: &[T]) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|e| format!("Failed to create '{}': {}", dir.display(), e))?;
+    }
+    let json = serde_json::to_string_pretty(list).map_err(|e| e.to_string())?;
+    let tmp = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp, json)
+        .await
+        .map_err(|e| format!("Failed to write '{}': {}", tmp.display(), e))?;
+    tokio::fs::rename(&tmp, path)
+        .await
+        .map_err(|e| format!("Failed to replace '{}': {}", path.display(), e))
+}
+
+fn same_players(a: &[WhitelistEntry], b: &[WhitelistEntry]) -> bool {
+    let keys =
+        |list: &[WhitelistEntry]| list.iter().map(WhitelistEntry::key).collect::<HashSet<_>>();
+    a.len() == b.len() && keys(a) == keys(b)
+}
+
+/// Applies the players added to and removed from a member since `before`.
+fn merge_edits(list: &mut Vec<WhitelistEntry>, before: &[WhitelistEntry], now: &[WhitelistEntry]) {
+    let before_keys: HashSet<String> = before.iter().map(WhitelistEntry::key).collect();
+    let now_keys: HashSet<String> = now.iter().map(WhitelistEntry::key).collect();
+    list.retain(|e| now_keys.contains(&e.key()) || !before_keys.contains(&e.key()));
+    for entry in now.iter().filter(|e| !before_keys.contains(&e.key())) {
+        if !list.iter().any(|e| e.key() == entry.key()) {
+            list.push(entry.clone());
+        }
+    }
+}
+
+/// Server ids and directories of each group's members.
+async fn groups(state: &AppState) -> HashMap<String, Vec<(String, String)>> {
+    let config = state.config.read().await;
+    let mut groups: HashMap<String, Vec<(String, String)>> = HashMap::new();
+    for server in &config.servers {
+        if let Some(ref group) = server.whitelist_group {
+            let member = (server.id.clone(), server.directory.clone());
+            groups.entry(group.clone()).or_default().push(member);
+        }
+    }
+    groups
+}
+
+/// Ids of the servers in `group`.
+pub async fn members(state: &AppState, group: &str) -> Vec<String> {
+    groups(state)
+        .await
+        .remove(group)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect()
+}
+
+/// The group's list, merged with its members' files.
+pub async fn list(state: &AppState, group: &str) -> Result<Vec<WhitelistEntry>, String> {
+    sync_group(state, group, None).await
+}
+
+/// Applies `change` to the group and pushes the result to every member.
+pub async fn apply(
+    state: &AppState,
+    group: &str,
+    change: Change,
+) -> Result<Vec<WhitelistEntry>, String> {
+    sync_group(state, group, Some(change)).await
+}
+
+async fn sync_group(
+    state: &AppState,
+    group: &str,
+    change: Option<Change>,
+) -> Result<Vec<WhitelistEntry>, String> {
+    let data_directory = state.config.read().await.agent.data_directory.clone();
+    let members = groups(state).await.remove(group).unwrap_or_default();
+    let mut sync = state.whitelists.lock().await;
+
+    let path = group_path(&data_directory, group);
+    let stored = read_list(&path).await?;
+    let mut list = stored.clone().unwrap_or_default();
+    let mut current: HashMap<String, Vec<WhitelistEntry>> = HashMap::new();
+    let mut unreadable = HashSet::new();
+    for (id, directory) in &members {
+        let file = Path::new(directory).join(WHITELIST_FILE);
+        let entries = match read_list(&file).await {
+            Ok(Some(entries)) => entries,
+            // Gets the group's list below
+            Ok(None) => continue,
+            // Possibly caught mid-write; tried again next time
+            Err(e) => {
+                tracing::warn!("Skipping the whitelist of '{}': {}", id, e);
+                unreadable.insert(id);
+                continue;
+            }
+        };
+        match sync.synced.get(id) {
+            Some(before) => merge_edits(&mut list, before, &entries),
+            // Not synced yet, so nothing it lacks counts as removed
+            None => merge_edits(&mut list, &[], &entries),
+        }
+        current.insert(id.clone(), entries);
+    }
+    match change {
+        Some(Change::Add(entry)) => {
+            list.retain(|e| e.key() != entry.key() && !e.is(&entry.name));
+            list.push(entry);
+        }
+        Some(Change::Remove(player)) => list.retain(|e| !e.is(&player)),
+        None => {}
+    }
+
+    if stored.as_deref() != Some(list.as_slice()) {
+        write_list(&path, &list).await?;
+    }
+    for (id, directory) in members.iter().filter(|(id, _)| !unreadable.contains(id)) {
+        let unchanged = current.get(id).is_some_and(|entries| same_players(entries, &list));
+        if !unchanged {
+            let file = Path::new(directory).join(WHITELIST_FILE);
+            if let Err(e) = write_list(&file, &list).await {
+                tracing::warn!("Failed to update the whitelist of '{}': {}", id, e);
+                continue;
+            }
+            if state.servers.contains_key(id) {
+                if let Err(e) = send_command(state, id, "whitelist reload").await {
+                    tracing::warn!("Failed to reload the whitelist of '{}': {}", id, e);
+                }
+            }
+            tracing::info!("Synced the '{}' whitelist to '{}'", group, id);
+        }
+        sync.synced.insert(id.clone(), list.clone());
+    }
+    Ok(list)
+}
+
+/// Periodically merges and pushes every group's whitelist, picking up
+/// `whitelist add` and `remove` run on any member.
+pub fn spawn_sync(state: AppState) {
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(SYNC_INTERVAL);
+        loop {
+            tick.tick().await;
+            let groups = groups(&state).await;
+            let members: HashSet<&String> =
+                groups.values().flatten().map(|(id, _)| id).collect();
+            state.whitelists.lock().await.synced.retain(|id, _| members.contains(id));
+            for group in groups.keys() {
+                if let Err(e) = sync_group(&state, group, None).await {
+                    tracing::warn!("Whitelist group '{}' sync failed: {}", group, e);
+                }
+            }
+        }
+    });
+}
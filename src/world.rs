@@ -0,0 +1,869 @@
+use std::path::{Component, Path, PathBuf};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    config::ServerConfig,
+    console, nbt,
+    process::{command_with_response, start_server, stop_server},
+    properties::ServerProperties,
+    runtimes::detect_minecraft_version,
+    state::AppState,
+};
+
+const LOCATE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct LocateResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    pub kind: String,
+    pub target: String,
+    pub x: i64,
+    /// Absent when the game reports `~`, i.e. no fixed height.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub y: Option<i64>,
+    pub z: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distance: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct WorldReset {
+    pub level_name: String,
+    /// The tarball holding the old world and its dimension folders.
+    pub archive: String,
+    /// The new `level-seed`, when one was set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<String>,
+    /// Whether the server was running and got restarted onto the new world.
+    pub restarted: bool,
+}
+
+/// Folders Bukkit-based servers keep beside a world for its other dimensions.
+const DIMENSION_SUFFIXES: &[&str] = &["_nether", "_the_end"];
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Dimension {
+    Nether,
+    End,
+}
+
+impl Dimension {
+    pub fn name(self) -> &'static str {
+        match self {
+            Dimension::Nether => "nether",
+            Dimension::End => "end",
+        }
+    }
+
+    /// Where the dimension of the world `level_name` is kept: its own
+    /// folder on Bukkit-based servers, a `DIM` folder inside the world on
+    /// vanilla ones.
+    fn folders(self, level_name: &str) -> [String; 2] {
+        let (suffix, dim) = match self {
+            Dimension::Nether => ("_nether", "DIM-1"),
+            Dimension::End => ("_the_end", "DIM1"),
+        };
+        [format!("{}{}", level_name, suffix), format!("{}/{}", level_name, dim)]
+    }
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct DimensionReset {
+    pub dimension: Dimension,
+    /// Deleted folders, relative to the server directory.
+    pub removed: Vec<String>,
+    pub archive: String,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct WorldInfo {
+    pub name: String,
+    /// Whether `level-name` points at it.
+    pub active: bool,
+    /// Including its dimension folders.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size_bytes: Option<u64>,
+    /// The `_nether` and `_the_end` folders that belong to it.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dimension_folders: Vec<String>,
+    /// When level.dat was last saved.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub saved_ms: Option<u64>,
+}
+
+/// Per-dimension folders holding `r.<x>.<z>.mca` files for the same regions.
+const REGION_FOLDERS: &[&str] = &["region", "entities", "poi"];
+const REGION_BLOCKS: i64 = 512;
+
+/// Which region files to trim. With both criteria, regions matching either
+/// are trimmed.
+#[derive(Debug, Clone, Deserialize, utoipa::ToSchema)]
+pub struct TrimRequest {
+    /// Keep regions overlapping the square this many blocks around the
+    /// center, in every dimension.
+    #[serde(default)]
+    pub radius: Option<u32>,
+    #[serde(default)]
+    pub center_x: i64,
+    #[serde(default)]
+    pub center_z: i64,
+    /// Keep regions saved within this many days.
+    #[serde(default)]
+    pub unused_days: Option<u32>,
+    /// Only report what would be trimmed.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct TrimReport {
+    pub world: String,
+    pub dry_run: bool,
+    /// Trimmed files, relative to the server directory.
+    pub files: Vec<String>,
+    pub bytes: u64,
+    /// The tarball holding the trimmed files, unless nothing was trimmed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archive: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct SpawnPoint {
+    pub x: i64,
+    pub y: i64,
+    pub z: i64,
+}
+
+/// What level.dat records about a world.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct LevelInfo {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spawn: Option<SpawnPoint>,
+    /// Game version that last saved the world, e.g. `1.21.4`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_version: Option<i64>,
+    /// `normal`, `flat`, `large_biomes`, `amplified`, `single_biome` or
+    /// `debug`; the generator's own id for anything else.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub world_type: Option<String>,
+    pub hardcore: bool,
+}
+
+/// Whether `name` is one folder inside the server directory, not a path
+/// out of it.
+fn single_folder(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    matches!((components.next(), components.next()), (Some(Component::Normal(_)), None))
+}
+
+/// The `level-name` in server.properties. It is joined onto the server
+/// directory, archived and deleted, so anything but a folder name is refused.
+async fn level_name(cfg: &ServerConfig) -> Result<String, String> {
+    let props = ServerProperties::load(&cfg.directory).await?;
+    let name = props.level_name();
+    if !single_folder(name) {
+        return Err(format!("level-name '{}' is not a folder name", name));
+    }
+    Ok(name.to_string())
+}
+
+pub async fn world_dir(cfg: &ServerConfig) -> Result<PathBuf, String> {
+    if !cfg.flavor().has_world() {
+        return Err(format!("'{}' is a proxy and has no world", cfg.id));
+    }
+    Ok(Path::new(&cfg.directory).join(level_name(cfg).await?))
+}
+
+pub async fn read_level_dat(cfg: &ServerConfig) -> Result<nbt::Tag, String> {
+    read_world_level(&world_dir(cfg).await?).await
+}
+
+async fn read_world_level(world: &Path) -> Result<nbt::Tag, String> {
+    let level_dat = world.join("level.dat");
+    if !level_dat.exists() {
+        return Err(format!("'{}' does not exist", level_dat.display()));
+    }
+    nbt::read_gzip_file(&level_dat).await
+}
+
+/// World seed from level.dat, covering both the 1.16+ and the older layout.
+pub fn seed_from_level(level: &nbt::Tag) -> Option<i64> {
+    level
+        .path(&["Data", "WorldGenSettings", "seed"])
+        .or_else(|| level.path(&["Data", "RandomSeed"]))
+        .and_then(nbt::Tag::as_i64)
+}
+
+pub async fn world_seed(cfg: &ServerConfig) -> Result<i64, String> {
+    let level = read_level_dat(cfg).await?;
+    seed_from_level(&level).ok_or_else(|| "level.dat does not contain a seed".to_string())
+}
+
+fn valid_target(target: &str) -> bool {
+    !target.is_empty()
+        && target
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | ':' | '#' | '.' | '/' | '-'))
+}
+
+/// The server's answer to `/locate`, anchored on the log prefix so chat
+/// sent while the command runs can't pass for it.
+fn locate_regex() -> &'static Regex {
+    static RE: std::sync::OnceLock<Regex> = std::sync::OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(&format!(
+            concat!(
+                r"{}(?:The nearest .* is at \[(-?\d+), (~|-?\d+), (-?\d+)\]",
+                r"(?: \((\d+) blocks away\))?|Could not find|There is no",
+                r"|Unknown or incomplete command|Incorrect argument)",
+            ),
+            console::INFO_PREFIX
+        ))
+        .expect("valid regex")
+    })
+}
+
+/// Runs `/locate` on the running server and parses the reported coordinates.
+pub async fn locate(
+    state: &AppState,
+    cfg: &ServerConfig,
+    kind: &str,
+    target: &str,
+) -> Result<LocateResult, String> {
+    if !valid_target(target) {
+        return Err(format!("invalid locate target '{}'", target));
+    }
+    // 1.19 moved everything under `locate structure|biome|poi`
+    let legacy = detect_minecraft_version(cfg)
+        .await
+        .and_then(|(id, _)| id.split('.').nth(1).and_then(|m| m.parse::<u32>().ok()))
+        .is_some_and(|minor| minor < 19);
+    let command = match (kind, legacy) {
+        ("structure", false) => format!("locate structure {}", target),
+        ("biome", false) => format!("locate biome {}", target),
+        ("poi", false) => format!("locate poi {}", target),
+        ("structure", true) => format!("locate {}", target),
+        ("biome", true) => format!("locatebiome {}", target),
+        ("poi", true) => return Err("poi lookups require Minecraft 1.19 or newer".to_string()),
+        _ => return Err("kind must be one of structure, biome, poi".to_string()),
+    };
+
+    let response = locate_regex();
+    let line = command_with_response(state, &cfg.id, &command, response, LOCATE_TIMEOUT).await?;
+    let caps = response
+        .captures(&line)
+        .filter(|c| c.get(1).is_some())
+        .ok_or_else(|| format!("Server could not locate '{}': {}", target, line))?;
+    let int = |i: usize| caps.get(i).and_then(|m| m.as_str().parse::<i64>().ok());
+
+    Ok(LocateResult {
+        seed: world_seed(cfg).await.ok(),
+        kind: kind.to_string(),
+        target: target.to_string(),
+        x: int(1).unwrap_or_default(),
+        y: int(2),
+        z: int(3).unwrap_or_default(),
+        distance: caps.get(4).and_then(|m| m.as_str().parse().ok()),
+    })
+}
+
+/// World spawn, from `Data.spawn.pos` since 1.21.9 and `Data.SpawnX` etc.
+/// before.
+fn spawn_from_level(level: &nbt::Tag) -> Option<SpawnPoint> {
+    let pos = level.path(&["Data", "spawn", "pos"]).and_then(nbt::Tag::as_int_array);
+    if let Some(&[x, y, z]) = pos {
+        return Some(SpawnPoint {
+            x: x.into(),
+            y: y.into(),
+            z: z.into(),
+        });
+    }
+    let coordinate = |key| level.path(&["Data", key]).and_then(nbt::Tag::as_i64);
+    Some(SpawnPoint {
+        x: coordinate("SpawnX")?,
+        y: coordinate("SpawnY")?,
+        z: coordinate("SpawnZ")?,
+    })
+}
+
+/// The overworld generator, named like `level-type`: from the 1.16+
+/// `WorldGenSettings`, or the older `generatorName`.
+fn world_type_from_level(level: &nbt::Tag) -> Option<String> {
+    let generator = ["Data", "WorldGenSettings", "dimensions", "minecraft:overworld", "generator"];
+    if let Some(generator) = level.path(&generator) {
+        let kind = generator.get("type").and_then(nbt::Tag::as_str)?;
+        let settings = generator.get("settings").and_then(nbt::Tag::as_str);
+        let biomes = generator.path(&["biome_source", "type"]).and_then(nbt::Tag::as_str);
+        let world_type = match (kind, settings, biomes) {
+            ("minecraft:flat", _, _) => "flat",
+            ("minecraft:debug", _, _) => "debug",
+            ("minecraft:noise", _, Some("minecraft:fixed")) => "single_biome",
+            ("minecraft:noise", Some("minecraft:overworld"), _) => "normal",
+            ("minecraft:noise", Some("minecraft:large_biomes"), _) => "large_biomes",
+            ("minecraft:noise", Some("minecraft:amplified"), _) => "amplified",
+            (kind, settings, _) => return Some(settings.unwrap_or(kind).to_string()),
+        };
+        return Some(world_type.to_string());
+    }
+    let name = level.path(&["Data", "generatorName"]).and_then(nbt::Tag::as_str)?;
+    Some(match name.to_ascii_lowercase().as_str() {
+        "default" | "default_1_1" => "normal".to_string(),
+        "largebiomes" => "large_biomes".to_string(),
+        "debug_all_block_states" => "debug".to_string(),
+        other => other.to_string(),
+    })
+}
+
+/// Seed, spawn, version and generator of the world folder `name`.
+pub async fn level_info(cfg: &ServerConfig, name: &str) -> Result<LevelInfo, String> {
+    let level = read_world_level(&Path::new(&cfg.directory).join(name)).await?;
+    let string = |keys: &[&str]| level.path(keys).and_then(nbt::Tag::as_str).map(str::to_string);
+    Ok(LevelInfo {
+        name: string(&["Data", "LevelName"]).unwrap_or_else(|| name.to_string()),
+        seed: seed_from_level(&level),
+        spawn: spawn_from_level(&level),
+        version: string(&["Data", "Version", "Name"]),
+        data_version: level.path(&["Data", "DataVersion"]).and_then(nbt::Tag::as_i64),
+        world_type: world_type_from_level(&level),
+        hardcore: level.path(&["Data", "hardcore"]).and_then(nbt::Tag::as_i64) == Some(1),
+    })
+}
+
+/// Folders in the server directory holding a level.dat, minus the
+/// dimension folders of another world.
+async fn world_folders(directory: &Path) -> Result<Vec<String>, String> {
+    let mut entries = tokio::fs::read_dir(directory)
+        .await
+        .map_err(|e| format!("Failed to read '{}': {}", directory.display(), e))?;
+    let mut folders = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.path().join("level.dat").is_file() {
+            folders.push(name);
+        }
+    }
+    let is_dimension = |name: &String| {
+        DIMENSION_SUFFIXES.iter().any(|suffix| {
+            name.strip_suffix(suffix).is_some_and(|base| folders.iter().any(|f| f == base))
+        })
+    };
+    let mut worlds: Vec<String> = folders.iter().filter(|f| !is_dimension(f)).cloned().collect();
+    worlds.sort();
+    Ok(worlds)
+}
+
+/// Whether `name` is one of the server's world folders.
+pub async fn is_world(cfg: &ServerConfig, name: &str) -> Result<bool, String> {
+    if !cfg.flavor().has_world() {
+        return Err(format!("'{}' is a proxy and has no world", cfg.id));
+    }
+    let worlds = world_folders(Path::new(&cfg.directory)).await?;
+    Ok(worlds.iter().any(|w| w == name))
+}
+
+/// The dimension folders of the world `name` that exist.
+fn dimension_folders(directory: &Path, name: &str) -> Vec<String> {
+    DIMENSION_SUFFIXES
+        .iter()
+        .map(|suffix| format!("{}{}", name, suffix))
+        .filter(|folder| directory.join(folder).is_dir())
+        .collect()
+}
+
+/// The worlds in the server directory, with their sizes.
+pub async fn list_worlds(cfg: &ServerConfig) -> Result<Vec<WorldInfo>, String> {
+    if !cfg.flavor().has_world() {
+        return Err(format!("'{}' is a proxy and has no world", cfg.id));
+    }
+    let directory = Path::new(&cfg.directory);
+    let level_name = ServerProperties::load(&cfg.directory).await?.level_name().to_string();
+    let mut worlds = Vec::new();
+    for name in world_folders(directory).await? {
+        let dimension_folders = dimension_folders(directory, &name);
+        let mut size_bytes = crate::disk::du_bytes(&directory.join(&name)).await.ok();
+        for folder in &dimension_folders {
+            let size = crate::disk::du_bytes(&directory.join(folder)).await.ok();
+            size_bytes = size_bytes.zip(size).map(|(a, b)| a + b);
+        }
+        let saved_ms = tokio::fs::metadata(directory.join(&name).join("level.dat"))
+            .await
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as u64);
+        worlds.push(WorldInfo {
+            active: name == level_name,
+            name,
+            size_bytes,
+            dimension_folders,
+            saved_ms,
+        });
+    }
+    Ok(worlds)
+}
+
+/// Points `level-name` at the world folder `name`, stopping the server
+/// first and starting it again afterwards when it is running. Returns
+/// whether it was restarted.
+pub async fn activate_world(
+    state: &AppState,
+    cfg: &ServerConfig,
+    name: &str,
+) -> Result<bool, String> {
+    let running = state.servers.contains_key(&cfg.id);
+    if running {
+        // Checked up front, so the server isn't left stopped
+        crate::maintenance::check(state).await?;
+        stop_server(state.clone(), &cfg.id).await?;
+    }
+    let mut properties = ServerProperties::load(&cfg.directory).await?;
+    properties.set("level-name", name);
+    properties.save(&cfg.directory).await?;
+    if running {
+        start_server(state.clone(), &cfg.id).await?;
+    }
+    Ok(running)
+}
+
+/// A new tarball named after `label` to archive reset folders in, in the
+/// server's `backup_directory`, or `data_directory/world-archives/<server id>`
+/// without one.
+async fn archive_path(
+    state: &AppState,
+    cfg: &ServerConfig,
+    label: &str,
+) -> Result<PathBuf, String> {
+    let dir = match cfg.backup_directory {
+        Some(ref dir) => PathBuf::from(dir),
+        None => {
+            let data_directory = state.config.read().await.agent.data_directory.clone();
+            Path::new(&data_directory).join("world-archives").join(&cfg.id)
+        }
+    };
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .map_err(|e| format!("Failed to create '{}': {}", dir.display(), e))?;
+    let timestamp = chrono::Local::now().format("%Y-%m-%d_%H-%M-%S");
+    Ok(dir.join(format!("{}_{}_{}.tar.gz", cfg.id, label, timestamp)))
+}
+
+/// Packs `paths` of the server directory into `archive`. The names go
+/// through stdin, so any number of them fits.
+async fn archive_paths(directory: &Path, archive: &Path, paths: &[String]) -> Result<(), String> {
+    let mut child = tokio::process::Command::new("tar")
+        .arg("-czf")
+        .arg(archive)
+        .arg("-C")
+        .arg(directory)
+        .args(["--null", "--verbatim-files-from", "-T", "-"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to execute tar command: {}", e))?;
+    let names: Vec<u8> = paths.iter().flat_map(|p| p.bytes().chain([0])).collect();
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(&names)
+            .await
+            .map_err(|e| format!("Failed to pass file names to tar: {}", e))?;
+    }
+    let output = child
+        .wait_with_output()
+        .await
+        .map_err(|e| format!("Failed to execute tar command: {}", e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Tar command failed: {}", stderr.trim()));
+    }
+    Ok(())
+}
+
+async fn remove_folders(directory: &Path, folders: &[String]) -> Result<(), String> {
+    for folder in folders {
+        let path = directory.join(folder);
+        tokio::fs::remove_dir_all(&path)
+            .await
+            .map_err(|e| format!("Failed to delete '{}': {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+/// Archives and deletes the active world so the server generates a new
+/// one, with `seed` as its `level-seed` when given (an empty seed picks a
+/// random one). A running server is stopped first and started again after.
+pub async fn reset_world(
+    state: &AppState,
+    cfg: &ServerConfig,
+    seed: Option<&str>,
+) -> Result<WorldReset, String> {
+    if seed.is_some_and(|s| s.chars().any(char::is_control)) {
+        return Err("seed must not contain control characters".to_string());
+    }
+    let world = world_dir(cfg).await?;
+    if !world.is_dir() {
+        return Err(format!("'{}' has no world to reset", cfg.id));
+    }
+    let directory = Path::new(&cfg.directory);
+    let level_name = level_name(cfg).await?;
+    crate::maintenance::check(state).await?;
+
+    let running = state.servers.contains_key(&cfg.id);
+    if running {
+        stop_server(state.clone(), &cfg.id).await?;
+    }
+
+    let archive = archive_path(state, cfg, &level_name).await?;
+    let mut folders = dimension_folders(directory, &level_name);
+    folders.insert(0, level_name.clone());
+    if let Err(e) = archive_paths(directory, &archive, &folders).await {
+        // Nothing was deleted, so the old world comes back up
+        if running {
+            start_server(state.clone(), &cfg.id).await?;
+        }
+        return Err(e);
+    }
+    tracing::info!("Archived the world of '{}' to {:?}", cfg.id, archive);
+    remove_folders(directory, &folders).await?;
+    if let Some(seed) = seed {
+        let mut properties = ServerProperties::load(&cfg.directory).await?;
+        properties.set_text("level-seed", seed);
+        properties.save(&cfg.directory).await?;
+    }
+    let archive = archive.to_string_lossy().into_owned();
+    if running {
+        start_server(state.clone(), &cfg.id).await.map_err(|e| {
+            format!("World reset and archived to '{}', but starting failed: {}", archive, e)
+        })?;
+    }
+    Ok(WorldReset {
+        level_name,
+        archive,
+        seed: seed.map(str::to_string),
+        restarted: running,
+    })
+}
+
+/// Archives and deletes one dimension of the active world, which the
+/// server regenerates on its next start. Only while it is stopped, since a
+/// running server would write its loaded chunks back.
+pub async fn reset_dimension(
+    state: &AppState,
+    cfg: &ServerConfig,
+    dimension: Dimension,
+) -> Result<DimensionReset, String> {
+    if state.servers.contains_key(&cfg.id) {
+        return Err(format!("Server '{}' must be stopped first", cfg.id));
+    }
+    crate::maintenance::check(state).await?;
+    world_dir(cfg).await?;
+    let directory = Path::new(&cfg.directory);
+    let level_name = level_name(cfg).await?;
+    let removed: Vec<String> = dimension
+        .folders(&level_name)
+        .into_iter()
+        .filter(|folder| directory.join(folder).is_dir())
+        .collect();
+    if removed.is_empty() {
+        return Err(format!("'{}' has no {} to reset", cfg.id, dimension.name()));
+    }
+
+    let label = format!("{}_{}", level_name, dimension.name());
+    let archive = archive_path(state, cfg, &label).await?;
+    archive_paths(directory, &archive, &removed).await?;
+    tracing::info!("Archived the {} of '{}' to {:?}", dimension.name(), cfg.id, archive);
+    remove_folders(directory, &removed).await?;
+    Ok(DimensionReset {
+        dimension,
+        removed,
+        archive: archive.to_string_lossy().into_owned(),
+    })
+}
+
+fn region_regex() -> &'static Regex {
+    static RE: std::sync::OnceLock<Regex> = std::sync::OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"^r\.(-?\d+)\.(-?\d+)\.mca$").expect("valid regex"))
+}
+
+/// Folders of the world `name` holding a `region` folder: the overworld
+/// and the vanilla and Bukkit-style Nether and End.
+fn dimension_roots(directory: &Path, name: &str) -> Vec<String> {
+    let mut roots = Vec::new();
+    for world in [name.to_string(), format!("{}_nether", name), format!("{}_the_end", name)] {
+        for dim in ["", "/DIM-1", "/DIM1"] {
+            let root = format!("{}{}", world, dim);
+            if directory.join(&root).join("region").is_dir() {
+                roots.push(root);
+            }
+        }
+    }
+    roots
+}
+
+/// Whether the region at `x`, `z` overlaps the square kept by `request`.
+/// A region too far out to place in blocks counts as inside, so it is kept.
+fn region_in_radius(x: i64, z: i64, radius: u32, request: &TrimRequest) -> bool {
+    let radius = i64::from(radius);
+    let overlaps = |region: i64, center: i64| {
+        let Some(start) = region.checked_mul(REGION_BLOCKS) else {
+            return true;
+        };
+        start <= center.saturating_add(radius)
+            && start.saturating_add(REGION_BLOCKS) > center.saturating_sub(radius)
+    };
+    overlaps(x, request.center_x) && overlaps(z, request.center_z)
+}
+
+/// The region files of the world `name` to trim, with their total size.
+async fn regions_to_trim(
+    directory: &Path,
+    name: &str,
+    request: &TrimRequest,
+) -> Result<(Vec<String>, u64), String> {
+    let cutoff = request.unused_days.map(|days| {
+        let age = std::time::Duration::from_secs(u64::from(days) * 86_400);
+        std::time::SystemTime::now().checked_sub(age).unwrap_or(std::time::UNIX_EPOCH)
+    });
+    let (mut files, mut bytes) = (Vec::new(), 0);
+    for root in dimension_roots(directory, name) {
+        let region_dir = directory.join(&root).join("region");
+        let mut entries = tokio::fs::read_dir(&region_dir)
+            .await
+            .map_err(|e| format!("Failed to read '{}': {}", region_dir.display(), e))?;
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let Some(caps) = region_regex().captures(&file_name) else {
+                continue;
+            };
+            let (Ok(x), Ok(z)) = (caps[1].parse::<i64>(), caps[2].parse::<i64>()) else {
+                continue;
+            };
+            let outside = request.radius.is_some_and(|r| !region_in_radius(x, z, r, request));
+            let unused = match cutoff {
+                Some(cutoff) if !outside => {
+                    let modified = entry.metadata().await.and_then(|m| m.modified());
+                    modified.is_ok_and(|m| m < cutoff)
+                }
+                _ => false,
+            };
+            if !outside && !unused {
+                continue;
+            }
+            for folder in REGION_FOLDERS {
+                let file = format!("{}/{}/{}", root, folder, file_name);
+                if let Ok(metadata) = tokio::fs::metadata(directory.join(&file)).await {
+                    bytes += metadata.len();
+                    files.push(file);
+                }
+            }
+        }
+    }
+    files.sort();
+    Ok((files, bytes))
+}
+
+/// Deletes the region files of the world `name` outside a radius or left
+/// unsaved for a number of days, archiving them first. Only while the
+/// server is stopped, since it keeps loaded regions open.
+pub async fn trim_world(
+    state: &AppState,
+    cfg: &ServerConfig,
+    name: &str,
+    request: &TrimRequest,
+) -> Result<TrimReport, String> {
+    if request.radius.is_none() && request.unused_days.is_none() {
+        return Err("give a radius, unused_days or both".to_string());
+    }
+    if !request.dry_run && state.servers.contains_key(&cfg.id) {
+        return Err(format!("Server '{}' must be stopped first", cfg.id));
+    }
+    let directory = Path::new(&cfg.directory);
+    let (files, bytes) = regions_to_trim(directory, name, request).await?;
+    let mut report = TrimReport {
+        world: name.to_string(),
+        dry_run: request.dry_run,
+        files,
+        bytes,
+        archive: None,
+    };
+    if request.dry_run || report.files.is_empty() {
+        return Ok(report);
+    }
+
+    let archive = archive_path(state, cfg, &format!("{}_trim", name)).await?;
+    archive_paths(directory, &archive, &report.files).await?;
+    for file in &report.files {
+        let path = directory.join(file);
+        tokio::fs::remove_file(&path)
+            .await
+            .map_err(|e| format!("Failed to delete '{}': {}", path.display(), e))?;
+    }
+    tracing::info!(
+        "Trimmed {} region files ({} bytes) from '{}' on '{}', archived to {:?}",
+        report.files.len(),
+        report.bytes,
+        name,
+        cfg.id,
+        archive
+    );
+    report.archive = Some(archive.to_string_lossy().into_owned());
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level_name_must_be_one_folder() {
+        assert!(single_folder("world"));
+        assert!(single_folder("my world"));
+        for name in ["../etc", "/tmp/world", "world/../..", ".", "..", "a/b", ""] {
+            assert!(!single_folder(name), "{} was accepted", name);
+        }
+    }
+
+    #[test]
+    fn locate_answers_are_read_from_the_server() {
+        let line = "[12:00:00 INFO]: The nearest minecraft:village_plains is at [96, ~, -208] \
+                    (229 blocks away)";
+        let caps = locate_regex().captures(line).unwrap();
+        assert_eq!((&caps[1], &caps[2], &caps[3], &caps[4]), ("96", "~", "-208", "229"));
+        let line = "[12:00:00] [Server thread/INFO]: Could not find a structure of type x";
+        assert!(locate_regex().captures(line).is_some_and(|c| c.get(1).is_none()));
+    }
+
+    #[test]
+    fn locate_answers_are_not_taken_from_chat() {
+        for line in [
+            "[12:00:00 INFO]: <Bob> The nearest x is at [0, 64, 0]",
+            "[12:00:00 INFO]: <Bob> Could not find",
+            "[12:00:00 INFO]: <Bob> ]: The nearest x is at [0, 64, 0]",
+        ] {
+            assert!(!locate_regex().is_match(line), "{}", line);
+        }
+    }
+
+    fn trim(radius: u32, center_x: i64, center_z: i64) -> TrimRequest {
+        TrimRequest {
+            radius: Some(radius),
+            center_x,
+            center_z,
+            unused_days: None,
+            dry_run: true,
+        }
+    }
+
+    #[test]
+    fn region_in_radius_keeps_overlapping_regions() {
+        let request = trim(1000, 0, 0);
+        // Blocks -1024..-513 and 512..1023 reach into the square
+        assert!(region_in_radius(0, 0, 1000, &request));
+        assert!(region_in_radius(-2, 1, 1000, &request));
+        assert!(!region_in_radius(2, 0, 1000, &request));
+        assert!(!region_in_radius(0, -3, 1000, &request));
+
+        let request = trim(100, 5000, -5000);
+        assert!(region_in_radius(9, -10, 100, &request));
+        assert!(!region_in_radius(0, 0, 100, &request));
+    }
+
+    #[test]
+    fn region_in_radius_does_not_overflow() {
+        let request = trim(u32::MAX, i64::MAX, i64::MIN);
+        assert!(region_in_radius(i64::MAX, i64::MIN, u32::MAX, &request));
+        assert!(!region_in_radius(0, 0, u32::MAX, &request));
+    }
+
+    /// A named NBT tag: its type, name and payload.
+    fn nbt(kind: u8, name: &str, payload: &[u8]) -> Vec<u8> {
+        let mut tag = vec![kind];
+        tag.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        tag.extend_from_slice(name.as_bytes());
+        tag.extend_from_slice(payload);
+        tag
+    }
+
+    fn compound(name: &str, children: &[Vec<u8>]) -> Vec<u8> {
+        let mut payload = children.concat();
+        payload.push(0);
+        nbt(10, name, &payload)
+    }
+
+    fn string(name: &str, value: &str) -> Vec<u8> {
+        let mut payload = (value.len() as u16).to_be_bytes().to_vec();
+        payload.extend_from_slice(value.as_bytes());
+        nbt(8, name, &payload)
+    }
+
+    #[tokio::test]
+    async fn level_info_reads_a_gzipped_level_dat() {
+        use std::io::Write;
+
+        // An int array: its length, then the ints
+        let pos: Vec<u8> = [3i32, 10, 64, -20].iter().flat_map(|n| n.to_be_bytes()).collect();
+        let generator = compound(
+            "generator",
+            &[string("type", "minecraft:noise"), string("settings", "minecraft:amplified")],
+        );
+        let level = compound(
+            "",
+            &[compound(
+                "Data",
+                &[
+                    string("LevelName", "Survival"),
+                    nbt(3, "DataVersion", &4189i32.to_be_bytes()),
+                    nbt(1, "hardcore", &[1]),
+                    compound("Version", &[string("Name", "1.21.4")]),
+                    compound("spawn", &[nbt(11, "pos", &pos)]),
+                    compound(
+                        "WorldGenSettings",
+                        &[
+                            nbt(4, "seed", &(-42i64).to_be_bytes()),
+                            compound(
+                                "dimensions",
+                                &[compound("minecraft:overworld", &[generator])],
+                            ),
+                        ],
+                    ),
+                ],
+            )],
+        );
+        let dir = std::env::temp_dir().join(format!("level-test-{}", std::process::id()));
+        tokio::fs::create_dir_all(dir.join("world")).await.unwrap();
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(&level).unwrap();
+        tokio::fs::write(dir.join("world/level.dat"), gz.finish().unwrap()).await.unwrap();
+
+        let cfg: ServerConfig = serde_json::from_value(serde_json::json!({
+            "id": "s",
+            "name": "s",
+            "directory": dir.to_string_lossy(),
+            "jar": "server.jar",
+            "memory_mb": 2048,
+            "autostart": false,
+        }))
+        .unwrap();
+        let info = level_info(&cfg, "world").await;
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        let info = info.unwrap();
+        assert_eq!(info.name, "Survival");
+        assert_eq!(info.seed, Some(-42));
+        let spawn = info.spawn.unwrap();
+        assert_eq!((spawn.x, spawn.y, spawn.z), (10, 64, -20));
+        assert_eq!(info.version.as_deref(), Some("1.21.4"));
+        assert_eq!(info.data_version, Some(4189));
+        assert_eq!(info.world_type.as_deref(), Some("amplified"));
+        assert!(info.hardcore);
+    }
+}
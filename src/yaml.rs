@@ -0,0 +1,58 @@
+//! Read-modify-write helpers for the YAML configs of Paper, Spigot and
+//! plugins. Comments are not preserved.
+
+use std::path::Path;
+
+use serde_yaml::{Mapping, Value};
+
+/// Loads a YAML document, treating a missing or empty file as an empty map.
+pub async fn load(path: &Path) -> Result<Value, String> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(contents) if contents.trim().is_empty() => Ok(Value::Mapping(Mapping::new())),
+        Ok(contents) => serde_yaml::from_str(&contents)
+            .map_err(|e| format!("Failed to parse '{}': {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Value::Mapping(Mapping::new())),
+        Err(e) => Err(format!("Failed to read '{}': {}", path.display(), e)),
+    }
+}
+
+pub async fn save(path: &Path, value: &Value) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| format!("Failed to create '{}': {}", parent.display(), e))?;
+    }
+    let contents = serde_yaml::to_string(value)
+        .map_err(|e| format!("Failed to serialize '{}': {}", path.display(), e))?;
+    tokio::fs::write(path, contents)
+        .await
+        .map_err(|e| format!("Failed to write '{}': {}", path.display(), e))
+}
+
+pub fn get<'a>(value: &'a Value, path: &[&str]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |v, key| v.get(*key))
+}
+
+/// Sets a nested key, creating (or replacing non-mapping) parents on the way.
+pub fn set(value: &mut Value, path: &[&str], new: Value) {
+    let Some((last, parents)) = path.split_last() else {
+        return;
+    };
+    let mut current = value;
+    for key in parents {
+        if !current.is_mapping() {
+            *current = Value::Mapping(Mapping::new());
+        }
+        let map = current.as_mapping_mut().expect("just made a mapping");
+        current = map
+            .entry(Value::from(*key))
+            .or_insert_with(|| Value::Mapping(Mapping::new()));
+    }
+    if !current.is_mapping() {
+        *current = Value::Mapping(Mapping::new());
+    }
+    current
+        .as_mapping_mut()
+        .expect("just made a mapping")
+        .insert(Value::from(*last), new);
+}